@@ -0,0 +1,137 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+
+use darkfi::{
+    rpc::{jsonrpc::JsonRequest, util::JsonValue},
+    Error, Result,
+};
+use log::{error, info};
+
+use super::MiningProxy;
+
+/// Maximum number of auxiliary blocks we keep track of
+const MAX_AUX_BLOCKS: usize = 32;
+
+/// A DarkFi auxiliary block, as returned by darkfid
+pub(crate) struct AuxBlock {
+    /// Auxiliary block hash, to commit in the Monero merge mining tag
+    pub hash: [u8; 32],
+}
+
+impl MiningProxy {
+    /// Retrieve the merge mining chain id of the darkfid network
+    pub(crate) async fn darkfid_get_chain_id(&self) -> Result<String> {
+        let req = JsonRequest::new("merge_mining_get_chain_id", vec![].into());
+        let rep = self.darkfid_rpc.request(req).await?;
+
+        let Some(chain_id) = rep
+            .get::<HashMap<String, JsonValue>>()
+            .and_then(|r| r.get("chain_id"))
+            .and_then(|c| c.get::<String>())
+        else {
+            return Err(Error::UnexpectedJsonRpc(format!("Invalid chain id: {rep:?}")))
+        };
+
+        Ok(chain_id.clone())
+    }
+
+    /// Retrieve an auxiliary block from darkfid for the Monero block of
+    /// given height and previous block id, rewarding our DarkFi address.
+    /// If our last auxiliary block is still valid, darkfid returns it again.
+    pub(crate) async fn darkfid_get_aux_block(
+        &self,
+        height: f64,
+        prev_id: &str,
+    ) -> Result<AuxBlock> {
+        let mut last_aux_hash = self.last_aux_hash.lock().await;
+        let params = HashMap::from([
+            ("address".to_string(), JsonValue::String(self.darkfi_address.clone())),
+            ("aux_hash".to_string(), JsonValue::String(hex::encode(*last_aux_hash))),
+            ("height".to_string(), JsonValue::Number(height)),
+            ("prev_id".to_string(), JsonValue::String(prev_id.to_string())),
+        ]);
+        let req = JsonRequest::new("merge_mining_get_aux_block", JsonValue::Object(params));
+        let rep = self.darkfid_rpc.request(req).await?;
+
+        let Some(aux_block) = rep.get::<HashMap<String, JsonValue>>() else {
+            return Err(Error::UnexpectedJsonRpc(format!("Invalid aux block: {rep:?}")))
+        };
+        let Some(hash) = aux_block.get("aux_hash").and_then(|h| h.get::<String>()) else {
+            return Err(Error::UnexpectedJsonRpc(format!("Invalid aux block: {rep:?}")))
+        };
+
+        let Ok(Ok(hash)) = hex::decode(hash).map(<[u8; 32]>::try_from) else {
+            return Err(Error::ParseFailed("Invalid aux block hash"))
+        };
+        *last_aux_hash = hash;
+
+        Ok(AuxBlock { hash })
+    }
+
+    /// Keep track of the Monero RandomX seed hash of the block template
+    /// committing to given auxiliary block hash, which is required to
+    /// submit its solution to darkfid.
+    pub(crate) async fn track_aux_block(&self, aux_hash: [u8; 32], seed_hash: String) {
+        let mut seed_hashes = self.seed_hashes.lock().await;
+        seed_hashes.retain(|(h, _)| h != &aux_hash);
+        if seed_hashes.len() == MAX_AUX_BLOCKS {
+            seed_hashes.remove(0);
+        }
+        seed_hashes.push((aux_hash, seed_hash));
+    }
+
+    /// Submit the given Monero block as a solution of the auxiliary block
+    /// of provided hash to darkfid. Since we merge mine a single chain,
+    /// the merge mining tag commits directly to the auxiliary block hash,
+    /// so its Merkle proof is empty.
+    pub(crate) async fn darkfid_submit_solution(
+        &self,
+        aux_hash: &[u8; 32],
+        blob: &str,
+    ) -> Result<()> {
+        let seed_hash = {
+            let seed_hashes = self.seed_hashes.lock().await;
+            let Some((_, seed_hash)) = seed_hashes.iter().find(|(h, _)| h == aux_hash) else {
+                return Err(Error::Custom("Unknown aux block".to_string()))
+            };
+            seed_hash.clone()
+        };
+
+        let aux_hash = JsonValue::String(hex::encode(aux_hash));
+        let params = HashMap::from([
+            ("aux_blob".to_string(), aux_hash.clone()),
+            ("aux_hash".to_string(), aux_hash),
+            ("blob".to_string(), JsonValue::String(blob.to_string())),
+            ("merkle_proof".to_string(), JsonValue::Array(vec![])),
+            ("seed_hash".to_string(), JsonValue::String(seed_hash)),
+        ]);
+        let req = JsonRequest::new("merge_mining_submit_solution", JsonValue::Object(params));
+        match self.darkfid_rpc.request(req).await {
+            Ok(rep) => {
+                info!(target: "darkfid::submit_solution", "DarkFi block accepted: {}", rep.stringify()?);
+                Ok(())
+            }
+            Err(e) => {
+                error!(target: "darkfid::submit_solution", "DarkFi block rejected: {}", e);
+                Err(e)
+            }
+        }
+    }
+}
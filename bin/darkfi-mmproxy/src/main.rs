@@ -21,14 +21,16 @@ use std::{collections::HashMap, sync::Arc};
 use darkfi::{
     async_daemonize, cli_desc,
     rpc::{
+        client::RpcClient,
         jsonrpc::{JsonRequest, JsonResponse},
         util::JsonValue,
     },
+    system::ExecutorPtr,
     Error, Result,
 };
 use log::{debug, error, info};
 use serde::Deserialize;
-use smol::{stream::StreamExt, Executor};
+use smol::{lock::Mutex, stream::StreamExt, Executor};
 use structopt::StructOpt;
 use structopt_toml::StructOptToml;
 use surf::StatusCode;
@@ -41,6 +43,9 @@ const CONFIG_FILE_CONTENTS: &str = include_str!("../darkfi_mmproxy.toml");
 mod monerod;
 use monerod::MonerodRequest;
 
+/// darkfid RPC functions
+mod darkfid;
+
 #[derive(Clone, Debug, Deserialize, StructOpt, StructOptToml)]
 #[serde(default)]
 #[structopt(name = "darkfi-mmproxy", about = cli_desc!())]
@@ -62,6 +67,9 @@ struct Args {
 
     #[structopt(flatten)]
     monerod: MonerodArgs,
+
+    #[structopt(flatten)]
+    darkfid: DarkfidArgs,
 }
 
 #[derive(Clone, Debug, Deserialize, StructOpt, StructOptToml)]
@@ -84,17 +92,43 @@ struct MonerodArgs {
     monero_rpc: Url,
 }
 
+#[derive(Clone, Debug, Deserialize, StructOpt, StructOptToml)]
+#[structopt()]
+struct DarkfidArgs {
+    #[structopt(long, default_value = "tcp://127.0.0.1:8341")]
+    /// darkfid miner JSON-RPC endpoint
+    darkfid_rpc: Url,
+
+    #[structopt(long)]
+    /// DarkFi wallet address to receive the merge mined blocks rewards
+    darkfi_address: Option<String>,
+}
+
 /// Mining proxy state
 struct MiningProxy {
     /// Monero network type
     monero_network: monero::Network,
     /// Monero RPC address
     monero_rpc: Url,
+    /// JSON-RPC client to execute requests to darkfid
+    darkfid_rpc: RpcClient,
+    /// DarkFi wallet address to receive the merge mined blocks rewards
+    darkfi_address: String,
+    /// Last auxiliary block hash we got from darkfid
+    last_aux_hash: Mutex<[u8; 32]>,
+    /// Monero RandomX seed hashes of the block templates we handed
+    /// out, keyed by the auxiliary block hash they commit to
+    seed_hashes: Mutex<Vec<([u8; 32], String)>>,
 }
 
 impl MiningProxy {
     /// Instantiate `MiningProxy` state
-    async fn new(monerod: MonerodArgs) -> Result<Self> {
+    async fn new(monerod: MonerodArgs, darkfid: DarkfidArgs, ex: ExecutorPtr) -> Result<Self> {
+        let Some(darkfi_address) = darkfid.darkfi_address else {
+            error!("DarkFi rewards address is missing");
+            return Err(Error::ParseFailed("DarkFi address missing"))
+        };
+
         let monero_network = match monerod.monero_network.to_lowercase().as_str() {
             "mainnet" => monero::Network::Mainnet,
             "testnet" => monero::Network::Testnet,
@@ -107,9 +141,24 @@ impl MiningProxy {
             }
         };
 
+        let darkfid_rpc = match RpcClient::new(darkfid.darkfid_rpc, ex).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed connecting to darkfid RPC: {}", e);
+                return Err(e)
+            }
+        };
+
         // Test that monerod RPC is reachable and is configured
         // with the matching network
-        let self_ = Self { monero_network, monero_rpc: monerod.monero_rpc };
+        let self_ = Self {
+            monero_network,
+            monero_rpc: monerod.monero_rpc,
+            darkfid_rpc,
+            darkfi_address,
+            last_aux_hash: Mutex::new([0u8; 32]),
+            seed_hashes: Mutex::new(vec![]),
+        };
 
         let req = JsonRequest::new("getinfo", vec![].into());
         let rep: JsonResponse = match self_.monero_request(MonerodRequest::Post(req)).await {
@@ -147,6 +196,17 @@ impl MiningProxy {
             return Err(Error::Custom("Monero network mismatch".to_string()))
         }
 
+        // Test that darkfid RPC is reachable and grab the chain id
+        // we are merge mining
+        let chain_id = match self_.darkfid_get_chain_id().await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed retrieving darkfid merge mining chain id: {}", e);
+                return Err(e)
+            }
+        };
+        info!("Merge mining DarkFi chain {}", chain_id);
+
         Ok(self_)
     }
 }
@@ -155,8 +215,8 @@ async_daemonize!(realmain);
 async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     info!("Starting DarkFi x Monero merge mining proxy");
 
-    let mmproxy = Arc::new(MiningProxy::new(args.monerod).await?);
-    let mut app = tide::with_state(mmproxy);
+    let mmproxy = Arc::new(MiningProxy::new(args.monerod, args.darkfid, ex.clone()).await?);
+    let mut app = tide::with_state(mmproxy.clone());
 
     // monerod `/getheight` endpoint proxy [HTTP GET]
     app.at("/getheight").get(|req: tide::Request<Arc<MiningProxy>>| async move {
@@ -238,5 +298,8 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     signals_handler.wait_termination(signals_task).await?;
     info!("Caught termination signal, cleaning up and exiting");
 
+    info!("Stopping darkfid JSON-RPC client...");
+    mmproxy.darkfid_rpc.stop().await;
+
     Ok(())
 }
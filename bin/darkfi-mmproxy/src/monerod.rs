@@ -26,7 +26,10 @@ use darkfi::{
     Error, Result,
 };
 use log::{debug, error, info};
-use monero::blockdata::transaction::{ExtraField, RawExtraField, SubField::MergeMining};
+use monero::blockdata::transaction::{
+    ExtraField, RawExtraField,
+    SubField::{MergeMining, Nonce},
+};
 
 use super::MiningProxy;
 
@@ -106,7 +109,10 @@ impl MiningProxy {
         Ok(rep)
     }
 
-    /// Proxy the `submitblock` RPC request
+    /// Proxy the `submitblock` RPC request. Each block is also submitted
+    /// to darkfid as a solution of the auxiliary block its merge mining
+    /// tag commits to. The monerod response is returned unchanged, since
+    /// it's the one the miner submitted the block for.
     pub async fn monerod_submit_block(&self, req: &JsonValue) -> Result<JsonValue> {
         info!(target: "monerod::submitblock", "Proxying submitblock request");
         let request = JsonRequest::try_from(req)?;
@@ -120,14 +126,31 @@ impl MiningProxy {
                 return Err(Error::Custom("Invalid request".to_string()))
             };
 
-            debug!(
-                target: "monerod::submitblock", "{:#?}",
-                monero::consensus::deserialize::<monero::Block>(&hex::decode(block).unwrap()).unwrap(),
-            );
+            let Ok(Ok(monero_block)) =
+                hex::decode(block).map(|b| monero::consensus::deserialize::<monero::Block>(&b))
+            else {
+                return Err(Error::Custom("Invalid request".to_string()))
+            };
+            debug!(target: "monerod::submitblock", "{:#?}", monero_block);
+
+            // Grab the auxiliary block hash from the merge mining tag
+            let Some(aux_hash) =
+                ExtraField::try_parse(&monero_block.miner_tx.prefix.extra).ok().and_then(|extra| {
+                    extra.0.into_iter().find_map(|field| match field {
+                        MergeMining(_, hash) => Some(hash.0),
+                        _ => None,
+                    })
+                })
+            else {
+                error!(target: "monerod::submitblock", "Block is missing the merge mining tag");
+                continue
+            };
+
+            // darkfid rejections are already logged
+            let _ = self.darkfid_submit_solution(&aux_hash, block).await;
         }
 
-        let response = self.monero_request(MonerodRequest::Post(request)).await?;
-        Ok(response)
+        self.monero_request(MonerodRequest::Post(request)).await
     }
 
     /// Perform the `getblocktemplate` request and modify it with the necessary
@@ -161,18 +184,13 @@ impl MiningProxy {
             return Err(Error::Custom("Non-standard Monero address".to_string()))
         }
 
-        // Create the Merge Mining data
-        // TODO: This is where we're gonna include the necessary DarkFi data
-        // that has to end up in Monero blocks.
-        let mm_tag = MergeMining(monero::VarInt(32), monero::Hash([0_u8; 32]));
-
-        // Construct `tx_extra` from all the extra fields we have to add to
-        // the coinbase transaction in the block we're mining.
-        let tx_extra: RawExtraField = ExtraField(vec![mm_tag]).into();
-
-        // Modify the params `reserve_size` to fit our Merge Mining data
-        debug!(target: "monerod::getblocktemplate", "Inserting \"reserve_size\":{}", tx_extra.0.len());
-        params.insert("reserve_size".to_string(), (tx_extra.0.len() as f64).into());
+        // Modify the params `reserve_size` to fit our Merge Mining data.
+        // Since we merge mine a single chain with a zero nonce, the tag data
+        // is zero and its Merkle root is the DarkFi auxiliary block hash.
+        let mm_tag = MergeMining(monero::VarInt(0), monero::Hash([0_u8; 32]));
+        let reserve_size = RawExtraField::from(ExtraField(vec![mm_tag])).0.len();
+        debug!(target: "monerod::getblocktemplate", "Inserting \"reserve_size\":{}", reserve_size);
+        params.insert("reserve_size".to_string(), (reserve_size as f64).into());
 
         // Remove `extra_nonce` from the request, XMRig tends to send this in daemon-mode
         params.remove("extra_nonce");
@@ -189,8 +207,24 @@ impl MiningProxy {
         )
         .unwrap();
 
-        // Update coinbase tx with our extra field
-        block_template.miner_tx.prefix.extra = tx_extra;
+        // Grab the DarkFi auxiliary block to merge mine
+        let (Some(height), Some(prev_hash), Some(seed_hash)) = (
+            gbt_result.get("height").and_then(|h| h.get::<f64>()),
+            gbt_result.get("prev_hash").and_then(|h| h.get::<String>()),
+            gbt_result.get("seed_hash").and_then(|h| h.get::<String>()),
+        ) else {
+            return Err(Error::Custom("Invalid monerod block template".to_string()))
+        };
+        let aux_block = self.darkfid_get_aux_block(*height, prev_hash).await?;
+        self.track_aux_block(aux_block.hash, seed_hash.clone()).await;
+
+        // Replace the reserved space in the coinbase tx extra with our tag
+        let Ok(mut extra) = ExtraField::try_parse(&block_template.miner_tx.prefix.extra) else {
+            return Err(Error::Custom("Invalid monerod block template".to_string()))
+        };
+        extra.0.retain(|field| !matches!(field, Nonce(_) | MergeMining(_, _)));
+        extra.0.push(MergeMining(monero::VarInt(0), monero::Hash(aux_block.hash)));
+        block_template.miner_tx.prefix.extra = extra.into();
 
         // Update `blocktemplate_blob` with the modified block:
         gbt_result.insert(
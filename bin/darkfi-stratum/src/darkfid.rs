@@ -0,0 +1,183 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+
+use darkfi::{
+    blockchain::Header,
+    rpc::{
+        client::RpcClient,
+        jsonrpc::{JsonRequest, JsonResult},
+        util::JsonValue,
+    },
+    system::{sleep, ExecutorPtr, PublisherPtr},
+    util::encoding::base64,
+    Error, Result,
+};
+use darkfi_serial::deserialize_async;
+use log::{error, info};
+use num_bigint::BigUint;
+use smol::lock::Mutex;
+use url::Url;
+
+/// Seconds to wait before reconnecting a dropped proposals subscription
+const RESUBSCRIBE_INTERVAL: u64 = 10;
+
+/// A next block template retrieved from darkfid
+#[derive(Clone)]
+pub struct BlockTemplate {
+    /// Template id, used to submit its solutions
+    pub id: String,
+    /// Block header to mine
+    pub header: Header,
+    /// Block mining target
+    pub target: BigUint,
+}
+
+/// darkfid JSON-RPC client, retrieving block templates rewarding
+/// the configured recipient and submitting their solutions.
+pub struct DarkfidClient {
+    /// darkfid JSON-RPC endpoint
+    endpoint: Url,
+    /// JSON-RPC client to execute requests to darkfid
+    rpc_client: Mutex<RpcClient>,
+    /// `miner.get_template` request params
+    template_params: JsonValue,
+    /// Executor to spawn the JSON-RPC clients on
+    ex: ExecutorPtr,
+}
+
+impl DarkfidClient {
+    /// Connect to darkfid at the given endpoint. Templates rewards will
+    /// go to the given recipient, using given spend hook and user data.
+    pub async fn new(
+        endpoint: Url,
+        recipient: String,
+        spend_hook: Option<String>,
+        user_data: Option<String>,
+        ex: ExecutorPtr,
+    ) -> Result<Self> {
+        let rpc_client = Mutex::new(RpcClient::new(endpoint.clone(), ex.clone()).await?);
+
+        let mut template_params = vec![JsonValue::String(recipient)];
+        if let Some(spend_hook) = spend_hook {
+            template_params.push(JsonValue::String(spend_hook));
+        }
+        if let Some(user_data) = user_data {
+            template_params.push(JsonValue::String(user_data));
+        }
+        let template_params = JsonValue::Array(template_params);
+
+        Ok(Self { endpoint, rpc_client, template_params, ex })
+    }
+
+    /// Stop the darkfid JSON-RPC client.
+    pub async fn stop(&self) {
+        self.rpc_client.lock().await.stop().await;
+    }
+
+    /// Auxiliary function to execute a request towards darkfid.
+    /// If the connection got dropped, we reconnect so next requests
+    /// can go through.
+    async fn request(&self, method: &str, params: JsonValue) -> Result<JsonValue> {
+        let mut rpc_client = self.rpc_client.lock().await;
+        match rpc_client.request(JsonRequest::new(method, params)).await {
+            Ok(rep) => Ok(rep),
+            Err(Error::JsonRpcError(e)) => Err(Error::JsonRpcError(e)),
+            Err(e) => {
+                error!(target: "darkfi_stratum::darkfid::request", "darkfid connection error: {}", e);
+                rpc_client.stop().await;
+                match RpcClient::new(self.endpoint.clone(), self.ex.clone()).await {
+                    Ok(c) => {
+                        info!(target: "darkfi_stratum::darkfid::request", "Reconnected to darkfid");
+                        *rpc_client = c;
+                    }
+                    Err(e) => {
+                        error!(target: "darkfi_stratum::darkfid::request", "Failed reconnecting to darkfid: {}", e)
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Retrieve a new next block template from darkfid.
+    pub async fn get_template(&self) -> Result<BlockTemplate> {
+        let rep = self.request("miner.get_template", self.template_params.clone()).await?;
+        let Some(template) = rep.get::<HashMap<String, JsonValue>>() else {
+            return Err(Error::UnexpectedJsonRpc(format!("Invalid block template: {rep:?}")))
+        };
+
+        let field = |name: &str| -> Result<&String> {
+            match template.get(name).and_then(|v| v.get::<String>()) {
+                Some(v) => Ok(v),
+                None => Err(Error::UnexpectedJsonRpc(format!("Block template missing {name}"))),
+            }
+        };
+
+        let id = field("template_id")?.clone();
+        let Ok(target) = field("target")?.parse::<BigUint>() else {
+            return Err(Error::ParseFailed("Invalid block template target"))
+        };
+        let Some(header) = base64::decode(field("header")?) else {
+            return Err(Error::ParseFailed("Invalid block template header"))
+        };
+        let header = deserialize_async(&header).await?;
+
+        Ok(BlockTemplate { id, header, target })
+    }
+
+    /// Submit a solved nonce for the block template of given id.
+    /// Returns the mined block hash.
+    pub async fn submit(&self, template_id: &str, nonce: u64) -> Result<String> {
+        let params = JsonValue::Array(vec![
+            JsonValue::String(template_id.to_string()),
+            JsonValue::String(nonce.to_string()),
+        ]);
+        let rep = self.request("miner.submit", params).await?;
+        let Some(hash) = rep.get::<String>() else {
+            return Err(Error::UnexpectedJsonRpc(format!("Invalid submission reply: {rep:?}")))
+        };
+
+        Ok(hash.clone())
+    }
+
+    /// Subscribe to darkfid new block proposals, notifying them to the
+    /// given publisher. Dropped subscriptions are reestablished.
+    pub async fn subscribe_proposals(&self, publisher: PublisherPtr<JsonResult>) -> Result<()> {
+        loop {
+            match RpcClient::new(self.endpoint.clone(), self.ex.clone()).await {
+                Ok(rpc_client) => {
+                    let req = JsonRequest::new(
+                        "blockchain.subscribe_proposals",
+                        JsonValue::Array(vec![]),
+                    );
+                    if let Err(e) = rpc_client.subscribe(req, publisher.clone()).await {
+                        error!(target: "darkfi_stratum::darkfid::subscribe_proposals", "Proposals subscription error: {}", e);
+                    }
+                    rpc_client.stop().await;
+                }
+                Err(e) => {
+                    error!(target: "darkfi_stratum::darkfid::subscribe_proposals", "Failed connecting to darkfid: {}", e)
+                }
+            }
+
+            sleep(RESUBSCRIBE_INTERVAL).await;
+        }
+    }
+}
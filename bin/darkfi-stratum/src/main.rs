@@ -0,0 +1,189 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{async_daemonize, cli_desc, system::StoppableTask, Error, Result};
+use log::{error, info};
+use serde::Deserialize;
+use smol::{stream::StreamExt, Executor};
+use structopt::StructOpt;
+use structopt_toml::StructOptToml;
+use url::Url;
+
+const CONFIG_FILE: &str = "darkfi_stratum.toml";
+const CONFIG_FILE_CONTENTS: &str = include_str!("../darkfi_stratum.toml");
+
+/// darkfid JSON-RPC client
+mod darkfid;
+use darkfid::DarkfidClient;
+
+/// Stratum server and workers handling
+mod stratum;
+use stratum::{StratumConfig, StratumServer};
+
+/// RandomX shares verifier
+mod verifier;
+
+#[derive(Clone, Debug, Deserialize, StructOpt, StructOptToml)]
+#[serde(default)]
+#[structopt(name = "darkfi-stratum", about = cli_desc!())]
+struct Args {
+    #[structopt(short, parse(from_occurrences))]
+    /// Increase verbosity (-vvv supported)
+    verbose: u8,
+
+    #[structopt(short, long)]
+    /// Configuration file to use
+    config: Option<String>,
+
+    #[structopt(long)]
+    /// Set log file output
+    log: Option<String>,
+
+    #[structopt(long, default_value = "tcp://0.0.0.0:3334")]
+    /// Stratum server listen URL
+    stratum_listen: Url,
+
+    #[structopt(long, default_value = "tcp://127.0.0.1:8341")]
+    /// darkfid miner JSON-RPC endpoint
+    darkfid_rpc: Url,
+
+    #[structopt(long)]
+    /// Wallet address to receive the mined blocks rewards
+    recipient: Option<String>,
+
+    #[structopt(long)]
+    /// Optional contract spend hook to use in the mined blocks rewards
+    spend_hook: Option<String>,
+
+    #[structopt(long)]
+    /// Optional contract user data to use in the mined blocks rewards
+    user_data: Option<String>,
+
+    #[structopt(long, default_value = "10000")]
+    /// Initial share difficulty assigned to each worker
+    share_difficulty: u64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Minimum share difficulty a worker can be adjusted to
+    min_share_difficulty: u64,
+
+    #[structopt(long, default_value = "10")]
+    /// Target time in seconds between each worker shares
+    share_target_time: u64,
+
+    #[structopt(long, default_value = "30")]
+    /// Block template refresh interval in seconds
+    job_refresh_interval: u64,
+}
+
+async_daemonize!(realmain);
+async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
+    info!(target: "darkfi_stratum", "Starting DarkFi Stratum mining server");
+
+    // Verify rewards recipient configuration
+    let Some(recipient) = args.recipient else {
+        error!(target: "darkfi_stratum", "Rewards recipient address is missing");
+        return Err(Error::ParseFailed("Recipient address missing"))
+    };
+    if args.user_data.is_some() && args.spend_hook.is_none() {
+        error!(target: "darkfi_stratum", "Rewards user data requires a spend hook");
+        return Err(Error::ParseFailed("User data without spend hook"))
+    }
+
+    if args.share_difficulty < args.min_share_difficulty ||
+        args.min_share_difficulty == 0 ||
+        args.share_target_time == 0
+    {
+        error!(target: "darkfi_stratum", "Invalid share difficulty configuration");
+        return Err(Error::ParseFailed("Invalid share difficulty configuration"))
+    }
+
+    // Connect to darkfid and generate the server
+    let darkfid = match DarkfidClient::new(
+        args.darkfid_rpc,
+        recipient,
+        args.spend_hook,
+        args.user_data,
+        ex.clone(),
+    )
+    .await
+    {
+        Ok(d) => d,
+        Err(e) => {
+            error!(target: "darkfi_stratum", "Failed connecting to darkfid JSON-RPC: {}", e);
+            return Err(e)
+        }
+    };
+    let config = StratumConfig {
+        share_difficulty: args.share_difficulty,
+        min_share_difficulty: args.min_share_difficulty,
+        share_target_time: args.share_target_time,
+        job_refresh_interval: args.job_refresh_interval,
+    };
+    let server = StratumServer::new(darkfid, config)?;
+
+    // Start the block templates refresh task
+    let templates_task = StoppableTask::new();
+    templates_task.clone().start(
+        server.clone().refresh_templates_task(ex.clone()),
+        |res| async move {
+            match res {
+                Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                Err(e) => {
+                    error!(target: "darkfi_stratum", "Failed refreshing block templates: {}", e)
+                }
+            }
+        },
+        Error::DetachedTaskStopped,
+        ex.clone(),
+    );
+
+    // Start the Stratum server
+    let stratum_task = StoppableTask::new();
+    stratum_task.clone().start(
+        server.clone().listen(args.stratum_listen.clone(), ex.clone()),
+        |res| async move {
+            match res {
+                Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                Err(e) => error!(target: "darkfi_stratum", "Stratum server error: {}", e),
+            }
+        },
+        Error::DetachedTaskStopped,
+        ex.clone(),
+    );
+    info!(target: "darkfi_stratum", "Stratum server ready, waiting for workers on {}", args.stratum_listen);
+
+    // Signal handling for graceful termination.
+    let (signals_handler, signals_task) = SignalHandler::new(ex)?;
+    signals_handler.wait_termination(signals_task).await?;
+    info!(target: "darkfi_stratum", "Caught termination signal, cleaning up and exiting");
+
+    info!(target: "darkfi_stratum", "Stopping Stratum server...");
+    stratum_task.stop().await;
+
+    info!(target: "darkfi_stratum", "Stopping block templates refresh task...");
+    templates_task.stop().await;
+
+    info!(target: "darkfi_stratum", "Stopping darkfid JSON-RPC client...");
+    server.stop().await;
+
+    info!(target: "darkfi_stratum", "Shut down successfully");
+    Ok(())
+}
@@ -0,0 +1,709 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Stratum v1 server, speaking the XMRig flavoured protocol: newline
+//! delimited JSON-RPC messages over TCP, where workers `login`, request
+//! jobs with `getjob`, submit shares with `submit`, keep the connection
+//! alive with `keepalived`, and receive new jobs through `job`
+//! notifications.
+//!
+//! Jobs are DarkFi block headers PoW hashing blobs, which follow the Monero
+//! hashing blob layout, so stock RandomX miners, like XMRig, can mine them
+//! using the `rx/0` algorithm, with `seed_hash`, the previous block hash,
+//! as the RandomX key. Workers roll the 4 byte little-endian nonce at the
+//! Monero nonce offset and check the RandomX output against the 8 byte
+//! little-endian `target`, as they do for Monero jobs. Each worker gets its
+//! own extra nonce in the blob upper nonce bytes, so they don't overlap.
+//! Only [`BLOCK_VERSION_2`] headers have a PoW hashing blob, so older block
+//! templates can't be mined through this server.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use darkfi::{
+    blockchain::{Header, BLOCK_VERSION_2},
+    rpc::{
+        jsonrpc::{ErrorCode, JsonError, JsonNotification, JsonRequest, JsonResponse},
+        util::JsonValue,
+    },
+    system::{sleep, ExecutorPtr, Publisher, PublisherPtr, Subscription},
+    Error, Result,
+};
+use log::{debug, error, info};
+use num_bigint::BigUint;
+use smol::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    lock::{Mutex, RwLock},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+
+use crate::{
+    darkfid::{BlockTemplate, DarkfidClient},
+    verifier::ShareVerifier,
+};
+
+/// Maximum size of a worker request line
+const MAX_REQUEST_SIZE: u64 = 4096;
+
+/// Number of most recent jobs each worker can submit shares for
+const MAX_WORKER_JOBS: usize = 4;
+
+/// Number of shares each worker difficulty adjustment window targets
+const VARDIFF_WINDOW_SHARES: u64 = 16;
+
+/// Maximum factor a worker difficulty can change by on each adjustment
+const VARDIFF_MAX_FACTOR: f64 = 4.0;
+
+/// Maximum number of shares each worker can submit per rate limit window
+const RATE_LIMIT_SHARES: u64 = 30;
+
+/// Worker shares rate limit window, in seconds
+const RATE_LIMIT_WINDOW: u64 = 60;
+
+/// RandomX variant of the jobs, as named by XMRig
+const JOB_ALGORITHM: &str = "rx/0";
+
+/// Stratum protocol extensions the server supports
+const STRATUM_EXTENSIONS: [&str; 2] = ["algo", "keepalive"];
+
+/// Stratum server configuration
+pub struct StratumConfig {
+    /// Initial share difficulty assigned to each worker
+    pub share_difficulty: u64,
+    /// Minimum share difficulty a worker can be adjusted to
+    pub min_share_difficulty: u64,
+    /// Target time in seconds between each worker shares
+    pub share_target_time: u64,
+    /// Block template refresh interval in seconds
+    pub job_refresh_interval: u64,
+}
+
+/// A job handed to a worker
+struct Job {
+    /// Job id, unique per worker
+    id: String,
+    /// Block template id the job was created from
+    template_id: String,
+    /// Share difficulty the job was issued with
+    difficulty: u64,
+    /// Nonces already submitted for this job
+    nonces: HashSet<u32>,
+}
+
+/// A connected worker state
+struct Worker {
+    /// Worker id, handed out on login
+    id: String,
+    /// Worker extra nonce, filling the upper bytes of its jobs nonces
+    extra_nonce: u32,
+    /// Worker login name, if logged in
+    login: Option<String>,
+    /// Current share difficulty
+    difficulty: u64,
+    /// Most recent jobs handed to the worker
+    jobs: VecDeque<Job>,
+    /// Jobs counter, used to generate job ids
+    jobs_counter: u64,
+    /// Current difficulty adjustment window start
+    window_start: Instant,
+    /// Shares accepted in current difficulty adjustment window
+    window_shares: u64,
+    /// Current shares rate limit window start
+    rate_window_start: Instant,
+    /// Shares submitted in current rate limit window
+    rate_window_shares: u64,
+    /// Total accepted shares
+    accepted: u64,
+    /// Total rejected shares
+    rejected: u64,
+    /// Total blocks found
+    blocks: u64,
+}
+
+impl Worker {
+    fn new(id: String, extra_nonce: u32, difficulty: u64) -> Self {
+        Self {
+            id,
+            extra_nonce,
+            login: None,
+            difficulty,
+            jobs: VecDeque::with_capacity(MAX_WORKER_JOBS + 1),
+            jobs_counter: 0,
+            window_start: Instant::now(),
+            window_shares: 0,
+            rate_window_start: Instant::now(),
+            rate_window_shares: 0,
+            accepted: 0,
+            rejected: 0,
+            blocks: 0,
+        }
+    }
+
+    /// Generate a new job for provided block template, using current
+    /// worker share difficulty, and return its Stratum representation.
+    fn new_job(&mut self, template: &BlockTemplate) -> JsonValue {
+        self.jobs_counter += 1;
+        let id = format!("{:x}", self.jobs_counter);
+        self.jobs.push_back(Job {
+            id: id.clone(),
+            template_id: template.id.clone(),
+            difficulty: self.difficulty,
+            nonces: HashSet::new(),
+        });
+        if self.jobs.len() > MAX_WORKER_JOBS {
+            self.jobs.pop_front();
+        }
+
+        let blob = hex::encode(job_header(template, self.extra_nonce, 0).pow_blob());
+        let target = stratum_target(&job_target(self.difficulty, &template.target));
+        JsonValue::Object(HashMap::from([
+            ("job_id".to_string(), JsonValue::String(id)),
+            ("blob".to_string(), JsonValue::String(blob)),
+            ("target".to_string(), JsonValue::String(target)),
+            ("algo".to_string(), JsonValue::String(JOB_ALGORITHM.to_string())),
+            ("height".to_string(), JsonValue::Number(template.header.height as f64)),
+            ("seed_hash".to_string(), JsonValue::String(template.header.previous.to_string())),
+        ]))
+    }
+
+    /// Account a submitted share in the worker rate limit window.
+    /// Returns `false` if the worker exceeded its shares rate limit.
+    fn rate_limit(&mut self) -> bool {
+        if self.rate_window_start.elapsed().as_secs() >= RATE_LIMIT_WINDOW {
+            self.rate_window_start = Instant::now();
+            self.rate_window_shares = 0;
+        }
+
+        self.rate_window_shares += 1;
+        self.rate_window_shares <= RATE_LIMIT_SHARES
+    }
+
+    /// Adjust the worker share difficulty based on its shares rate over
+    /// the current window, so it submits a share every `target_time`
+    /// seconds. Returns `true` if the difficulty changed.
+    fn adjust_difficulty(&mut self, min_difficulty: u64, target_time: u64) -> bool {
+        let elapsed = self.window_start.elapsed().as_secs_f64();
+        let window_time = (target_time * VARDIFF_WINDOW_SHARES) as f64;
+        if self.window_shares < VARDIFF_WINDOW_SHARES && elapsed < window_time {
+            return false
+        }
+
+        // Scale the difficulty by the observed over the expected shares ratio
+        let expected = elapsed / target_time as f64;
+        let factor = (self.window_shares as f64 / expected)
+            .clamp(1.0 / VARDIFF_MAX_FACTOR, VARDIFF_MAX_FACTOR);
+        let difficulty = ((self.difficulty as f64 * factor) as u64).max(min_difficulty);
+
+        // Start a new window
+        self.window_start = Instant::now();
+        self.window_shares = 0;
+
+        if difficulty == self.difficulty {
+            return false
+        }
+        self.difficulty = difficulty;
+
+        true
+    }
+}
+
+/// Atomic pointer to the Stratum server
+pub type StratumServerPtr = Arc<StratumServer>;
+
+/// Stratum server distributing darkfid block templates as jobs to many
+/// workers, validating their shares and submitting block solutions.
+pub struct StratumServer {
+    /// darkfid JSON-RPC client
+    darkfid: DarkfidClient,
+    /// RandomX shares verifier
+    verifier: ShareVerifier,
+    /// Server configuration
+    config: StratumConfig,
+    /// Known block templates, keyed by their template id
+    templates: RwLock<HashMap<String, BlockTemplate>>,
+    /// Current block template to hand out jobs for
+    current: RwLock<Option<BlockTemplate>>,
+    /// Publisher notifying connected workers about new block templates
+    publisher: PublisherPtr<BlockTemplate>,
+    /// Workers counter, used to generate worker ids
+    workers_counter: AtomicU64,
+}
+
+impl StratumServer {
+    pub fn new(darkfid: DarkfidClient, config: StratumConfig) -> Result<StratumServerPtr> {
+        Ok(Arc::new(Self {
+            darkfid,
+            verifier: ShareVerifier::new()?,
+            config,
+            templates: RwLock::new(HashMap::new()),
+            current: RwLock::new(None),
+            publisher: Publisher::new(),
+            workers_counter: AtomicU64::new(0),
+        }))
+    }
+
+    /// Stop the server darkfid JSON-RPC client.
+    pub async fn stop(&self) {
+        self.darkfid.stop().await;
+    }
+
+    /// Async task refreshing the current block template on every new
+    /// proposal darkfid notifies, and on every configured interval, so
+    /// workers always mine on top of the best fork, including new
+    /// transactions.
+    pub async fn refresh_templates_task(self: StratumServerPtr, ex: ExecutorPtr) -> Result<()> {
+        let proposals = Publisher::new();
+        let subscription = proposals.clone().subscribe().await;
+        let server = self.clone();
+        let _subscription_task =
+            ex.spawn(async move { server.darkfid.subscribe_proposals(proposals).await });
+
+        loop {
+            if let Err(e) = self.refresh_template().await {
+                error!(target: "darkfi_stratum::stratum::refresh_templates_task", "Failed retrieving block template: {}", e);
+            }
+
+            smol::future::or(
+                async {
+                    subscription.receive().await;
+                },
+                sleep(self.config.job_refresh_interval),
+            )
+            .await;
+        }
+    }
+
+    /// Retrieve a new block template from darkfid and notify it to the workers.
+    async fn refresh_template(&self) -> Result<()> {
+        let template = self.darkfid.get_template().await?;
+        if template.header.version < BLOCK_VERSION_2 {
+            return Err(Error::Custom(format!(
+                "Block template {} version {} has no PoW hashing blob",
+                template.id, template.header.version
+            )))
+        }
+        debug!(target: "darkfi_stratum::stratum::refresh_template", "New block template {} for height {}", template.id, template.header.height);
+
+        // Drop stale templates and store the new one
+        let mut templates = self.templates.write().await;
+        templates.retain(|_, t| t.header.height >= template.header.height);
+        templates.insert(template.id.clone(), template.clone());
+        drop(templates);
+
+        *self.current.write().await = Some(template.clone());
+        self.publisher.notify(template).await;
+
+        Ok(())
+    }
+
+    /// Listen for workers connections on provided URL.
+    pub async fn listen(self: StratumServerPtr, listen: Url, ex: ExecutorPtr) -> Result<()> {
+        let listener = TcpListener::bind(&*listen.socket_addrs(|| None)?).await?;
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(c) => c,
+                Err(e) => {
+                    error!(target: "darkfi_stratum::stratum::listen", "Failed accepting worker connection: {}", e);
+                    continue
+                }
+            };
+            ex.spawn(self.clone().handle_worker(stream, peer)).detach();
+        }
+    }
+
+    /// Handle a worker connection, answering its requests and pushing
+    /// new jobs to it, until it disconnects.
+    async fn handle_worker(self: StratumServerPtr, stream: TcpStream, peer: SocketAddr) {
+        let counter = self.workers_counter.fetch_add(1, Ordering::SeqCst);
+        let id = format!("{counter:x}");
+        info!(target: "darkfi_stratum::stratum::handle_worker", "Worker {} connected from {}", id, peer);
+        let worker = Mutex::new(Worker::new(id, counter as u32, self.config.share_difficulty));
+        let writer = Mutex::new(stream.clone());
+        let subscription = self.publisher.clone().subscribe().await;
+
+        let result = smol::future::or(
+            self.handle_requests(stream, &worker, &writer),
+            self.push_jobs(&subscription, &worker, &writer),
+        )
+        .await;
+        subscription.unsubscribe().await;
+
+        let worker = worker.into_inner();
+        if let Err(e) = result {
+            debug!(target: "darkfi_stratum::stratum::handle_worker", "Worker {} connection error: {}", worker.id, e);
+        }
+        info!(
+            target: "darkfi_stratum::stratum::handle_worker",
+            "Worker {} disconnected, shares accepted: {}, rejected: {}, blocks found: {}",
+            worker.id, worker.accepted, worker.rejected, worker.blocks,
+        );
+    }
+
+    /// Read and answer worker requests until the connection closes.
+    async fn handle_requests(
+        &self,
+        stream: TcpStream,
+        worker: &Mutex<Worker>,
+        writer: &Mutex<TcpStream>,
+    ) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if (&mut reader).take(MAX_REQUEST_SIZE).read_line(&mut line).await? == 0 {
+                return Ok(())
+            }
+            if !line.ends_with('\n') {
+                return Err(Error::Custom("Worker request too large or incomplete".to_string()))
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue
+            }
+
+            let (reply, job) = self.handle_request(worker, line).await;
+            write_message(writer, &reply).await?;
+            if let Some(job) = job {
+                write_message(writer, &(&JsonNotification::new("job", job)).into()).await?;
+            }
+        }
+    }
+
+    /// Push a new job to the worker for every new block template.
+    async fn push_jobs(
+        &self,
+        subscription: &Subscription<BlockTemplate>,
+        worker: &Mutex<Worker>,
+        writer: &Mutex<TcpStream>,
+    ) -> Result<()> {
+        loop {
+            let template = subscription.receive().await;
+
+            // Only logged in workers get jobs. Workers that stopped
+            // submitting shares get their difficulty eased here.
+            let mut worker = worker.lock().await;
+            if worker.login.is_none() {
+                continue
+            }
+            worker
+                .adjust_difficulty(self.config.min_share_difficulty, self.config.share_target_time);
+            let job = worker.new_job(&template);
+            drop(worker);
+
+            write_message(writer, &(&JsonNotification::new("job", job)).into()).await?;
+        }
+    }
+
+    /// Handle a worker request line, returning the reply and an optional
+    /// new job to push to the worker afterwards.
+    async fn handle_request(
+        &self,
+        worker: &Mutex<Worker>,
+        line: &str,
+    ) -> (JsonValue, Option<JsonValue>) {
+        let Ok(request) = line.parse::<JsonValue>() else {
+            return (json_error(ErrorCode::ParseError, None, 0), None)
+        };
+        let Ok(request) = JsonRequest::try_from(&request) else {
+            return (json_error(ErrorCode::InvalidRequest, None, 0), None)
+        };
+        let id = request.id;
+        let Some(params) = request.params.get::<HashMap<String, JsonValue>>() else {
+            return (json_error(ErrorCode::InvalidParams, None, id), None)
+        };
+
+        match request.method.as_str() {
+            "login" => (self.login(worker, id, params).await, None),
+            "getjob" => (self.getjob(worker, id).await, None),
+            "submit" => self.submit(worker, id, params).await,
+            "keepalived" => (status_reply("KEEPALIVED", id), None),
+            _ => (json_error(ErrorCode::MethodNotFound, None, id), None),
+        }
+    }
+
+    /// Handle a worker `login` request, replying with its id and first job.
+    async fn login(
+        &self,
+        worker: &Mutex<Worker>,
+        id: u16,
+        params: &HashMap<String, JsonValue>,
+    ) -> JsonValue {
+        let Some(login) = params.get("login").and_then(|l| l.get::<String>()) else {
+            return json_error(ErrorCode::InvalidParams, None, id)
+        };
+
+        let Some(template) = self.current.read().await.clone() else {
+            return stratum_error("No block template available yet", id)
+        };
+
+        let mut worker = worker.lock().await;
+        worker.login = Some(login.clone());
+        let job = worker.new_job(&template);
+        info!(target: "darkfi_stratum::stratum::login", "Worker {} logged in as {}", worker.id, login);
+
+        let extensions = STRATUM_EXTENSIONS.iter().map(|e| JsonValue::String(e.to_string()));
+        let result = JsonValue::Object(HashMap::from([
+            ("id".to_string(), JsonValue::String(worker.id.clone())),
+            ("job".to_string(), job),
+            ("extensions".to_string(), JsonValue::Array(extensions.collect())),
+            ("status".to_string(), JsonValue::String("OK".to_string())),
+        ]));
+        (&JsonResponse::new(result, id)).into()
+    }
+
+    /// Handle a worker `getjob` request, replying with a new job.
+    async fn getjob(&self, worker: &Mutex<Worker>, id: u16) -> JsonValue {
+        let Some(template) = self.current.read().await.clone() else {
+            return stratum_error("No block template available yet", id)
+        };
+
+        let mut worker = worker.lock().await;
+        if worker.login.is_none() {
+            return stratum_error("Unauthenticated", id)
+        }
+
+        (&JsonResponse::new(worker.new_job(&template), id)).into()
+    }
+
+    /// Handle a worker `submit` request. The share PoW is verified against
+    /// the job target, and if it also meets the block target, the solution
+    /// is submitted to darkfid. The share nonce is only recorded once its
+    /// PoW is verified, so failed verifications don't burn it. If the worker
+    /// difficulty gets adjusted, a new job using it is returned.
+    async fn submit(
+        &self,
+        worker: &Mutex<Worker>,
+        id: u16,
+        params: &HashMap<String, JsonValue>,
+    ) -> (JsonValue, Option<JsonValue>) {
+        let (Some(worker_id), Some(job_id), Some(nonce)) = (
+            params.get("id").and_then(|w| w.get::<String>()),
+            params.get("job_id").and_then(|j| j.get::<String>()),
+            params.get("nonce").and_then(|n| n.get::<String>()),
+        ) else {
+            return (json_error(ErrorCode::InvalidParams, None, id), None)
+        };
+        let Some(nonce) = parse_nonce(nonce) else {
+            return (stratum_error("Invalid nonce", id), None)
+        };
+
+        // Grab the share job and check it's not a duplicate
+        let mut w = worker.lock().await;
+        if w.login.is_none() || w.id != *worker_id {
+            return (stratum_error("Unauthenticated", id), None)
+        }
+        if !w.rate_limit() {
+            w.rejected += 1;
+            return (stratum_error("Too many shares", id), None)
+        }
+        let Some(job) = w.jobs.iter().find(|j| j.id == *job_id) else {
+            w.rejected += 1;
+            return (stratum_error("Unknown job", id), None)
+        };
+        if job.nonces.contains(&nonce) {
+            w.rejected += 1;
+            return (stratum_error("Duplicate share", id), None)
+        }
+        let (template_id, difficulty) = (job.template_id.clone(), job.difficulty);
+        let extra_nonce = w.extra_nonce;
+        drop(w);
+
+        let Some(template) = self.templates.read().await.get(&template_id).cloned() else {
+            worker.lock().await.rejected += 1;
+            return (stratum_error("Stale job", id), None)
+        };
+
+        // Verify the share PoW
+        let header = job_header(&template, extra_nonce, nonce);
+        let out_hash = match self.verifier.hash(&header).await {
+            Ok(h) => h,
+            Err(e) => {
+                error!(target: "darkfi_stratum::stratum::submit", "Failed verifying worker {} share: {}", worker_id, e);
+                return (stratum_error("Share verification failed", id), None)
+            }
+        };
+        if out_hash > job_target(difficulty, &template.target) {
+            worker.lock().await.rejected += 1;
+            return (stratum_error("Low difficulty share", id), None)
+        }
+
+        // Record the verified share nonce, rejecting it if it got
+        // submitted again while we were verifying it.
+        let mut w = worker.lock().await;
+        if let Some(job) = w.jobs.iter_mut().find(|j| j.id == *job_id) {
+            if !job.nonces.insert(nonce) {
+                w.rejected += 1;
+                return (stratum_error("Duplicate share", id), None)
+            }
+        }
+        drop(w);
+
+        // Submit block solutions to darkfid
+        let mut found_block = false;
+        if out_hash <= template.target {
+            match self.darkfid.submit(&template.id, header.nonce).await {
+                Ok(hash) => {
+                    info!(target: "darkfi_stratum::stratum::submit", "Worker {} found block {} - {}", worker_id, header.height, hash);
+                    found_block = true;
+                }
+                Err(e) => {
+                    error!(target: "darkfi_stratum::stratum::submit", "Failed submitting worker {} block solution: {}", worker_id, e)
+                }
+            }
+        }
+
+        // Account the share and adjust the worker difficulty
+        let current = self.current.read().await.clone();
+        let mut w = worker.lock().await;
+        w.accepted += 1;
+        w.window_shares += 1;
+        if found_block {
+            w.blocks += 1;
+        }
+        let mut job = None;
+        if w.adjust_difficulty(self.config.min_share_difficulty, self.config.share_target_time) {
+            debug!(target: "darkfi_stratum::stratum::submit", "Worker {} share difficulty adjusted to {}", w.id, w.difficulty);
+            if let Some(template) = current {
+                job = Some(w.new_job(&template));
+            }
+        }
+
+        (status_reply("OK", id), job)
+    }
+}
+
+/// Compute a job target for provided share difficulty. Jobs targets are
+/// never harder than the block target, so no block solutions are missed.
+fn job_target(difficulty: u64, block_target: &BigUint) -> BigUint {
+    let share_target = BigUint::from_bytes_be(&[0xFF; 32]) / difficulty;
+    share_target.max(block_target.clone())
+}
+
+/// Encode provided job target as a Stratum target: its upper 8 bytes in
+/// little-endian hex, which workers compare against the upper 8 bytes of
+/// their little-endian PoW hash, so shares meeting it also meet the job
+/// target.
+fn stratum_target(target: &BigUint) -> String {
+    let upper = (target >> 192u32).to_u64_digits().first().copied().unwrap_or(0);
+    hex::encode(upper.to_le_bytes())
+}
+
+/// Generate the header a worker mines for provided block template, with
+/// its nonce upper bytes set to the worker extra nonce and its lower bytes
+/// set to provided worker nonce.
+fn job_header(template: &BlockTemplate, extra_nonce: u32, nonce: u32) -> Header {
+    let mut header = template.header.clone();
+    header.nonce = ((extra_nonce as u64) << 32) | nonce as u64;
+    header
+}
+
+/// Parse a worker submitted nonce, which is a hex encoded 4 bytes
+/// little-endian integer, as found at the hashing blob nonce offset.
+fn parse_nonce(nonce: &str) -> Option<u32> {
+    let bytes: [u8; 4] = hex::decode(nonce).ok()?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// Auxiliary function to generate a Stratum status reply.
+fn status_reply(status: &str, id: u16) -> JsonValue {
+    let result = JsonValue::Object(HashMap::from([(
+        "status".to_string(),
+        JsonValue::String(status.to_string()),
+    )]));
+    (&JsonResponse::new(result, id)).into()
+}
+
+/// Auxiliary function to generate a Stratum error reply.
+fn stratum_error(message: &str, id: u16) -> JsonValue {
+    json_error(ErrorCode::ServerError(-1), Some(message.to_string()), id)
+}
+
+/// Auxiliary function to generate a JSON-RPC error reply.
+fn json_error(code: ErrorCode, message: Option<String>, id: u16) -> JsonValue {
+    (&JsonError::new(code, message, id)).into()
+}
+
+/// Auxiliary function to write a newline delimited message to a worker.
+async fn write_message(writer: &Mutex<TcpStream>, message: &JsonValue) -> Result<()> {
+    let mut data = message.stringify()?;
+    data.push('\n');
+    writer.lock().await.write_all(data.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::{job_target, parse_nonce, stratum_target, Worker, RATE_LIMIT_SHARES};
+
+    #[test]
+    fn test_parse_nonce() {
+        assert_eq!(parse_nonce("01000000"), Some(1));
+        assert_eq!(parse_nonce("00010000"), Some(256));
+        assert_eq!(parse_nonce("ffffffff"), Some(u32::MAX));
+        assert_eq!(parse_nonce(""), None);
+        assert_eq!(parse_nonce("010000"), None);
+        assert_eq!(parse_nonce("0100000000000000"), None);
+        assert_eq!(parse_nonce("zzzzzzzz"), None);
+    }
+
+    #[test]
+    fn test_stratum_target() {
+        let max = BigUint::from_bytes_be(&[0xFF; 32]);
+        assert_eq!(stratum_target(&max), "ffffffffffffffff");
+
+        // Difficulty 2 target upper 8 bytes are 0x7fffffffffffffff
+        assert_eq!(stratum_target(&(&max / 2u64)), "ffffffffffffff7f");
+
+        // Targets under 2^192 can't be met by any share
+        assert_eq!(stratum_target(&BigUint::from(1u64)), "0000000000000000");
+    }
+
+    #[test]
+    fn test_job_target() {
+        let max = BigUint::from_bytes_be(&[0xFF; 32]);
+
+        // Share target is used when easier than block target
+        let block_target = BigUint::from(1u64);
+        assert_eq!(job_target(2, &block_target), &max / 2u64);
+
+        // Block target is used when easier than share target
+        let block_target = &max / 2u64;
+        assert_eq!(job_target(1000, &block_target), block_target);
+    }
+
+    #[test]
+    fn test_worker_rate_limit() {
+        let mut worker = Worker::new("0".to_string(), 0, 1000);
+        for _ in 0..RATE_LIMIT_SHARES {
+            assert!(worker.rate_limit());
+        }
+        assert!(!worker.rate_limit());
+        assert_eq!(worker.rate_window_shares, RATE_LIMIT_SHARES + 1);
+    }
+}
@@ -0,0 +1,112 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi::{
+    blockchain::{Header, HeaderHash},
+    Error, Result,
+};
+use log::{debug, error};
+use num_bigint::BigUint;
+use randomx::{RandomXCache, RandomXFlags, RandomXVM};
+use smol::channel::{Receiver, Sender};
+
+/// Maximum number of share verification requests waiting for the verifier
+const MAX_PENDING_REQUESTS: usize = 256;
+
+/// A share PoW verification request
+struct VerifyRequest {
+    /// RandomX key, which is the header previous block hash
+    key: HeaderHash,
+    /// Header to compute the PoW output of
+    header: Header,
+    /// Channel to send the RandomX output to
+    reply: Sender<BigUint>,
+}
+
+/// RandomX shares verifier. Since RandomX VMs are expensive to set up,
+/// verification happens in a dedicated thread, which keeps its VM
+/// around for as long as the requests key, the previous block hash,
+/// doesn't change. Its requests queue is bounded, so when workers
+/// submit shares faster than they can be verified, the excess ones
+/// get rejected instead of piling up.
+pub struct ShareVerifier {
+    /// Channel to send verification requests to the verifier thread
+    sender: Sender<VerifyRequest>,
+}
+
+impl ShareVerifier {
+    /// Spawn the verifier thread.
+    pub fn new() -> Result<Self> {
+        let (sender, receiver) = smol::channel::bounded(MAX_PENDING_REQUESTS);
+        std::thread::Builder::new()
+            .name("randomx-verifier".to_string())
+            .spawn(move || verifier_loop(receiver))?;
+
+        Ok(Self { sender })
+    }
+
+    /// Compute the RandomX output of provided header PoW input, as the
+    /// DarkFi PoW does, so it can be compared against a target. Returns
+    /// an error if the verifier requests queue is full.
+    pub async fn hash(&self, header: &Header) -> Result<BigUint> {
+        let (reply, receiver) = smol::channel::bounded(1);
+        let request = VerifyRequest { key: header.previous, header: header.clone(), reply };
+        if self.sender.try_send(request).is_err() {
+            return Err(Error::Custom("Share verifier queue is full".to_string()))
+        }
+
+        Ok(receiver.recv().await?)
+    }
+}
+
+/// Verifier thread main loop, handling requests until the requests channel closes.
+/// If the RandomX VM setup fails, the request reply channel gets dropped, so the
+/// requester gets an error.
+fn verifier_loop(requests: Receiver<VerifyRequest>) {
+    let flags = RandomXFlags::default();
+    let Ok(mut request) = requests.recv_blocking() else { return };
+    loop {
+        // Setup the RandomX VM for the current request key
+        let key = request.key;
+        let Ok(cache) = RandomXCache::new(flags, key.inner()) else {
+            error!(target: "darkfi_stratum::verifier", "Failed initializing RandomX cache for key {}", key);
+            let Ok(next) = requests.recv_blocking() else { return };
+            request = next;
+            continue
+        };
+        let Ok(vm) = RandomXVM::new(flags, &cache) else {
+            error!(target: "darkfi_stratum::verifier", "Failed initializing RandomX VM for key {}", key);
+            let Ok(next) = requests.recv_blocking() else { return };
+            request = next;
+            continue
+        };
+        debug!(target: "darkfi_stratum::verifier", "Initialized RandomX VM for key {}", key);
+
+        // Handle requests until the key changes
+        loop {
+            let out_hash = vm.hash(&request.header.pow_input());
+            let _ = request.reply.send_blocking(request.header.pow_output(&out_hash));
+
+            let Ok(next) = requests.recv_blocking() else { return };
+            request = next;
+            if request.key != key {
+                break
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::Path;
+
+use log::{error, info};
+use smol::{
+    fs::File,
+    io::{AsyncWriteExt, BufReader, BufWriter},
+};
+
+use darkfi::{
+    blockchain::{BlockInfo, Blockchain, HeaderHash},
+    validator::ValidatorPtr,
+    Error, Result,
+};
+use darkfi_serial::{AsyncDecodable, AsyncEncodable, SerialDecodable, SerialEncodable};
+
+/// Bootstrap file magic bytes
+pub const BOOTSTRAP_MAGIC: [u8; 4] = *b"DRKB";
+/// Current bootstrap file format version
+pub const BOOTSTRAP_VERSION: u8 = 1;
+/// Number of blocks retrieved or applied at once
+const BOOTSTRAP_BATCH: u32 = 100;
+
+/// Bootstrap file header, preceding the serialized blocks sequence.
+#[derive(Debug, SerialEncodable, SerialDecodable)]
+pub struct BootstrapHeader {
+    /// File magic bytes
+    pub magic: [u8; 4],
+    /// File format version
+    pub version: u8,
+    /// Genesis block hash of the exported blockchain
+    pub genesis: HeaderHash,
+    /// Height of the first exported block
+    pub start: u32,
+    /// Number of exported blocks
+    pub count: u32,
+}
+
+impl BootstrapHeader {
+    /// Read and validate a bootstrap header from the start of provided file.
+    async fn read(reader: &mut BufReader<File>) -> Result<Self> {
+        let header = Self::decode_async(reader).await?;
+        if header.magic != BOOTSTRAP_MAGIC {
+            return Err(Error::ParseFailed("Not a bootstrap file"))
+        }
+        if header.version != BOOTSTRAP_VERSION {
+            return Err(Error::ParseFailed("Unsupported bootstrap file version"))
+        }
+        Ok(header)
+    }
+}
+
+/// Export the canonical blocks in the provided heights range into a bootstrap
+/// file at given path. If no end height is provided, blocks get exported up
+/// to our last one. Returns the number of exported blocks.
+pub async fn export_blocks(
+    blockchain: &Blockchain,
+    path: &Path,
+    from: u32,
+    to: Option<u32>,
+) -> Result<u32> {
+    let last = blockchain.last()?.0;
+    let to = to.unwrap_or(last).min(last);
+    if from > to {
+        return Err(Error::BlockHeightNotFound(from))
+    }
+
+    // Pruned blocks can't be exported. Genesis block is never pruned.
+    if let Some(pruned_height) = blockchain.pruned_height()? {
+        if to > 0 && from.max(1) < pruned_height {
+            error!(target: "darkfid::bootstrap::export_blocks", "Blocks before {} have been pruned", pruned_height);
+            return Err(Error::BlockHeightNotFound(from))
+        }
+    }
+
+    let count = to - from + 1;
+    info!(target: "darkfid::bootstrap::export_blocks", "Exporting {} blocks ({} - {}) to: {:?}", count, from, to, path);
+
+    let mut writer = BufWriter::new(File::create(path).await?);
+    let header = BootstrapHeader {
+        magic: BOOTSTRAP_MAGIC,
+        version: BOOTSTRAP_VERSION,
+        genesis: blockchain.genesis()?.1,
+        start: from,
+        count,
+    };
+    header.encode_async(&mut writer).await?;
+
+    // Stream the blocks in batches, so we don't load them all in memory
+    let mut exported = 0;
+    let mut height = from;
+    while height <= to {
+        let end = height.saturating_add(BOOTSTRAP_BATCH - 1).min(to);
+        let heights: Vec<u32> = (height..=end).collect();
+        for block in blockchain.get_blocks_by_heights(&heights)? {
+            block.encode_async(&mut writer).await?;
+        }
+        exported += heights.len() as u32;
+        info!(target: "darkfid::bootstrap::export_blocks", "Blocks exported: {}/{}", exported, count);
+        height = end + 1;
+    }
+    writer.flush().await?;
+
+    Ok(exported)
+}
+
+/// Verify the blocks sequence of the bootstrap file at given path forms a
+/// hash chain, starting from our block before its first one, that it
+/// doesn't conflict with our configured checkpoints and that it contains
+/// the provided checkpoint.
+async fn verify_sequence(
+    validator: &ValidatorPtr,
+    path: &Path,
+    checkpoint: &(u32, HeaderHash),
+) -> Result<()> {
+    info!(target: "darkfid::bootstrap::verify_sequence", "Verifying bootstrap file blocks sequence...");
+    let mut reader = BufReader::new(File::open(path).await?);
+    let header = BootstrapHeader::read(&mut reader).await?;
+
+    // Grab the hash of the block before the first one
+    let mut previous = match header.start {
+        0 => None,
+        start => Some(validator.blockchain.blocks.get_order(&[start - 1], true)?[0].unwrap()),
+    };
+
+    let mut found = false;
+    for index in 0..header.count {
+        let block = BlockInfo::decode_async(&mut reader).await?;
+        let block_hash = block.hash();
+        if block.header.height != header.start + index ||
+            previous.is_some_and(|p| p != block.header.previous)
+        {
+            return Err(Error::BlockIsInvalid(block_hash.as_string()))
+        }
+        if !validator.consensus.verify_checkpoint(block.header.height, &block_hash) {
+            error!(target: "darkfid::bootstrap::verify_sequence", "Bootstrap file block {} conflicts with our checkpoints", block.header.height);
+            return Err(Error::BlockIsInvalid(block_hash.as_string()))
+        }
+        if block.header.height == checkpoint.0 {
+            if block_hash != checkpoint.1 {
+                error!(target: "darkfid::bootstrap::verify_sequence", "Bootstrap file doesn't follow the checkpoint");
+                return Err(Error::BlockIsInvalid(block_hash.as_string()))
+            }
+            found = true;
+            break
+        }
+        previous = Some(block_hash);
+    }
+
+    if !found {
+        error!(target: "darkfid::bootstrap::verify_sequence", "Bootstrap file doesn't contain the checkpoint");
+        return Err(Error::BlockHeightNotFound(checkpoint.0))
+    }
+
+    info!(target: "darkfid::bootstrap::verify_sequence", "Bootstrap file blocks sequence verified!");
+    Ok(())
+}
+
+/// Apply a batch of blocks, either as trusted checkpoint blocks or
+/// through full verification.
+async fn apply_batch(validator: &ValidatorPtr, batch: &[BlockInfo], trusted: bool) -> Result<()> {
+    if trusted {
+        let headers: Vec<HeaderHash> = batch.iter().map(|b| b.hash()).collect();
+        validator.add_checkpoint_blocks(batch, &headers).await
+    } else {
+        validator.add_blocks(batch).await
+    }
+}
+
+/// Import the blocks of the bootstrap file at given path. Blocks up to the
+/// optional trusted checkpoint are applied without formal verification, once
+/// the file sequence has been verified to lead to it, while the rest go
+/// through full block verification. Blocks we already have are skipped,
+/// after checking they match ours, and blocks conflicting with our configured
+/// checkpoints are rejected. Returns the number of imported blocks.
+pub async fn import_blocks(
+    validator: &ValidatorPtr,
+    path: &Path,
+    checkpoint: Option<(u32, HeaderHash)>,
+) -> Result<u32> {
+    let mut reader = BufReader::new(File::open(path).await?);
+    let header = BootstrapHeader::read(&mut reader).await?;
+
+    // Check the file follows our network
+    if header.genesis != validator.blockchain.genesis()?.1 {
+        error!(target: "darkfid::bootstrap::import_blocks", "Bootstrap file genesis block doesn't match ours");
+        return Err(Error::BlockIsInvalid(header.genesis.as_string()))
+    }
+
+    // Check the file doesn't skip any block
+    let last = validator.blockchain.last()?;
+    if header.start > last.0 + 1 {
+        error!(target: "darkfid::bootstrap::import_blocks", "Bootstrap file starts after our next block: {}", last.0 + 1);
+        return Err(Error::BlockHeightNotFound(last.0 + 1))
+    }
+
+    // Verify the trusted part of the file sequence
+    if let Some(checkpoint) = &checkpoint {
+        if !validator.consensus.verify_checkpoint(checkpoint.0, &checkpoint.1) {
+            error!(target: "darkfid::bootstrap::import_blocks", "Provided checkpoint conflicts with our checkpoints");
+            return Err(Error::BlockIsInvalid(checkpoint.1.as_string()))
+        }
+        if checkpoint.0 > last.0 {
+            verify_sequence(validator, path, checkpoint).await?;
+        }
+    }
+
+    info!(target: "darkfid::bootstrap::import_blocks", "Importing {} blocks from: {:?}", header.count, path);
+    let mut imported = 0;
+    let mut batch = vec![];
+    let mut batch_trusted = false;
+    for index in 0..header.count {
+        let block = BlockInfo::decode_async(&mut reader).await?;
+        let height = block.header.height;
+        if height != header.start + index {
+            return Err(Error::BlockIsInvalid(block.hash().as_string()))
+        }
+
+        // Skip blocks we already have, if they match ours
+        if height <= last.0 {
+            if validator.blockchain.blocks.get_order(&[height], true)?[0].unwrap() != block.hash() {
+                error!(target: "darkfid::bootstrap::import_blocks", "Bootstrap file block {} doesn't match ours", height);
+                return Err(Error::BlockIsInvalid(block.hash().as_string()))
+            }
+            continue
+        }
+
+        // Check block doesn't conflict with our checkpoints
+        if !validator.consensus.verify_checkpoint(height, &block.hash()) {
+            error!(target: "darkfid::bootstrap::import_blocks", "Bootstrap file block {} conflicts with our checkpoints", height);
+            return Err(Error::BlockIsInvalid(block.hash().as_string()))
+        }
+
+        // Apply current batch if its full or we crossed the checkpoint
+        let trusted = checkpoint.as_ref().is_some_and(|c| height <= c.0);
+        if batch.len() == BOOTSTRAP_BATCH as usize ||
+            (!batch.is_empty() && trusted != batch_trusted)
+        {
+            apply_batch(validator, &batch, batch_trusted).await?;
+            imported += batch.len() as u32;
+            info!(target: "darkfid::bootstrap::import_blocks", "Blocks imported: {}/{}", imported, header.count);
+            batch.clear();
+        }
+        batch_trusted = trusted;
+        batch.push(block);
+    }
+
+    if !batch.is_empty() {
+        apply_batch(validator, &batch, batch_trusted).await?;
+        imported += batch.len() as u32;
+        info!(target: "darkfid::bootstrap::import_blocks", "Blocks imported: {}/{}", imported, header.count);
+    }
+
+    Ok(imported)
+}
@@ -28,6 +28,8 @@ pub enum RpcError {
     // State-related errors,
     NotSynced = -32120,
     UnknownBlockHeight = -32121,
+    UnknownTransaction = -32122,
+    TransactionPruned = -32123,
 
     // Parsing errors
     ParseError = -32190,
@@ -37,6 +39,10 @@ pub enum RpcError {
 
     // Misc errors
     PingFailed = -32300,
+    BlockGenerationFail = -32301,
+    UnknownBlockTemplate = -32302,
+    BlockTemplateFail = -32303,
+    BlockSubmissionFail = -32304,
 }
 
 fn to_tuple(e: RpcError) -> (i32, String) {
@@ -47,12 +53,18 @@ fn to_tuple(e: RpcError) -> (i32, String) {
         // State-related errors
         RpcError::NotSynced => "Blockchain is not synced",
         RpcError::UnknownBlockHeight => "Did not find block height",
+        RpcError::UnknownTransaction => "Did not find transaction block",
+        RpcError::TransactionPruned => "Transaction block has been pruned",
         // Parsing errors
         RpcError::ParseError => "Parse error",
         // Contract-related errors
         RpcError::ContractZkasDbNotFound => "zkas database not found for given contract",
         // Misc errors
         RpcError::PingFailed => "Miner daemon ping error",
+        RpcError::BlockGenerationFail => "Failed generating regtest blocks",
+        RpcError::UnknownBlockTemplate => "Did not find block template",
+        RpcError::BlockTemplateFail => "Failed generating block template",
+        RpcError::BlockSubmissionFail => "Failed submitting mined block",
     };
 
     (e as i32, msg.to_string())
@@ -0,0 +1,471 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashSet, fmt};
+
+use darkfi_sdk::{
+    crypto::{ContractId, MerkleTree},
+    tx::TransactionHash,
+};
+use darkfi_serial::{deserialize, serialize};
+use log::{info, warn};
+use sled_overlay::sled;
+
+use darkfi::{
+    blockchain::{
+        block_store::append_tx_to_merkle_tree, contract_store::SMART_CONTRACT_ZKAS_DB_NAME,
+        BlockDifficulty, Blockchain, Header, HeaderHash, BLOCK_VERSION_2,
+    },
+    zkas::ZkBinary,
+    Result,
+};
+
+/// An inconsistency found in the blockchain database by [`check_database`].
+#[derive(Debug)]
+pub enum Inconsistency {
+    /// Block order record at given height is missing or corrupted
+    OrderMissing(u32),
+    /// Header of the block at given height is missing or corrupted
+    HeaderMissing(u32, HeaderHash),
+    /// Header of the block at given height contains a different height
+    HeaderHeight(u32, HeaderHash, u32),
+    /// Header `previous` of the block at given height doesn't link to the block before it
+    PreviousLink(u32, HeaderHash),
+    /// Block at given height is missing or corrupted
+    BlockMissing(u32, HeaderHash),
+    /// Transaction of the block at given height is missing or corrupted
+    TxMissing(u32, TransactionHash),
+    /// Transactions Merkle root of the block at given height doesn't match its header one
+    MerkleRoot(u32, HeaderHash),
+    /// Transaction location record is missing or doesn't match its expected location
+    TxLocation(TransactionHash, (u32, u16)),
+    /// Transaction location record points to a block position not holding the transaction
+    TxLocationOrphan(TransactionHash),
+    /// Difficulty record at given height is missing or corrupted
+    DifficultyMissing(u32),
+    /// Difficulty record at given height doesn't match its block or the cummulative sequence
+    DifficultyMismatch(u32),
+    /// Difficulty record exists above our last block height
+    DifficultyOrphan(u32),
+    /// Contract has initialized states but no wasm bincode
+    WasmMissing(ContractId),
+    /// Contract state tree referenced by its pointers is missing
+    StateTreeMissing(ContractId, blake3::Hash),
+    /// Contract zkas tree contains a corrupted record
+    ZkasCorrupted(ContractId),
+    /// Stored contracts state SMT root doesn't match the one recomputed from its buckets
+    StateSmt,
+    /// Contracts state root doesn't match the one committed in our last block header
+    StateRoot(u32, HeaderHash),
+}
+
+impl Inconsistency {
+    /// Returns the block height the inconsistency breaks the blocks sequence at,
+    /// if the inconsistency concerns the blocks sequence itself.
+    fn broken_height(&self) -> Option<u32> {
+        match self {
+            Self::OrderMissing(height) |
+            Self::HeaderMissing(height, _) |
+            Self::HeaderHeight(height, _, _) |
+            Self::PreviousLink(height, _) |
+            Self::BlockMissing(height, _) |
+            Self::TxMissing(height, _) |
+            Self::MerkleRoot(height, _) |
+            Self::DifficultyMissing(height) |
+            Self::DifficultyMismatch(height) => Some(*height),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OrderMissing(height) => write!(f, "Block order record {} is missing", height),
+            Self::HeaderMissing(height, hash) => {
+                write!(f, "Header {} of block {} is missing", hash, height)
+            }
+            Self::HeaderHeight(height, hash, found) => {
+                write!(f, "Header {} of block {} contains height {}", hash, height, found)
+            }
+            Self::PreviousLink(height, hash) => {
+                write!(f, "Header {} of block {} doesn't link to previous block", hash, height)
+            }
+            Self::BlockMissing(height, hash) => write!(f, "Block {} {} is missing", height, hash),
+            Self::TxMissing(height, tx) => {
+                write!(f, "Transaction {} of block {} is missing", tx, height)
+            }
+            Self::MerkleRoot(height, hash) => {
+                write!(f, "Block {} {} transactions Merkle root mismatch", height, hash)
+            }
+            Self::TxLocation(tx, (height, index)) => {
+                write!(f, "Transaction {} location is not the expected {}:{}", tx, height, index)
+            }
+            Self::TxLocationOrphan(tx) => {
+                write!(f, "Transaction {} location points to a different transaction", tx)
+            }
+            Self::DifficultyMissing(height) => {
+                write!(f, "Difficulty record {} is missing", height)
+            }
+            Self::DifficultyMismatch(height) => {
+                write!(f, "Difficulty record {} is inconsistent", height)
+            }
+            Self::DifficultyOrphan(height) => {
+                write!(f, "Difficulty record {} is above our last block", height)
+            }
+            Self::WasmMissing(contract_id) => {
+                write!(f, "Contract {} wasm bincode is missing", contract_id)
+            }
+            Self::StateTreeMissing(contract_id, pointer) => {
+                write!(f, "Contract {} state tree {} is missing", contract_id, pointer)
+            }
+            Self::ZkasCorrupted(contract_id) => {
+                write!(f, "Contract {} zkas tree contains corrupted records", contract_id)
+            }
+            Self::StateSmt => write!(f, "Stored contracts state SMT is inconsistent"),
+            Self::StateRoot(height, hash) => {
+                write!(f, "Contracts state root doesn't match block {} {} header", height, hash)
+            }
+        }
+    }
+}
+
+/// Walk the blockchain database and verify its consistency: block order
+/// against header heights, header `previous` links, transactions Merkle
+/// roots, transactions locations, cummulative block difficulties and
+/// contracts wasm, state trees and zkas presence, along with the contracts
+/// state SMT and root. Returns the found inconsistencies.
+///
+/// If `repair` is set, we try to repair the found inconsistencies, and the
+/// database is checked again, returning the ones that remain:
+/// * Transactions locations are rebuilt from their blocks.
+/// * Orphan difficulty records are removed.
+/// * The stored contracts state SMT is rebuilt from its buckets.
+/// * If the blocks sequence is broken, it is truncated right before the
+///   broken height, as long as the contracts state matches the one committed
+///   in the header of the new last block, which must be a `BLOCK_VERSION_2`
+///   one. Truncated blocks headers, bodies
+///   and transactions are removed along with their records.
+///
+/// Any other inconsistency requires the database to be rebuilt, for example
+/// using a bootstrap file or a state snapshot.
+pub fn check_database(blockchain: &Blockchain, repair: bool) -> Result<Vec<Inconsistency>> {
+    let issues = check_blockchain(blockchain)?;
+    if !repair || issues.is_empty() {
+        return Ok(issues)
+    }
+
+    info!(target: "darkfid::integrity::check_database", "Repairing {} inconsistencies...", issues.len());
+    repair_blockchain(blockchain, &issues)?;
+    blockchain.sled_db.flush()?;
+
+    info!(target: "darkfid::integrity::check_database", "Checking database again...");
+    check_blockchain(blockchain)
+}
+
+/// Auxiliary function to perform all the checks over the database.
+fn check_blockchain(blockchain: &Blockchain) -> Result<Vec<Inconsistency>> {
+    let mut issues = vec![];
+
+    // Grab our last block height and the pruned height
+    let last = match blockchain.blocks.order.last()? {
+        Some((key, _)) => match <[u8; 4]>::try_from(key.as_ref()) {
+            Ok(bytes) => u32::from_be_bytes(bytes),
+            Err(_) => {
+                issues.push(Inconsistency::OrderMissing(0));
+                return Ok(issues)
+            }
+        },
+        None => {
+            issues.push(Inconsistency::OrderMissing(0));
+            return Ok(issues)
+        }
+    };
+    let pruned = blockchain.blocks.get_pruned_height().ok().flatten().unwrap_or(1);
+    info!(target: "darkfid::integrity::check_blockchain", "Checking {} blocks...", last + 1);
+
+    // Walk the blocks sequence
+    let mut previous: Option<(HeaderHash, Header)> = None;
+    let mut previous_difficulty: Option<BlockDifficulty> = None;
+    let mut locations = HashSet::new();
+    for height in 0..=last {
+        if height % 10000 == 0 {
+            info!(target: "darkfid::integrity::check_blockchain", "Checking block {}/{}", height, last);
+        }
+
+        // Check block order record
+        let hash = match blockchain.blocks.get_order(&[height], false) {
+            Ok(hashes) if hashes[0].is_some() => hashes[0].unwrap(),
+            _ => {
+                issues.push(Inconsistency::OrderMissing(height));
+                previous = None;
+                previous_difficulty = None;
+                continue
+            }
+        };
+
+        // Check block header and its links
+        let header = match blockchain.headers.get(&[hash], false) {
+            Ok(headers) if headers[0].is_some() => headers[0].clone().unwrap(),
+            _ => {
+                issues.push(Inconsistency::HeaderMissing(height, hash));
+                previous = None;
+                previous_difficulty = None;
+                continue
+            }
+        };
+        if header.height != height {
+            issues.push(Inconsistency::HeaderHeight(height, hash, header.height));
+        }
+        if let Some((previous_hash, _)) = &previous {
+            if &header.previous != previous_hash {
+                issues.push(Inconsistency::PreviousLink(height, hash));
+            }
+        }
+
+        // Check block difficulty record
+        let difficulty = blockchain
+            .blocks
+            .get_difficulty(&[height], false)
+            .ok()
+            .and_then(|difficulties| difficulties.into_iter().next().flatten());
+        match difficulty {
+            Some(difficulty) => {
+                let expected = match &previous_difficulty {
+                    Some(p) => &p.cummulative_difficulty + &difficulty.difficulty,
+                    None if height == 0 => difficulty.difficulty.clone(),
+                    None => difficulty.cummulative_difficulty.clone(),
+                };
+                if difficulty.height != height ||
+                    difficulty.timestamp != header.timestamp ||
+                    difficulty.cummulative_difficulty != expected
+                {
+                    issues.push(Inconsistency::DifficultyMismatch(height));
+                }
+                previous_difficulty = Some(difficulty);
+            }
+            None => {
+                issues.push(Inconsistency::DifficultyMissing(height));
+                previous_difficulty = None;
+            }
+        }
+
+        // Pruned blocks have no body to check
+        if height != 0 && height < pruned {
+            previous = Some((hash, header));
+            continue
+        }
+
+        // Check block body, its transactions and their locations
+        let block = match blockchain.blocks.get(&[hash], false) {
+            Ok(blocks) if blocks[0].is_some() => blocks[0].clone().unwrap(),
+            _ => {
+                issues.push(Inconsistency::BlockMissing(height, hash));
+                previous = Some((hash, header));
+                continue
+            }
+        };
+        let txs = blockchain
+            .transactions
+            .get(&block.txs, false)
+            .unwrap_or_else(|_| vec![None; block.txs.len()]);
+        let mut tree = MerkleTree::new(1);
+        let mut complete = true;
+        for (index, (tx_hash, tx)) in block.txs.iter().zip(txs.iter()).enumerate() {
+            let location = (height, index as u16);
+            locations.insert(*tx_hash);
+            match blockchain.transactions.location.get(tx_hash.inner()) {
+                Ok(Some(found)) if deserialize::<(u32, u16)>(&found).ok() == Some(location) => {}
+                _ => issues.push(Inconsistency::TxLocation(*tx_hash, location)),
+            }
+            match tx {
+                Some(tx) => append_tx_to_merkle_tree(&mut tree, tx),
+                None => {
+                    issues.push(Inconsistency::TxMissing(height, *tx_hash));
+                    complete = false;
+                }
+            }
+        }
+        if complete && tree.root(0).unwrap() != header.root {
+            issues.push(Inconsistency::MerkleRoot(height, hash));
+        }
+
+        previous = Some((hash, header));
+    }
+
+    // Check for transactions locations not matching any checked block position.
+    // Pruned blocks transactions are not checked.
+    for record in blockchain.transactions.location.iter() {
+        let (key, value) = record?;
+        let Ok(tx_hash) = deserialize::<TransactionHash>(&key) else {
+            warn!(target: "darkfid::integrity::check_blockchain", "Corrupted transaction location key found");
+            continue
+        };
+        if locations.contains(&tx_hash) {
+            continue
+        }
+        match deserialize::<(u32, u16)>(&value) {
+            Ok((height, _)) if height > 0 && height < pruned => {}
+            _ => issues.push(Inconsistency::TxLocationOrphan(tx_hash)),
+        }
+    }
+
+    // Check for difficulty records above our last block
+    for record in blockchain.blocks.difficulty.range((last + 1).to_be_bytes()..) {
+        let (key, _) = record?;
+        if let Ok(bytes) = <[u8; 4]>::try_from(key.as_ref()) {
+            issues.push(Inconsistency::DifficultyOrphan(u32::from_be_bytes(bytes)));
+        }
+    }
+
+    // Check contracts wasm bincodes, state trees and zkas records
+    info!(target: "darkfid::integrity::check_blockchain", "Checking contracts...");
+    let tree_names: HashSet<Vec<u8>> =
+        blockchain.sled_db.tree_names().iter().map(|name| name.to_vec()).collect();
+    for (contract_id, pointers) in blockchain.contracts.get_all_states()? {
+        if !blockchain.contracts.wasm.contains_key(serialize(&contract_id))? {
+            issues.push(Inconsistency::WasmMissing(contract_id));
+        }
+
+        for pointer in &pointers {
+            if !tree_names.contains(&pointer.as_bytes().to_vec()) {
+                issues.push(Inconsistency::StateTreeMissing(contract_id, *pointer));
+            }
+        }
+
+        let zkas_pointer = contract_id.hash_state_id(SMART_CONTRACT_ZKAS_DB_NAME);
+        if !pointers.contains(&blake3::Hash::from(zkas_pointer)) ||
+            !tree_names.contains(&zkas_pointer.to_vec())
+        {
+            continue
+        }
+        for record in blockchain.sled_db.open_tree(zkas_pointer)?.iter() {
+            let (_, value) = record?;
+            let valid = match deserialize::<(Vec<u8>, Vec<u8>)>(&value) {
+                Ok((zkbin, _)) => ZkBinary::decode(&zkbin).is_ok(),
+                Err(_) => false,
+            };
+            if !valid {
+                issues.push(Inconsistency::ZkasCorrupted(contract_id));
+                break
+            }
+        }
+    }
+
+    // Check contracts state SMT and root
+    info!(target: "darkfid::integrity::check_blockchain", "Checking contracts state SMT...");
+    let state_root = blockchain.contracts.compute_state_root()?;
+    match blockchain.contracts.get_state_root() {
+        Ok(stored) if stored == state_root => {}
+        _ => issues.push(Inconsistency::StateSmt),
+    }
+    if let Some((hash, header)) = &previous {
+        if header.version >= BLOCK_VERSION_2 && header.state_root != state_root {
+            issues.push(Inconsistency::StateRoot(header.height, *hash));
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Auxiliary function to repair provided inconsistencies, where possible.
+fn repair_blockchain(blockchain: &Blockchain, issues: &[Inconsistency]) -> Result<()> {
+    // Truncate the blocks sequence if its broken and contracts state matches
+    // the committed one of the last valid block.
+    let state_root = blockchain.contracts.compute_state_root()?;
+    let mut last = blockchain.last()?.0;
+    if let Some(broken) = issues.iter().filter_map(|issue| issue.broken_height()).min() {
+        let new_last = broken.saturating_sub(1);
+        let header = match blockchain.blocks.get_order(&[new_last], false)?[0] {
+            Some(hash) => blockchain.headers.get(&[hash], false).ok().and_then(|h| h[0].clone()),
+            None => None,
+        };
+        match header {
+            Some(header)
+                if header.version >= BLOCK_VERSION_2 && header.state_root == state_root =>
+            {
+                info!(target: "darkfid::integrity::repair_blockchain", "Truncating blocks sequence to height {}", new_last);
+                let heights: Vec<u32> = (broken..=last).collect();
+                let mut order = sled::Batch::default();
+                let mut difficulty = sled::Batch::default();
+                for height in &heights {
+                    order.remove(&height.to_be_bytes());
+                    difficulty.remove(&height.to_be_bytes());
+                }
+
+                // Grab the truncated blocks records we can still find,
+                // so we don't leave them orphaned.
+                let hashes: Vec<HeaderHash> = blockchain
+                    .blocks
+                    .get_order(&heights, false)
+                    .map(|hashes| hashes.into_iter().flatten().collect())
+                    .unwrap_or_default();
+                let mut headers = sled::Batch::default();
+                for hash in &hashes {
+                    headers.remove(hash.inner());
+                }
+                let blocks = blockchain.blocks.remove_batch(&hashes);
+                let txs: Vec<TransactionHash> = hashes
+                    .iter()
+                    .filter_map(|hash| blockchain.blocks.get(&[*hash], true).ok())
+                    .flat_map(|blocks| blocks[0].clone().unwrap().txs)
+                    .collect();
+                let txs = blockchain.transactions.remove_batch(&txs);
+                let mut locations = sled::Batch::default();
+                for record in blockchain.transactions.location.iter() {
+                    let (key, value) = record?;
+                    if let Ok((height, _)) = deserialize::<(u32, u16)>(&value) {
+                        if height >= broken {
+                            locations.remove(key);
+                        }
+                    }
+                }
+                blockchain.transactions.main.apply_batch(txs)?;
+                blockchain.blocks.main.apply_batch(blocks)?;
+                blockchain.headers.main.apply_batch(headers)?;
+                blockchain.blocks.order.apply_batch(order)?;
+                blockchain.blocks.difficulty.apply_batch(difficulty)?;
+                blockchain.transactions.location.apply_batch(locations)?;
+                last = new_last;
+            }
+            _ => {
+                warn!(target: "darkfid::integrity::repair_blockchain", "Blocks sequence is broken at height {} and can't be truncated", broken);
+            }
+        }
+    }
+
+    for issue in issues {
+        match issue {
+            Inconsistency::TxLocation(tx_hash, location) if location.0 <= last => {
+                blockchain.transactions.location.insert(tx_hash.inner(), serialize(location))?;
+            }
+            Inconsistency::TxLocationOrphan(tx_hash) => {
+                blockchain.transactions.location.remove(tx_hash.inner())?;
+            }
+            Inconsistency::DifficultyOrphan(height) => {
+                blockchain.blocks.difficulty.remove(height.to_be_bytes())?;
+            }
+            Inconsistency::StateSmt => {
+                blockchain.contracts.rebuild_state_smt()?;
+            }
+            _ => { /* Not repairable */ }
+        }
+    }
+
+    Ok(())
+}
@@ -23,17 +23,20 @@ use std::{
 
 use log::{debug, error, info};
 use smol::lock::Mutex;
+use tinyjson::JsonValue;
 use url::Url;
 
 use darkfi::{
     net::settings::Settings,
     rpc::{
         client::RpcChadClient,
+        clock_sync::{clock_sync_task, ClockSyncSettings},
         jsonrpc::JsonSubscriber,
         server::{listen_and_serve, RequestHandler},
+        settings::RpcSettings,
     },
     system::{ExecutorPtr, StoppableTask, StoppableTaskPtr},
-    validator::{Validator, ValidatorConfig, ValidatorPtr},
+    validator::{consensus::BestChainEvent, Validator, ValidatorConfig, ValidatorPtr},
     Error, Result,
 };
 
@@ -46,11 +49,27 @@ use error::{server_error, RpcError};
 /// JSON-RPC requests handler and methods
 mod rpc;
 mod rpc_blockchain;
+mod rpc_miner;
+use rpc_miner::DarkfiMinerRpc;
+mod rpc_regtest;
 mod rpc_tx;
 
 /// Validator async tasks
 pub mod task;
-use task::{consensus::ConsensusInitTaskConfig, consensus_init_task};
+
+/// Blockchain bootstrap files export and import
+pub mod bootstrap;
+
+/// Blockchain state snapshots export and import
+pub mod snapshot;
+
+/// Offline database integrity checker
+pub mod integrity;
+use task::{
+    consensus::ConsensusInitTaskConfig,
+    consensus_init_task,
+    miner::{BlockTemplates, RegtestMiner},
+};
 
 /// P2P net protocols
 mod proto;
@@ -88,6 +107,10 @@ pub struct DarkfiNode {
     rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
     /// JSON-RPC client to execute requests to the miner daemon
     rpc_client: Option<Mutex<MinerRpcClient>>,
+    /// On demand blocks producer, if node runs in regtest mode
+    regtest: Option<RegtestMiner>,
+    /// Next block templates issued to external miners
+    templates: BlockTemplates,
 }
 
 impl DarkfiNode {
@@ -97,6 +120,7 @@ impl DarkfiNode {
         txs_batch_size: usize,
         subscribers: HashMap<&'static str, JsonSubscriber>,
         rpc_client: Option<Mutex<MinerRpcClient>>,
+        regtest: Option<RegtestMiner>,
     ) -> DarkfiNodePtr {
         Arc::new(Self {
             p2p_handler,
@@ -105,6 +129,8 @@ impl DarkfiNode {
             subscribers,
             rpc_connections: Mutex::new(HashSet::new()),
             rpc_client,
+            regtest,
+            templates: BlockTemplates::default(),
         })
     }
 }
@@ -118,10 +144,22 @@ pub struct Darkfid {
     node: DarkfiNodePtr,
     /// `dnet` background task
     dnet_task: StoppableTaskPtr,
+    /// Best chain events background task
+    chain_task: StoppableTaskPtr,
+    /// Finalized transactions background task
+    finalized_txs_task: StoppableTaskPtr,
     /// JSON-RPC background task
     rpc_task: StoppableTaskPtr,
+    /// Miner JSON-RPC listen URL, if external miners methods are enabled
+    miner_rpc_listen: Option<Url>,
+    /// Miner JSON-RPC background task
+    miner_rpc_task: StoppableTaskPtr,
     /// Consensus protocol background task
     consensus_task: StoppableTaskPtr,
+    /// Clock sync settings, if system clock offset estimation is enabled
+    clock_settings: Option<ClockSyncSettings>,
+    /// Clock sync background task
+    clock_task: StoppableTaskPtr,
 }
 
 impl Darkfid {
@@ -129,12 +167,20 @@ impl Darkfid {
     ///
     /// Generates a new `DarkfiNode` for provided configuration,
     /// along with all the corresponding background tasks.
+    /// If no clock sync settings are provided, the system clock
+    /// is used as is. In regtest mode, blocks are only generated
+    /// on demand, through the `regtest.generate` JSON-RPC method.
+    /// External miners methods are only served if a miner JSON-RPC
+    /// listen URL is provided.
     pub async fn init(
         sled_db: &sled_overlay::sled::Db,
         config: &ValidatorConfig,
         net_settings: &Settings,
+        clock_settings: &Option<ClockSyncSettings>,
         minerd_endpoint: &Option<Url>,
+        miner_rpc_listen: &Option<Url>,
         txs_batch_size: &Option<usize>,
+        regtest: bool,
         ex: &ExecutorPtr,
     ) -> Result<DarkfidPtr> {
         info!(target: "darkfid::Darkfid::init", "Initializing a Darkfi daemon...");
@@ -160,7 +206,10 @@ impl Darkfid {
         let mut subscribers = HashMap::new();
         subscribers.insert("blocks", JsonSubscriber::new("blockchain.subscribe_blocks"));
         subscribers.insert("txs", JsonSubscriber::new("blockchain.subscribe_txs"));
+        subscribers
+            .insert("finalized_txs", JsonSubscriber::new("blockchain.subscribe_finalized_txs"));
         subscribers.insert("proposals", JsonSubscriber::new("blockchain.subscribe_proposals"));
+        subscribers.insert("chain", JsonSubscriber::new("blockchain.subscribe_chain_events"));
         subscribers.insert("dnet", JsonSubscriber::new("dnet.subscribe_events"));
 
         // Initialize JSON-RPC client to perform requests to minerd
@@ -175,26 +224,58 @@ impl Darkfid {
             None => None,
         };
 
+        // Initialize regtest blocks producer
+        let regtest = if regtest {
+            info!(target: "darkfid::Darkfid::init", "Node is running in regtest mode");
+            Some(RegtestMiner::new(&validator)?)
+        } else {
+            None
+        };
+
         // Initialize node
-        let node =
-            DarkfiNode::new(p2p_handler, validator, txs_batch_size, subscribers, rpc_client).await;
+        let node = DarkfiNode::new(
+            p2p_handler,
+            validator,
+            txs_batch_size,
+            subscribers,
+            rpc_client,
+            regtest,
+        )
+        .await;
 
         // Generate the background tasks
         let dnet_task = StoppableTask::new();
+        let chain_task = StoppableTask::new();
+        let finalized_txs_task = StoppableTask::new();
         let rpc_task = StoppableTask::new();
+        let miner_rpc_task = StoppableTask::new();
         let consensus_task = StoppableTask::new();
+        let clock_task = StoppableTask::new();
 
         info!(target: "darkfid::Darkfid::init", "Darkfi daemon initialized successfully!");
 
-        Ok(Arc::new(Self { node, dnet_task, rpc_task, consensus_task }))
+        Ok(Arc::new(Self {
+            node,
+            dnet_task,
+            chain_task,
+            finalized_txs_task,
+            rpc_task,
+            miner_rpc_listen: miner_rpc_listen.clone(),
+            miner_rpc_task,
+            consensus_task,
+            clock_settings: clock_settings.clone(),
+            clock_task,
+        }))
     }
 
-    /// Start the DarkFi daemon in the given executor, using the provided JSON-RPC listen url
-    /// and consensus initialization configuration.
+    /// Start the DarkFi daemon in the given executor, using the provided JSON-RPC listen url,
+    /// optional JSON-RPC server settings and consensus initialization configuration.
+    /// The miner JSON-RPC server, if configured, uses the same server settings.
     pub async fn start(
         &self,
         executor: &ExecutorPtr,
         rpc_listen: &Url,
+        rpc_settings: &Option<RpcSettings>,
         config: &ConsensusInitTaskConfig,
     ) -> Result<()> {
         info!(target: "darkfid::Darkfid::start", "Starting Darkfi daemon...");
@@ -230,11 +311,88 @@ impl Darkfid {
             executor.clone(),
         );
 
+        // Start the best chain events task
+        info!(target: "darkfid::Darkfid::start", "Starting best chain events subs task");
+        let chain_sub_ = self.node.subscribers.get("chain").unwrap().clone();
+        let validator_ = self.node.validator.clone();
+        self.chain_task.clone().start(
+            async move {
+                let chain_sub = validator_.consensus.events.clone().subscribe().await;
+                loop {
+                    let event = chain_sub.receive().await;
+                    debug!(target: "darkfid::Darkfid::chain_task", "Got best chain event: {:?}", event);
+                    let (event, height, hash) = match event {
+                        BestChainEvent::Appended(height, hash) => ("appended", height, hash),
+                        BestChainEvent::Reverted(height, hash) => ("reverted", height, hash),
+                        BestChainEvent::Finalized(height, hash) => ("finalized", height, hash),
+                    };
+                    let event = JsonValue::from(HashMap::from([
+                        ("event".to_string(), JsonValue::String(event.to_string())),
+                        ("height".to_string(), JsonValue::Number(height as f64)),
+                        ("hash".to_string(), JsonValue::String(hash.to_string())),
+                    ]));
+                    chain_sub_.notify(vec![event].into()).await;
+                }
+            },
+            |res| async {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!(target: "darkfid::Darkfid::start", "Failed starting best chain events subs task: {}", e),
+                }
+            },
+            Error::DetachedTaskStopped,
+            executor.clone(),
+        );
+
+        // Start the finalized transactions task, which publishes the
+        // transactions of each block the validator reports as finalized.
+        info!(target: "darkfid::Darkfid::start", "Starting finalized txs subs task");
+        let finalized_txs_sub_ = self.node.subscribers.get("finalized_txs").unwrap().clone();
+        let validator_ = self.node.validator.clone();
+        self.finalized_txs_task.clone().start(
+            async move {
+                let chain_sub = validator_.consensus.events.clone().subscribe().await;
+                loop {
+                    let BestChainEvent::Finalized(height, hash) = chain_sub.receive().await else {
+                        continue
+                    };
+                    let block = match validator_.blockchain.blocks.get(&[hash], true) {
+                        Ok(b) => b[0].clone().unwrap(),
+                        Err(e) => {
+                            error!(target: "darkfid::Darkfid::finalized_txs_task", "Failed retrieving finalized block {}: {}", hash, e);
+                            continue
+                        }
+                    };
+                    debug!(target: "darkfid::Darkfid::finalized_txs_task", "Got finalized block {} - {}", height, hash);
+                    let txs = block.txs.iter().map(|tx| JsonValue::String(tx.to_string())).collect();
+                    let notif_block = JsonValue::from(HashMap::from([
+                        ("height".to_string(), JsonValue::Number(height as f64)),
+                        ("hash".to_string(), JsonValue::String(hash.to_string())),
+                        ("txs".to_string(), JsonValue::Array(txs)),
+                    ]));
+                    finalized_txs_sub_.notify(vec![notif_block].into()).await;
+                }
+            },
+            |res| async {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!(target: "darkfid::Darkfid::start", "Failed starting finalized txs subs task: {}", e),
+                }
+            },
+            Error::DetachedTaskStopped,
+            executor.clone(),
+        );
+
         // Start the JSON-RPC task
         info!(target: "darkfid::Darkfid::start", "Starting JSON-RPC server");
         let node_ = self.node.clone();
         self.rpc_task.clone().start(
-            listen_and_serve(rpc_listen.clone(), self.node.clone(), None, executor.clone()),
+            listen_and_serve(
+                rpc_listen.clone(),
+                self.node.clone(),
+                rpc_settings.clone(),
+                executor.clone(),
+            ),
             |res| async move {
                 match res {
                     Ok(()) | Err(Error::RpcServerStopped) => node_.stop_connections().await,
@@ -245,6 +403,29 @@ impl Darkfid {
             executor.clone(),
         );
 
+        // Start the miner JSON-RPC task
+        if let Some(miner_rpc_listen) = &self.miner_rpc_listen {
+            info!(target: "darkfid::Darkfid::start", "Starting miner JSON-RPC server");
+            let miner_rpc = Arc::new(DarkfiMinerRpc::new(self.node.clone()));
+            let miner_rpc_ = miner_rpc.clone();
+            self.miner_rpc_task.clone().start(
+                listen_and_serve(
+                    miner_rpc_listen.clone(),
+                    miner_rpc,
+                    rpc_settings.clone(),
+                    executor.clone(),
+                ),
+                |res| async move {
+                    match res {
+                        Ok(()) | Err(Error::RpcServerStopped) => miner_rpc_.stop_connections().await,
+                        Err(e) => error!(target: "darkfid::Darkfid::start", "Failed starting miner JSON-RPC server: {}", e),
+                    }
+                },
+                Error::RpcServerStopped,
+                executor.clone(),
+            );
+        }
+
         // Start the P2P network
         info!(target: "darkfid::Darkfid::start", "Starting P2P network");
         self.node
@@ -253,6 +434,28 @@ impl Darkfid {
             .start(executor, &self.node.validator, &self.node.subscribers)
             .await?;
 
+        // Start the clock sync task
+        if let Some(clock_settings) = &self.clock_settings {
+            info!(target: "darkfid::Darkfid::start", "Starting clock sync task");
+            let clock_offset =
+                self.node.validator.consensus.module.read().await.clock_offset.clone();
+            self.clock_task.clone().start(
+                clock_sync_task(
+                    clock_settings.clone(),
+                    Some(self.node.p2p_handler.p2p.clone()),
+                    clock_offset,
+                ),
+                |res| async {
+                    match res {
+                        Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                        Err(e) => error!(target: "darkfid::Darkfid::start", "Failed starting clock sync task: {}", e),
+                    }
+                },
+                Error::DetachedTaskStopped,
+                executor.clone(),
+            );
+        }
+
         // Start the consensus protocol
         info!(target: "darkfid::Darkfid::start", "Starting consensus protocol task");
         self.consensus_task.clone().start(
@@ -283,10 +486,30 @@ impl Darkfid {
         info!(target: "darkfid::Darkfid::stop", "Stopping dnet subs task...");
         self.dnet_task.stop().await;
 
+        // Stop the best chain events task
+        info!(target: "darkfid::Darkfid::stop", "Stopping best chain events subs task...");
+        self.chain_task.stop().await;
+
+        // Stop the finalized transactions task
+        info!(target: "darkfid::Darkfid::stop", "Stopping finalized txs subs task...");
+        self.finalized_txs_task.stop().await;
+
         // Stop the JSON-RPC task
         info!(target: "darkfid::Darkfid::stop", "Stopping JSON-RPC server...");
         self.rpc_task.stop().await;
 
+        // Stop the miner JSON-RPC task
+        if self.miner_rpc_listen.is_some() {
+            info!(target: "darkfid::Darkfid::stop", "Stopping miner JSON-RPC server...");
+            self.miner_rpc_task.stop().await;
+        }
+
+        // Stop the clock sync task
+        if self.clock_settings.is_some() {
+            info!(target: "darkfid::Darkfid::stop", "Stopping clock sync task...");
+            self.clock_task.stop().await;
+        }
+
         // Stop the P2P network
         info!(target: "darkfid::Darkfid::stop", "Stopping P2P network protocols handler...");
         self.node.p2p_handler.stop().await;
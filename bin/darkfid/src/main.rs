@@ -15,29 +15,40 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-
-use std::sync::Arc;
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 
 use log::{debug, error, info};
 use smol::{fs::read_to_string, stream::StreamExt};
 use structopt_toml::{serde::Deserialize, structopt::StructOpt, StructOptToml};
-use url::Url;
+use url::{Host, Url};
 
 use darkfi::{
     async_daemonize,
-    blockchain::BlockInfo,
+    blockchain::{BlockInfo, Blockchain, HeaderHash},
     cli_desc,
-    net::settings::SettingsOpt,
+    net::{hosts::LOCAL_HOST_STRS, settings::SettingsOpt},
+    rpc::{clock_sync::ClockSyncSettingsOpt, settings::RpcSettingsOpt},
     util::{
         encoding::base64,
         path::{expand_path, get_config_path},
     },
-    validator::ValidatorConfig,
+    validator::{
+        consensus::{DEFAULT_MAX_FORKS, DEFAULT_MAX_FORK_DEPTH},
+        pow::DifficultyAlgorithm,
+        Validator, ValidatorConfig, DEFAULT_MEMPOOL_MAX_SIZE, DEFAULT_MIN_RELAY_FEE_RATE,
+        DEFAULT_PENDING_TX_EXPIRY,
+    },
     Error, Result,
 };
 use darkfi_serial::deserialize_async;
 
-use darkfid::{task::consensus::ConsensusInitTaskConfig, Darkfid};
+use darkfid::{
+    bootstrap::{export_blocks, import_blocks},
+    integrity::check_database,
+    snapshot::{export_snapshot, import_snapshot, snapshot_import_interrupted},
+    task::consensus::ConsensusInitTaskConfig,
+    Darkfid,
+};
 
 const CONFIG_FILE: &str = "darkfid_config.toml";
 const CONFIG_FILE_CONTENTS: &str = include_str!("../darkfid_config.toml");
@@ -47,6 +58,19 @@ const CONFIG_FILE_CONTENTS: &str = include_str!("../darkfid_config.toml");
 const GENESIS_BLOCK_LOCALNET: &str = include_str!("../genesis_block_localnet");
 const GENESIS_BLOCK_TESTNET: &str = include_str!("../genesis_block_testnet");
 const GENESIS_BLOCK_MAINNET: &str = include_str!("../genesis_block_mainnet");
+/// Built-in trusted checkpoints of each network
+const CHECKPOINTS_TESTNET: &str = include_str!("../checkpoints_testnet");
+const CHECKPOINTS_MAINNET: &str = include_str!("../checkpoints_mainnet");
+/// Heights `BLOCK_VERSION_2` blocks activate at on each network.
+/// Public networks haven't scheduled it yet, so their blocks stay at
+/// the default version, not committing to the contracts state root.
+const BLOCK_V2_HEIGHT_LOCALNET: Option<u32> = Some(1);
+const BLOCK_V2_HEIGHT_TESTNET: Option<u32> = None;
+const BLOCK_V2_HEIGHT_MAINNET: Option<u32> = None;
+/// PoW difficulty adjustment algorithm of each public network, which all
+/// their nodes must agree on, so it can't be configured.
+const POW_ALGORITHM_TESTNET: DifficultyAlgorithm = DifficultyAlgorithm::Monero;
+const POW_ALGORITHM_MAINNET: DifficultyAlgorithm = DifficultyAlgorithm::Monero;
 
 #[derive(Clone, Debug, Deserialize, StructOpt, StructOptToml)]
 #[serde(default)]
@@ -67,6 +91,75 @@ struct Args {
     #[structopt(short, parse(from_occurrences))]
     /// Increase verbosity (-vvv supported)
     verbose: u8,
+
+    #[structopt(subcommand)]
+    /// Optional sub command to execute instead of running the node
+    command: Option<Subcmd>,
+}
+
+#[derive(Clone, Debug, Deserialize, StructOpt)]
+enum Subcmd {
+    /// Export blockchain blocks into a bootstrap file
+    Export {
+        /// Path of the bootstrap file to create
+        path: String,
+
+        #[structopt(long, default_value = "0")]
+        /// Height of the first block to export
+        from: u32,
+
+        #[structopt(long)]
+        /// Height of the last block to export, defaults to our last block
+        to: Option<u32>,
+    },
+
+    /// Import blockchain blocks from a bootstrap file
+    Import {
+        /// Path of the bootstrap file to import
+        path: String,
+
+        #[structopt(long)]
+        /// Optional trusted checkpoint height, up to which blocks
+        /// are applied without formal verification
+        checkpoint_height: Option<u32>,
+
+        #[structopt(long)]
+        /// Optional trusted checkpoint hash
+        checkpoint: Option<String>,
+    },
+
+    /// Export a snapshot of the blockchain state at our last block
+    Snapshot {
+        /// Path of the snapshot file to create
+        path: String,
+    },
+
+    /// Initialize a fresh blockchain from a trusted snapshot file,
+    /// retrieved from a trusted source publishing it along with its
+    /// checkpoint. An interrupted restore must be retried.
+    Restore {
+        /// Path of the snapshot file to import
+        path: String,
+
+        #[structopt(long)]
+        /// Trusted checkpoint height the snapshot was taken at
+        checkpoint_height: u32,
+
+        #[structopt(long)]
+        /// Trusted checkpoint hash the snapshot was taken at
+        checkpoint: String,
+
+        #[structopt(long)]
+        /// Trusted state commitment at the checkpoint
+        state: String,
+    },
+
+    /// Check the blockchain database integrity, without starting the node
+    CheckDb {
+        #[structopt(long)]
+        /// Try to repair the found inconsistencies
+        repair: bool,
+    },
 }
 
 /// Defines a blockchain network configuration.
@@ -78,6 +171,10 @@ pub struct BlockchainNetwork {
     /// JSON-RPC listen URL
     rpc_listen: Url,
 
+    #[structopt(long)]
+    /// Optional local JSON-RPC listen URL serving external miners methods
+    miner_rpc_listen: Option<Url>,
+
     #[structopt(long, default_value = "~/.local/darkfi/darkfid/localnet")]
     /// Path to blockchain database
     database: String,
@@ -98,6 +195,10 @@ pub struct BlockchainNetwork {
     /// Optional fixed PoW difficulty, used for testing
     pow_fixed_difficulty: Option<usize>,
 
+    #[structopt(long)]
+    /// PoW difficulty adjustment algorithm (monero, lwma), localnet and regtest only
+    pow_algorithm: Option<String>,
+
     #[structopt(long)]
     /// Wallet address to receive mining rewards
     recipient: Option<String>,
@@ -118,6 +219,11 @@ pub struct BlockchainNetwork {
     /// Disable transaction's fee verification, used for testing
     skip_fees: bool,
 
+    #[serde(default)]
+    #[structopt(long)]
+    /// Disable system clock offset estimation against network time
+    skip_clock_sync: bool,
+
     #[structopt(long)]
     /// Optional sync checkpoint height
     checkpoint_height: Option<u32>,
@@ -126,6 +232,11 @@ pub struct BlockchainNetwork {
     /// Optional sync checkpoint hash
     checkpoint: Option<String>,
 
+    #[serde(default)]
+    #[structopt(long)]
+    /// Additional trusted checkpoints, in the form `height:hash`
+    checkpoints: Vec<String>,
+
     #[structopt(long)]
     /// Optional bootstrap timestamp
     bootstrap: Option<u64>,
@@ -134,6 +245,40 @@ pub struct BlockchainNetwork {
     /// Garbage collection task transactions batch size
     txs_batch_size: Option<usize>,
 
+    #[structopt(long)]
+    /// Optional number of finalized blocks to keep full data for, enabling pruned mode
+    prune: Option<u32>,
+
+    #[structopt(long)]
+    /// Maximum total size of pending transactions, in bytes
+    mempool_max_size: Option<u64>,
+
+    #[structopt(long)]
+    /// Minimum fee rate, as fee paid per 1000 gas units, to accept a transaction
+    min_relay_fee_rate: Option<u64>,
+
+    #[structopt(long)]
+    /// Number of blocks after which a pending transaction expires
+    pending_tx_expiry: Option<u32>,
+
+    #[structopt(long)]
+    /// Maximum number of forks tracked at the same time
+    max_forks: Option<usize>,
+
+    #[structopt(long)]
+    /// Maximum number of blocks a fork tip can be behind the best fork tip
+    max_fork_depth: Option<u32>,
+
+    /// JSON-RPC server settings
+    #[serde(default)]
+    #[structopt(flatten)]
+    rpc: RpcSettingsOpt,
+
+    /// Clock sync settings
+    #[serde(default)]
+    #[structopt(flatten)]
+    clock: ClockSyncSettingsOpt,
+
     /// P2P network settings
     #[structopt(flatten)]
     net: SettingsOpt,
@@ -144,21 +289,41 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
     info!(target: "darkfid", "Initializing DarkFi node...");
 
     // Grab blockchain network configuration
-    let (blockchain_config, genesis_block) = match args.network.as_str() {
-        "localnet" => {
-            (parse_blockchain_config(args.config, "localnet").await?, GENESIS_BLOCK_LOCALNET)
-        }
-        "testnet" => {
-            (parse_blockchain_config(args.config, "testnet").await?, GENESIS_BLOCK_TESTNET)
-        }
-        "mainnet" => {
-            (parse_blockchain_config(args.config, "mainnet").await?, GENESIS_BLOCK_MAINNET)
-        }
-        _ => {
-            error!("Unsupported chain `{}`", args.network);
-            return Err(Error::UnsupportedChain)
-        }
-    };
+    let (mut blockchain_config, genesis_block, checkpoints, block_v2_height, pow_algorithm) =
+        match args.network.as_str() {
+            "localnet" => (
+                parse_blockchain_config(args.config, "localnet").await?,
+                GENESIS_BLOCK_LOCALNET,
+                "",
+                BLOCK_V2_HEIGHT_LOCALNET,
+                None,
+            ),
+            "regtest" => (
+                parse_blockchain_config(args.config, "regtest").await?,
+                GENESIS_BLOCK_LOCALNET,
+                "",
+                BLOCK_V2_HEIGHT_LOCALNET,
+                None,
+            ),
+            "testnet" => (
+                parse_blockchain_config(args.config, "testnet").await?,
+                GENESIS_BLOCK_TESTNET,
+                CHECKPOINTS_TESTNET,
+                BLOCK_V2_HEIGHT_TESTNET,
+                Some(POW_ALGORITHM_TESTNET),
+            ),
+            "mainnet" => (
+                parse_blockchain_config(args.config, "mainnet").await?,
+                GENESIS_BLOCK_MAINNET,
+                CHECKPOINTS_MAINNET,
+                BLOCK_V2_HEIGHT_MAINNET,
+                Some(POW_ALGORITHM_MAINNET),
+            ),
+            _ => {
+                error!("Unsupported chain `{}`", args.network);
+                return Err(Error::UnsupportedChain)
+            }
+        };
 
     // Parse the genesis block
     let bytes = base64::decode(genesis_block.trim()).unwrap();
@@ -174,6 +339,15 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
     let db_path = expand_path(&blockchain_config.database)?;
     let sled_db = sled_overlay::sled::open(&db_path)?;
 
+    // Regtest mode uses trivial difficulty and produces blocks only on demand,
+    // so it never syncs or mines on its own.
+    let regtest = args.network == "regtest";
+    if regtest {
+        blockchain_config.pow_fixed_difficulty = Some(1);
+        blockchain_config.skip_sync = true;
+        blockchain_config.minerd_endpoint = None;
+    }
+
     // Initialize validator configuration
     let pow_fixed_difficulty = if let Some(diff) = blockchain_config.pow_fixed_difficulty {
         info!(target: "darkfid", "Node is configured to run with fixed PoW difficulty: {}", diff);
@@ -182,21 +356,146 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
         None
     };
 
+    // Public networks use their fixed PoW difficulty adjustment algorithm
+    let pow_algorithm = match (pow_algorithm, &blockchain_config.pow_algorithm) {
+        (Some(_), Some(_)) => {
+            error!(target: "darkfid", "PoW difficulty adjustment algorithm can't be configured on {}", args.network);
+            return Err(Error::ConfigInvalid)
+        }
+        (Some(algorithm), None) => algorithm,
+        (None, Some(algorithm)) => algorithm.parse::<DifficultyAlgorithm>()?,
+        (None, None) => DifficultyAlgorithm::default(),
+    };
+    info!(target: "darkfid", "Node is configured to use {:?} PoW difficulty adjustment", pow_algorithm);
+
+    // Merge the built-in trusted checkpoints with the configured ones
+    let mut configured_checkpoints = blockchain_config.checkpoints.clone();
+    if let (Some(height), Some(hash)) =
+        (blockchain_config.checkpoint_height, &blockchain_config.checkpoint)
+    {
+        configured_checkpoints.push(format!("{height}:{hash}"));
+    }
+    let checkpoints = parse_checkpoints(checkpoints, &configured_checkpoints)?;
+    if let Some((height, hash)) = checkpoints.last() {
+        info!(target: "darkfid", "Node is configured with {} checkpoints, last one: {} - {}", checkpoints.len(), height, hash);
+    }
+
     let config = ValidatorConfig {
         finalization_threshold: blockchain_config.threshold,
         pow_target: blockchain_config.pow_target,
         pow_fixed_difficulty,
+        pow_algorithm,
         genesis_block,
         verify_fees: !blockchain_config.skip_fees,
+        prune: blockchain_config.prune,
+        mempool_max_size: blockchain_config.mempool_max_size.unwrap_or(DEFAULT_MEMPOOL_MAX_SIZE),
+        min_relay_fee_rate: blockchain_config
+            .min_relay_fee_rate
+            .unwrap_or(DEFAULT_MIN_RELAY_FEE_RATE),
+        pending_tx_expiry: blockchain_config.pending_tx_expiry.unwrap_or(DEFAULT_PENDING_TX_EXPIRY),
+        max_forks: blockchain_config.max_forks.unwrap_or(DEFAULT_MAX_FORKS),
+        max_fork_depth: blockchain_config.max_fork_depth.unwrap_or(DEFAULT_MAX_FORK_DEPTH),
+        checkpoints,
+        block_v2_height,
+    };
+
+    // Execute requested sub command, if any. Database checks and snapshot
+    // restores operate over the blockchain directly, since they must not
+    // touch a potentially inconsistent database through the validator.
+    let blockchain = Blockchain::new(&sled_db)?;
+    if let Some(command) = args.command {
+        match command {
+            Subcmd::Export { path, from, to } => {
+                check_snapshot_import(&blockchain)?;
+                let validator = Validator::new(&sled_db, &config).await?;
+                let path = expand_path(&path)?;
+                let exported = export_blocks(&validator.blockchain, &path, from, to).await?;
+                info!(target: "darkfid", "Exported {} blocks to: {:?}", exported, path);
+            }
+            Subcmd::Import { path, checkpoint_height, checkpoint } => {
+                let checkpoint = match (checkpoint_height, checkpoint) {
+                    (Some(height), Some(hash)) => Some((height, HeaderHash::from_str(&hash)?)),
+                    (None, None) => None,
+                    _ => return Err(Error::ParseFailed("Checkpoint height or hash missing")),
+                };
+                check_snapshot_import(&blockchain)?;
+                let validator = Validator::new(&sled_db, &config).await?;
+                let path = expand_path(&path)?;
+                let imported = import_blocks(&validator, &path, checkpoint).await?;
+                info!(target: "darkfid", "Imported {} blocks from: {:?}", imported, path);
+            }
+            Subcmd::Snapshot { path } => {
+                check_snapshot_import(&blockchain)?;
+                let path = expand_path(&path)?;
+                let (height, hash, state) = export_snapshot(&blockchain, &path).await?;
+                info!(target: "darkfid", "Exported snapshot to: {:?}", path);
+                info!(target: "darkfid", "Checkpoint height: {}", height);
+                info!(target: "darkfid", "Checkpoint hash: {}", hash);
+                info!(target: "darkfid", "State commitment: {}", state);
+            }
+            Subcmd::Restore { path, checkpoint_height, checkpoint, state } => {
+                let checkpoint = (
+                    checkpoint_height,
+                    HeaderHash::from_str(&checkpoint)?,
+                    blake3::Hash::from_hex(&state)
+                        .map_err(|_| Error::ParseFailed("Invalid state commitment"))?,
+                );
+                // Initialize the genesis block of a fresh database
+                if !snapshot_import_interrupted(&blockchain)? {
+                    Validator::new(&sled_db, &config).await?;
+                }
+                let path = expand_path(&path)?;
+                import_snapshot(&blockchain, &path, checkpoint, &config).await?;
+                info!(target: "darkfid", "Restored snapshot from: {:?}", path);
+            }
+            Subcmd::CheckDb { repair } => {
+                let issues = check_database(&blockchain, repair)?;
+                for issue in &issues {
+                    error!(target: "darkfid", "{}", issue);
+                }
+                if !issues.is_empty() {
+                    sled_db.flush_async().await?;
+                    error!(target: "darkfid", "Database check found {} inconsistencies", issues.len());
+                    return Err(Error::DatabaseError("Database is inconsistent".to_string()))
+                }
+                info!(target: "darkfid", "Database check completed, no inconsistencies found");
+            }
+        }
+
+        // Flush sled database data
+        let flushed_bytes = sled_db.flush_async().await?;
+        info!(target: "darkfid", "Flushed {} bytes", flushed_bytes);
+        return Ok(())
+    }
+    check_snapshot_import(&blockchain)?;
+
+    // Initialize clock sync configuration
+    let clock_settings = if blockchain_config.skip_clock_sync {
+        info!(target: "darkfid", "Clock sync is disabled, using system clock as is");
+        None
+    } else {
+        Some(blockchain_config.clock.into())
     };
 
+    // Block templates generation is expensive, so external miners
+    // methods must not be exposed publicly.
+    if let Some(miner_rpc_listen) = &blockchain_config.miner_rpc_listen {
+        if !is_local_listen_url(miner_rpc_listen) {
+            error!(target: "darkfid", "Miner JSON-RPC must listen on a local address: {}", miner_rpc_listen);
+            return Err(Error::ConfigInvalid)
+        }
+    }
+
     // Generate the daemon
     let daemon = Darkfid::init(
         &sled_db,
         &config,
         &blockchain_config.net.into(),
+        &clock_settings,
         &blockchain_config.minerd_endpoint,
+        &blockchain_config.miner_rpc_listen,
         &blockchain_config.txs_batch_size,
+        regtest,
         &ex,
     )
     .await?;
@@ -212,7 +511,9 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
         user_data: blockchain_config.user_data,
         bootstrap,
     };
-    daemon.start(&ex, &blockchain_config.rpc_listen, &config).await?;
+    daemon
+        .start(&ex, &blockchain_config.rpc_listen, &Some(blockchain_config.rpc.into()), &config)
+        .await?;
 
     // Signal handling for graceful termination.
     let (signals_handler, signals_task) = SignalHandler::new(ex)?;
@@ -270,3 +571,121 @@ pub async fn parse_blockchain_config(
 
     Ok(network_config)
 }
+
+/// Auxiliary function to refuse using a database containing an
+/// interrupted snapshot import, since it is inconsistent.
+fn check_snapshot_import(blockchain: &Blockchain) -> Result<()> {
+    if snapshot_import_interrupted(blockchain)? {
+        error!(target: "darkfid", "Database contains an interrupted snapshot import, restore must be retried");
+        return Err(Error::DatabaseError("Interrupted snapshot import".to_string()))
+    }
+    Ok(())
+}
+
+/// Auxiliary function to parse provided built-in checkpoints list, extended by
+/// the configured ones, into a height ordered `(height, hash)` vector.
+/// Each checkpoint is in the form `height:hash`, while built-in list lines
+/// starting with `#` are ignored. Conflicting checkpoints are rejected.
+fn parse_checkpoints(builtin: &str, configured: &[String]) -> Result<Vec<(u32, HeaderHash)>> {
+    let mut checkpoints = BTreeMap::new();
+    let lines = builtin.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    for checkpoint in lines.chain(configured.iter().map(|c| c.trim())) {
+        let Some((height, hash)) = checkpoint.split_once(':') else {
+            return Err(Error::ParseFailed("Invalid checkpoint"))
+        };
+        let Ok(height) = height.parse::<u32>() else {
+            return Err(Error::ParseFailed("Invalid checkpoint height"))
+        };
+        let hash = HeaderHash::from_str(hash)?;
+        if let Some(existing) = checkpoints.insert(height, hash) {
+            if existing != hash {
+                error!(target: "darkfid", "Conflicting checkpoints for height {}: {} - {}", height, existing, hash);
+                return Err(Error::ParseFailed("Conflicting checkpoints"))
+            }
+        }
+    }
+
+    Ok(checkpoints.into_iter().collect())
+}
+
+/// Auxiliary function to check if provided listen URL is only reachable
+/// from the local machine, either being a loopback address or a Unix socket.
+fn is_local_listen_url(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        Some(Host::Domain(domain)) => LOCAL_HOST_STRS.contains(&domain),
+        None => url.scheme() == "unix",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use darkfi::{blockchain::BlockInfo, Error, Result};
+    use darkfi_serial::deserialize;
+    use url::Url;
+
+    use super::{
+        base64, is_local_listen_url, parse_checkpoints, CHECKPOINTS_MAINNET, CHECKPOINTS_TESTNET,
+        GENESIS_BLOCK_TESTNET,
+    };
+
+    const HASH_A: &str = "5e9f9d8f78bf6d8da84246349ede11c933d4852d07bce53028adca4f3837785b";
+    const HASH_B: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    #[test]
+    fn builtin_checkpoints() -> Result<()> {
+        // Testnet checkpoints must start with its genesis block
+        let checkpoints = parse_checkpoints(CHECKPOINTS_TESTNET, &[])?;
+        let bytes = base64::decode(GENESIS_BLOCK_TESTNET.trim()).unwrap();
+        let genesis: BlockInfo = deserialize(&bytes)?;
+        assert_eq!(checkpoints[0], (0, genesis.hash()));
+
+        // Mainnet checkpoints must be valid
+        parse_checkpoints(CHECKPOINTS_MAINNET, &[])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoints_parsing() -> Result<()> {
+        // Configured checkpoints extend the built-in ones, ordered by height,
+        // while comments, empty lines and duplicates are ignored
+        let builtin = format!("# comment\n\n 5:{HASH_A} \n1:{HASH_B}\n");
+        let configured = vec![format!("3:{HASH_B}"), format!("5:{HASH_A}")];
+        let checkpoints = parse_checkpoints(&builtin, &configured)?;
+        let heights: Vec<u32> = checkpoints.iter().map(|(height, _)| *height).collect();
+        assert_eq!(heights, vec![1, 3, 5]);
+        assert_eq!(checkpoints[2].1.as_string(), HASH_A);
+
+        // Conflicting checkpoints are rejected
+        let configured = vec![format!("5:{HASH_B}")];
+        assert!(matches!(
+            parse_checkpoints(&builtin, &configured),
+            Err(Error::ParseFailed("Conflicting checkpoints"))
+        ));
+
+        // Malformed checkpoints are rejected
+        for checkpoint in [HASH_A.to_string(), format!("-1:{HASH_A}"), format!("a:{HASH_A}")] {
+            assert!(parse_checkpoints("", &[checkpoint]).is_err());
+        }
+        assert!(parse_checkpoints("1:deadbeef", &[]).is_err());
+        assert!(parse_checkpoints("1:", &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_listen_urls() -> Result<()> {
+        for url in ["tcp://127.0.0.1:8241", "tcp+tls://[::1]:8241", "tcp://localhost:8241"] {
+            assert!(is_local_listen_url(&Url::parse(url)?));
+        }
+        assert!(is_local_listen_url(&Url::parse("unix:///tmp/darkfid_miner.sock")?));
+
+        for url in ["tcp://0.0.0.0:8241", "tcp://192.168.1.2:8241", "tcp://dark.fi:8241"] {
+            assert!(!is_local_listen_url(&Url::parse(url)?));
+        }
+
+        Ok(())
+    }
+}
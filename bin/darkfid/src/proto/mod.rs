@@ -37,7 +37,8 @@ pub use protocol_proposal::{ProposalMessage, ProtocolProposalHandler, ProtocolPr
 mod protocol_sync;
 pub use protocol_sync::{
     ForkSyncRequest, ForkSyncResponse, HeaderSyncRequest, HeaderSyncResponse, ProtocolSyncHandler,
-    ProtocolSyncHandlerPtr, SyncRequest, SyncResponse, TipRequest, TipResponse, BATCH,
+    ProtocolSyncHandlerPtr, PrunedHeightRequest, PrunedHeightResponse, SyncRequest, SyncResponse,
+    TipRequest, TipResponse, BATCH, PRUNED_HEIGHT_FEATURE, PRUNED_HEIGHT_FEATURE_VERSION,
 };
 
 /// Transaction broadcast protocol
@@ -70,8 +71,12 @@ impl DarkfidP2pHandler {
             "Initializing a new Darkfid P2P handler..."
         );
 
+        // Advertise our optional protocols features
+        let mut settings = settings.clone();
+        settings.features.push((PRUNED_HEIGHT_FEATURE.to_string(), PRUNED_HEIGHT_FEATURE_VERSION));
+
         // Generate a new P2P instance
-        let p2p = P2p::new(settings.clone(), executor.clone()).await?;
+        let p2p = P2p::new(settings, executor.clone()).await?;
 
         // Generate a new `ProtocolProposal` messages handler
         let proposals = ProtocolProposalHandler::init(&p2p).await;
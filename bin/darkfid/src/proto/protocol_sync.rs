@@ -66,6 +66,33 @@ pub struct TipResponse {
 
 impl_p2p_message!(TipResponse, "tipresponse");
 
+/// P2P feature advertised by nodes supporting `PrunedHeightRequest`.
+pub const PRUNED_HEIGHT_FEATURE: &str = "darkfid.prunedheight";
+
+/// Current version of the `PRUNED_HEIGHT_FEATURE`.
+pub const PRUNED_HEIGHT_FEATURE_VERSION: u32 = 1;
+
+/// Structure represening a request to ask a node for the height
+/// before which it has pruned full blocks. Nodes without a dispatcher
+/// for it stop the channel, so it must only be sent to peers advertising
+/// the `PRUNED_HEIGHT_FEATURE`. Other peers are considered as keeping
+/// all blocks.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct PrunedHeightRequest;
+
+impl_p2p_message!(PrunedHeightRequest, "prunedheightrequest");
+
+/// Structure representing the response to `PrunedHeightRequest`,
+/// containing the height before which we have pruned full blocks,
+/// if we run in pruned mode.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct PrunedHeightResponse {
+    /// Height before which full blocks have been pruned
+    pub pruned_height: Option<u32>,
+}
+
+impl_p2p_message!(PrunedHeightResponse, "prunedheightresponse");
+
 /// Structure represening a request to ask a node for up to `BATCH` headers before
 /// the provided header height.
 #[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
@@ -138,6 +165,8 @@ pub type ProtocolSyncHandlerPtr = Arc<ProtocolSyncHandler>;
 pub struct ProtocolSyncHandler {
     /// The generic handler for `TipRequest` messages.
     tip_handler: ProtocolGenericHandlerPtr<TipRequest, TipResponse>,
+    /// The generic handler for `PrunedHeightRequest` messages.
+    pruned_handler: ProtocolGenericHandlerPtr<PrunedHeightRequest, PrunedHeightResponse>,
     /// The generic handler for `HeaderSyncRequest` messages.
     header_handler: ProtocolGenericHandlerPtr<HeaderSyncRequest, HeaderSyncResponse>,
     /// The generic handler for `SyncRequest` messages.
@@ -157,13 +186,21 @@ impl ProtocolSyncHandler {
 
         let tip_handler =
             ProtocolGenericHandler::new(p2p, "ProtocolSyncTip", SESSION_DEFAULT).await;
+        let pruned_handler =
+            ProtocolGenericHandler::new(p2p, "ProtocolSyncPruned", SESSION_DEFAULT).await;
         let header_handler =
             ProtocolGenericHandler::new(p2p, "ProtocolSyncHeader", SESSION_DEFAULT).await;
         let sync_handler = ProtocolGenericHandler::new(p2p, "ProtocolSync", SESSION_DEFAULT).await;
         let fork_sync_handler =
             ProtocolGenericHandler::new(p2p, "ProtocolSyncFork", SESSION_DEFAULT).await;
 
-        Arc::new(Self { tip_handler, header_handler, sync_handler, fork_sync_handler })
+        Arc::new(Self {
+            tip_handler,
+            pruned_handler,
+            header_handler,
+            sync_handler,
+            fork_sync_handler,
+        })
     }
 
     /// Start all `ProtocolSync` background tasks.
@@ -185,6 +222,18 @@ impl ProtocolSyncHandler {
             executor.clone(),
         );
 
+        self.pruned_handler.task.clone().start(
+            handle_receive_pruned_request(self.pruned_handler.clone(), validator.clone()),
+            |res| async move {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!(target: "darkfid::proto::protocol_sync::start", "Failed starting ProtocolSyncPruned handler task: {e}"),
+                }
+            },
+            Error::DetachedTaskStopped,
+            executor.clone(),
+        );
+
         self.header_handler.task.clone().start(
             handle_receive_header_request(self.header_handler.clone(), validator.clone()),
             |res| async move {
@@ -233,6 +282,7 @@ impl ProtocolSyncHandler {
     pub async fn stop(&self) {
         debug!(target: "darkfid::proto::protocol_sync::stop", "Terminating sync protocols handlers tasks...");
         self.tip_handler.task.stop().await;
+        self.pruned_handler.task.stop().await;
         self.header_handler.task.stop().await;
         self.sync_handler.task.stop().await;
         self.fork_sync_handler.task.stop().await;
@@ -263,8 +313,9 @@ async fn handle_receive_tip_request(
         let response = if !*validator.synced.read().await {
             TipResponse { synced: false, height: None, hash: None }
         } else {
-            // Check we follow the same sequence
-            match validator.blockchain.blocks.contains(&request.tip) {
+            // Check we follow the same sequence. We check the headers,
+            // since full blocks might have been pruned.
+            match validator.blockchain.headers.contains(&request.tip) {
                 Ok(contains) => {
                     if !contains {
                         debug!(
@@ -278,7 +329,7 @@ async fn handle_receive_tip_request(
                 Err(e) => {
                     error!(
                         target: "darkfid::proto::protocol_sync::handle_receive_tip_request",
-                        "block_store.contains fail: {e}"
+                        "header_store.contains fail: {e}"
                     );
                     handler.send_action(channel, ProtocolGenericAction::Skip).await;
                     continue
@@ -306,6 +357,48 @@ async fn handle_receive_tip_request(
     }
 }
 
+/// Background handler function for ProtocolSyncPruned.
+async fn handle_receive_pruned_request(
+    handler: ProtocolGenericHandlerPtr<PrunedHeightRequest, PrunedHeightResponse>,
+    validator: ValidatorPtr,
+) -> Result<()> {
+    debug!(target: "darkfid::proto::protocol_sync::handle_receive_pruned_request", "START");
+    loop {
+        // Wait for a new pruned height request message
+        let (channel, _) = match handler.receiver.recv().await {
+            Ok(r) => r,
+            Err(e) => {
+                debug!(
+                    target: "darkfid::proto::protocol_sync::handle_receive_pruned_request",
+                    "recv fail: {e}"
+                );
+                continue
+            }
+        };
+
+        // Grab our pruned height
+        let pruned_height = match validator.blockchain.pruned_height() {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    target: "darkfid::proto::protocol_sync::handle_receive_pruned_request",
+                    "blockchain.pruned_height fail: {e}"
+                );
+                handler.send_action(channel, ProtocolGenericAction::Skip).await;
+                continue
+            }
+        };
+
+        // Send response
+        handler
+            .send_action(
+                channel,
+                ProtocolGenericAction::Response(PrunedHeightResponse { pruned_height }),
+            )
+            .await;
+    }
+}
+
 /// Background handler function for ProtocolSyncHeader.
 async fn handle_receive_header_request(
     handler: ProtocolGenericHandlerPtr<HeaderSyncRequest, HeaderSyncResponse>,
@@ -131,7 +131,8 @@ async fn handle_receive_tx(
             continue
         }
 
-        // Append transaction
+        // Append transaction. If it replaces pending transactions spending
+        // the same coins, it gets broadcasted so rest nodes replace them too.
         if let Err(e) = validator.append_tx(&tx, true).await {
             debug!(
                 target: "darkfid::proto::protocol_tx::handle_receive_tx",
@@ -64,13 +64,19 @@ impl RequestHandler for DarkfiNode {
             // ==================
             "blockchain.get_block" => self.blockchain_get_block(req.id, req.params).await,
             "blockchain.get_tx" => self.blockchain_get_tx(req.id, req.params).await,
+            "blockchain.get_tx_proof" => self.blockchain_get_tx_proof(req.id, req.params).await,
+            "blockchain.get_finality_status" => self.blockchain_get_finality_status(req.id, req.params).await,
+            "blockchain.get_headers" => self.blockchain_get_headers(req.id, req.params).await,
             "blockchain.last_known_block" => self.blockchain_last_known_block(req.id, req.params).await,
             "blockchain.best_fork_next_block_height" => self.blockchain_best_fork_next_block_height(req.id, req.params).await,
             "blockchain.block_target" => self.blockchain_block_target(req.id, req.params).await,
+            "blockchain.get_consensus_stats" => self.blockchain_get_consensus_stats(req.id, req.params).await,
             "blockchain.lookup_zkas" => self.blockchain_lookup_zkas(req.id, req.params).await,
             "blockchain.subscribe_blocks" => self.blockchain_subscribe_blocks(req.id, req.params).await,
             "blockchain.subscribe_txs" =>  self.blockchain_subscribe_txs(req.id, req.params).await,
+            "blockchain.subscribe_finalized_txs" => self.blockchain_subscribe_finalized_txs(req.id, req.params).await,
             "blockchain.subscribe_proposals" => self.blockchain_subscribe_proposals(req.id, req.params).await,
+            "blockchain.subscribe_chain_events" => self.blockchain_subscribe_chain_events(req.id, req.params).await,
             "merge_mining_get_chain_id" => self.merge_mining_get_chain_id(req.id, req.params).await,
 
             // ===================
@@ -79,9 +85,14 @@ impl RequestHandler for DarkfiNode {
             "tx.simulate" => self.tx_simulate(req.id, req.params).await,
             "tx.broadcast" => self.tx_broadcast(req.id, req.params).await,
             "tx.pending" => self.tx_pending(req.id, req.params).await,
-            "tx.clean_pending" => self.tx_pending(req.id, req.params).await,
+            "tx.clean_pending" => self.tx_clean_pending(req.id, req.params).await,
             "tx.calculate_gas" => self.tx_calculate_gas(req.id, req.params).await,
 
+            // ===============
+            // Regtest methods
+            // ===============
+            "regtest.generate" => self.regtest_generate(req.id, req.params).await,
+
             // ==============
             // Invalid method
             // ==============
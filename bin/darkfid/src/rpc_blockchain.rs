@@ -24,16 +24,25 @@ use log::{debug, error};
 use tinyjson::JsonValue;
 
 use darkfi::{
-    blockchain::contract_store::SMART_CONTRACT_ZKAS_DB_NAME,
+    blockchain::{contract_store::SMART_CONTRACT_ZKAS_DB_NAME, Header, HeaderHash},
     rpc::jsonrpc::{
         ErrorCode::{InternalError, InvalidParams, ParseError},
         JsonError, JsonResponse, JsonResult,
     },
     util::encoding::base64,
+    validator::{consensus::TxFinalityStatus, utils::best_fork_index},
+    Error,
 };
 
 use crate::{server_error, DarkfiNode, RpcError};
 
+/// Maximum number of headers returned by a single `blockchain.get_headers` request
+const MAX_HEADERS_PER_REQUEST: u32 = 1000;
+
+/// Maximum number of blocks difficulties returned by a single
+/// `blockchain.get_consensus_stats` request
+const MAX_DIFFICULTIES_PER_REQUEST: usize = 1000;
+
 impl DarkfiNode {
     // RPCAPI:
     // Queries the blockchain database for a block in the given height.
@@ -85,6 +94,8 @@ impl DarkfiNode {
     // **Returns:**
     // * Serialized [`Transaction`](https://darkrenaissance.github.io/darkfi/dev/darkfi/tx/struct.Transaction.html)
     //   object encoded with base64
+    // * A `TransactionPruned` error if the node runs in pruned mode and the
+    //   transaction block has been pruned
     //
     // --> {"jsonrpc": "2.0", "method": "blockchain.get_tx", "params": ["TxHash"], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": "ABCD...", "id": 1}
@@ -100,6 +111,15 @@ impl DarkfiNode {
             Err(_) => return JsonError::new(ParseError, None, id).into(),
         };
 
+        match self.validator.blockchain.is_tx_pruned(&tx_hash) {
+            Ok(false) => { /* Do nothing */ }
+            Ok(true) => return server_error(RpcError::TransactionPruned, id, None),
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_tx", "Failed checking tx pruning: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        }
+
         let txs = match self.validator.blockchain.transactions.get(&[tx_hash], true) {
             Ok(txs) => txs,
             Err(e) => {
@@ -116,6 +136,177 @@ impl DarkfiNode {
         JsonResponse::new(JsonValue::String(tx_enc), id).into()
     }
 
+    // RPCAPI:
+    // Generates the Merkle inclusion proof of a given transaction in its block.
+    // The proof leads to the block header `root`, so light clients can verify the
+    // transaction is included in a header they have synced.
+    //
+    // **Params:**
+    // * `array[0]`: Hex-encoded transaction hash string
+    //
+    // **Returns:**
+    // * Serialized [`TxInclusionProof`](https://darkrenaissance.github.io/darkfi/dev/darkfi/blockchain/block_store/struct.TxInclusionProof.html)
+    //   object encoded with base64
+    // * A `TransactionPruned` error if the node runs in pruned mode and the
+    //   transaction block has been pruned
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_tx_proof", "params": ["TxHash"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": "ABCD...", "id": 1}
+    pub async fn blockchain_get_tx_proof(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let tx_hash = params[0].get::<String>().unwrap();
+        let tx_hash = match TransactionHash::from_str(tx_hash) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ParseError, None, id).into(),
+        };
+
+        let proof = match self.validator.blockchain.get_tx_proof(&tx_hash) {
+            Ok(v) => v,
+            Err(Error::TransactionPruned(_)) => {
+                return server_error(RpcError::TransactionPruned, id, None)
+            }
+            Err(e) => {
+                debug!(target: "darkfid::rpc::blockchain_get_tx_proof", "Failed generating tx proof: {}", e);
+                return server_error(RpcError::UnknownTransaction, id, None)
+            }
+        };
+
+        let proof = base64::encode(&serialize_async(&proof).await);
+        JsonResponse::new(JsonValue::String(proof), id).into()
+    }
+
+    // RPCAPI:
+    // Queries the finality status of a given transaction. A transaction is either
+    // `pending`, waiting to be included in a block, `proposed`, included in a best
+    // fork proposal, or `finalized`, included in a canonical block. For proposed and
+    // finalized transactions, the block height and hash are returned, along with the
+    // confirmations, which is the number of blocks from the transaction block up to
+    // the best fork tip. Proposed transactions get finalized once their confirmations
+    // reach the `finalization_threshold`, unless the node switches to another fork.
+    //
+    // **Params:**
+    // * `array[0]`: Hex-encoded transaction hash string
+    //
+    // **Returns:**
+    // * Object with the transaction `status`, and for proposed or finalized
+    //   transactions, its block `height`, `hash` and `confirmations`
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_finality_status", "params": ["TxHash"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": {"status": "proposed", "height": 42, "hash": "BlockHash", "confirmations": 2, "finalization_threshold": 6}, "id": 1}
+    pub async fn blockchain_get_finality_status(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let tx_hash = params[0].get::<String>().unwrap();
+        let tx_hash = match TransactionHash::from_str(tx_hash) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ParseError, None, id).into(),
+        };
+
+        let status = match self.validator.consensus.tx_finality_status(&tx_hash).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_finality_status", "Failed retrieving tx finality status: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        let (status, block) = match status {
+            TxFinalityStatus::Unknown => {
+                return server_error(RpcError::UnknownTransaction, id, None)
+            }
+            TxFinalityStatus::Pending => ("pending", None),
+            TxFinalityStatus::Proposed(height, hash, confirmations) => {
+                ("proposed", Some((height, hash, confirmations)))
+            }
+            TxFinalityStatus::Finalized(height, hash, confirmations) => {
+                ("finalized", Some((height, hash, confirmations)))
+            }
+        };
+
+        let finalization_threshold = self.validator.consensus.finalization_threshold;
+        let mut ret = HashMap::from([
+            ("status".to_string(), JsonValue::String(status.to_string())),
+            (
+                "finalization_threshold".to_string(),
+                JsonValue::Number(finalization_threshold as f64),
+            ),
+        ]);
+        if let Some((height, hash, confirmations)) = block {
+            ret.insert("height".to_string(), JsonValue::Number(height as f64));
+            ret.insert("hash".to_string(), JsonValue::String(hash.to_string()));
+            ret.insert("confirmations".to_string(), JsonValue::Number(confirmations as f64));
+        }
+
+        JsonResponse::new(JsonValue::Object(ret), id).into()
+    }
+
+    // RPCAPI:
+    // Queries the blockchain database for a sequence of canonical block headers,
+    // starting from the given height. Used by light clients to sync headers only.
+    //
+    // **Params:**
+    // * `array[0]`: `u32` Starting block height (as string)
+    // * `array[1]`: `u32` Maximum number of headers to return (as string), capped to 1000
+    //
+    // **Returns:**
+    // * Serialized `Vec` of [`Header`](https://darkrenaissance.github.io/darkfi/dev/darkfi/blockchain/header_store/struct.Header.html)
+    //   objects encoded with base64
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_headers", "params": ["1", "100"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": "ABCD...", "id": 1}
+    pub async fn blockchain_get_headers(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 2 || !params[0].is_string() || !params[1].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(from) = params[0].get::<String>().unwrap().parse::<u32>() else {
+            return JsonError::new(ParseError, None, id).into()
+        };
+        let Ok(count) = params[1].get::<String>().unwrap().parse::<u32>() else {
+            return JsonError::new(ParseError, None, id).into()
+        };
+
+        let blockchain = &self.validator.blockchain;
+        let last = match blockchain.last() {
+            Ok((height, _)) => height,
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_headers", "Failed fetching last block: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+        if from > last {
+            return server_error(RpcError::UnknownBlockHeight, id, None)
+        }
+
+        let to = from.saturating_add(count.min(MAX_HEADERS_PER_REQUEST)).min(last + 1);
+        let heights: Vec<u32> = (from..to).collect();
+        let headers = match blockchain.blocks.get_order(&heights, true) {
+            Ok(hashes) => {
+                let hashes: Vec<HeaderHash> = hashes.into_iter().flatten().collect();
+                blockchain.headers.get(&hashes, true)
+            }
+            Err(e) => Err(e),
+        };
+        let headers: Vec<Header> = match headers {
+            Ok(v) => v.into_iter().flatten().collect(),
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_headers", "Failed fetching headers: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        let headers = base64::encode(&serialize_async(&headers).await);
+        JsonResponse::new(JsonValue::String(headers), id).into()
+    }
+
     // RPCAPI:
     // Queries the blockchain database to find the last known block.
     //
@@ -191,6 +382,138 @@ impl DarkfiNode {
         JsonResponse::new(JsonValue::Number(block_target as f64), id).into()
     }
 
+    // RPCAPI:
+    // Queries the consensus state, to monitor the network health. Returns the forks
+    // currently tracked, with their length, tip hash and ranks, along with the forks
+    // and proposals counters since startup. Orphaned proposals are the ones discarded
+    // because their fork got dropped, or another fork got finalized. Additionally,
+    // the difficulty history of the last canonical blocks is returned, along with
+    // the distribution of their observed intervals, in seconds.
+    //
+    // **Params:**
+    // * `array[0]`: `u32` Number of last blocks difficulties to return (as string), capped to 1000
+    //
+    // **Returns:**
+    // * Object with the consensus statistics
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_consensus_stats", "params": ["100"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": {
+    //         "finalization_threshold": 6,
+    //         "forks": [{"length": 2, "tip": "BlockHash", "tip_height": 42, "targets_rank": "123", "hashes_rank": "456", "best": true}],
+    //         "dropped_forks": 0, "rejected_proposals": 0, "orphaned_proposals": 0,
+    //         "block_target": 120,
+    //         "block_intervals": {"count": 99, "min": 30, "max": 300, "mean": 118.5, "median": 115},
+    //         "difficulties": [{"height": 1, "timestamp": 1234, "difficulty": "123"}]
+    //     }, "id": 1}
+    pub async fn blockchain_get_consensus_stats(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(count) = params[0].get::<String>().unwrap().parse::<usize>() else {
+            return JsonError::new(ParseError, None, id).into()
+        };
+
+        let consensus = &self.validator.consensus;
+
+        // Grab the tracked forks
+        let (last_height, last_hash) = match consensus.blockchain.last() {
+            Ok(last) => last,
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_consensus_stats", "Failed fetching last block: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+        let forks = consensus.forks.read().await;
+        let best = best_fork_index(&forks).ok();
+        let mut forks_info = Vec::with_capacity(forks.len());
+        for (index, fork) in forks.iter().enumerate() {
+            let tip = fork.proposals.last().unwrap_or(&last_hash);
+            forks_info.push(JsonValue::from(HashMap::from([
+                ("length".to_string(), JsonValue::Number(fork.proposals.len() as f64)),
+                ("tip".to_string(), JsonValue::String(tip.to_string())),
+                (
+                    "tip_height".to_string(),
+                    JsonValue::Number((last_height + fork.proposals.len() as u32) as f64),
+                ),
+                ("targets_rank".to_string(), JsonValue::String(fork.targets_rank.to_string())),
+                ("hashes_rank".to_string(), JsonValue::String(fork.hashes_rank.to_string())),
+                ("best".to_string(), JsonValue::Boolean(best == Some(index))),
+            ])));
+        }
+        drop(forks);
+        let metrics = consensus.forks_metrics().await;
+
+        // Grab the last blocks difficulties
+        let difficulties = match consensus
+            .blockchain
+            .blocks
+            .get_last_n_difficulties(count.min(MAX_DIFFICULTIES_PER_REQUEST))
+        {
+            Ok(d) => d,
+            Err(e) => {
+                error!(target: "darkfid::rpc::blockchain_get_consensus_stats", "Failed fetching blocks difficulties: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        // Compute their intervals distribution
+        let mut intervals: Vec<u64> = difficulties
+            .windows(2)
+            .map(|w| w[1].timestamp.inner().saturating_sub(w[0].timestamp.inner()))
+            .collect();
+        intervals.sort_unstable();
+        let mut block_intervals =
+            HashMap::from([("count".to_string(), JsonValue::Number(intervals.len() as f64))]);
+        if !intervals.is_empty() {
+            let mean = intervals.iter().sum::<u64>() as f64 / intervals.len() as f64;
+            block_intervals.extend([
+                ("min".to_string(), JsonValue::Number(intervals[0] as f64)),
+                ("max".to_string(), JsonValue::Number(*intervals.last().unwrap() as f64)),
+                ("mean".to_string(), JsonValue::Number(mean)),
+                ("median".to_string(), JsonValue::Number(intervals[intervals.len() / 2] as f64)),
+            ]);
+        }
+
+        let difficulties = difficulties
+            .iter()
+            .map(|d| {
+                JsonValue::from(HashMap::from([
+                    ("height".to_string(), JsonValue::Number(d.height as f64)),
+                    ("timestamp".to_string(), JsonValue::Number(d.timestamp.inner() as f64)),
+                    ("difficulty".to_string(), JsonValue::String(d.difficulty.to_string())),
+                ]))
+            })
+            .collect();
+
+        let block_target = consensus.module.read().await.target;
+
+        JsonResponse::new(
+            JsonValue::from(HashMap::from([
+                (
+                    "finalization_threshold".to_string(),
+                    JsonValue::Number(consensus.finalization_threshold as f64),
+                ),
+                ("forks".to_string(), JsonValue::Array(forks_info)),
+                ("dropped_forks".to_string(), JsonValue::Number(metrics.dropped_forks as f64)),
+                (
+                    "rejected_proposals".to_string(),
+                    JsonValue::Number(metrics.rejected_proposals as f64),
+                ),
+                (
+                    "orphaned_proposals".to_string(),
+                    JsonValue::Number(metrics.orphaned_proposals as f64),
+                ),
+                ("block_target".to_string(), JsonValue::Number(block_target as f64)),
+                ("block_intervals".to_string(), JsonValue::from(block_intervals)),
+                ("difficulties".to_string(), JsonValue::Array(difficulties)),
+            ])),
+            id,
+        )
+        .into()
+    }
+
     // RPCAPI:
     // Initializes a subscription to new incoming blocks.
     // Once a subscription is established, `darkfid` will send JSON-RPC notifications of
@@ -223,6 +546,26 @@ impl DarkfiNode {
         self.subscribers.get("txs").unwrap().clone().into()
     }
 
+    // RPCAPI:
+    // Initializes a subscription to finalized transactions. Once a subscription is established,
+    // `darkfid` will send JSON-RPC notifications of the transactions included in each newly
+    // finalized block, along with the block height and hash.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.subscribe_finalized_txs", "params": [], "id": 1}
+    // <-- {"jsonrpc": "2.0", "method": "blockchain.subscribe_finalized_txs", "params": [{"height": `height`, "hash": `hash`, "txs": [`tx_hash`, ...]}]}
+    pub async fn blockchain_subscribe_finalized_txs(
+        &self,
+        id: u16,
+        params: JsonValue,
+    ) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if !params.is_empty() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        self.subscribers.get("finalized_txs").unwrap().clone().into()
+    }
+
     // RPCAPI:
     // Initializes a subscription to new incoming proposals. Once a subscription is established,
     // `darkfid` will send JSON-RPC notifications of new incoming proposals to the subscriber.
@@ -238,6 +581,29 @@ impl DarkfiNode {
         self.subscribers.get("proposals").unwrap().clone().into()
     }
 
+    // RPCAPI:
+    // Initializes a subscription to best chain view changes. Once a subscription is established,
+    // `darkfid` will send JSON-RPC notifications for each proposal appended to the best fork,
+    // each previously appended proposal that got reverted because the node switched forks,
+    // and each previously appended proposal that got finalized. Reverted events are sent
+    // highest first, before the appended events of the new best fork.
+    // `event` is one of `appended`, `reverted` or `finalized`.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.subscribe_chain_events", "params": [], "id": 1}
+    // <-- {"jsonrpc": "2.0", "method": "blockchain.subscribe_chain_events", "params": [{"event": `event`, "height": `height`, "hash": `hash`}]}
+    pub async fn blockchain_subscribe_chain_events(
+        &self,
+        id: u16,
+        params: JsonValue,
+    ) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if !params.is_empty() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        self.subscribers.get("chain").unwrap().clone().into()
+    }
+
     // RPCAPI:
     // Performs a lookup of zkas bincodes for a given contract ID and returns all of
     // them, including their namespace.
@@ -311,6 +677,7 @@ impl DarkfiNode {
 
     // RPCAPI:
     // Returns the `chain_id` used for merge mining. A 32-byte hash of the genesis block.
+    // Also served on the miner JSON-RPC endpoint.
     //
     // --> {"jsonrpc": "2.0", "method": "merge_mining_get_chain_id", "params": [], "id": 0}
     // <-- {"jsonrpc": "2.0", "result": {"chain_id": 02f8...7863"}, "id": 0}
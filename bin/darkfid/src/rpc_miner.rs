@@ -0,0 +1,353 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use darkfi_sdk::{
+    hex::{decode_hex, decode_hex_arr},
+    num_traits::{One, ToPrimitive},
+};
+use darkfi_serial::serialize_async;
+use log::{debug, error, info};
+use num_bigint::BigUint;
+use smol::lock::{Mutex, MutexGuard};
+use tinyjson::JsonValue;
+
+use darkfi::{
+    blockchain::{HeaderHash, MoneroPowData, PowData},
+    rpc::{
+        jsonrpc::{
+            ErrorCode::{InvalidParams, MethodNotFound},
+            JsonError, JsonRequest, JsonResponse, JsonResult,
+        },
+        server::RequestHandler,
+    },
+    system::StoppableTaskPtr,
+    util::encoding::base64,
+    Error,
+};
+
+use super::{DarkfiNode, DarkfiNodePtr};
+use crate::{server_error, task::miner::MinerRewardsRecipientConfig, RpcError};
+
+/// JSON-RPC requests handler of the external miners methods. Since block
+/// templates generation is expensive, these methods are served on their
+/// own endpoint, which must only be reachable by trusted miners.
+pub struct DarkfiMinerRpc {
+    /// Darkfi node instance
+    node: DarkfiNodePtr,
+    /// JSON-RPC connection tracker
+    rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
+}
+
+impl DarkfiMinerRpc {
+    pub fn new(node: DarkfiNodePtr) -> Self {
+        Self { node, rpc_connections: Mutex::new(HashSet::new()) }
+    }
+}
+
+#[async_trait]
+#[rustfmt::skip]
+impl RequestHandler for DarkfiMinerRpc {
+    async fn handle_request(&self, req: JsonRequest) -> JsonResult {
+        debug!(target: "darkfid::rpc_miner", "--> {}", req.stringify().unwrap());
+
+        match req.method.as_str() {
+            "ping" => self.pong(req.id, req.params).await,
+            "miner.get_template" => self.node.miner_get_template(req.id, req.params).await,
+            "miner.submit" => self.node.miner_submit(req.id, req.params).await,
+            "merge_mining_get_chain_id" => self.node.merge_mining_get_chain_id(req.id, req.params).await,
+            "merge_mining_get_aux_block" => self.node.merge_mining_get_aux_block(req.id, req.params).await,
+            "merge_mining_submit_solution" => self.node.merge_mining_submit_solution(req.id, req.params).await,
+            _ => JsonError::new(MethodNotFound, None, req.id).into(),
+        }
+    }
+
+    async fn connections_mut(&self) -> MutexGuard<'life0, HashSet<StoppableTaskPtr>> {
+        self.rpc_connections.lock().await
+    }
+}
+
+impl DarkfiNode {
+    // RPCAPI:
+    // Generates a template for the next block of the current best fork,
+    // rewarding the given address, so mining software other than `minerd`
+    // can produce blocks. Optionally, a spend hook and a base58 encoded
+    // user data can be provided for the reward output. The template
+    // includes selected pending transactions, along with the reward one.
+    // Returns the template id, the block height, its mining `BigUint`
+    // target (String), and the base64 encoded serialized block header and
+    // full block. Miners must find a header nonce so that the header PoW
+    // hash meets the target, and submit it using `miner.submit`.
+    // Served on the miner JSON-RPC endpoint.
+    //
+    // --> {"jsonrpc": "2.0", "method": "miner.get_template", "params": ["address", "spend_hook", "user_data"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": {"template_id": "TemplateId", "height": 1, "target": "123", "header": "base64", "block": "base64"}, "id": 1}
+    pub async fn miner_get_template(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.is_empty() || params.len() > 3 || params.iter().any(|p| !p.is_string()) {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        if !*self.validator.synced.read().await {
+            error!(target: "darkfid::rpc::miner_get_template", "Blockchain is not synced");
+            return server_error(RpcError::NotSynced, id, None)
+        }
+
+        let params: Vec<&str> =
+            params.iter().map(|p| p.get::<String>().unwrap().as_str()).collect();
+        let recipient_config = match MinerRewardsRecipientConfig::parse(
+            params[0],
+            params.get(1).copied(),
+            params.get(2).copied(),
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                error!(target: "darkfid::rpc::miner_get_template", "Invalid rewards recipient: {}", e);
+                return server_error(RpcError::ParseError, id, Some(&e.to_string()))
+            }
+        };
+
+        let (template_id, target, block) = match self
+            .templates
+            .generate(self, &recipient_config)
+            .await
+        {
+            Ok(t) => t,
+            Err(e) => {
+                error!(target: "darkfid::rpc::miner_get_template", "Failed generating block template: {}", e);
+                return server_error(RpcError::BlockTemplateFail, id, None)
+            }
+        };
+
+        let header = base64::encode(&serialize_async(&block.header).await);
+        let block_height = block.header.height;
+        let block = base64::encode(&serialize_async(&block).await);
+
+        JsonResponse::new(
+            JsonValue::Object(HashMap::from([
+                ("template_id".to_string(), JsonValue::String(template_id.to_string())),
+                ("height".to_string(), JsonValue::Number(block_height as f64)),
+                ("target".to_string(), JsonValue::String(target.to_string())),
+                ("header".to_string(), JsonValue::String(header)),
+                ("block".to_string(), JsonValue::String(block)),
+            ])),
+            id,
+        )
+        .into()
+    }
+
+    // RPCAPI:
+    // Submits a solved `u64` (String) nonce for the block template of the
+    // given id, as returned by `miner.get_template`. The mined block is
+    // verified and appended as a proposal, which is then broadcasted to
+    // the network. Templates become stale once the best fork moves past
+    // their height, so miners should request new ones on chain updates.
+    // Returns the hex-encoded mined block hash.
+    // Served on the miner JSON-RPC endpoint.
+    //
+    // --> {"jsonrpc": "2.0", "method": "miner.submit", "params": ["TemplateId", "1234"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": "BlockHash", "id": 1}
+    pub async fn miner_submit(&self, id: u16, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 2 || !params[0].is_string() || !params[1].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(template_id) = HeaderHash::from_str(params[0].get::<String>().unwrap()) else {
+            return server_error(RpcError::ParseError, id, Some("Invalid template id"))
+        };
+
+        let Ok(nonce) = params[1].get::<String>().unwrap().parse::<u64>() else {
+            return server_error(RpcError::ParseError, id, Some("Invalid nonce"))
+        };
+
+        let proposal = match self.templates.submit(self, &template_id, nonce, PowData::DarkFi).await
+        {
+            Ok(p) => p,
+            Err(Error::BlockNotFound(_)) => {
+                return server_error(RpcError::UnknownBlockTemplate, id, None)
+            }
+            Err(e) => {
+                error!(target: "darkfid::rpc::miner_submit", "Failed submitting mined block: {}", e);
+                return server_error(RpcError::BlockSubmissionFail, id, Some(&e.to_string()))
+            }
+        };
+
+        info!(target: "darkfid::rpc::miner_submit", "Appended mined block {} - {}", proposal.block.header.height, proposal.hash);
+
+        JsonResponse::new(JsonValue::String(proposal.hash.to_string()), id).into()
+    }
+
+    // RPCAPI:
+    // Returns an auxiliary block for merge mining DarkFi with Monero,
+    // following the p2pool merge mining RPC specification. The auxiliary
+    // block is a template for the next block of the current best fork,
+    // rewarding the given DarkFi address. If the provided `aux_hash` is
+    // a template that still extends the best fork, it gets returned again.
+    // The `aux_hash` must be committed in the Monero coinbase transaction
+    // merge mining tag, and `aux_diff` is the DarkFi mining difficulty.
+    // Only version 2 blocks can be merge mined.
+    // Served on the miner JSON-RPC endpoint.
+    //
+    // --> {"jsonrpc": "2.0", "method": "merge_mining_get_aux_block", "params": {"address": "address", "aux_hash": "AuxHash", "height": 3000000, "prev_id": "MoneroPrevId"}, "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": {"aux_blob": "AuxHash", "aux_diff": 123, "aux_hash": "AuxHash"}, "id": 1}
+    pub async fn merge_mining_get_aux_block(&self, id: u16, params: JsonValue) -> JsonResult {
+        let Some(params) = params.get::<HashMap<String, JsonValue>>() else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+        let Some(address) = params.get("address").and_then(|a| a.get::<String>()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+        let Some(aux_hash) = params.get("aux_hash").and_then(|a| a.get::<String>()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        if !*self.validator.synced.read().await {
+            error!(target: "darkfid::rpc::merge_mining_get_aux_block", "Blockchain is not synced");
+            return server_error(RpcError::NotSynced, id, None)
+        }
+
+        let recipient_config = match MinerRewardsRecipientConfig::parse(address, None, None) {
+            Ok(c) => c,
+            Err(e) => {
+                error!(target: "darkfid::rpc::merge_mining_get_aux_block", "Invalid rewards recipient: {}", e);
+                return server_error(RpcError::ParseError, id, Some(&e.to_string()))
+            }
+        };
+
+        // Reuse the previous template if it is still valid
+        let previous = match HeaderHash::from_str(aux_hash) {
+            Ok(aux_hash) => match self.templates.get(self, &aux_hash).await {
+                Ok(t) => t.map(|(target, _)| (aux_hash, target)),
+                Err(e) => {
+                    error!(target: "darkfid::rpc::merge_mining_get_aux_block", "Failed retrieving block template: {}", e);
+                    return server_error(RpcError::BlockTemplateFail, id, None)
+                }
+            },
+            Err(_) => None,
+        };
+
+        let (template_id, target) = match previous {
+            Some(t) => t,
+            None => match self.templates.generate(self, &recipient_config).await {
+                Ok((template_id, target, _)) => (template_id, target),
+                Err(e) => {
+                    error!(target: "darkfid::rpc::merge_mining_get_aux_block", "Failed generating block template: {}", e);
+                    return server_error(RpcError::BlockTemplateFail, id, None)
+                }
+            },
+        };
+
+        // Monero difficulty is the inverse of the mining target
+        let difficulty = (BigUint::one() << 256) / target;
+        let difficulty = difficulty.to_u64().unwrap_or(u64::MAX);
+
+        JsonResponse::new(
+            JsonValue::Object(HashMap::from([
+                ("aux_blob".to_string(), JsonValue::String(template_id.to_string())),
+                ("aux_diff".to_string(), JsonValue::Number(difficulty as f64)),
+                ("aux_hash".to_string(), JsonValue::String(template_id.to_string())),
+            ])),
+            id,
+        )
+        .into()
+    }
+
+    // RPCAPI:
+    // Submits a merge mined Monero block solution for the auxiliary block
+    // of the given `aux_hash`, as returned by `merge_mining_get_aux_block`.
+    // Along with the hex encoded Monero block blob, the merge mining tag
+    // Merkle proof of the `aux_hash` must be provided, as well as the
+    // Monero RandomX seed hash. The `path` is ignored, since the `aux_hash`
+    // slot in the merge mining tree is derived from the tag nonce and our
+    // chain id. The mined block is verified and appended as a proposal,
+    // which is then broadcasted to the network.
+    // Served on the miner JSON-RPC endpoint.
+    //
+    // --> {"jsonrpc": "2.0", "method": "merge_mining_submit_solution", "params": {"aux_blob": "AuxHash", "aux_hash": "AuxHash", "blob": "MoneroBlob", "merkle_proof": ["hash1", "hash2"], "path": 0, "seed_hash": "SeedHash"}, "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": {"status": "accepted", "block_hash": "BlockHash"}, "id": 1}
+    pub async fn merge_mining_submit_solution(&self, id: u16, params: JsonValue) -> JsonResult {
+        let Some(params) = params.get::<HashMap<String, JsonValue>>() else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+        let (Some(aux_hash), Some(blob), Some(merkle_proof), Some(seed_hash)) = (
+            params.get("aux_hash").and_then(|p| p.get::<String>()),
+            params.get("blob").and_then(|p| p.get::<String>()),
+            params.get("merkle_proof").and_then(|p| p.get::<Vec<JsonValue>>()),
+            params.get("seed_hash").and_then(|p| p.get::<String>()),
+        ) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let Ok(template_id) = HeaderHash::from_str(aux_hash) else {
+            return server_error(RpcError::ParseError, id, Some("Invalid aux hash"))
+        };
+
+        let Ok(blob) = decode_hex(blob).collect::<Result<Vec<u8>, _>>() else {
+            return server_error(RpcError::ParseError, id, Some("Invalid Monero block blob"))
+        };
+
+        let mut aux_branch = Vec::with_capacity(merkle_proof.len());
+        for hash in merkle_proof {
+            let Some(Ok(hash)) = hash.get::<String>().map(|h| decode_hex_arr(h)) else {
+                return server_error(RpcError::ParseError, id, Some("Invalid merkle proof"))
+            };
+            aux_branch.push(hash);
+        }
+
+        let Ok(seed_hash) = decode_hex_arr(seed_hash) else {
+            return server_error(RpcError::ParseError, id, Some("Invalid seed hash"))
+        };
+
+        let pow_data = match MoneroPowData::new(&blob, seed_hash, aux_branch) {
+            Ok(p) => p,
+            Err(e) => return server_error(RpcError::ParseError, id, Some(&e.to_string())),
+        };
+
+        let proposal = match self
+            .templates
+            .submit(self, &template_id, 0, PowData::Monero(pow_data))
+            .await
+        {
+            Ok(p) => p,
+            Err(Error::BlockNotFound(_)) => {
+                return server_error(RpcError::UnknownBlockTemplate, id, None)
+            }
+            Err(e) => {
+                error!(target: "darkfid::rpc::merge_mining_submit_solution", "Failed submitting merge mined block: {}", e);
+                return server_error(RpcError::BlockSubmissionFail, id, Some(&e.to_string()))
+            }
+        };
+
+        info!(target: "darkfid::rpc::merge_mining_submit_solution", "Appended merge mined block {} - {}", proposal.block.header.height, proposal.hash);
+
+        JsonResponse::new(
+            JsonValue::Object(HashMap::from([
+                ("status".to_string(), JsonValue::String("accepted".to_string())),
+                ("block_hash".to_string(), JsonValue::String(proposal.hash.to_string())),
+            ])),
+            id,
+        )
+        .into()
+    }
+}
@@ -0,0 +1,107 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::str::FromStr;
+
+use log::error;
+use tinyjson::JsonValue;
+
+use darkfi::{
+    rpc::jsonrpc::{
+        ErrorCode::{InvalidParams, MethodNotFound},
+        JsonError, JsonResponse, JsonResult,
+    },
+    util::time::Timestamp,
+};
+use darkfi_sdk::crypto::PublicKey;
+
+use super::DarkfiNode;
+use crate::{
+    server_error,
+    task::miner::{MinerRewardsRecipientConfig, MAX_REGTEST_BLOCKS},
+    RpcError,
+};
+
+impl DarkfiNode {
+    // RPCAPI:
+    // Generates, mines and appends the given number of blocks on top of the
+    // canonical blockchain, rewarding the given address. Only available when
+    // the node runs in regtest mode. Up to `MAX_REGTEST_BLOCKS` blocks can be
+    // generated per request. Pending transactions are included in the
+    // generated blocks. An optional `u64` (String) timestamp can be provided
+    // for the first block, with each next one being a block target later,
+    // otherwise current time is used.
+    // Returns a vector of hex-encoded generated block hashes.
+    //
+    // --> {"jsonrpc": "2.0", "method": "regtest.generate", "params": [1, "address", "1234"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": ["BlockHash",...], "id": 1}
+    pub async fn regtest_generate(&self, id: u16, params: JsonValue) -> JsonResult {
+        let Some(regtest) = &self.regtest else {
+            return JsonError::new(MethodNotFound, None, id).into()
+        };
+
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() < 2 ||
+            params.len() > 3 ||
+            !params[0].is_number() ||
+            !params[1].is_string() ||
+            (params.len() == 3 && !params[2].is_string())
+        {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let n = *params[0].get::<f64>().unwrap();
+        if n < 1.0 || n > MAX_REGTEST_BLOCKS as f64 || n.fract() != 0.0 {
+            let msg = format!("Blocks number must be between 1 and {}", MAX_REGTEST_BLOCKS);
+            return JsonError::new(InvalidParams, Some(msg), id).into()
+        }
+        let n = n as usize;
+
+        let recipient = match PublicKey::from_str(params[1].get::<String>().unwrap()) {
+            Ok(address) => address,
+            Err(_) => {
+                error!(target: "darkfid::rpc::regtest_generate", "Invalid reward address");
+                return server_error(RpcError::ParseError, id, Some("Invalid reward address"))
+            }
+        };
+        let recipient_config =
+            MinerRewardsRecipientConfig { recipient, spend_hook: None, user_data: None };
+
+        let timestamp = if params.len() == 3 {
+            match params[2].get::<String>().unwrap().parse::<u64>() {
+                Ok(t) => Some(Timestamp::from(t)),
+                Err(_) => return server_error(RpcError::ParseError, id, Some("Invalid timestamp")),
+            }
+        } else {
+            None
+        };
+
+        let hashes = match regtest.generate(self, n, &recipient_config, timestamp).await {
+            Ok(h) => h,
+            Err(e) => {
+                error!(target: "darkfid::rpc::regtest_generate", "Failed generating blocks: {}", e);
+                return server_error(RpcError::BlockGenerationFail, id, Some(&e.to_string()))
+            }
+        };
+
+        let hashes: Vec<JsonValue> =
+            hashes.iter().map(|x| JsonValue::String(x.to_string())).collect();
+
+        JsonResponse::new(JsonValue::Array(hashes), id).into()
+    }
+}
@@ -0,0 +1,404 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::Path;
+
+use darkfi_sdk::{crypto::MerkleTree, pasta::group::ff::PrimeField};
+use log::{error, info};
+use sled_overlay::sled;
+use smol::{
+    fs::File,
+    io::{AsyncWriteExt, BufReader, BufWriter},
+};
+
+use darkfi::{
+    blockchain::{
+        block_store::{append_tx_to_merkle_tree, BlockDifficulty},
+        state_root_from_buckets, BlockInfo, Blockchain, Header, HeaderHash, BLOCK_VERSION_2,
+        SLED_BLOCK_DIFFICULTY_TREE, SLED_STATE_BUCKETS_TREE, SLED_STATE_SMT_TREE,
+    },
+    validator::{pow::PoWModule, ValidatorConfig},
+    Error, Result,
+};
+use darkfi_serial::{
+    deserialize, serialize, AsyncDecodable, AsyncEncodable, SerialDecodable, SerialEncodable,
+};
+
+/// Snapshot file magic bytes
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"DRKS";
+/// Current snapshot file format version
+pub const SNAPSHOT_VERSION: u8 = 1;
+/// Number of headers or tree records retrieved or applied at once
+const SNAPSHOT_BATCH: usize = 1000;
+/// Database key marking a snapshot import in progress, holding the
+/// snapshot checkpoint hash
+pub const SNAPSHOT_IMPORT_MARKER: &[u8] = b"_snapshot_import";
+
+/// Snapshot file header, preceding the serialized headers sequence,
+/// the checkpoint block and the state trees records.
+#[derive(Debug, SerialEncodable, SerialDecodable)]
+pub struct SnapshotHeader {
+    /// File magic bytes
+    pub magic: [u8; 4],
+    /// File format version
+    pub version: u8,
+    /// Genesis block hash of the snapshot blockchain
+    pub genesis: HeaderHash,
+    /// Checkpoint block height the snapshot was taken at
+    pub height: u32,
+    /// Checkpoint block hash the snapshot was taken at
+    pub hash: HeaderHash,
+    /// State commitment at the checkpoint block
+    pub state: blake3::Hash,
+    /// Number of state trees in the snapshot
+    pub trees: u32,
+}
+
+impl SnapshotHeader {
+    /// Read and validate a snapshot header from the start of provided file.
+    async fn read(reader: &mut BufReader<File>) -> Result<Self> {
+        let header = Self::decode_async(reader).await?;
+        if header.magic != SNAPSHOT_MAGIC {
+            return Err(Error::ParseFailed("Not a snapshot file"))
+        }
+        if header.version != SNAPSHOT_VERSION {
+            return Err(Error::ParseFailed("Unsupported snapshot file version"))
+        }
+        Ok(header)
+    }
+}
+
+/// Export a snapshot of the blockchain state at our last block into a
+/// snapshot file at given path. The snapshot contains all canonical
+/// headers after genesis, the last block and all the state trees
+/// records. Returns the snapshot checkpoint height, hash and state
+/// commitment, which must be published so other nodes can trust it.
+pub async fn export_snapshot(
+    blockchain: &Blockchain,
+    path: &Path,
+) -> Result<(u32, HeaderHash, blake3::Hash)> {
+    let (height, hash) = blockchain.last()?;
+    let state = blockchain.state_checksum()?;
+    let trees = blockchain.state_trees()?;
+    info!(target: "darkfid::snapshot::export_snapshot", "Exporting snapshot at block {} - {} to: {:?}", height, hash, path);
+
+    let mut writer = BufWriter::new(File::create(path).await?);
+    let header = SnapshotHeader {
+        magic: SNAPSHOT_MAGIC,
+        version: SNAPSHOT_VERSION,
+        genesis: blockchain.genesis()?.1,
+        height,
+        hash,
+        state,
+        trees: trees.len() as u32,
+    };
+    header.encode_async(&mut writer).await?;
+
+    // Stream the headers in batches, so we don't load them all in memory
+    let mut start = 1;
+    while start <= height {
+        let end = start.saturating_add(SNAPSHOT_BATCH as u32 - 1).min(height);
+        let heights: Vec<u32> = (start..=end).collect();
+        let hashes: Vec<HeaderHash> =
+            blockchain.blocks.get_order(&heights, true)?.into_iter().flatten().collect();
+        for header in blockchain.headers.get(&hashes, true)?.into_iter().flatten() {
+            header.encode_async(&mut writer).await?;
+        }
+        start = end + 1;
+    }
+    info!(target: "darkfid::snapshot::export_snapshot", "Headers exported: {}", height);
+
+    // Export the checkpoint block
+    blockchain.last_block()?.encode_async(&mut writer).await?;
+
+    // Export the state trees, in the same order and form they
+    // are hashed in the state commitment.
+    for name in &trees {
+        let tree = blockchain.sled_db.open_tree(name)?;
+        name.encode_async(&mut writer).await?;
+        (tree.len() as u64).encode_async(&mut writer).await?;
+        for record in tree.iter() {
+            let (key, value) = record?;
+            (key.to_vec(), value.to_vec()).encode_async(&mut writer).await?;
+        }
+    }
+    info!(target: "darkfid::snapshot::export_snapshot", "State trees exported: {}", trees.len());
+    writer.flush().await?;
+
+    Ok((height, hash, state))
+}
+
+/// Generate a PoW module at provided blockchain genesis block, using the
+/// configured PoW parameters, over a temporary headers-only blockchain.
+fn genesis_pow_module(blockchain: &Blockchain, config: &ValidatorConfig) -> Result<PoWModule> {
+    let genesis_block = blockchain.genesis_block()?;
+    let db = sled::Config::new().temporary(true).open()?;
+    let genesis = Blockchain::new(&db)?;
+    genesis.add_block(&genesis_block)?;
+    genesis
+        .blocks
+        .insert_difficulty(&[BlockDifficulty::genesis(genesis_block.header.timestamp)])?;
+    PoWModule::new(
+        genesis,
+        config.pow_target,
+        config.pow_fixed_difficulty.clone(),
+        config.pow_algorithm,
+    )
+}
+
+/// Verify the snapshot file at given path against provided checkpoint
+/// and state commitment. The headers sequence must form a hash chain
+/// from our genesis block to the checkpoint, with each header having a
+/// valid PoW for its expected mining target and not conflicting with our
+/// configured checkpoints, the checkpoint block must
+/// match its header and the state trees records must hash to the
+/// provided state commitment, containing the checkpoint cummulative
+/// difficulty we computed.
+pub async fn verify_snapshot(
+    blockchain: &Blockchain,
+    path: &Path,
+    checkpoint: &(u32, HeaderHash, blake3::Hash),
+    config: &ValidatorConfig,
+) -> Result<()> {
+    info!(target: "darkfid::snapshot::verify_snapshot", "Verifying snapshot file...");
+    let mut reader = BufReader::new(File::open(path).await?);
+    let header = SnapshotHeader::read(&mut reader).await?;
+
+    // Verify the headers sequence and their PoW
+    let mut module = genesis_pow_module(blockchain, config)?;
+    let mut previous = blockchain.genesis()?.1;
+    for height in 1..=header.height {
+        let block_header = Header::decode_async(&mut reader).await?;
+        let block_hash = block_header.hash();
+        if block_header.height != height || block_header.previous != previous {
+            error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file headers sequence is broken at: {}", height);
+            return Err(Error::BlockIsInvalid(block_hash.as_string()))
+        }
+        if config.checkpoints.iter().any(|c| c.0 == height && c.1 != block_hash) {
+            error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file header {} conflicts with our checkpoints", height);
+            return Err(Error::BlockIsInvalid(block_hash.as_string()))
+        }
+        if let Err(e) = module.verify_current_header(&block_header) {
+            error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file header {} PoW is invalid: {}", height, e);
+            return Err(Error::BlockIsInvalid(block_hash.as_string()))
+        }
+        module.append(block_header.timestamp, &module.next_difficulty()?);
+        previous = block_hash;
+    }
+    if previous != checkpoint.1 {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file headers don't lead to the checkpoint");
+        return Err(Error::BlockIsInvalid(previous.as_string()))
+    }
+
+    // Verify the checkpoint block transactions match its header
+    let block = BlockInfo::decode_async(&mut reader).await?;
+    if block.hash() != checkpoint.1 {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file block doesn't match the checkpoint");
+        return Err(Error::BlockIsInvalid(block.hash().as_string()))
+    }
+    let mut tree = MerkleTree::new(1);
+    for tx in &block.txs {
+        append_tx_to_merkle_tree(&mut tree, tx);
+    }
+    if tree.root(0).unwrap() != block.header.root {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file block Merkle tree root is invalid");
+        return Err(Error::BlockIsInvalid(block.hash().as_string()))
+    }
+
+    // Verify the state trees against the state commitment, the contracts
+    // state SMT against its buckets and the checkpoint block state root,
+    // and the checkpoint block difficulty against the one we computed.
+    let mut hasher = blake3::Hasher::new();
+    let mut buckets = vec![];
+    let mut smt_root = None;
+    let difficulty_key = header.height.to_be_bytes();
+    let mut difficulty_verified = false;
+    for _ in 0..header.trees {
+        let name = Vec::<u8>::decode_async(&mut reader).await?;
+        let records = u64::decode_async(&mut reader).await?;
+        hasher.update(&serialize(&name));
+        hasher.update(&serialize(&records));
+        for _ in 0..records {
+            let record = <(Vec<u8>, Vec<u8>)>::decode_async(&mut reader).await?;
+            hasher.update(&serialize(&record));
+            if name == SLED_STATE_SMT_TREE && record.0 == [0] {
+                smt_root = Some(record.1.clone());
+            }
+            if name == SLED_STATE_BUCKETS_TREE {
+                buckets.push(record.clone());
+            }
+            if name == SLED_BLOCK_DIFFICULTY_TREE && record.0 == difficulty_key {
+                let difficulty: BlockDifficulty = deserialize(&record.1)?;
+                difficulty_verified =
+                    difficulty.cummulative_difficulty == module.cummulative_difficulty;
+            }
+        }
+    }
+    let state = hasher.finalize();
+    if state != checkpoint.2 {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file state doesn't match the state commitment: {}", state);
+        return Err(Error::BlockIsInvalid(checkpoint.1.as_string()))
+    }
+    let state_root = state_root_from_buckets(&buckets)?;
+    let smt_verified = match smt_root {
+        Some(smt_root) => smt_root == state_root.to_repr(),
+        None => buckets.is_empty(),
+    };
+    if !smt_verified {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file contracts state SMT doesn't match its buckets");
+        return Err(Error::BlockIsInvalid(checkpoint.1.as_string()))
+    }
+    if block.header.version >= BLOCK_VERSION_2 && state_root != block.header.state_root {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file contracts state doesn't match the checkpoint block state root");
+        return Err(Error::BlockIsInvalid(checkpoint.1.as_string()))
+    }
+    if header.height > 0 && !difficulty_verified {
+        error!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file checkpoint block difficulty doesn't match the headers one");
+        return Err(Error::BlockIsInvalid(checkpoint.1.as_string()))
+    }
+
+    info!(target: "darkfid::snapshot::verify_snapshot", "Snapshot file verified!");
+    Ok(())
+}
+
+/// Import the snapshot file at given path into a fresh blockchain, only
+/// containing the genesis block. The snapshot is fully verified against
+/// provided trusted checkpoint height, hash and state commitment before
+/// anything gets written. Afterwards, the node continues validating
+/// blocks after the checkpoint, while blocks before it are marked as
+/// pruned, so we never serve them to syncing peers.
+///
+/// The write is marked in progress until it completes, so an interrupted
+/// import is detected by [`snapshot_import_interrupted`] and can be retried
+/// with the same snapshot, overwriting the partially written records.
+///
+/// Snapshot files are retrieved out of band, i.e. from a trusted source
+/// publishing them along with their checkpoint. Serving and downloading
+/// snapshots over the P2P network is not supported.
+pub async fn import_snapshot(
+    blockchain: &Blockchain,
+    path: &Path,
+    checkpoint: (u32, HeaderHash, blake3::Hash),
+    config: &ValidatorConfig,
+) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path).await?);
+    let header = SnapshotHeader::read(&mut reader).await?;
+
+    // Check the file follows our network and the checkpoint
+    if header.genesis != blockchain.genesis()?.1 {
+        error!(target: "darkfid::snapshot::import_snapshot", "Snapshot file genesis block doesn't match ours");
+        return Err(Error::BlockIsInvalid(header.genesis.as_string()))
+    }
+    if header.height != checkpoint.0 || header.hash != checkpoint.1 || header.state != checkpoint.2
+    {
+        error!(target: "darkfid::snapshot::import_snapshot", "Snapshot file doesn't match the checkpoint");
+        return Err(Error::BlockIsInvalid(header.hash.as_string()))
+    }
+
+    // Snapshots can only be applied over a fresh blockchain,
+    // or retried over an interrupted import of the same snapshot.
+    match blockchain.sled_db.get(SNAPSHOT_IMPORT_MARKER)? {
+        Some(marker) => {
+            if marker != header.hash.inner() {
+                error!(target: "darkfid::snapshot::import_snapshot", "Database contains an interrupted import of another snapshot");
+                return Err(Error::BlockAlreadyExists(blockchain.last()?.1.as_string()))
+            }
+            info!(target: "darkfid::snapshot::import_snapshot", "Retrying interrupted snapshot import");
+        }
+        None => {
+            if blockchain.last()?.0 != 0 {
+                error!(target: "darkfid::snapshot::import_snapshot", "Blockchain already contains blocks after genesis");
+                return Err(Error::BlockAlreadyExists(blockchain.last()?.1.as_string()))
+            }
+        }
+    }
+
+    verify_snapshot(blockchain, path, &checkpoint, config).await?;
+    info!(target: "darkfid::snapshot::import_snapshot", "Importing snapshot at block {} - {} from: {:?}", header.height, header.hash, path);
+
+    // Mark the import in progress, before writing anything
+    blockchain.sled_db.insert(SNAPSHOT_IMPORT_MARKER, header.hash.inner())?;
+    blockchain.sled_db.flush()?;
+
+    // Insert the headers and their order, in batches
+    let mut headers = Vec::with_capacity(SNAPSHOT_BATCH);
+    for height in 1..=header.height {
+        headers.push(Header::decode_async(&mut reader).await?);
+        if headers.len() == SNAPSHOT_BATCH || height == header.height {
+            let heights: Vec<u32> = headers.iter().map(|h| h.height).collect();
+            let (headers_batch, hashes) = blockchain.headers.insert_batch(&headers);
+            blockchain.headers.main.apply_batch(headers_batch)?;
+            blockchain
+                .blocks
+                .order
+                .apply_batch(blockchain.blocks.insert_batch_order(&heights, &hashes))?;
+            headers.clear();
+        }
+    }
+    info!(target: "darkfid::snapshot::import_snapshot", "Headers imported: {}", header.height);
+
+    // Read the checkpoint block, which gets inserted after the state
+    let block = BlockInfo::decode_async(&mut reader).await?;
+
+    // Replace our genesis state trees with the snapshot ones
+    for name in blockchain.state_trees()? {
+        blockchain.sled_db.open_tree(&name)?.clear()?;
+    }
+    for _ in 0..header.trees {
+        let name = Vec::<u8>::decode_async(&mut reader).await?;
+        let tree = blockchain.sled_db.open_tree(&name)?;
+        tree.clear()?;
+        let records = u64::decode_async(&mut reader).await?;
+        let mut batch = sled::Batch::default();
+        for index in 1..=records {
+            let (key, value) = <(Vec<u8>, Vec<u8>)>::decode_async(&mut reader).await?;
+            batch.insert(key, value);
+            if index % SNAPSHOT_BATCH as u64 == 0 {
+                tree.apply_batch(batch)?;
+                batch = sled::Batch::default();
+            }
+        }
+        tree.apply_batch(batch)?;
+    }
+    info!(target: "darkfid::snapshot::import_snapshot", "State trees imported: {}", header.trees);
+
+    // Insert the checkpoint block and mark everything before it as pruned
+    blockchain.add_block(&block)?;
+    blockchain
+        .blocks
+        .pruned
+        .apply_batch(blockchain.blocks.insert_batch_pruned_height(header.height))?;
+
+    // Sanity check the written state
+    let state = blockchain.state_checksum()?;
+    if state != checkpoint.2 {
+        error!(target: "darkfid::snapshot::import_snapshot", "Imported state doesn't match the state commitment: {}", state);
+        return Err(Error::BlockIsInvalid(header.hash.as_string()))
+    }
+
+    // Import completed
+    blockchain.sled_db.remove(SNAPSHOT_IMPORT_MARKER)?;
+
+    Ok(())
+}
+
+/// Check if provided blockchain contains an interrupted snapshot import,
+/// in which case it is inconsistent until the import gets retried.
+pub fn snapshot_import_interrupted(blockchain: &Blockchain) -> Result<bool> {
+    Ok(blockchain.sled_db.contains_key(SNAPSHOT_IMPORT_MARKER)?)
+}
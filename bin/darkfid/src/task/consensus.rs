@@ -25,10 +25,6 @@ use darkfi::{
     util::{encoding::base64, time::Timestamp},
     Error, Result,
 };
-use darkfi_sdk::{
-    crypto::{FuncId, PublicKey},
-    pasta::{group::ff::PrimeField, pallas},
-};
 use darkfi_serial::serialize_async;
 use log::{error, info};
 
@@ -83,6 +79,13 @@ pub async fn consensus_init_task(
             None
         };
 
+        // Sync until the highest one of the configured and the validator checkpoints
+        let checkpoint = match (checkpoint, node.validator.consensus.last_checkpoint()) {
+            (Some(c), Some(l)) if l.0 > c.0 => Some(l),
+            (None, l) => l,
+            (c, _) => c,
+        };
+
         sync_task(&node, checkpoint).await?;
         checkpoint
     } else {
@@ -93,38 +96,14 @@ pub async fn consensus_init_task(
     // Grab rewards recipient public key(address) if node is a miner,
     // along with configured spend hook and user data.
     let recipient_config = if config.miner {
-        if config.recipient.is_none() {
+        let Some(recipient) = &config.recipient else {
             return Err(Error::ParseFailed("Recipient address missing"))
-        }
-        let recipient = match PublicKey::from_str(config.recipient.as_ref().unwrap()) {
-            Ok(address) => address,
-            Err(_) => return Err(Error::InvalidAddress),
-        };
-
-        let spend_hook = match &config.spend_hook {
-            Some(s) => match FuncId::from_str(s) {
-                Ok(s) => Some(s),
-                Err(_) => return Err(Error::ParseFailed("Invalid spend hook")),
-            },
-            None => None,
         };
-
-        let user_data = match &config.user_data {
-            Some(u) => {
-                let bytes: [u8; 32] = match bs58::decode(&u).into_vec()?.try_into() {
-                    Ok(b) => b,
-                    Err(_) => return Err(Error::ParseFailed("Invalid user data")),
-                };
-
-                match pallas::Base::from_repr(bytes).into() {
-                    Some(v) => Some(v),
-                    None => return Err(Error::ParseFailed("Invalid user data")),
-                }
-            }
-            None => None,
-        };
-
-        Some(MinerRewardsRecipientConfig { recipient, spend_hook, user_data })
+        Some(MinerRewardsRecipientConfig::parse(
+            recipient,
+            config.spend_hook.as_deref(),
+            config.user_data.as_deref(),
+        )?)
     } else {
         None
     };
@@ -26,6 +26,15 @@ use crate::DarkfiNodePtr;
 pub async fn garbage_collect_task(node: DarkfiNodePtr) -> Result<()> {
     info!(target: "darkfid::task::garbage_collect_task", "Starting garbage collection task...");
 
+    // Remove expired transactions first, so we don't verify them again
+    if let Err(e) = node.validator.expire_pending_txs().await {
+        error!(
+            target: "darkfid::task::garbage_collect_task",
+            "Expired transactions removal failed: {e}"
+        );
+        return Ok(())
+    }
+
     // Grab all current unproposed transactions.  We verify them in batches,
     // to not load them all in memory.
     let (mut last_checked, mut txs) =
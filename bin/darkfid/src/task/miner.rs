@@ -16,15 +16,19 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{collections::HashMap, str::FromStr, time::Instant};
+
 use darkfi::{
-    blockchain::{BlockInfo, Header},
+    blockchain::{BlockInfo, Header, HeaderHash, PowData, BLOCK_VERSION_2},
     rpc::{jsonrpc::JsonNotification, util::JsonValue},
     system::{ExecutorPtr, StoppableTask, Subscription},
     tx::{ContractCallLeaf, Transaction, TransactionBuilder},
     util::{encoding::base64, time::Timestamp},
     validator::{
         consensus::{Fork, Proposal},
-        utils::best_fork_index,
+        utils::{best_fork_index, block_version},
+        verification::apply_block_transactions,
+        ValidatorPtr,
     },
     zk::{empty_witnesses, ProvingKey, ZkCircuit},
     zkas::ZkBinary,
@@ -35,16 +39,19 @@ use darkfi_money_contract::{
 };
 use darkfi_sdk::{
     crypto::{poseidon_hash, FuncId, PublicKey, SecretKey, MONEY_CONTRACT_ID},
-    pasta::pallas,
+    pasta::{group::ff::PrimeField, pallas},
     ContractCall,
 };
 use darkfi_serial::{serialize_async, Encodable};
 use log::{error, info};
 use num_bigint::BigUint;
 use rand::rngs::OsRng;
-use smol::channel::{Receiver, Sender};
+use smol::{
+    channel::{Receiver, Sender},
+    lock::Mutex,
+};
 
-use crate::{proto::ProposalMessage, task::garbage_collect_task, DarkfiNodePtr};
+use crate::{proto::ProposalMessage, task::garbage_collect_task, DarkfiNode, DarkfiNodePtr};
 
 /// Auxiliary structure representing node miner rewards recipient configuration
 pub struct MinerRewardsRecipientConfig {
@@ -53,6 +60,56 @@ pub struct MinerRewardsRecipientConfig {
     pub user_data: Option<pallas::Base>,
 }
 
+impl MinerRewardsRecipientConfig {
+    /// Parse a rewards recipient configuration from its string representations:
+    /// the recipient address, and optional spend hook and base58 encoded user data.
+    pub fn parse(
+        recipient: &str,
+        spend_hook: Option<&str>,
+        user_data: Option<&str>,
+    ) -> Result<Self> {
+        let recipient = match PublicKey::from_str(recipient) {
+            Ok(address) => address,
+            Err(_) => return Err(Error::InvalidAddress),
+        };
+
+        let spend_hook = match spend_hook {
+            Some(s) => match FuncId::from_str(s) {
+                Ok(s) => Some(s),
+                Err(_) => return Err(Error::ParseFailed("Invalid spend hook")),
+            },
+            None => None,
+        };
+
+        let user_data = match user_data {
+            Some(u) => {
+                let bytes: [u8; 32] = match bs58::decode(&u).into_vec()?.try_into() {
+                    Ok(b) => b,
+                    Err(_) => return Err(Error::ParseFailed("Invalid user data")),
+                };
+
+                match pallas::Base::from_repr(bytes).into() {
+                    Some(v) => Some(v),
+                    None => return Err(Error::ParseFailed("Invalid user data")),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Self { recipient, spend_hook, user_data })
+    }
+
+    /// Compute the blake3 hash of the configuration, identifying the rewards recipient.
+    pub fn hash(&self) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        // Blake3 hasher .update() method never fails.
+        self.recipient.encode(&mut hasher).expect("blake3 hasher");
+        self.spend_hook.encode(&mut hasher).expect("blake3 hasher");
+        self.user_data.encode(&mut hasher).expect("blake3 hasher");
+        hasher.finalize()
+    }
+}
+
 /// Async task used for participating in the PoW block production.
 ///
 /// Miner initializes their setup and waits for next finalization,
@@ -320,6 +377,8 @@ async fn mine_next_block(
         pk,
         node.validator.consensus.module.read().await.target,
         node.validator.verify_fees,
+        node.validator.consensus.block_v2_height,
+        extended_fork.module.clock_offset.adjusted_time(),
     )
     .await?;
 
@@ -352,7 +411,9 @@ async fn mine_next_block(
     Ok(())
 }
 
-/// Auxiliary function to generate next block in an atomic manner.
+/// Auxiliary function to generate next block in an atomic manner,
+/// using provided timestamp for its header.
+#[allow(clippy::too_many_arguments)]
 async fn generate_next_block(
     extended_fork: &Fork,
     secret: &mut SecretKey,
@@ -361,6 +422,8 @@ async fn generate_next_block(
     pk: &ProvingKey,
     block_target: u32,
     verify_fees: bool,
+    block_v2_height: Option<u32>,
+    timestamp: Timestamp,
 ) -> Result<(BigUint, BlockInfo)> {
     // Grab forks' last block proposal(previous)
     let last_proposal = extended_fork.last_proposal()?;
@@ -385,7 +448,8 @@ async fn generate_next_block(
     txs.push(tx);
 
     // Generate the new header
-    let header = Header::new(last_proposal.hash, next_block_height, Timestamp::current_time(), 0);
+    let mut header = Header::new(last_proposal.hash, next_block_height, timestamp, 0);
+    header.version = block_version(next_block_height, block_v2_height);
 
     // Generate the block
     let mut next_block = BlockInfo::new_empty(header);
@@ -393,6 +457,13 @@ async fn generate_next_block(
     // Add transactions to the block
     next_block.append_txs(txs);
 
+    // Commit to the contracts state after applying the block transactions
+    if next_block.header.version >= BLOCK_VERSION_2 {
+        let overlay =
+            apply_block_transactions(&extended_fork.overlay, &next_block, block_target).await?;
+        next_block.header.state_root = overlay.lock().unwrap().contracts.get_state_root()?;
+    }
+
     // Grab the next mine target
     let target = extended_fork.module.next_mine_target()?;
 
@@ -433,3 +504,302 @@ fn generate_transaction(
 
     Ok(tx)
 }
+
+/// Maximum number of blocks a single regtest generation request can produce.
+pub const MAX_REGTEST_BLOCKS: usize = 1000;
+
+/// Regtest mode block producer. Instead of participating in consensus,
+/// blocks are generated on demand, extending the canonical blockchain
+/// directly, so applications can get deterministic and instant chains.
+pub struct RegtestMiner {
+    /// Money::PoWReward zkas bin
+    zkbin: ZkBinary,
+    /// Money::PoWReward proving key
+    pk: ProvingKey,
+    /// Blocks signing secret key, also used to serialize generation requests
+    secret: Mutex<SecretKey>,
+}
+
+impl RegtestMiner {
+    /// Generate a new regtest miner, building the PoWReward proving key
+    /// from the zkas bin stored in provided node blockchain.
+    pub fn new(validator: &ValidatorPtr) -> Result<Self> {
+        info!(target: "darkfid::task::miner::RegtestMiner", "Generating zkas bin and proving keys...");
+        let (zkbin, _) = validator.blockchain.contracts.get_zkas(
+            &validator.blockchain.sled_db,
+            &MONEY_CONTRACT_ID,
+            MONEY_CONTRACT_ZKAS_MINT_NS_V1,
+        )?;
+        let circuit = ZkCircuit::new(empty_witnesses(&zkbin)?, &zkbin);
+        let pk = ProvingKey::build(zkbin.k, &circuit);
+        let secret = Mutex::new(SecretKey::random(&mut OsRng));
+
+        Ok(Self { zkbin, pk, secret })
+    }
+
+    /// Generate, mine and append `n` blocks on top of the canonical blockchain,
+    /// rewarding provided recipient. Blocks include current pending transactions.
+    /// If a timestamp is provided, it is used for the first block and each next
+    /// one is `pow_target` seconds after its previous, otherwise current time is
+    /// used. Timestamps must still pass the PoW module timestamp checks.
+    /// Returns the generated blocks hashes.
+    pub async fn generate(
+        &self,
+        node: &DarkfiNode,
+        n: usize,
+        recipient_config: &MinerRewardsRecipientConfig,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Vec<HeaderHash>> {
+        // Grab the secret key lock, so generation requests don't interleave
+        let mut secret = self.secret.lock().await;
+
+        let block_sub = node.subscribers.get("blocks").unwrap();
+        let block_target = node.validator.consensus.module.read().await.target;
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let mut hashes = Vec::with_capacity(n);
+        for i in 0..n {
+            // Reset forks so we extend the canonical blockchain
+            node.validator.consensus.purge_forks().await?;
+            let extended_fork = node.validator.consensus.forks.read().await[0].full_clone()?;
+
+            // Generate next block
+            let timestamp = match timestamp {
+                Some(t) => t.checked_add((i as u64 * block_target as u64).into())?,
+                None => extended_fork.module.clock_offset.adjusted_time(),
+            };
+            let (_, mut next_block) = generate_next_block(
+                &extended_fork,
+                &mut secret,
+                recipient_config,
+                &self.zkbin,
+                &self.pk,
+                block_target,
+                node.validator.verify_fees,
+                node.validator.consensus.block_v2_height,
+                timestamp,
+            )
+            .await?;
+
+            // Mine it using the fork PoW module
+            let module = extended_fork.module.clone();
+            let mut next_block = smol::unblock(move || {
+                let (_, stop_signal) = smol::channel::bounded(1);
+                module.mine_block(&mut next_block, threads, &stop_signal)?;
+                Ok::<BlockInfo, Error>(next_block)
+            })
+            .await?;
+
+            // Sign the mined block and append it to canonical
+            next_block.sign(&secret);
+            node.validator.add_blocks(&[next_block.clone()]).await?;
+
+            // Notify subscribers
+            let notif_block = base64::encode(&serialize_async(&next_block).await);
+            block_sub.notify(JsonValue::Array(vec![JsonValue::String(notif_block)])).await;
+
+            let hash = next_block.hash();
+            info!(target: "darkfid::task::miner::RegtestMiner", "Generated block {} - {}", next_block.header.height, hash);
+            hashes.push(hash);
+        }
+
+        // Reset forks over the new canonical tip
+        node.validator.consensus.purge_forks().await?;
+
+        Ok(hashes)
+    }
+}
+
+/// Default maximum number of block templates kept at any time.
+pub const MAX_BLOCK_TEMPLATES: usize = 64;
+
+/// Auxiliary structure representing an issued block template.
+#[derive(Clone)]
+struct BlockTemplate {
+    /// Unmined next block
+    block: BlockInfo,
+    /// Secret key to sign the mined block with
+    secret: SecretKey,
+    /// Block mining target
+    target: BigUint,
+    /// Hash of the rewards recipient configuration
+    recipient: blake3::Hash,
+    /// Template creation instant
+    created: Instant,
+}
+
+/// Next block templates served to external mining software. Each template
+/// is a fully built next block of the current best fork, rewarding the
+/// requested recipient, so miners only have to find its header nonce.
+/// A template is reused for the same recipient until the best fork tip
+/// changes, and templates are kept until the best fork moves past their
+/// height, up to a maximum count, after which the oldest get evicted.
+pub struct BlockTemplates {
+    /// Money::PoWReward zkas bin and proving key, built on first use
+    keys: Mutex<Option<(ZkBinary, ProvingKey)>>,
+    /// Issued templates, keyed by template id
+    templates: Mutex<HashMap<HeaderHash, BlockTemplate>>,
+    /// Maximum number of templates to keep
+    max_templates: usize,
+}
+
+impl Default for BlockTemplates {
+    fn default() -> Self {
+        Self::new(MAX_BLOCK_TEMPLATES)
+    }
+}
+
+impl BlockTemplates {
+    /// Initialize the templates store, keeping up to provided templates count.
+    pub fn new(max_templates: usize) -> Self {
+        Self { keys: Mutex::new(None), templates: Mutex::new(HashMap::new()), max_templates }
+    }
+
+    /// Generate a template for the next block of the current best fork,
+    /// rewarding provided recipient. If we already issued one for the same
+    /// recipient over the current best fork tip, it gets reused. Returns the
+    /// template id, which is the unmined block hash, along with the block
+    /// mining target and the block.
+    pub async fn generate(
+        &self,
+        node: &DarkfiNode,
+        recipient_config: &MinerRewardsRecipientConfig,
+    ) -> Result<(HeaderHash, BigUint, BlockInfo)> {
+        // Grab the keys lock, so templates are generated sequentially
+        let mut keys = self.keys.lock().await;
+
+        // Grab best fork to extend
+        let forks = node.validator.consensus.forks.read().await;
+        let index = best_fork_index(&forks)?;
+        let tip = forks[index].last_proposal()?.hash;
+        let extended_fork = forks[index].full_clone()?;
+        drop(forks);
+
+        // Check if we already have a template for this recipient and tip
+        let recipient = recipient_config.hash();
+        for (id, template) in self.templates.lock().await.iter() {
+            if template.recipient == recipient && template.block.header.previous == tip {
+                return Ok((*id, template.target.clone(), template.block.clone()))
+            }
+        }
+
+        // Build the PoWReward proving key if its our first template
+        if keys.is_none() {
+            info!(target: "darkfid::task::miner::BlockTemplates", "Generating zkas bin and proving keys...");
+            let (zkbin, _) = node.validator.blockchain.contracts.get_zkas(
+                &node.validator.blockchain.sled_db,
+                &MONEY_CONTRACT_ID,
+                MONEY_CONTRACT_ZKAS_MINT_NS_V1,
+            )?;
+            let circuit = ZkCircuit::new(empty_witnesses(&zkbin)?, &zkbin);
+            let k = zkbin.k;
+            let pk = smol::unblock(move || ProvingKey::build(k, &circuit)).await;
+            *keys = Some((zkbin, pk));
+        }
+        let (zkbin, pk) = keys.as_ref().unwrap();
+
+        // Generate the next block using a fresh secret key, since templates
+        // for the same height must not share their signing keys.
+        let mut secret = SecretKey::random(&mut OsRng);
+        let (target, block) = generate_next_block(
+            &extended_fork,
+            &mut secret,
+            recipient_config,
+            zkbin,
+            pk,
+            node.validator.consensus.module.read().await.target,
+            node.validator.verify_fees,
+            node.validator.consensus.block_v2_height,
+            extended_fork.module.clock_offset.adjusted_time(),
+        )
+        .await?;
+        drop(keys);
+
+        // Drop stale templates, evict the oldest ones if we are
+        // at capacity, and store the new one
+        let id = block.hash();
+        let mut templates = self.templates.lock().await;
+        templates.retain(|_, t| t.block.header.height >= block.header.height);
+        while templates.len() >= self.max_templates {
+            let Some(oldest) = templates
+                .iter()
+                .min_by_key(|(_, t)| (t.block.header.height, t.created))
+                .map(|(id, _)| *id)
+            else {
+                break
+            };
+            templates.remove(&oldest);
+        }
+        templates.insert(
+            id,
+            BlockTemplate {
+                block: block.clone(),
+                secret,
+                target: target.clone(),
+                recipient,
+                created: Instant::now(),
+            },
+        );
+
+        Ok((id, target, block))
+    }
+
+    /// Retrieve the template of provided id, along with its mining target,
+    /// if it still extends the current best fork.
+    pub async fn get(
+        &self,
+        node: &DarkfiNode,
+        id: &HeaderHash,
+    ) -> Result<Option<(BigUint, BlockInfo)>> {
+        let Some(BlockTemplate { block, target, .. }) =
+            self.templates.lock().await.get(id).cloned()
+        else {
+            return Ok(None)
+        };
+
+        let forks = node.validator.consensus.forks.read().await;
+        let index = best_fork_index(&forks)?;
+        if forks[index].last_proposal()?.hash != block.header.previous {
+            return Ok(None)
+        }
+
+        Ok(Some((target, block)))
+    }
+
+    /// Submit a solved nonce, along with its PoW data, for the template of
+    /// provided id. The mined block is signed and appended as a proposal,
+    /// which gets broadcasted to the network and notified to proposals
+    /// subscribers.
+    pub async fn submit(
+        &self,
+        node: &DarkfiNode,
+        id: &HeaderHash,
+        nonce: u64,
+        pow_data: PowData,
+    ) -> Result<Proposal> {
+        let Some(BlockTemplate { mut block, secret, .. }) =
+            self.templates.lock().await.get(id).cloned()
+        else {
+            return Err(Error::BlockNotFound(id.as_string()))
+        };
+
+        // Sign the mined block and append it as a proposal
+        block.header.nonce = nonce;
+        block.header.pow_data = pow_data;
+        block.sign(&secret);
+        let proposal = Proposal::new(block);
+        node.validator.append_proposal(&proposal).await?;
+
+        // Template got solved, so we don't need it anymore
+        self.templates.lock().await.remove(id);
+
+        // Broadcast proposal to the network
+        let message = ProposalMessage(proposal.clone());
+        node.p2p_handler.p2p.broadcast(&message).await;
+
+        // Notify proposals subscriber
+        let enc_prop = JsonValue::String(base64::encode(&serialize_async(&proposal).await));
+        node.subscribers.get("proposals").unwrap().notify(vec![enc_prop].into()).await;
+
+        Ok(proposal)
+    }
+}
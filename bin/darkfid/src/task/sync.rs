@@ -23,14 +23,15 @@ use darkfi::{
     util::encoding::base64, validator::consensus::Proposal, Error, Result,
 };
 use darkfi_serial::serialize_async;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use rand::{prelude::SliceRandom, rngs::OsRng};
 use tinyjson::JsonValue;
 
 use crate::{
     proto::{
-        ForkSyncRequest, ForkSyncResponse, HeaderSyncRequest, HeaderSyncResponse, SyncRequest,
-        SyncResponse, TipRequest, TipResponse, BATCH,
+        ForkSyncRequest, ForkSyncResponse, HeaderSyncRequest, HeaderSyncResponse,
+        PrunedHeightRequest, PrunedHeightResponse, SyncRequest, SyncResponse, TipRequest,
+        TipResponse, BATCH, PRUNED_HEIGHT_FEATURE, PRUNED_HEIGHT_FEATURE_VERSION,
     },
     DarkfiNodePtr,
 };
@@ -70,7 +71,7 @@ pub async fn sync_task(node: &DarkfiNodePtr, checkpoint: Option<(u32, HeaderHash
     info!(target: "darkfid::task::sync_task", "Last known block: {} - {}", last.0, last.1);
 
     // Grab the most common tip and the corresponding peers
-    let (mut common_tip_height, mut common_tip_peers) =
+    let (mut common_tip_height, mut common_tip_peers, mut pruned_peers) =
         most_common_tip(node, &last.1, checkpoint).await;
 
     // If last known block header is before the checkpoint, we sync until that first.
@@ -82,11 +83,13 @@ pub async fn sync_task(node: &DarkfiNodePtr, checkpoint: Option<(u32, HeaderHash
             retrieve_headers(node, &common_tip_peers, last.0, checkpoint.0 + 1).await?;
 
             // Retrieve all the blocks for those headers and apply them to canonical
-            last = retrieve_blocks(node, &common_tip_peers, last, block_sub, true).await?;
+            last = retrieve_blocks(node, &common_tip_peers, &pruned_peers, last, block_sub, true)
+                .await?;
             info!(target: "darkfid::task::sync_task", "Last received block: {} - {}", last.0, last.1);
 
             // Grab synced peers most common tip again
-            (common_tip_height, common_tip_peers) = most_common_tip(node, &last.1, None).await;
+            (common_tip_height, common_tip_peers, pruned_peers) =
+                most_common_tip(node, &last.1, None).await;
         }
     }
 
@@ -98,7 +101,7 @@ pub async fn sync_task(node: &DarkfiNodePtr, checkpoint: Option<(u32, HeaderHash
 
         // Retrieve all the blocks for those headers and apply them to canonical
         let last_received =
-            retrieve_blocks(node, &common_tip_peers, last, block_sub, false).await?;
+            retrieve_blocks(node, &common_tip_peers, &pruned_peers, last, block_sub, false).await?;
         info!(target: "darkfid::task::sync_task", "Last received block: {} - {}", last_received.0, last_received.1);
 
         if last == last_received {
@@ -108,7 +111,8 @@ pub async fn sync_task(node: &DarkfiNodePtr, checkpoint: Option<(u32, HeaderHash
         last = last_received;
 
         // Grab synced peers most common tip again
-        (common_tip_height, common_tip_peers) = most_common_tip(node, &last.1, None).await;
+        (common_tip_height, common_tip_peers, pruned_peers) =
+            most_common_tip(node, &last.1, None).await;
     }
 
     // Sync best fork
@@ -131,15 +135,17 @@ pub async fn sync_task(node: &DarkfiNodePtr, checkpoint: Option<(u32, HeaderHash
 }
 
 /// Auxiliary function to block until node is connected to at least one synced peer,
-/// and retrieve the synced peers tips.
+/// and retrieve the synced peers tips, along with the pruned heights of the peers
+/// running in pruned mode, keyed by their channel id.
 async fn synced_peers(
     node: &DarkfiNodePtr,
     last_tip: &HeaderHash,
     checkpoint: Option<(u32, HeaderHash)>,
-) -> HashMap<(u32, [u8; 32]), Vec<ChannelPtr>> {
+) -> (HashMap<(u32, [u8; 32]), Vec<ChannelPtr>>, HashMap<u32, u32>) {
     info!(target: "darkfid::task::sync::synced_peers", "Receiving tip from peers...");
     let comms_timeout = node.p2p_handler.p2p.settings().read().await.outbound_connect_timeout;
     let mut tips = HashMap::new();
+    let mut pruned = HashMap::new();
     loop {
         // Grab channels
         let peers = node.p2p_handler.p2p.hosts().channels();
@@ -195,6 +201,9 @@ async fn synced_peers(
 
             // Handle response
             if response.synced && response.height.is_some() && response.hash.is_some() {
+                if let Some(pruned_height) = peer_pruned_height(&peer, comms_timeout).await {
+                    pruned.insert(peer.info.id, pruned_height);
+                }
                 let tip = (response.height.unwrap(), *response.hash.unwrap().inner());
                 let Some(tip_peers) = tips.get_mut(&tip) else {
                     tips.insert(tip, vec![peer.clone()]);
@@ -218,17 +227,61 @@ async fn synced_peers(
         sleep(comms_timeout).await;
     }
 
-    tips
+    (tips, pruned)
+}
+
+/// Auxiliary function to ask a synced peer for the height before which it has pruned
+/// full blocks. Peers not advertising the `PRUNED_HEIGHT_FEATURE`, or not responding,
+/// are considered as keeping all blocks.
+async fn peer_pruned_height(peer: &ChannelPtr, comms_timeout: u64) -> Option<u32> {
+    // Check the peer supports the request, so we don't get disconnected
+    let Some(version) = peer.version.lock().await.clone() else { return None };
+    if !version.features.iter().any(|(service, version)| {
+        service == PRUNED_HEIGHT_FEATURE && *version >= PRUNED_HEIGHT_FEATURE_VERSION
+    }) {
+        debug!(target: "darkfid::task::sync::peer_pruned_height", "Peer {peer:?} doesn't support `PrunedHeightRequest`");
+        return None
+    }
+
+    // Communication setup
+    let Ok(response_sub) = peer.subscribe_msg::<PrunedHeightResponse>().await else {
+        debug!(target: "darkfid::task::sync::peer_pruned_height", "Failure during `PrunedHeightResponse` communication setup with peer: {peer:?}");
+        return None
+    };
+
+    // Node sends a `PrunedHeightRequest`
+    if let Err(e) = peer.send(&PrunedHeightRequest).await {
+        debug!(target: "darkfid::task::sync::peer_pruned_height", "Failure during `PrunedHeightRequest` send to peer {peer:?}: {e}");
+        return None
+    };
+
+    // Node waits for response
+    let Ok(response) = response_sub.receive_with_timeout(comms_timeout).await else {
+        debug!(target: "darkfid::task::sync::peer_pruned_height", "Timeout while waiting for `PrunedHeightResponse` from peer: {peer:?}");
+        return None
+    };
+
+    response.pruned_height
+}
+
+/// Auxiliary function to check if a peer keeps the full block of provided height,
+/// based on the pruned heights of the peers running in pruned mode.
+pub fn peer_keeps_block(pruned_peers: &HashMap<u32, u32>, peer_id: u32, height: u32) -> bool {
+    match pruned_peers.get(&peer_id) {
+        Some(pruned_height) => height >= *pruned_height,
+        None => true,
+    }
 }
 
 /// Auxiliary function to ask all peers for their current tip and find the most common one.
+/// The pruned heights of the peers running in pruned mode are also returned.
 async fn most_common_tip(
     node: &DarkfiNodePtr,
     last_tip: &HeaderHash,
     checkpoint: Option<(u32, HeaderHash)>,
-) -> (u32, Vec<ChannelPtr>) {
+) -> (u32, Vec<ChannelPtr>, HashMap<u32, u32>) {
     // Grab synced peers tips
-    let tips = synced_peers(node, last_tip, checkpoint).await;
+    let (tips, pruned) = synced_peers(node, last_tip, checkpoint).await;
 
     // Grab the most common highest tip peers
     info!(target: "darkfid::task::sync::most_common_tip", "Finding most common tip...");
@@ -248,7 +301,7 @@ async fn most_common_tip(
     }
 
     info!(target: "darkfid::task::sync::most_common_tip", "Most common tip: {} - {}", common_tip.0, HeaderHash::new(common_tip.1));
-    (common_tip.0, common_tip.2)
+    (common_tip.0, common_tip.2, pruned)
 }
 
 /// Auxiliary function to retrieve headers backwards until our last known one and verify them.
@@ -365,14 +418,29 @@ async fn retrieve_headers(
         info!(target: "darkfid::task::sync::retrieve_headers", "Headers verified: {}/{}", verified_headers, total);
     }
 
+    // Verify headers follow our checkpoints
+    for (height, hash) in node.validator.consensus.checkpoints.range(last_known.0 + 1..) {
+        let header = node.validator.blockchain.headers.get_after_sync(height - 1, 1)?;
+        if header.is_empty() || header[0].height != *height {
+            break
+        }
+        if header[0].hash() != *hash {
+            error!(target: "darkfid::task::sync::retrieve_headers", "Header {} doesn't match checkpoint: {}", height, hash);
+            node.validator.blockchain.headers.remove_all_sync()?;
+            return Err(Error::BlockIsInvalid(header[0].hash().as_string()))
+        }
+    }
+
     info!(target: "darkfid::task::sync::retrieve_headers", "Headers sequence verified!");
     Ok(())
 }
 
 /// Auxiliary function to retrieve blocks of provided headers and apply them to canonical.
+/// Peers running in pruned mode are only asked for blocks they still keep.
 async fn retrieve_blocks(
     node: &DarkfiNodePtr,
     peers: &[ChannelPtr],
+    pruned_peers: &HashMap<u32, u32>,
     last_known: (u32, HeaderHash),
     block_sub: &JsonSubscriber,
     checkpoint_blocks: bool,
@@ -395,6 +463,7 @@ async fn retrieve_blocks(
     let mut received_blocks = 0;
     let total = node.validator.blockchain.headers.len_sync();
     'blocks_loop: loop {
+        let mut requested = false;
         'peers_loop: for (index, peer) in peers.iter().enumerate() {
             // Grab the response sub reference
             let Some(ref response_sub) = peer_subs[index] else {
@@ -406,6 +475,14 @@ async fn retrieve_blocks(
             if headers.is_empty() {
                 break 'blocks_loop
             }
+
+            // Skip pruned peers that no longer keep these blocks
+            if !peer_keeps_block(pruned_peers, peer.info.id, headers[0].height) {
+                debug!(target: "darkfid::task::sync::retrieve_blocks", "Peer {peer:?} has pruned block {}, skipping...", headers[0].height);
+                continue
+            }
+            requested = true;
+
             let mut headers_hashes = Vec::with_capacity(headers.len());
             let mut synced_headers = Vec::with_capacity(headers.len());
             for header in &headers {
@@ -478,6 +555,16 @@ async fn retrieve_blocks(
 
             info!(target: "darkfid::task::sync::retrieve_blocks", "Blocks received: {}/{}", received_blocks, total);
         }
+
+        // Check if any peer could serve us the missing blocks
+        if !requested {
+            let next = node.validator.blockchain.headers.get_after_sync(0, 1)?;
+            if next.is_empty() {
+                break
+            }
+            warn!(target: "darkfid::task::sync::retrieve_blocks", "No peer keeps block {}, all are pruned", next[0].height);
+            return Err(Error::BlockNotFound(next[0].hash().as_string()))
+        }
     }
 
     Ok(last_received)
@@ -0,0 +1,138 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{
+    blockchain::{HeaderHash, PowData},
+    validator::utils::best_fork_index,
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::{
+    crypto::{PublicKey, SecretKey},
+    num_traits::One,
+};
+use num_bigint::BigUint;
+use rand::rngs::OsRng;
+use smol::Executor;
+
+use crate::{
+    task::miner::{BlockTemplates, MinerRewardsRecipientConfig},
+    tests::{Harness, HarnessConfig},
+};
+
+fn recipient_config() -> MinerRewardsRecipientConfig {
+    MinerRewardsRecipientConfig {
+        recipient: PublicKey::from_secret(SecretKey::random(&mut OsRng)),
+        spend_hook: None,
+        user_data: None,
+    }
+}
+
+async fn block_templates_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:18940".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:18941".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+    let node = &th.alice;
+    let genesis = node.validator.blockchain.last()?.1;
+
+    // Keep up to two templates
+    let templates = BlockTemplates::new(2);
+    let alice = recipient_config();
+    let bob = recipient_config();
+    let charlie = recipient_config();
+
+    // Templates are reused for the same recipient and best fork tip
+    let (alice_id, alice_target, alice_block) = templates.generate(node, &alice).await?;
+    assert_eq!(alice_block.header.previous, genesis);
+    assert_eq!(alice_block.hash(), alice_id);
+    let (id, target, _) = templates.generate(node, &alice).await?;
+    assert_eq!((id, target), (alice_id, alice_target.clone()));
+    let (bob_id, _, _) = templates.generate(node, &bob).await?;
+    assert_ne!(alice_id, bob_id);
+
+    // Issued templates can be retrieved while they extend the best fork
+    let (target, block) = templates.get(node, &alice_id).await?.unwrap();
+    assert_eq!((target, block.hash()), (alice_target, alice_id));
+    assert!(templates.get(node, &HeaderHash::new([0u8; 32])).await?.is_none());
+
+    // The oldest template gets evicted once we are at capacity
+    let (charlie_id, _, _) = templates.generate(node, &charlie).await?;
+    assert!(templates.get(node, &alice_id).await?.is_none());
+    assert!(templates.get(node, &bob_id).await?.is_some());
+
+    // Unknown templates can't be submitted
+    assert!(matches!(
+        templates.submit(node, &alice_id, 0, PowData::DarkFi).await,
+        Err(Error::BlockNotFound(_))
+    ));
+
+    // Mine Bob's template using the best fork PoW module and submit it
+    let (_, mut block) = templates.get(node, &bob_id).await?.unwrap();
+    let forks = node.validator.consensus.forks.read().await;
+    let module = forks[best_fork_index(&forks)?].module.clone();
+    drop(forks);
+    let (_, stop_signal) = smol::channel::bounded(1);
+    module.mine_block(&mut block, 1, &stop_signal)?;
+    let proposal =
+        templates.submit(node, &bob_id, block.header.nonce, block.header.pow_data).await?;
+    assert_eq!(proposal.block.header.previous, genesis);
+
+    // Proposal must now be the best fork tip
+    let forks = node.validator.consensus.forks.read().await;
+    assert_eq!(forks[best_fork_index(&forks)?].last_proposal()?.hash, proposal.hash);
+    drop(forks);
+
+    // Submitted templates are removed, while the rest no longer extend the best fork
+    assert!(templates.get(node, &bob_id).await?.is_none());
+    assert!(templates.get(node, &charlie_id).await?.is_none());
+
+    // New templates extend the new best fork tip
+    let (id, _, block) = templates.generate(node, &charlie).await?;
+    assert_ne!(id, charlie_id);
+    assert_eq!(block.header.previous, proposal.hash);
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn block_templates() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                block_templates_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
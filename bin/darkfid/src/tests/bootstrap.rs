@@ -0,0 +1,136 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{blockchain::HeaderHash, validator::ValidatorPtr, Error, Result};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::{
+    bootstrap::{export_blocks, import_blocks},
+    integrity::check_database,
+    tests::{Harness, HarnessConfig},
+};
+
+async fn bootstrap_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19140".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19141".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a sequence of blocks, so some of them get finalized
+    let mut block = th.alice.validator.blockchain.last_block()?;
+    for _ in 0..6 {
+        block = th.generate_next_block(&block).await?;
+        th.add_blocks(&vec![block.clone()]).await?;
+    }
+    let alice = &th.alice.validator;
+    let last = alice.blockchain.last()?;
+    assert!(last.0 > 1);
+
+    // Export all Alice blocks, including genesis
+    let path = std::env::temp_dir().join(format!("darkfid_bootstrap_{}", std::process::id()));
+    assert_eq!(export_blocks(&alice.blockchain, &path, 0, None).await?, last.0 + 1);
+
+    // Generate a fresh validator to import them
+    let new_validator = || th.generate_validator(&th.validator_config);
+
+    // Import them through full verification, skipping the genesis block we already have
+    let charlie: ValidatorPtr = new_validator().await?;
+    assert_eq!(import_blocks(&charlie, &path, None).await?, last.0);
+    assert_eq!(charlie.blockchain.last()?, last);
+    assert_eq!(charlie.blockchain.state_checksum()?, alice.blockchain.state_checksum()?);
+    assert!(check_database(&charlie.blockchain, false)?.is_empty());
+
+    // Importing them again is a no-op
+    assert_eq!(import_blocks(&charlie, &path, None).await?, 0);
+
+    // Import them trusting a checkpoint in the middle of the sequence
+    let dave = new_validator().await?;
+    let checkpoint = (1, alice.blockchain.blocks.get_order(&[1], true)?[0].unwrap());
+    assert_eq!(import_blocks(&dave, &path, Some(checkpoint)).await?, last.0);
+    assert_eq!(dave.blockchain.last()?, last);
+    assert!(check_database(&dave.blockchain, false)?.is_empty());
+
+    // Files must not contradict the checkpoint
+    let eve = new_validator().await?;
+    let checkpoint = (1, last.1);
+    assert!(import_blocks(&eve, &path, Some(checkpoint)).await.is_err());
+    assert_eq!(eve.blockchain.last()?.0, 0);
+
+    // Files must not conflict with our configured checkpoints,
+    // neither can the provided checkpoint.
+    let mut config = th.validator_config.clone();
+    config.checkpoints.push((2, HeaderHash::new([0u8; 32])));
+    let frank = th.generate_validator(&config).await?;
+    assert!(import_blocks(&frank, &path, None).await.is_err());
+    assert_eq!(frank.blockchain.last()?.0, 0);
+    let checkpoint = (1, alice.blockchain.blocks.get_order(&[1], true)?[0].unwrap());
+    assert!(import_blocks(&frank, &path, Some(checkpoint)).await.is_err());
+    assert_eq!(frank.blockchain.last()?.0, 0);
+    assert!(import_blocks(&frank, &path, Some((2, last.1))).await.is_err());
+    assert_eq!(frank.blockchain.last()?.0, 0);
+
+    // Truncated files are rejected, without applying the partial batch
+    let bytes = std::fs::read(&path)?;
+    std::fs::write(&path, &bytes[..bytes.len() - 10])?;
+    assert!(import_blocks(&eve, &path, None).await.is_err());
+    assert_eq!(eve.blockchain.last()?.0, 0);
+
+    // Files with a bad magic header are rejected
+    let mut bad_magic = bytes.clone();
+    bad_magic[..4].copy_from_slice(b"DRKX");
+    std::fs::write(&path, &bad_magic)?;
+    assert!(matches!(
+        import_blocks(&eve, &path, None).await,
+        Err(Error::ParseFailed("Not a bootstrap file"))
+    ));
+    assert_eq!(eve.blockchain.last()?.0, 0);
+
+    std::fs::remove_file(&path)?;
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn bootstrap() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                bootstrap_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{
+    blockchain::HeaderHash,
+    validator::{consensus::Proposal, ValidatorConfig},
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::tests::{Harness, HarnessConfig};
+
+async fn checkpoints_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:18840".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:18841".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate the checkpointed sequence
+    let genesis = th.alice.validator.blockchain.last_block()?;
+    let block1 = th.generate_next_block(&genesis).await?;
+    let block2 = th.generate_next_block(&block1).await?;
+
+    // A validator with a genesis checkpoint not matching its genesis block must fail
+    let config = ValidatorConfig {
+        checkpoints: vec![(0, HeaderHash::new([0u8; 32]))],
+        ..th.validator_config.clone()
+    };
+    assert!(matches!(th.generate_validator(&config).await, Err(Error::BlockIsInvalid(_))));
+
+    // Generate a validator enforcing the genesis and second block checkpoints
+    let config = ValidatorConfig {
+        checkpoints: vec![(0, genesis.hash()), (2, block2.hash())],
+        ..th.validator_config.clone()
+    };
+    let validator = th.generate_validator(&config).await?;
+    validator.consensus.generate_empty_fork().await?;
+
+    // Proposals at heights without a checkpoint are accepted
+    // until our canonical blockchain passes the last checkpoint
+    let fork_block1 = th.generate_next_block(&genesis).await?;
+    validator.append_proposal(&Proposal::new(fork_block1.clone())).await?;
+
+    // Proposals conflicting with a checkpoint are rejected
+    let fork_block2 = th.generate_next_block(&fork_block1).await?;
+    assert!(matches!(
+        validator.append_proposal(&Proposal::new(fork_block2)).await,
+        Err(Error::ProposalConflictsCheckpoint)
+    ));
+
+    // Extend the checkpointed sequence until finalization passes the last checkpoint
+    validator.append_proposal(&Proposal::new(block1)).await?;
+    validator.append_proposal(&Proposal::new(block2.clone())).await?;
+    let mut last = block2;
+    while validator.blockchain.last()?.0 < 2 {
+        last = th.generate_next_block(&last).await?;
+        validator.append_proposal(&Proposal::new(last.clone())).await?;
+        validator.finalization().await?;
+    }
+
+    // Proposals forking off below the last checkpoint are now rejected
+    let fork_block1 = th.generate_next_block(&genesis).await?;
+    assert!(matches!(
+        validator.append_proposal(&Proposal::new(fork_block1)).await,
+        Err(Error::ProposalConflictsCheckpoint)
+    ));
+    assert_eq!(validator.consensus.forks_metrics().await.rejected_proposals, 2);
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn checkpoints() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                checkpoints_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{
+    blockchain::HeaderHash,
+    validator::{consensus::Proposal, ValidatorConfig, ValidatorPtr},
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::tests::{Harness, HarnessConfig};
+
+/// Auxiliary function to open a new sled tree in the overlay of the fork
+/// ending with provided proposal, like contracts deployments do.
+async fn open_fork_tree(validator: &ValidatorPtr, tip: &HeaderHash, tree: &[u8]) -> Result<()> {
+    let forks = validator.consensus.forks.read().await;
+    let fork = forks.iter().find(|fork| fork.proposals.last() == Some(tip)).unwrap();
+    fork.overlay.lock().unwrap().overlay.lock().unwrap().open_tree(tree, false)?;
+    Ok(())
+}
+
+/// Auxiliary function to check if provided proposal is held by any fork.
+async fn proposal_exists(validator: &ValidatorPtr, proposal: &HeaderHash) -> bool {
+    validator.consensus.forks.read().await.iter().any(|fork| fork.proposals.contains(proposal))
+}
+
+/// Auxiliary function to check if provided tree exists in the database.
+fn tree_exists(validator: &ValidatorPtr, tree: &[u8]) -> bool {
+    validator.blockchain.sled_db.tree_names().iter().any(|name| name.as_ref() == tree)
+}
+
+async fn fork_limits_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19640".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19641".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a validator tracking up to two forks, with their tips up to two
+    // blocks behind the best fork tip, which never finalizes anything.
+    let config = ValidatorConfig {
+        finalization_threshold: 10,
+        max_forks: 2,
+        max_fork_depth: 2,
+        ..th.validator_config.clone()
+    };
+    let validator = th.generate_validator(&config).await?;
+    validator.consensus.generate_empty_fork().await?;
+
+    // Generate two forks with a single block
+    let genesis = validator.blockchain.last_block()?;
+    let a1 = th.generate_next_block(&genesis).await?;
+    let b1 = th.generate_next_block(&genesis).await?;
+    validator.append_proposal(&Proposal::new(a1.clone())).await?;
+    validator.append_proposal(&Proposal::new(b1.clone())).await?;
+    assert_eq!(validator.consensus.forks.read().await.len(), 2);
+
+    // Second fork writes a new tree
+    let b_tree = b"_test_fork_tree_b";
+    open_fork_tree(&validator, &b1.hash(), b_tree).await?;
+    assert!(tree_exists(&validator, b_tree));
+
+    // Extend the first fork until the second one falls too deep behind it
+    let a2 = th.generate_next_block(&a1).await?;
+    validator.append_proposal(&Proposal::new(a2.clone())).await?;
+    assert_eq!(validator.consensus.forks.read().await.len(), 2);
+    let a3 = th.generate_next_block(&a2).await?;
+    validator.append_proposal(&Proposal::new(a3.clone())).await?;
+
+    // Second fork must have been dropped along with its new tree
+    let forks = validator.consensus.forks.read().await;
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0].proposals, vec![a1.hash(), a2.hash(), a3.hash()]);
+    drop(forks);
+    assert!(!proposal_exists(&validator, &b1.hash()).await);
+    assert!(!tree_exists(&validator, b_tree));
+    let metrics = validator.consensus.forks_metrics().await;
+    assert_eq!((metrics.dropped_forks, metrics.orphaned_proposals), (1, 1));
+    assert_eq!(metrics.rejected_proposals, 0);
+
+    // Proposals forking off too deep behind the best fork tip are rejected
+    let c1 = th.generate_next_block(&genesis).await?;
+    assert!(matches!(
+        validator.append_proposal(&Proposal::new(c1.clone())).await,
+        Err(Error::ProposalForkTooDeep)
+    ));
+    assert!(!proposal_exists(&validator, &c1.hash()).await);
+    assert_eq!(validator.consensus.forks_metrics().await.rejected_proposals, 1);
+
+    // Generate a second fork, along with an empty one writing a new tree
+    let d3 = th.generate_next_block(&a2).await?;
+    validator.append_proposal(&Proposal::new(d3.clone())).await?;
+    validator.consensus.generate_empty_fork().await?;
+    assert_eq!(validator.consensus.forks.read().await.len(), 3);
+    let empty_tree = b"_test_fork_tree_empty";
+    let forks = validator.consensus.forks.read().await;
+    let empty_fork = forks.iter().find(|fork| fork.proposals.is_empty()).unwrap();
+    empty_fork.overlay.lock().unwrap().overlay.lock().unwrap().open_tree(empty_tree, false)?;
+    drop(forks);
+    assert!(tree_exists(&validator, empty_tree));
+
+    // Empty forks fall too deep behind the best fork tip as well, so
+    // extending it must drop the empty fork along with its new tree.
+    let a4 = th.generate_next_block(&a3).await?;
+    validator.append_proposal(&Proposal::new(a4.clone())).await?;
+    let forks = validator.consensus.forks.read().await;
+    assert_eq!(forks.len(), 2);
+    assert!(forks.iter().all(|fork| !fork.proposals.is_empty()));
+    drop(forks);
+    assert!(proposal_exists(&validator, &d3.hash()).await);
+    assert!(!tree_exists(&validator, empty_tree));
+    let metrics = validator.consensus.forks_metrics().await;
+    assert_eq!((metrics.dropped_forks, metrics.orphaned_proposals), (2, 1));
+
+    // A third fork, as long as the second one, exceeds the maximum number of
+    // forks, so the lowest ranking between the two must get dropped.
+    let f3 = th.generate_next_block(&a2).await?;
+    let result = validator.append_proposal(&Proposal::new(f3.clone())).await;
+    let forks = validator.consensus.forks.read().await;
+    assert_eq!(forks.len(), 2);
+    assert!(forks.iter().any(|fork| fork.proposals.last() == Some(&a4.hash())));
+    drop(forks);
+    let metrics = validator.consensus.forks_metrics().await;
+    assert_eq!((metrics.dropped_forks, metrics.orphaned_proposals), (3, 2));
+    match result {
+        // New fork ranked lowest, so its proposal got rejected
+        Err(Error::ProposalForkRankTooLow) => {
+            assert!(proposal_exists(&validator, &d3.hash()).await);
+            assert!(!proposal_exists(&validator, &f3.hash()).await);
+            assert_eq!(metrics.rejected_proposals, 2);
+        }
+        // Existing fork ranked lowest, so it got dropped
+        Ok(()) => {
+            assert!(!proposal_exists(&validator, &d3.hash()).await);
+            assert!(proposal_exists(&validator, &f3.hash()).await);
+            assert_eq!(metrics.rejected_proposals, 1);
+        }
+        Err(e) => return Err(e),
+    }
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn fork_limits() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                fork_limits_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -18,7 +18,10 @@
 
 use darkfi::{
     blockchain::{BlockInfo, Blockchain, HeaderHash},
-    validator::{consensus::Fork, pow::PoWModule},
+    validator::{
+        consensus::Fork,
+        pow::{DifficultyAlgorithm, PoWModule},
+    },
     Result,
 };
 use sled_overlay::sled;
@@ -39,7 +42,7 @@ fn forks() -> Result<()> {
         let genesis_block_hash = genesis_block.hash();
 
         // Generate the PoW module
-        let module = PoWModule::new(blockchain.clone(), 90, None)?;
+        let module = PoWModule::new(blockchain.clone(), 90, None, DifficultyAlgorithm::Monero)?;
 
         // Create a fork
         let fork = Fork::new(blockchain.clone(), module).await?;
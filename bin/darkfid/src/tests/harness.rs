@@ -16,15 +16,27 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use darkfi::{
-    blockchain::{BlockInfo, Header, HeaderHash},
+    blockchain::{
+        BlockInfo, BlockchainOverlay, BlockchainOverlayPtr, Header, HeaderHash, BLOCK_VERSION_2,
+    },
     net::Settings,
     rpc::jsonrpc::JsonSubscriber,
     system::sleep,
     tx::{ContractCallLeaf, TransactionBuilder},
-    validator::{consensus::Proposal, Validator, ValidatorConfig},
+    validator::{
+        consensus::{Proposal, DEFAULT_MAX_FORKS, DEFAULT_MAX_FORK_DEPTH},
+        pow::DifficultyAlgorithm,
+        utils::block_version,
+        verification::apply_block_transactions,
+        Validator, ValidatorConfig, ValidatorPtr, DEFAULT_MEMPOOL_MAX_SIZE,
+        DEFAULT_MIN_RELAY_FEE_RATE, DEFAULT_PENDING_TX_EXPIRY,
+    },
     zk::{empty_witnesses, ProvingKey, ZkCircuit},
     Result,
 };
@@ -61,6 +73,11 @@ pub struct Harness {
     pub validator_config: ValidatorConfig,
     pub alice: DarkfiNodePtr,
     pub bob: DarkfiNodePtr,
+    /// Validator kept at genesis, over which generated blocks
+    /// transactions are applied to compute their state roots
+    pub state: ValidatorPtr,
+    /// Generated blocks state overlays, over the genesis state
+    pub overlays: Mutex<HashMap<HeaderHash, BlockchainOverlayPtr>>,
 }
 
 impl Harness {
@@ -85,8 +102,17 @@ impl Harness {
             finalization_threshold: config.finalization_threshold,
             pow_target: config.pow_target,
             pow_fixed_difficulty: config.pow_fixed_difficulty.clone(),
+            pow_algorithm: DifficultyAlgorithm::Monero,
             genesis_block,
             verify_fees,
+            prune: None,
+            mempool_max_size: DEFAULT_MEMPOOL_MAX_SIZE,
+            min_relay_fee_rate: DEFAULT_MIN_RELAY_FEE_RATE,
+            pending_tx_expiry: DEFAULT_PENDING_TX_EXPIRY,
+            max_forks: DEFAULT_MAX_FORKS,
+            max_fork_depth: DEFAULT_MAX_FORK_DEPTH,
+            checkpoints: vec![],
+            block_v2_height: Some(1),
         };
 
         // Generate validators using pregenerated vks
@@ -105,7 +131,17 @@ impl Harness {
         settings.peers = vec![alice_url];
         let bob = generate_node(&vks, &validator_config, &settings, ex, false, None).await?;
 
-        Ok(Self { config, vks, validator_config, alice, bob })
+        // Genesis state validator
+        let state = generate_validator(&vks, &validator_config).await?;
+        let overlays = Mutex::new(HashMap::new());
+
+        Ok(Self { config, vks, validator_config, alice, bob, state, overlays })
+    }
+
+    /// Generate a new validator over a temporary database, using
+    /// provided configuration.
+    pub async fn generate_validator(&self, config: &ValidatorConfig) -> Result<ValidatorPtr> {
+        generate_validator(&self.vks, config).await
     }
 
     pub async fn validate_chains(&self, total_blocks: usize) -> Result<()> {
@@ -113,11 +149,19 @@ impl Harness {
         let bob = &self.bob.validator;
 
         alice
-            .validate_blockchain(self.config.pow_target, self.config.pow_fixed_difficulty.clone())
+            .validate_blockchain(
+                self.config.pow_target,
+                self.config.pow_fixed_difficulty.clone(),
+                self.validator_config.pow_algorithm,
+            )
             .await?;
 
-        bob.validate_blockchain(self.config.pow_target, self.config.pow_fixed_difficulty.clone())
-            .await?;
+        bob.validate_blockchain(
+            self.config.pow_target,
+            self.config.pow_fixed_difficulty.clone(),
+            self.validator_config.pow_algorithm,
+        )
+        .await?;
 
         let alice_blockchain_len = alice.blockchain.len();
         assert_eq!(alice_blockchain_len, bob.blockchain.len());
@@ -208,7 +252,8 @@ impl Harness {
         let timestamp = previous.header.timestamp.checked_add(1.into())?;
 
         // Generate header
-        let header = Header::new(previous.hash(), block_height, timestamp, last_nonce);
+        let mut header = Header::new(previous.hash(), block_height, timestamp, last_nonce);
+        header.version = block_version(block_height, self.validator_config.block_v2_height);
 
         // Generate the block
         let mut block = BlockInfo::new_empty(header);
@@ -216,6 +261,18 @@ impl Harness {
         // Add producer transaction to the block
         block.append_txs(vec![tx]);
 
+        // Apply the block over its previous block state to compute its state root
+        let previous_overlay = match self.overlays.lock().unwrap().get(&previous.hash()) {
+            Some(overlay) => overlay.clone(),
+            None => BlockchainOverlay::new(&self.state.blockchain)?,
+        };
+        let overlay =
+            apply_block_transactions(&previous_overlay, &block, self.config.pow_target).await?;
+        if block.header.version >= BLOCK_VERSION_2 {
+            block.header.state_root = overlay.lock().unwrap().contracts.get_state_root()?;
+        }
+        self.overlays.lock().unwrap().insert(block.hash(), overlay);
+
         // Attach signature
         block.sign(&keypair.secret);
 
@@ -223,6 +280,17 @@ impl Harness {
     }
 }
 
+/// Generate a new validator over a temporary database, with provided
+/// pregenerated vks injected.
+pub async fn generate_validator(
+    vks: &Vec<(Vec<u8>, String, Vec<u8>)>,
+    config: &ValidatorConfig,
+) -> Result<ValidatorPtr> {
+    let sled_db = sled::Config::new().temporary(true).open()?;
+    vks::inject(&sled_db, vks)?;
+    Validator::new(&sled_db, config).await
+}
+
 // Note: This function should mirror `darkfid::Darkfid::init`
 pub async fn generate_node(
     vks: &Vec<(Vec<u8>, String, Vec<u8>)>,
@@ -232,21 +300,26 @@ pub async fn generate_node(
     skip_sync: bool,
     checkpoint: Option<(u32, HeaderHash)>,
 ) -> Result<DarkfiNodePtr> {
-    let sled_db = sled::Config::new().temporary(true).open()?;
-    vks::inject(&sled_db, vks)?;
-
-    let validator = Validator::new(&sled_db, config).await?;
+    let validator = generate_validator(vks, config).await?;
 
     let mut subscribers = HashMap::new();
     subscribers.insert("blocks", JsonSubscriber::new("blockchain.subscribe_blocks"));
     subscribers.insert("txs", JsonSubscriber::new("blockchain.subscribe_txs"));
+    subscribers.insert("finalized_txs", JsonSubscriber::new("blockchain.subscribe_finalized_txs"));
     subscribers.insert("proposals", JsonSubscriber::new("blockchain.subscribe_proposals"));
+    subscribers.insert("chain", JsonSubscriber::new("blockchain.subscribe_chain_events"));
     subscribers.insert("dnet", JsonSubscriber::new("dnet.subscribe_events"));
 
     let p2p_handler = DarkfidP2pHandler::init(settings, ex).await?;
-    let node =
-        DarkfiNode::new(p2p_handler.clone(), validator.clone(), 50, subscribers.clone(), None)
-            .await;
+    let node = DarkfiNode::new(
+        p2p_handler.clone(),
+        validator.clone(),
+        50,
+        subscribers.clone(),
+        None,
+        None,
+    )
+    .await;
 
     p2p_handler.clone().start(ex, &validator, &subscribers).await?;
 
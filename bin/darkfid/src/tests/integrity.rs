@@ -0,0 +1,128 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::Result;
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::{
+    integrity::{check_database, Inconsistency},
+    tests::{Harness, HarnessConfig},
+};
+
+async fn repair_database_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19240".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19241".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a sequence of blocks, so some of them get finalized
+    let mut block = th.alice.validator.blockchain.last_block()?;
+    for _ in 0..6 {
+        block = th.generate_next_block(&block).await?;
+        th.add_blocks(&vec![block.clone()]).await?;
+    }
+    let blockchain = &th.alice.validator.blockchain;
+    let (last, last_hash) = blockchain.last()?;
+    assert!(last > 0);
+    assert!(check_database(blockchain, false)?.is_empty());
+
+    // Corrupt a transaction location record, detect and repair it
+    let last_block = blockchain.last_block()?;
+    let tx_hash = last_block.txs.last().unwrap().hash();
+    blockchain.transactions.location.remove(tx_hash.inner())?;
+    let issues = check_database(blockchain, false)?;
+    assert_eq!(issues.len(), 1);
+    assert!(
+        matches!(issues[0], Inconsistency::TxLocation(tx, (height, _)) if tx == tx_hash && height == last)
+    );
+    assert!(check_database(blockchain, true)?.is_empty());
+    assert!(blockchain.transactions.location.contains_key(tx_hash.inner())?);
+
+    // Insert an orphan difficulty record, detect and repair it
+    let difficulty = blockchain.blocks.difficulty.get(last.to_be_bytes())?.unwrap();
+    blockchain.blocks.difficulty.insert((last + 5).to_be_bytes(), difficulty)?;
+    let issues = check_database(blockchain, false)?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0], Inconsistency::DifficultyOrphan(height) if height == last + 5));
+    assert!(check_database(blockchain, true)?.is_empty());
+    assert!(!blockchain.blocks.difficulty.contains_key((last + 5).to_be_bytes())?);
+
+    // Corrupt the contracts state SMT root node, detect and rebuild it
+    let state_root = blockchain.contracts.get_state_root()?;
+    assert_eq!(last_block.header.state_root, state_root);
+    blockchain.contracts.state_smt.remove([0])?;
+    let issues = check_database(blockchain, false)?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0], Inconsistency::StateSmt));
+    assert!(check_database(blockchain, true)?.is_empty());
+    assert_eq!(blockchain.contracts.get_state_root()?, state_root);
+
+    // Write a block without its difficulty record and state changes,
+    // like an interrupted write would, detect and repair it
+    let next = th.generate_next_block(&last_block).await?;
+    let next_hash = blockchain.add_block(&next)?;
+    let next_txs: Vec<_> = next.txs.iter().map(|tx| tx.hash()).collect();
+    let issues = check_database(blockchain, false)?;
+    assert!(issues.iter().any(
+        |issue| matches!(issue, Inconsistency::DifficultyMissing(height) if *height == last + 1)
+    ));
+
+    // Repair must truncate the blocks sequence, removing all the block records
+    assert!(check_database(blockchain, true)?.is_empty());
+    assert_eq!(blockchain.last()?, (last, last_hash));
+    assert!(blockchain.headers.get(&[next_hash], false)?[0].is_none());
+    assert!(blockchain.blocks.get(&[next_hash], false)?[0].is_none());
+    assert!(blockchain.transactions.get(&next_txs, false)?.iter().all(|tx| tx.is_none()));
+    assert!(next_txs.iter().all(|tx| !blockchain
+        .transactions
+        .location
+        .contains_key(tx.inner())
+        .unwrap()));
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn repair_database() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                repair_database_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
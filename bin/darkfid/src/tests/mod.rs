@@ -18,18 +18,44 @@
 
 use std::sync::Arc;
 
-use darkfi::{net::Settings, validator::utils::best_fork_index, Result};
+use darkfi::{
+    blockchain::{Header, HeaderHash},
+    net::Settings,
+    validator::{consensus::TxFinalityStatus, light_client::LightClient, utils::best_fork_index},
+    Result,
+};
 use darkfi_contract_test_harness::init_logger;
-use darkfi_sdk::num_traits::One;
+use darkfi_sdk::{num_traits::One, tx::TransactionHash};
 use num_bigint::BigUint;
+use sled_overlay::sled;
 use smol::Executor;
 use url::Url;
 
+use crate::integrity::check_database;
+
 mod harness;
 use harness::{generate_node, Harness, HarnessConfig};
 
+mod block_templates;
+
+mod bootstrap;
+
+mod checkpoints;
+
+mod fork_limits;
+
 mod forks;
 
+mod integrity;
+
+mod pending_txs;
+
+mod pruning;
+
+mod regtest;
+
+mod snapshot;
+
 mod sync_forks;
 
 mod unproposed_txs;
@@ -122,6 +148,9 @@ async fn sync_blocks_real(ex: Arc<Executor<'static>>) -> Result<()> {
 
     // Nodes must have two forks with 2 blocks each
     th.validate_fork_chains(2, vec![2, 2]).await;
+    let metrics = alice.consensus.forks_metrics().await;
+    assert_eq!((metrics.forks, metrics.proposals, metrics.max_fork_length), (2, 4, 2));
+    assert_eq!((metrics.rejected_proposals, metrics.orphaned_proposals), (0, 0));
     // Check charlie has the correct forks
     let charlie_forks = charlie.consensus.forks.read().await;
     if small_best {
@@ -163,13 +192,20 @@ async fn sync_blocks_real(ex: Arc<Executor<'static>>) -> Result<()> {
     assert_eq!(last, bob.blockchain.last()?.1);
     // Nodes must have one fork with 2 blocks
     th.validate_fork_chains(1, vec![2]).await;
+    assert!(alice.consensus.forks_metrics().await.orphaned_proposals > 0);
     let last_proposal = alice.consensus.forks.read().await[0].proposals[1];
     assert_eq!(last_proposal, fork_sequence.last().unwrap().hash());
     assert_eq!(last_proposal, bob.consensus.forks.read().await[0].proposals[1]);
 
     // Same for Charlie
     charlie.finalization().await?;
-    charlie.validate_blockchain(pow_target, pow_fixed_difficulty).await?;
+    charlie
+        .validate_blockchain(
+            pow_target,
+            pow_fixed_difficulty.clone(),
+            th.validator_config.pow_algorithm,
+        )
+        .await?;
     assert_eq!(alice.blockchain.len(), charlie.blockchain.len());
     assert!(charlie.blockchain.headers.is_empty_sync());
     assert_eq!(last, charlie.blockchain.last()?.1);
@@ -178,6 +214,46 @@ async fn sync_blocks_real(ex: Arc<Executor<'static>>) -> Result<()> {
     assert_eq!(charlie_forks[0].proposals.len(), 2);
     assert_eq!(charlie_forks[0].diffs.len(), 2);
     assert_eq!(last_proposal, charlie_forks[0].proposals[1]);
+    drop(charlie_forks);
+
+    // Verify nodes databases integrity
+    assert!(check_database(&alice.blockchain, false)?.is_empty());
+    assert!(check_database(&charlie.blockchain, false)?.is_empty());
+
+    // Sync a light client from Alice headers
+    let mut light_client = LightClient::new(
+        &sled::Config::new().temporary(true).open()?,
+        &alice.blockchain.genesis_block()?,
+        pow_target,
+        pow_fixed_difficulty,
+        th.validator_config.pow_algorithm,
+    )?;
+    let heights: Vec<u32> = (1..=alice.blockchain.last()?.0).collect();
+    let hashes: Vec<HeaderHash> =
+        alice.blockchain.blocks.get_order(&heights, true)?.into_iter().flatten().collect();
+    let headers: Vec<Header> =
+        alice.blockchain.headers.get(&hashes, true)?.into_iter().flatten().collect();
+    light_client.append_headers(&headers)?;
+    assert_eq!(light_client.last()?, alice.blockchain.last()?);
+
+    // Verify a transaction inclusion proof against the light client headers
+    let tx_hash = alice.blockchain.last_block()?.txs.last().unwrap().hash();
+    let proof = alice.blockchain.get_tx_proof(&tx_hash)?;
+    assert!(light_client.verify_tx_proof(&tx_hash, &proof)?);
+    let genesis_tx_hash = genesis.txs.last().unwrap().hash();
+    assert!(!light_client.verify_tx_proof(&genesis_tx_hash, &proof)?);
+
+    // Verify transactions finality status, counting confirmations
+    // up to the best fork tip, which is two proposals ahead
+    let last_height = alice.blockchain.last()?.0;
+    let status = alice.consensus.tx_finality_status(&tx_hash).await?;
+    assert_eq!(status, TxFinalityStatus::Finalized(last_height, last, 3));
+    let last_block = fork_sequence.last().unwrap();
+    let proposal_tx_hash = last_block.txs.last().unwrap().hash();
+    let status = alice.consensus.tx_finality_status(&proposal_tx_hash).await?;
+    assert_eq!(status, TxFinalityStatus::Proposed(last_height + 2, last_block.hash(), 1));
+    let status = alice.consensus.tx_finality_status(&TransactionHash::new([0u8; 32])).await?;
+    assert_eq!(status, TxFinalityStatus::Unknown);
 
     // Thanks for reading
     Ok(())
@@ -233,8 +309,17 @@ fn darkfid_programmatic_control() -> Result<()> {
         finalization_threshold: 1,
         pow_target: 20,
         pow_fixed_difficulty: Some(BigUint::one()),
+        pow_algorithm: darkfi::validator::pow::DifficultyAlgorithm::Monero,
         genesis_block,
         verify_fees: false,
+        prune: None,
+        mempool_max_size: darkfi::validator::DEFAULT_MEMPOOL_MAX_SIZE,
+        min_relay_fee_rate: darkfi::validator::DEFAULT_MIN_RELAY_FEE_RATE,
+        pending_tx_expiry: darkfi::validator::DEFAULT_PENDING_TX_EXPIRY,
+        max_forks: darkfi::validator::consensus::DEFAULT_MAX_FORKS,
+        max_fork_depth: darkfi::validator::consensus::DEFAULT_MAX_FORK_DEPTH,
+        checkpoints: vec![],
+        block_v2_height: Some(1),
     };
     let consensus_config = crate::ConsensusInitTaskConfig {
         skip_sync: true,
@@ -250,6 +335,7 @@ fn darkfid_programmatic_control() -> Result<()> {
     let (_, vks) = darkfi_contract_test_harness::vks::get_cached_pks_and_vks()?;
     darkfi_contract_test_harness::vks::inject(&sled_db, &vks)?;
     let rpc_listen = Url::parse("tcp://127.0.0.1:8240")?;
+    let miner_rpc_listen = Url::parse("tcp://127.0.0.1:8241")?;
 
     // Create an executor and communication signals
     let ex = Arc::new(smol::Executor::new());
@@ -265,19 +351,22 @@ fn darkfid_programmatic_control() -> Result<()> {
                     &darkfi::net::Settings::default(),
                     &None,
                     &None,
+                    &Some(miner_rpc_listen),
+                    &None,
+                    false,
                     &ex,
                 )
                 .await
                 .unwrap();
 
                 // Start it
-                daemon.start(&ex, &rpc_listen, &consensus_config).await.unwrap();
+                daemon.start(&ex, &rpc_listen, &None, &consensus_config).await.unwrap();
 
                 // Stop it
                 daemon.stop().await.unwrap();
 
                 // Start it again
-                daemon.start(&ex, &rpc_listen, &consensus_config).await.unwrap();
+                daemon.start(&ex, &rpc_listen, &None, &consensus_config).await.unwrap();
 
                 // Stop it
                 daemon.stop().await.unwrap();
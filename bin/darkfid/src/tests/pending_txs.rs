@@ -0,0 +1,152 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use darkfi::{
+    blockchain::{BlockInfo, PendingTxFee},
+    error::TxVerifyFailed,
+    tx::Transaction,
+    validator::{consensus::Proposal, Validator, ValidatorConfig},
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::tests::{Harness, HarnessConfig};
+
+/// Auxiliary function to append the next block of provided one to the
+/// validator and trigger its finalization check.
+async fn finalize_next_block(
+    th: &Harness,
+    validator: &Validator,
+    block: &mut BlockInfo,
+) -> Result<()> {
+    let next = th.generate_next_block(block).await?;
+    validator.append_proposal(&Proposal::new(next.clone())).await?;
+    validator.finalization().await?;
+    *block = next;
+
+    Ok(())
+}
+
+async fn pending_txs_expiry_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19440".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19441".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a validator where pending transactions expire after two blocks
+    let config = ValidatorConfig { pending_tx_expiry: 2, ..th.validator_config.clone() };
+    let validator = th.generate_validator(&config).await?;
+    let blockchain = &validator.blockchain;
+
+    // Store two pending transactions, received at genesis height
+    let txs: Vec<Transaction> = (1..3)
+        .map(|signatures| Transaction {
+            signatures: vec![vec![]; signatures],
+            ..Default::default()
+        })
+        .collect();
+    let fees = vec![PendingTxFee::new(1000, 0, 100); 2];
+    let hashes = blockchain.add_pending_txs_with_metadata(&txs, &fees, &[vec![], vec![]])?;
+    let (tx, legacy_tx) = (hashes[0], hashes[1]);
+
+    // Forks reload their mempool from the pending transactions store
+    validator.consensus.generate_empty_fork().await?;
+    assert_eq!(validator.consensus.forks.read().await[0].mempool, hashes);
+
+    // Drop the second transaction received height record, like older databases
+    blockchain.transactions.pending_height.remove(legacy_tx.inner())?;
+
+    // Finalize a block, so legacy records start aging from its height
+    let mut block = blockchain.last_block()?;
+    for _ in 0..3 {
+        finalize_next_block(&th, &validator, &mut block).await?;
+    }
+    assert_eq!(blockchain.last()?.0, 1);
+    let heights: HashMap<_, _> =
+        blockchain.transactions.get_all_pending_height()?.into_iter().collect();
+    assert_eq!(heights, HashMap::from([(tx, 0), (legacy_tx, 1)]));
+    assert_eq!(blockchain.get_pending_txs_hashes()?, hashes);
+
+    // First transaction expires once the canonical blockchain grows by two blocks,
+    // getting removed from the pending transactions store and the forks mempools.
+    finalize_next_block(&th, &validator, &mut block).await?;
+    assert_eq!(blockchain.last()?.0, 2);
+    assert_eq!(blockchain.get_pending_txs_hashes()?, vec![legacy_tx]);
+    assert_eq!(blockchain.transactions.get_pending_fee(&[tx])?, vec![None]);
+    for fork in validator.consensus.forks.read().await.iter() {
+        assert_eq!(fork.mempool, vec![legacy_tx]);
+    }
+
+    // Expired transaction is remembered, so it gets rejected if relayed again
+    assert_eq!(blockchain.transactions.get_all_expired()?, vec![(tx, 2)]);
+    assert!(matches!(
+        validator.append_tx(&txs[0], true).await,
+        Err(Error::TxVerifyFailed(TxVerifyFailed::ExpiredTx(_)))
+    ));
+
+    // Legacy transaction expires two blocks after its backfilled height
+    finalize_next_block(&th, &validator, &mut block).await?;
+    assert_eq!(blockchain.last()?.0, 3);
+    assert!(blockchain.get_pending_txs_hashes()?.is_empty());
+    assert!(blockchain.transactions.get_all_pending_height()?.is_empty());
+    assert_eq!(blockchain.transactions.get_pending_size()?, 0);
+    for fork in validator.consensus.forks.read().await.iter() {
+        assert!(fork.mempool.is_empty());
+    }
+
+    // Expired transactions are forgotten after another expiry period
+    finalize_next_block(&th, &validator, &mut block).await?;
+    assert_eq!(blockchain.last()?.0, 4);
+    assert!(!blockchain.transactions.contains_expired(&tx)?);
+    assert!(blockchain.transactions.contains_expired(&legacy_tx)?);
+    finalize_next_block(&th, &validator, &mut block).await?;
+    assert_eq!(blockchain.last()?.0, 5);
+    assert!(blockchain.transactions.get_all_expired()?.is_empty());
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn pending_txs_expiry() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                pending_txs_expiry_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use darkfi::{
+    net::Settings,
+    rpc::jsonrpc::{ErrorCode::ServerError, JsonResult},
+    validator::ValidatorConfig,
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+use tinyjson::JsonValue;
+use url::Url;
+
+use crate::{
+    integrity::check_database,
+    proto::PRUNED_HEIGHT_FEATURE,
+    task::sync::peer_keeps_block,
+    tests::{generate_node, Harness, HarnessConfig},
+    RpcError,
+};
+
+async fn sync_pruned_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:18740".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:18741".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a sequence of blocks, so some of them get finalized
+    let mut block = th.alice.validator.blockchain.last_block()?;
+    for _ in 0..8 {
+        block = th.generate_next_block(&block).await?;
+        th.add_blocks(&vec![block.clone()]).await?;
+    }
+    let alice = &th.alice.validator;
+    let last = alice.blockchain.last()?.0;
+    assert!(last > 4);
+    assert_eq!(alice.blockchain.pruned_height()?, None);
+
+    // We are going to create a third node, keeping only
+    // the last two finalized blocks, and sync it from Alice
+    let mut settings = Settings { localnet: true, inbound_connections: 3, ..Default::default() };
+    let charlie_url = Url::parse("tcp+tls://127.0.0.1:18742")?;
+    settings.inbound_addrs = vec![charlie_url.clone()];
+    let alice_url = th.alice.p2p_handler.p2p.settings().read().await.inbound_addrs[0].clone();
+    settings.peers = vec![alice_url];
+    let pruned_config = ValidatorConfig { prune: Some(2), ..th.validator_config.clone() };
+    let charlie_node = generate_node(&th.vks, &pruned_config, &settings, &ex, false, None).await?;
+    let charlie = &charlie_node.validator;
+
+    // Alice must have advertised she supports pruned height requests
+    for channel in charlie_node.p2p_handler.p2p.hosts().channels() {
+        let version = channel.version.lock().await.clone().unwrap();
+        assert!(version.features.iter().any(|(service, _)| service == PRUNED_HEIGHT_FEATURE));
+    }
+
+    // Verify node synced and pruned the blocks before the configured depth,
+    // keeping the genesis block and all the headers
+    assert_eq!(charlie.blockchain.last()?, alice.blockchain.last()?);
+    assert_eq!(charlie.blockchain.pruned_height()?, Some(last - 2));
+    let heights: Vec<u32> = (0..=last).collect();
+    let hashes: Vec<_> =
+        charlie.blockchain.blocks.get_order(&heights, true)?.into_iter().flatten().collect();
+    assert_eq!(charlie.blockchain.headers.get(&hashes, true)?.len(), hashes.len());
+    for (height, block) in charlie.blockchain.blocks.get(&hashes, false)?.iter().enumerate() {
+        let height = height as u32;
+        assert_eq!(block.is_some(), height == 0 || height >= last - 2);
+    }
+    assert!(check_database(&charlie.blockchain, false)?.is_empty());
+
+    // Pruned transactions must be reported as such
+    let pruned_tx = alice.blockchain.get_blocks_by_hash(&[hashes[1]])?[0].txs[0].hash();
+    let kept_tx = alice.blockchain.last_block()?.txs[0].hash();
+    assert!(charlie.blockchain.is_tx_pruned(&pruned_tx)?);
+    assert!(!charlie.blockchain.is_tx_pruned(&kept_tx)?);
+    assert!(!alice.blockchain.is_tx_pruned(&pruned_tx)?);
+    assert!(matches!(
+        charlie.blockchain.get_tx_proof(&pruned_tx),
+        Err(Error::TransactionPruned(_))
+    ));
+    assert!(charlie.blockchain.get_tx_proof(&kept_tx).is_ok());
+    let params = JsonValue::Array(vec![JsonValue::String(pruned_tx.to_string())]);
+    for rep in [
+        charlie_node.blockchain_get_tx(1, params.clone()).await,
+        charlie_node.blockchain_get_tx_proof(1, params).await,
+    ] {
+        let JsonResult::Error(e) = rep else { panic!("Pruned transaction was served") };
+        assert_eq!(e.error.code, ServerError(RpcError::TransactionPruned as i32).code());
+    }
+
+    // A fourth node syncing only from Charlie must skip it,
+    // since it no longer keeps the blocks it needs
+    let dave_url = Url::parse("tcp+tls://127.0.0.1:18743")?;
+    settings.inbound_addrs = vec![dave_url];
+    settings.peers = vec![charlie_url];
+    let dave = generate_node(&th.vks, &th.validator_config, &settings, &ex, false, None).await;
+    assert!(matches!(dave, Err(Error::BlockNotFound(_))));
+
+    // Pruning again until the same height is a no-op
+    assert_eq!(charlie.blockchain.prune(last - 2)?, 0);
+    assert_eq!(charlie.blockchain.pruned_height()?, Some(last - 2));
+
+    // Prune the rest of the blocks, except the last one
+    assert_eq!(charlie.blockchain.prune(last)?, 2);
+    assert_eq!(charlie.blockchain.pruned_height()?, Some(last));
+    assert!(charlie
+        .blockchain
+        .blocks
+        .get(&hashes[1..last as usize], false)?
+        .iter()
+        .all(|b| b.is_none()));
+    assert!(charlie.blockchain.genesis_block().is_ok());
+    assert!(charlie.blockchain.last_block().is_ok());
+    assert!(check_database(&charlie.blockchain, false)?.is_empty());
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn sync_pruned() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                sync_pruned_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
+
+#[test]
+fn pruned_peers_blocks() {
+    let pruned_peers = HashMap::from([(1, 10)]);
+
+    // Peer running in pruned mode only keeps blocks after its pruned height
+    assert!(!peer_keeps_block(&pruned_peers, 1, 0));
+    assert!(!peer_keeps_block(&pruned_peers, 1, 9));
+    assert!(peer_keeps_block(&pruned_peers, 1, 10));
+    assert!(peer_keeps_block(&pruned_peers, 1, 11));
+
+    // Rest peers keep all blocks
+    assert!(peer_keeps_block(&pruned_peers, 2, 0));
+    assert!(peer_keeps_block(&pruned_peers, 2, 11));
+}
@@ -0,0 +1,147 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use darkfi::{
+    blockchain::HeaderHash,
+    net::Settings,
+    rpc::jsonrpc::{JsonResult, JsonSubscriber},
+    Result,
+};
+use darkfi_contract_test_harness::{init_logger, Holder, TestHarness};
+use darkfi_money_contract::{client::MoneyNote, model::MoneyPoWRewardParamsV1};
+use darkfi_sdk::{
+    blockchain::expected_reward,
+    crypto::{BaseBlind, Keypair},
+    num_traits::One,
+};
+use darkfi_serial::deserialize;
+use num_bigint::BigUint;
+use rand::rngs::OsRng;
+use smol::Executor;
+use tinyjson::JsonValue;
+use url::Url;
+
+use crate::{
+    proto::DarkfidP2pHandler,
+    task::miner::{RegtestMiner, MAX_REGTEST_BLOCKS},
+    tests::{Harness, HarnessConfig},
+    DarkfiNode,
+};
+
+async fn regtest_generate_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode, without fees verification
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19540".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19541".to_string(),
+    };
+    let th = Harness::new(config, false, &ex).await?;
+
+    // Generate a regtest node
+    let validator = th.generate_validator(&th.validator_config).await?;
+    let mut subscribers = HashMap::new();
+    subscribers.insert("blocks", JsonSubscriber::new("blockchain.subscribe_blocks"));
+    let settings = Settings {
+        localnet: true,
+        inbound_addrs: vec![Url::parse("tcp+tls://127.0.0.1:19542")?],
+        ..Default::default()
+    };
+    let p2p_handler = DarkfidP2pHandler::init(&settings, &ex).await?;
+    let regtest = RegtestMiner::new(&validator)?;
+    let node =
+        DarkfiNode::new(p2p_handler, validator.clone(), 50, subscribers, None, Some(regtest)).await;
+    validator.consensus.generate_empty_fork().await?;
+
+    // Append a pending transaction
+    const HOLDERS: [Holder; 1] = [Holder::Alice];
+    let mut contract_th = TestHarness::new(&HOLDERS, false).await?;
+    let (tx, _, _, _) = contract_th
+        .token_mint(1, &Holder::Alice, &Holder::Alice, BaseBlind::random(&mut OsRng), None, None, 1)
+        .await?;
+    validator.append_tx(&tx, true).await?;
+    assert_eq!(validator.blockchain.get_pending_txs_hashes()?, vec![tx.hash()]);
+
+    // Blocks number must be a whole number within bounds
+    let recipient = Keypair::random(&mut OsRng);
+    let address = JsonValue::String(recipient.public.to_string());
+    for n in [0.0, 1.5, -1.0, (MAX_REGTEST_BLOCKS + 1) as f64] {
+        let params = JsonValue::Array(vec![JsonValue::Number(n), address.clone()]);
+        assert!(matches!(node.regtest_generate(1, params).await, JsonResult::Error(_)));
+    }
+    assert_eq!(validator.blockchain.last()?.0, 0);
+
+    // Generate two blocks
+    let params = JsonValue::Array(vec![JsonValue::Number(2.0), address]);
+    let JsonResult::Response(response) = node.regtest_generate(1, params).await else {
+        panic!("Blocks generation failed")
+    };
+    let hashes: Vec<HeaderHash> = response
+        .result
+        .get::<Vec<JsonValue>>()
+        .unwrap()
+        .iter()
+        .map(|hash| HeaderHash::from_str(hash.get::<String>().unwrap()).unwrap())
+        .collect();
+
+    // Verify blocks extended the canonical blockchain
+    assert_eq!(validator.blockchain.last()?, (2, hashes[1]));
+    let blocks = validator.blockchain.get_blocks_by_hash(&hashes)?;
+    for (index, block) in blocks.iter().enumerate() {
+        let height = index as u32 + 1;
+        assert_eq!(block.header.height, height);
+
+        // Verify the block rewarded the recipient
+        let producer_tx = block.txs.last().unwrap();
+        let params: MoneyPoWRewardParamsV1 = deserialize(&producer_tx.calls[0].data.data[1..])?;
+        assert_eq!(params.input.value, expected_reward(height));
+        let note = params.output.note.decrypt::<MoneyNote>(&recipient.secret).unwrap();
+        assert_eq!(note.value, expected_reward(height));
+    }
+
+    // Pending transaction got included in the first block
+    assert_eq!(blocks[0].txs.len(), 2);
+    assert_eq!(blocks[0].txs[0].hash(), tx.hash());
+    assert_eq!(blocks[1].txs.len(), 1);
+    assert!(validator.blockchain.get_pending_txs_hashes()?.is_empty());
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn regtest_generate() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                regtest_generate_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi::{
+    blockchain::HeaderHash,
+    validator::{ValidatorConfig, ValidatorPtr},
+    Error, Result,
+};
+use darkfi_contract_test_harness::init_logger;
+use darkfi_sdk::num_traits::One;
+use num_bigint::BigUint;
+use smol::Executor;
+
+use crate::{
+    integrity::check_database,
+    snapshot::{
+        export_snapshot, import_snapshot, snapshot_import_interrupted, verify_snapshot,
+        SNAPSHOT_IMPORT_MARKER,
+    },
+    tests::{Harness, HarnessConfig},
+};
+
+async fn snapshot_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19040".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19041".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Generate a sequence of blocks, so some of them get finalized
+    let mut block = th.alice.validator.blockchain.last_block()?;
+    for _ in 0..6 {
+        block = th.generate_next_block(&block).await?;
+        th.add_blocks(&vec![block.clone()]).await?;
+    }
+    let alice = &th.alice.validator;
+    assert!(alice.blockchain.last()?.0 > 0);
+
+    // Export Alice snapshot
+    let path = std::env::temp_dir().join(format!("darkfid_snapshot_{}", std::process::id()));
+    let checkpoint = export_snapshot(&alice.blockchain, &path).await?;
+    assert_eq!((checkpoint.0, checkpoint.1), alice.blockchain.last()?);
+    assert_eq!(checkpoint.2, alice.blockchain.state_checksum()?);
+
+    // Generate a fresh validator to import it
+    let new_validator = || th.generate_validator(&th.validator_config);
+    let charlie: ValidatorPtr = new_validator().await?;
+
+    // Snapshot must only verify against its checkpoint
+    let validator_config = &th.validator_config;
+    verify_snapshot(&charlie.blockchain, &path, &checkpoint, validator_config).await?;
+    let wrong_hash = (checkpoint.0, HeaderHash::new([0u8; 32]), checkpoint.2);
+    assert!(verify_snapshot(&charlie.blockchain, &path, &wrong_hash, validator_config)
+        .await
+        .is_err());
+    let wrong_state = (checkpoint.0, checkpoint.1, blake3::hash(b"wrong"));
+    assert!(verify_snapshot(&charlie.blockchain, &path, &wrong_state, validator_config)
+        .await
+        .is_err());
+    assert!(import_snapshot(&charlie.blockchain, &path, wrong_state, validator_config)
+        .await
+        .is_err());
+    assert!(!snapshot_import_interrupted(&charlie.blockchain)?);
+
+    // Snapshot headers must have valid PoW for our network difficulty
+    let wrong_pow = ValidatorConfig {
+        pow_fixed_difficulty: Some(BigUint::from(1u64 << 32)),
+        ..th.validator_config.clone()
+    };
+    assert!(verify_snapshot(&charlie.blockchain, &path, &checkpoint, &wrong_pow).await.is_err());
+    assert!(import_snapshot(&charlie.blockchain, &path, checkpoint, &wrong_pow).await.is_err());
+    assert_eq!(charlie.blockchain.last()?.0, 0);
+    assert!(!snapshot_import_interrupted(&charlie.blockchain)?);
+
+    // Snapshot headers must not conflict with our checkpoints
+    let wrong_checkpoints = ValidatorConfig {
+        checkpoints: vec![(1, HeaderHash::new([0u8; 32]))],
+        ..th.validator_config.clone()
+    };
+    assert!(verify_snapshot(&charlie.blockchain, &path, &checkpoint, &wrong_checkpoints)
+        .await
+        .is_err());
+    assert!(import_snapshot(&charlie.blockchain, &path, checkpoint, &wrong_checkpoints)
+        .await
+        .is_err());
+    assert_eq!(charlie.blockchain.last()?.0, 0);
+
+    // Import it and verify the imported state matches Alice's
+    import_snapshot(&charlie.blockchain, &path, checkpoint, validator_config).await?;
+    assert!(!snapshot_import_interrupted(&charlie.blockchain)?);
+    assert_eq!(charlie.blockchain.last()?, alice.blockchain.last()?);
+    assert_eq!(charlie.blockchain.last_block()?.hash(), alice.blockchain.last_block()?.hash());
+    assert_eq!(charlie.blockchain.state_checksum()?, checkpoint.2);
+    let state_root = charlie.blockchain.last_block()?.header.state_root;
+    assert_eq!(charlie.blockchain.contracts.get_state_root()?, state_root);
+    assert_eq!(charlie.blockchain.pruned_height()?, Some(checkpoint.0));
+    assert!(check_database(&charlie.blockchain, false)?.is_empty());
+
+    // Snapshot can't be imported twice
+    assert!(matches!(
+        import_snapshot(&charlie.blockchain, &path, checkpoint, validator_config).await,
+        Err(Error::BlockAlreadyExists(_))
+    ));
+
+    // An interrupted import is detected, and can be retried using the same snapshot
+    charlie.blockchain.sled_db.insert(SNAPSHOT_IMPORT_MARKER, checkpoint.1.inner())?;
+    assert!(snapshot_import_interrupted(&charlie.blockchain)?);
+    import_snapshot(&charlie.blockchain, &path, checkpoint, validator_config).await?;
+    assert!(!snapshot_import_interrupted(&charlie.blockchain)?);
+    assert_eq!(charlie.blockchain.state_checksum()?, checkpoint.2);
+
+    // Interrupted imports can't be retried using another snapshot
+    let dave = new_validator().await?;
+    dave.blockchain.sled_db.insert(SNAPSHOT_IMPORT_MARKER, &[0u8; 32])?;
+    assert!(matches!(
+        import_snapshot(&dave.blockchain, &path, checkpoint, validator_config).await,
+        Err(Error::BlockAlreadyExists(_))
+    ));
+
+    std::fs::remove_file(&path)?;
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn snapshot() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                snapshot_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -18,7 +18,12 @@
 
 use std::sync::Arc;
 
-use darkfi::{net::Settings, validator::utils::best_fork_index, Result};
+use darkfi::{
+    blockchain::BlockInfo,
+    net::Settings,
+    validator::{consensus::BestChainEvent, utils::best_fork_index},
+    Result,
+};
 use darkfi_contract_test_harness::init_logger;
 use darkfi_sdk::num_traits::One;
 use num_bigint::BigUint;
@@ -42,6 +47,9 @@ async fn sync_forks_real(ex: Arc<Executor<'static>>) -> Result<()> {
     };
     let th = Harness::new(config, true, &ex).await?;
 
+    // Subscribe to Alice best chain events
+    let events = th.alice.validator.consensus.events.clone().subscribe().await;
+
     // Retrieve genesis block and generate 3 forks
     let genesis = th.alice.validator.blockchain.last_block()?;
 
@@ -49,7 +57,13 @@ async fn sync_forks_real(ex: Arc<Executor<'static>>) -> Result<()> {
     let block1 = th.generate_next_block(&genesis).await?;
     let block2 = th.generate_next_block(&block1).await?;
     let block3 = th.generate_next_block(&block2).await?;
-    th.add_blocks(&vec![block1, block2, block3]).await?;
+    th.add_blocks(&vec![block1.clone(), block2.clone(), block3.clone()]).await?;
+
+    // Verify Alice notified the appended proposals
+    for block in [&block1, &block2, &block3] {
+        let expected = BestChainEvent::Appended(block.header.height, block.hash());
+        assert_eq!(events.receive().await, expected);
+    }
 
     // Generate a fork with 1 block
     let block4 = th.generate_next_block(&genesis).await?;
@@ -126,3 +140,97 @@ fn sync_forks() -> Result<()> {
 
     Ok(())
 }
+
+async fn best_chain_events_real(ex: Arc<Executor<'static>>) -> Result<()> {
+    init_logger();
+
+    // Initialize harness in testing mode
+    let config = HarnessConfig {
+        pow_target: 90,
+        pow_fixed_difficulty: Some(BigUint::one()),
+        finalization_threshold: 3,
+        alice_url: "tcp+tls://127.0.0.1:19340".to_string(),
+        bob_url: "tcp+tls://127.0.0.1:19341".to_string(),
+    };
+    let th = Harness::new(config, true, &ex).await?;
+
+    // Subscribe to Alice best chain events
+    let events = th.alice.validator.consensus.events.clone().subscribe().await;
+
+    // Generate a fork with 2 blocks
+    let genesis = th.alice.validator.blockchain.last_block()?;
+    let a1 = th.generate_next_block(&genesis).await?;
+    let a2 = th.generate_next_block(&a1).await?;
+    th.add_blocks(&vec![a1.clone(), a2.clone()]).await?;
+    for block in [&a1, &a2] {
+        let expected = BestChainEvent::Appended(block.header.height, block.hash());
+        assert_eq!(events.receive().await, expected);
+    }
+
+    // Generate another fork with 2 blocks, tying with the first one
+    let b1 = th.generate_next_block(&genesis).await?;
+    let b2 = th.generate_next_block(&b1).await?;
+    th.add_blocks(&vec![b1.clone(), b2.clone()]).await?;
+
+    // Grab the tie winner, verifying its events if it changed the best fork
+    let forks = th.alice.validator.consensus.forks.read().await;
+    let best_tip = forks[best_fork_index(&forks)?].last_proposal()?.hash;
+    drop(forks);
+    let (winner, loser): ([BlockInfo; 2], [BlockInfo; 2]) = if best_tip == b2.hash() {
+        let expected = vec![
+            BestChainEvent::Reverted(2, a2.hash()),
+            BestChainEvent::Reverted(1, a1.hash()),
+            BestChainEvent::Appended(1, b1.hash()),
+            BestChainEvent::Appended(2, b2.hash()),
+        ];
+        for event in expected {
+            assert_eq!(events.receive().await, event);
+        }
+        ([b1, b2], [a1, a2])
+    } else {
+        ([a1, a2], [b1, b2])
+    };
+
+    // Extend the tie loser so it becomes the best fork and gets its first block finalized.
+    // Winner proposals must be reverted highest first, before the loser ones get appended.
+    let l3 = th.generate_next_block(&loser[1]).await?;
+    th.add_blocks(&vec![l3.clone()]).await?;
+    let expected = vec![
+        BestChainEvent::Reverted(2, winner[1].hash()),
+        BestChainEvent::Reverted(1, winner[0].hash()),
+        BestChainEvent::Appended(1, loser[0].hash()),
+        BestChainEvent::Appended(2, loser[1].hash()),
+        BestChainEvent::Appended(3, l3.hash()),
+        BestChainEvent::Finalized(1, loser[0].hash()),
+    ];
+    for event in expected {
+        assert_eq!(events.receive().await, event);
+    }
+    assert_eq!(th.alice.validator.blockchain.last()?, (1, loser[0].hash()));
+
+    // Extending the best fork finalizes the next block
+    let l4 = th.generate_next_block(&l3).await?;
+    th.add_blocks(&vec![l4.clone()]).await?;
+    assert_eq!(events.receive().await, BestChainEvent::Appended(4, l4.hash()));
+    assert_eq!(events.receive().await, BestChainEvent::Finalized(2, loser[1].hash()));
+
+    // Thanks for reading
+    Ok(())
+}
+
+#[test]
+fn best_chain_events() -> Result<()> {
+    let ex = Arc::new(Executor::new());
+    let (signal, shutdown) = smol::channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new().each(0..4, |_| smol::block_on(ex.run(shutdown.recv()))).finish(
+        || {
+            smol::block_on(async {
+                best_chain_events_real(ex.clone()).await.unwrap();
+                drop(signal);
+            })
+        },
+    );
+
+    Ok(())
+}
@@ -20,7 +20,7 @@ use std::{fs, process::exit, sync::Arc};
 
 use url::Url;
 
-use darkfi::{rpc::client::RpcClient, util::path::expand_path, Result};
+use darkfi::{rpc::darkfid_client::DarkfidClient, util::path::expand_path, Result};
 
 use crate::walletdb::{WalletDb, WalletPtr};
 
@@ -29,7 +29,7 @@ pub struct Drk {
     /// Wallet database operations handler
     pub wallet: WalletPtr,
     /// JSON-RPC client to execute requests to darkfid daemon
-    pub rpc_client: Option<RpcClient>,
+    pub rpc_client: Option<DarkfidClient>,
     /// Flag indicating if fun stuff are enabled
     pub fun: bool,
 }
@@ -65,7 +65,7 @@ impl Drk {
 
         // Initialize rpc client
         let rpc_client = if let Some(endpoint) = endpoint {
-            Some(RpcClient::new(endpoint, ex).await?)
+            Some(DarkfidClient::new(endpoint, ex).await?)
         } else {
             None
         };
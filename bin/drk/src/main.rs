@@ -1777,13 +1777,13 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
             let drk = Drk::new(
                 blockchain_config.wallet_path,
                 blockchain_config.wallet_pass,
-                Some(blockchain_config.endpoint.clone()),
-                ex.clone(),
+                Some(blockchain_config.endpoint),
+                ex,
                 args.fun,
             )
             .await?;
 
-            if let Err(e) = drk.subscribe_blocks(blockchain_config.endpoint, ex).await {
+            if let Err(e) = drk.subscribe_blocks().await {
                 eprintln!("Block subscription failed: {e:?}");
                 exit(2);
             }
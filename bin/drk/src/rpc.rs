@@ -16,27 +16,20 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{sync::Arc, time::Instant};
-
-use url::Url;
+use std::time::Instant;
 
 use darkfi::{
     blockchain::BlockInfo,
-    rpc::{
-        client::RpcClient,
-        jsonrpc::{ErrorCode, JsonError, JsonRequest, JsonResult},
-        util::JsonValue,
-    },
-    system::{Publisher, StoppableTask},
+    rpc::darkfid_client::DarkfidClient,
+    system::{Publisher, Subscription},
     tx::Transaction,
-    util::encoding::base64,
     Error, Result,
 };
 use darkfi_sdk::{
     crypto::{ContractId, DAO_CONTRACT_ID, DEPLOYOOOR_CONTRACT_ID, MONEY_CONTRACT_ID},
     tx::TransactionHash,
 };
-use darkfi_serial::{deserialize_async, serialize_async};
+use darkfi_serial::serialize_async;
 
 use crate::{
     error::{WalletDbError, WalletDbResult},
@@ -50,15 +43,8 @@ impl Drk {
     /// scanned and we check if any of them call the money contract, and if
     /// the payments are intended for us. If so, we decrypt them and append
     /// the metadata to our wallet.
-    pub async fn subscribe_blocks(
-        &self,
-        endpoint: Url,
-        ex: Arc<smol::Executor<'static>>,
-    ) -> Result<()> {
-        let rep = self
-            .darkfid_daemon_request("blockchain.last_known_block", &JsonValue::Array(vec![]))
-            .await?;
-        let last_known = *rep.get::<f64>().unwrap() as u32;
+    pub async fn subscribe_blocks(&self) -> Result<()> {
+        let last_known = self.darkfid_client()?.last_known_block().await?;
         let last_scanned = match self.last_scanned_block() {
             Ok(l) => l,
             Err(e) => {
@@ -79,104 +65,48 @@ impl Drk {
         println!("Subscribing to receive notifications of incoming blocks");
         let publisher = Publisher::new();
         let subscription = publisher.clone().subscribe().await;
-        let _publisher = publisher.clone();
-        let _ex = ex.clone();
-        StoppableTask::new().start(
-            // Weird hack to prevent lifetimes hell
-            async move {
-                let rpc_client = RpcClient::new(endpoint, _ex).await?;
-                let req = JsonRequest::new("blockchain.subscribe_blocks", JsonValue::Array(vec![]));
-                rpc_client.subscribe(req, _publisher).await
-            },
-            |res| async move {
-                match res {
-                    Ok(()) => { /* Do nothing */ }
-                    Err(e) => {
-                        eprintln!("[subscribe_blocks] JSON-RPC server error: {e:?}");
-                        publisher
-                            .notify(JsonResult::Error(JsonError::new(
-                                ErrorCode::InternalError,
-                                None,
-                                0,
-                            )))
-                            .await;
-                    }
-                }
-            },
-            Error::RpcServerStopped,
-            ex,
-        );
-        println!("Detached subscription to background");
         println!("All is good. Waiting for block notifications...");
 
-        let e = loop {
-            match subscription.receive().await {
-                JsonResult::Notification(n) => {
-                    println!("Got Block notification from darkfid subscription");
-                    if n.method != "blockchain.subscribe_blocks" {
-                        break Error::UnexpectedJsonRpc(format!(
-                            "Got foreign notification from darkfid: {}",
-                            n.method
-                        ))
-                    }
-
-                    // Verify parameters
-                    if !n.params.is_array() {
-                        break Error::UnexpectedJsonRpc(
-                            "Received notification params are not an array".to_string(),
-                        )
-                    }
-                    let params = n.params.get::<Vec<JsonValue>>().unwrap();
-                    if params.is_empty() {
-                        break Error::UnexpectedJsonRpc(
-                            "Notification parameters are empty".to_string(),
-                        )
-                    }
-
-                    for param in params {
-                        let param = param.get::<String>().unwrap();
-                        let bytes = base64::decode(param).unwrap();
-
-                        let block_data: BlockInfo = deserialize_async(&bytes).await?;
-                        println!("Deserialized successfully. Scanning block...");
-                        if let Err(e) = self.scan_block(&block_data).await {
-                            return Err(Error::DatabaseError(format!(
-                                "[subscribe_blocks] Scanning block failed: {e:?}"
-                            )))
-                        }
-                        let txs_hashes = match self.insert_tx_history_records(&block_data.txs).await {
-                            Ok(hashes) => hashes,
-                            Err(e) => {
-                                return Err(Error::DatabaseError(format!(
-                                    "[subscribe_blocks] Inserting transaction history records failed: {e:?}"
-                                )))
-                            },
-                        };
-                        if let Err(e) =
-                            self.update_tx_history_records_status(&txs_hashes, "Finalized")
-                        {
-                            return Err(Error::DatabaseError(format!(
-                                "[subscribe_blocks] Update transaction history record status failed: {e:?}"
-                            )))
-                        }
-                    }
+        let result = smol::future::or(
+            async {
+                if let Err(e) = self.darkfid_client()?.subscribe_blocks(publisher).await {
+                    eprintln!("[subscribe_blocks] JSON-RPC server error: {e:?}");
+                    return Err(e)
                 }
+                Ok(())
+            },
+            self.handle_subscribed_blocks(&subscription),
+        )
+        .await;
+        subscription.unsubscribe().await;
 
-                JsonResult::Error(e) => {
-                    // Some error happened in the transmission
-                    break Error::UnexpectedJsonRpc(format!("Got error from JSON-RPC: {e:?}"))
-                }
+        result
+    }
 
-                x => {
-                    // And this is weird
-                    break Error::UnexpectedJsonRpc(format!(
-                        "Got unexpected data from JSON-RPC: {x:?}"
-                    ))
+    /// Auxiliary function to scan blocks received from a darkfid blocks subscription.
+    async fn handle_subscribed_blocks(&self, subscription: &Subscription<BlockInfo>) -> Result<()> {
+        loop {
+            let block_data = subscription.receive().await;
+            println!("Got Block notification from darkfid subscription. Scanning block...");
+            if let Err(e) = self.scan_block(&block_data).await {
+                return Err(Error::DatabaseError(format!(
+                    "[subscribe_blocks] Scanning block failed: {e:?}"
+                )))
+            }
+            let txs_hashes = match self.insert_tx_history_records(&block_data.txs).await {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    return Err(Error::DatabaseError(format!(
+                        "[subscribe_blocks] Inserting transaction history records failed: {e:?}"
+                    )))
                 }
+            };
+            if let Err(e) = self.update_tx_history_records_status(&txs_hashes, "Finalized") {
+                return Err(Error::DatabaseError(format!(
+                    "[subscribe_blocks] Update transaction history record status failed: {e:?}"
+                )))
             }
-        };
-
-        Err(e)
+        }
     }
 
     /// `scan_block` will go over over transactions in a block and handle their calls
@@ -256,17 +186,17 @@ impl Drk {
         };
 
         loop {
-            let rep = match self
-                .darkfid_daemon_request("blockchain.last_known_block", &JsonValue::Array(vec![]))
-                .await
-            {
-                Ok(r) => r,
+            let last = match self.darkfid_client() {
+                Ok(client) => client.last_known_block().await,
+                Err(e) => Err(e),
+            };
+            let last = match last {
+                Ok(l) => l,
                 Err(e) => {
                     eprintln!("[scan_blocks] RPC client request failed: {e:?}");
                     return Err(WalletDbError::GenericError)
                 }
             };
-            let last = *rep.get::<f64>().unwrap() as u32;
 
             println!("Requested to scan from block number: {height}");
             println!("Last known block number reported by darkfid: {last}");
@@ -299,16 +229,7 @@ impl Drk {
 
     // Queries darkfid for a block with given height.
     async fn get_block_by_height(&self, height: u32) -> Result<BlockInfo> {
-        let params = self
-            .darkfid_daemon_request(
-                "blockchain.get_block",
-                &JsonValue::Array(vec![JsonValue::String(height.to_string())]),
-            )
-            .await?;
-        let param = params.get::<String>().unwrap();
-        let bytes = base64::decode(param).unwrap();
-        let block = deserialize_async(&bytes).await?;
-        Ok(block)
+        self.darkfid_client()?.get_block(height).await
     }
 
     /// Broadcast a given transaction to darkfid and forward onto the network.
@@ -316,11 +237,7 @@ impl Drk {
     pub async fn broadcast_tx(&self, tx: &Transaction) -> Result<String> {
         println!("Broadcasting transaction...");
 
-        let params =
-            JsonValue::Array(vec![JsonValue::String(base64::encode(&serialize_async(tx).await))]);
-        let rep = self.darkfid_daemon_request("tx.broadcast", &params).await?;
-
-        let txid = rep.get::<String>().unwrap().clone();
+        let txid = self.darkfid_client()?.broadcast_tx(tx).await?.to_string();
 
         // Store transactions history record
         if let Err(e) = self.insert_tx_history_record(tx).await {
@@ -334,113 +251,52 @@ impl Drk {
 
     /// Queries darkfid for a tx with given hash.
     pub async fn get_tx(&self, tx_hash: &TransactionHash) -> Result<Option<Transaction>> {
-        let tx_hash_str = tx_hash.to_string();
-        match self
-            .darkfid_daemon_request(
-                "blockchain.get_tx",
-                &JsonValue::Array(vec![JsonValue::String(tx_hash_str)]),
-            )
-            .await
-        {
-            Ok(param) => {
-                let tx_bytes = base64::decode(param.get::<String>().unwrap()).unwrap();
-                let tx = deserialize_async(&tx_bytes).await?;
-                Ok(Some(tx))
-            }
-
+        match self.darkfid_client()?.get_tx(tx_hash).await {
+            Ok(tx) => Ok(Some(tx)),
             Err(_) => Ok(None),
         }
     }
 
     /// Simulate the transaction with the state machine.
     pub async fn simulate_tx(&self, tx: &Transaction) -> Result<bool> {
-        let tx_str = base64::encode(&serialize_async(tx).await);
-        let rep = self
-            .darkfid_daemon_request(
-                "tx.simulate",
-                &JsonValue::Array(vec![JsonValue::String(tx_str)]),
-            )
-            .await?;
-
-        let is_valid = *rep.get::<bool>().unwrap();
-        Ok(is_valid)
+        self.darkfid_client()?.simulate_tx(tx).await
     }
 
     /// Try to fetch zkas bincodes for the given `ContractId`.
     pub async fn lookup_zkas(&self, contract_id: &ContractId) -> Result<Vec<(String, Vec<u8>)>> {
-        let params = JsonValue::Array(vec![JsonValue::String(format!("{contract_id}"))]);
-        let rep = self.darkfid_daemon_request("blockchain.lookup_zkas", &params).await?;
-        let params = rep.get::<Vec<JsonValue>>().unwrap();
-
-        let mut ret = Vec::with_capacity(params.len());
-        for param in params {
-            let zkas_ns = param[0].get::<String>().unwrap().clone();
-            let zkas_bincode_bytes = base64::decode(param[1].get::<String>().unwrap()).unwrap();
-            ret.push((zkas_ns, zkas_bincode_bytes));
-        }
-
-        Ok(ret)
+        self.darkfid_client()?.lookup_zkas(contract_id).await
     }
 
     /// Queries darkfid for given transaction's gas.
     pub async fn get_tx_gas(&self, tx: &Transaction, include_fee: bool) -> Result<u64> {
-        let params = JsonValue::Array(vec![
-            JsonValue::String(base64::encode(&serialize_async(tx).await)),
-            JsonValue::Boolean(include_fee),
-        ]);
-        let rep = self.darkfid_daemon_request("tx.calculate_gas", &params).await?;
-
-        let gas = *rep.get::<f64>().unwrap() as u64;
-
-        Ok(gas)
+        self.darkfid_client()?.calculate_gas(tx, include_fee).await
     }
 
     /// Queries darkfid for current best fork next height.
     pub async fn get_next_block_height(&self) -> Result<u32> {
-        let rep = self
-            .darkfid_daemon_request(
-                "blockchain.best_fork_next_block_height",
-                &JsonValue::Array(vec![]),
-            )
-            .await?;
-
-        let next_height = *rep.get::<f64>().unwrap() as u32;
-
-        Ok(next_height)
+        self.darkfid_client()?.best_fork_next_block_height().await
     }
 
     /// Queries darkfid for currently configured block target time.
     pub async fn get_block_target(&self) -> Result<u32> {
-        let rep = self
-            .darkfid_daemon_request("blockchain.block_target", &JsonValue::Array(vec![]))
-            .await?;
-
-        let next_height = *rep.get::<f64>().unwrap() as u32;
-
-        Ok(next_height)
+        self.darkfid_client()?.block_target().await
     }
 
     /// Auxiliary function to ping configured darkfid daemon for liveness.
     pub async fn ping(&self) -> Result<()> {
         println!("Executing ping request to darkfid...");
         let latency = Instant::now();
-        let rep = self.darkfid_daemon_request("ping", &JsonValue::Array(vec![])).await?;
+        self.darkfid_client()?.ping().await?;
         let latency = latency.elapsed();
-        println!("Got reply: {rep:?}");
+        println!("Got reply: pong");
         println!("Latency: {latency:?}");
         Ok(())
     }
 
-    /// Auxiliary function to execute a request towards the configured darkfid daemon JSON-RPC endpoint.
-    pub async fn darkfid_daemon_request(
-        &self,
-        method: &str,
-        params: &JsonValue,
-    ) -> Result<JsonValue> {
+    /// Auxiliary function to retrieve the configured darkfid daemon JSON-RPC client.
+    pub fn darkfid_client(&self) -> Result<&DarkfidClient> {
         let Some(ref rpc_client) = self.rpc_client else { return Err(Error::RpcClientStopped) };
-        let req = JsonRequest::new(method, params.clone());
-        let rep = rpc_client.request(req).await?;
-        Ok(rep)
+        Ok(rpc_client)
     }
 
     /// Auxiliary function to stop current JSON-RPC client, if its initialized.
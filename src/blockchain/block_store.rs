@@ -17,9 +17,10 @@
  */
 
 use darkfi_sdk::{
+    bridgetree::Hashable,
     crypto::{
         schnorr::{SchnorrSecret, Signature},
-        MerkleTree, SecretKey,
+        MerkleNode, MerkleTree, SecretKey,
     },
     pasta::{group::ff::FromUniformBytes, pallas},
     tx::TransactionHash,
@@ -235,6 +236,10 @@ impl BlockDifficulty {
 pub const SLED_BLOCK_TREE: &[u8] = b"_blocks";
 pub const SLED_BLOCK_ORDER_TREE: &[u8] = b"_block_order";
 pub const SLED_BLOCK_DIFFICULTY_TREE: &[u8] = b"_block_difficulty";
+pub const SLED_BLOCK_PRUNED_TREE: &[u8] = b"_block_pruned";
+
+/// Key of the pruned height record in the store's pruned tree
+const SLED_PRUNED_HEIGHT_KEY: &[u8] = b"pruned_height";
 
 /// The `BlockStore` is a structure representing all `sled` trees related
 /// to storing the blockchain's blocks information.
@@ -251,6 +256,10 @@ pub struct BlockStore {
     /// blockchain's blocks, where the key is the block height number,
     /// and the value is the blocks' hash.
     pub difficulty: sled::Tree,
+    /// The `sled` tree storing the pruning state of the blockchain's
+    /// blocks, holding the height before which blocks have been
+    /// removed from the main tree.
+    pub pruned: sled::Tree,
 }
 
 impl BlockStore {
@@ -259,7 +268,8 @@ impl BlockStore {
         let main = db.open_tree(SLED_BLOCK_TREE)?;
         let order = db.open_tree(SLED_BLOCK_ORDER_TREE)?;
         let difficulty = db.open_tree(SLED_BLOCK_DIFFICULTY_TREE)?;
-        Ok(Self { main, order, difficulty })
+        let pruned = db.open_tree(SLED_BLOCK_PRUNED_TREE)?;
+        Ok(Self { main, order, difficulty, pruned })
     }
 
     /// Insert a slice of [`Block`] into the store's main tree.
@@ -330,6 +340,28 @@ impl BlockStore {
         batch
     }
 
+    /// Generate the sled batch corresponding to an insert to the pruned
+    /// tree, so caller can handle the write operation.
+    /// The provided height is the height before which blocks have been
+    /// removed from the main tree.
+    pub fn insert_batch_pruned_height(&self, height: u32) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+        batch.insert(SLED_PRUNED_HEIGHT_KEY, &height.to_be_bytes());
+        batch
+    }
+
+    /// Generate the sled batch corresponding to a remove from the main
+    /// tree, so caller can handle the write operation.
+    pub fn remove_batch(&self, block_hashes: &[HeaderHash]) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+
+        for hash in block_hashes {
+            batch.remove(hash.inner());
+        }
+
+        batch
+    }
+
     /// Check if the store's main tree contains a given block hash.
     pub fn contains(&self, blockhash: &HeaderHash) -> Result<bool> {
         Ok(self.main.contains_key(blockhash.inner())?)
@@ -532,6 +564,14 @@ impl BlockStore {
         Ok(last_n)
     }
 
+    /// Fetch the height before which blocks have been removed from the
+    /// main tree. If the store was never pruned, returns `None`.
+    pub fn get_pruned_height(&self) -> Result<Option<u32>> {
+        let Some(found) = self.pruned.get(SLED_PRUNED_HEIGHT_KEY)? else { return Ok(None) };
+        let height_bytes: [u8; 4] = found.as_ref().try_into().unwrap();
+        Ok(Some(u32::from_be_bytes(height_bytes)))
+    }
+
     /// Retrieve store's order tree records count.
     pub fn len(&self) -> usize {
         self.order.len()
@@ -670,8 +710,76 @@ impl BlockStoreOverlay {
 
 /// Auxiliary function to append a transaction to a Merkle tree.
 pub fn append_tx_to_merkle_tree(tree: &mut MerkleTree, tx: &Transaction) {
+    tree.append(tx_merkle_leaf(&tx.hash()));
+}
+
+/// Auxiliary function to compute the Merkle tree leaf of a transaction hash.
+pub fn tx_merkle_leaf(tx_hash: &TransactionHash) -> MerkleNode {
     let mut buf = [0u8; 64];
-    buf[..32].copy_from_slice(tx.hash().inner());
-    let leaf = pallas::Base::from_uniform_bytes(&buf);
-    tree.append(leaf.into());
+    buf[..32].copy_from_slice(tx_hash.inner());
+    pallas::Base::from_uniform_bytes(&buf).into()
+}
+
+/// Merkle inclusion proof of a transaction in the transactions tree
+/// its block header `root` commits to.
+#[derive(Debug, Clone, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct TxInclusionProof {
+    /// Height of the block containing the transaction
+    pub height: u32,
+    /// Hash of the block containing the transaction
+    pub block: HeaderHash,
+    /// Index of the transaction in the block
+    pub index: u16,
+    /// Merkle path from the transaction leaf to the tree root
+    pub path: Vec<MerkleNode>,
+}
+
+impl TxInclusionProof {
+    /// Generate the inclusion proof of the transaction at provided index
+    /// of given [`Block`], found at provided height.
+    pub fn new(height: u32, block: &Block, index: u16) -> Result<Self> {
+        if index as usize >= block.txs.len() {
+            return Err(Error::TransactionNotFound(format!("{}:{}", block.header, index)))
+        }
+
+        // Rebuild the block transactions tree, marking the requested leaf
+        let mut tree = MerkleTree::new(1);
+        let mut position = None;
+        for (i, tx_hash) in block.txs.iter().enumerate() {
+            tree.append(tx_merkle_leaf(tx_hash));
+            if i == index as usize {
+                position = tree.mark();
+            }
+        }
+
+        let Some(path) = position.and_then(|position| tree.witness(position, 0).ok()) else {
+            return Err(Error::TransactionNotFound(block.txs[index as usize].to_string()))
+        };
+
+        Ok(Self { height, block: block.header, index, path })
+    }
+
+    /// Compute the transactions tree root this proof leads to, for
+    /// provided transaction hash.
+    pub fn root(&self, tx_hash: &TransactionHash) -> MerkleNode {
+        let position = self.index as u64;
+        let mut current = tx_merkle_leaf(tx_hash);
+        for (level, sibling) in self.path.iter().enumerate() {
+            let level = level as u8;
+            current = if position & (1 << level) == 0 {
+                MerkleNode::combine(level.into(), &current, sibling)
+            } else {
+                MerkleNode::combine(level.into(), sibling, &current)
+            };
+        }
+        current
+    }
+
+    /// Verify the proof includes provided transaction hash in the block
+    /// of given [`Header`].
+    pub fn verify(&self, tx_hash: &TransactionHash, header: &Header) -> bool {
+        header.hash() == self.block &&
+            header.height == self.height &&
+            self.root(tx_hash) == header.root
+    }
 }
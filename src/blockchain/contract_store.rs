@@ -16,12 +16,28 @@ r* This program is distributed in the hope that it will be useful,
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::io::Cursor;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Cursor,
+    sync::{Arc, Mutex, OnceLock},
+};
 
-use darkfi_sdk::crypto::ContractId;
+use darkfi_sdk::{
+    crypto::{
+        smt::{gen_empty_nodes, util::FieldHasher, SparseMerkleTree, StorageAdapter},
+        util::hash_to_base,
+        ContractId,
+    },
+    error::{ContractError, ContractResult},
+    pasta::{
+        group::ff::{Field, PrimeField},
+        pallas,
+    },
+};
 use darkfi_serial::{deserialize, serialize};
 use log::{debug, error};
-use sled_overlay::sled;
+use num_bigint::BigUint;
+use sled_overlay::{sled, SledDbOverlay};
 
 use crate::{
     zk::{empty_witnesses, VerifyingKey, ZkCircuit},
@@ -33,6 +49,26 @@ use super::{parse_record, SledDbOverlayPtr};
 
 pub const SLED_CONTRACTS_TREE: &[u8] = b"_contracts";
 pub const SLED_BINCODE_TREE: &[u8] = b"_wasm_bincode";
+pub const SLED_STATE_SMT_TREE: &[u8] = b"_state_smt";
+pub const SLED_STATE_BUCKETS_TREE: &[u8] = b"_state_buckets";
+pub const SLED_VERIFYING_KEYS_TREE: &[u8] = b"_verifying_keys";
+
+/// Depth of the contracts [`StateSmt`], grouping records in up to 2^32
+/// buckets, so each write only rehashes a small bucket and its path.
+pub const STATE_SMT_DEPTH: usize = 32;
+
+/// Key of the contracts [`StateSmt`] root node in its sled tree,
+/// as the little-endian bytes of its index.
+const STATE_SMT_ROOT_KEY: &[u8] = &[0];
+
+/// BLAKE2b personalization of the contracts [`StateSmt`] nodes
+const STATE_SMT_NODE_PERSONALIZATION: &[u8] = b"DarkFi:StateNode";
+
+/// BLAKE2b personalization of the contracts [`StateSmt`] leaves
+const STATE_SMT_LEAF_PERSONALIZATION: &[u8] = b"DarkFi:StateLeaf";
+
+/// Maximum number of verifying keys kept in memory by the [`VerifyingKeyCache`]
+pub const MAX_CACHED_VERIFYING_KEYS: usize = 64;
 
 /// The hardcoded db name for the zkas circuits database tree
 pub const SMART_CONTRACT_ZKAS_DB_NAME: &str = "_zkas";
@@ -58,6 +94,25 @@ pub struct ContractStore {
     /// ```
     /// These values get mutated with `init()` and `remove()`.
     pub state: sled::Tree,
+    /// The `sled` tree storing the contracts [`StateSmt`] nodes.
+    /// The layout looks like this:
+    /// ```plaintext
+    ///  tree: "_state_smt"
+    ///   key: BigUint node index (little-endian bytes)
+    /// value: pallas::Base
+    /// ```
+    pub state_smt: sled::Tree,
+    /// The `sled` tree storing the contracts [`StateSmt`] buckets,
+    /// which are its leaves preimages.
+    /// The layout looks like this:
+    /// ```plaintext
+    ///  tree: "_state_buckets"
+    ///   key: u32 bucket index (big-endian bytes)
+    /// value: Vec<(blake3(tree || key), blake3(tree || key || value))>
+    /// ```
+    pub state_buckets: sled::Tree,
+    /// Cache of the zkas circuits built [`VerifyingKey`]s
+    pub vks: VerifyingKeyCache,
 }
 
 impl ContractStore {
@@ -65,7 +120,10 @@ impl ContractStore {
     pub fn new(db: &sled::Db) -> Result<Self> {
         let wasm = db.open_tree(SLED_BINCODE_TREE)?;
         let state = db.open_tree(SLED_CONTRACTS_TREE)?;
-        Ok(Self { wasm, state })
+        let state_smt = db.open_tree(SLED_STATE_SMT_TREE)?;
+        let state_buckets = db.open_tree(SLED_STATE_BUCKETS_TREE)?;
+        let vks = VerifyingKeyCache::new(db)?;
+        Ok(Self { wasm, state, state_smt, state_buckets, vks })
     }
 
     /// Fetches the bincode for a given ContractId from the store's wasm tree.
@@ -165,19 +223,11 @@ impl ContractStore {
             return Err(Error::ZkasBincodeNotFound)
         };
 
-        // If anything in this function panics, that means corrupted data managed
-        // to get into this sled tree. This should not be possible.
-        let (zkbin, vkbin): (Vec<u8>, Vec<u8>) = deserialize(&zkas_bytes).unwrap();
-
-        // The first vec is the compiled zkas binary
-        let zkbin = ZkBinary::decode(&zkbin).unwrap();
-
-        // Construct the circuit to be able to read the VerifyingKey
-        let circuit = ZkCircuit::new(empty_witnesses(&zkbin).unwrap(), &zkbin);
-
-        // The second one is the serialized VerifyingKey for it
-        let mut vk_buf = Cursor::new(vkbin);
-        let vk = VerifyingKey::read::<Cursor<Vec<u8>>, ZkCircuit>(&mut vk_buf, circuit).unwrap();
+        // Canonical records can get cached right away
+        let (zkbin, zkas_hash, vk, vk_bytes) = self.vks.decode_zkas(&zkas_bytes)?;
+        if let Some(vk_bytes) = vk_bytes {
+            self.vks.insert(&zkas_hash, &vk, &vk_bytes)?;
+        }
 
         Ok((zkbin, vk))
     }
@@ -209,16 +259,135 @@ impl ContractStore {
 
         Ok(contracts)
     }
+
+    /// Retrieve the contracts [`StateSmt`] root from the store's state SMT tree.
+    pub fn get_state_root(&self) -> Result<pallas::Base> {
+        match self.state_smt.get(STATE_SMT_ROOT_KEY)? {
+            Some(bytes) => parse_state_smt_node(&bytes),
+            None => Ok(state_smt_empty_nodes()[0]),
+        }
+    }
+
+    /// Recompute the contracts [`StateSmt`] root from scratch, using the
+    /// store's state buckets tree. Used to check the stored tree nodes
+    /// are consistent.
+    pub fn compute_state_root(&self) -> Result<pallas::Base> {
+        let mut buckets = vec![];
+        for record in self.state_buckets.iter() {
+            let (key, value) = record?;
+            buckets.push((key.to_vec(), value.to_vec()));
+        }
+
+        state_root_from_buckets(&buckets)
+    }
+
+    /// Rebuild the store's state SMT tree from its state buckets tree.
+    pub fn rebuild_state_smt(&self) -> Result<()> {
+        let mut leaves = vec![];
+        for record in self.state_buckets.iter() {
+            let (key, value) = record?;
+            let index = parse_state_bucket_index(&key)?;
+            leaves.push((pallas::Base::from(index as u64), state_bucket_leaf(&value)));
+        }
+
+        self.state_smt.clear()?;
+        let storage = StateSmtTreeStorage(&self.state_smt);
+        let mut smt = StateSmt::new(storage, StateHasher, state_smt_empty_nodes());
+        smt.insert_batch(leaves)?;
+
+        Ok(())
+    }
+}
+
+/// Verifying keys changes of a [`ContractStoreOverlay`], which only reach
+/// the [`VerifyingKeyCache`] once the overlay is applied to canonical state.
+#[derive(Clone, Default)]
+pub struct PendingVerifyingKeys {
+    /// Verifying keys of the circuits written in the overlay
+    inserted: HashMap<blake3::Hash, (VerifyingKey, Vec<u8>)>,
+    /// Verifying keys of the circuits replaced in the overlay
+    removed: HashSet<blake3::Hash>,
 }
 
 /// Overlay structure over a [`ContractStore`] instance.
-pub struct ContractStoreOverlay(SledDbOverlayPtr);
+/// The [`VerifyingKeyCache`] is shared with the store, since
+/// it lives outside of the overlay, while the overlay verifying
+/// keys changes are kept aside until it gets applied.
+pub struct ContractStoreOverlay(
+    SledDbOverlayPtr,
+    VerifyingKeyCache,
+    Arc<Mutex<PendingVerifyingKeys>>,
+);
 
 impl ContractStoreOverlay {
-    pub fn new(overlay: &SledDbOverlayPtr) -> Result<Self> {
+    pub fn new(overlay: &SledDbOverlayPtr, vks: &VerifyingKeyCache) -> Result<Self> {
         overlay.lock().unwrap().open_tree(SLED_BINCODE_TREE, true)?;
         overlay.lock().unwrap().open_tree(SLED_CONTRACTS_TREE, true)?;
-        Ok(Self(overlay.clone()))
+        overlay.lock().unwrap().open_tree(SLED_STATE_SMT_TREE, true)?;
+        overlay.lock().unwrap().open_tree(SLED_STATE_BUCKETS_TREE, true)?;
+        Ok(Self(
+            overlay.clone(),
+            vks.clone(),
+            Arc::new(Mutex::new(PendingVerifyingKeys::default())),
+        ))
+    }
+
+    /// Generate a new `ContractStoreOverlay` over provided overlay pointer,
+    /// with a copy of this overlay pending verifying keys changes.
+    pub fn clone_with(&self, overlay: &SledDbOverlayPtr) -> Result<Self> {
+        let store = Self::new(overlay, &self.1)?;
+        *store.2.lock().unwrap() = self.2.lock().unwrap().clone();
+        Ok(store)
+    }
+
+    /// Retrieve the underlying [`VerifyingKeyCache`].
+    pub fn vks(&self) -> &VerifyingKeyCache {
+        &self.1
+    }
+
+    /// Retrieve the serialized [`VerifyingKey`] of provided zkas bincode hash,
+    /// either from the overlay pending changes or the cache, if it exists.
+    pub fn get_serialized_vk(&self, zkas_hash: &blake3::Hash) -> Result<Option<Vec<u8>>> {
+        let pending = self.2.lock().unwrap();
+        if let Some((_, vk_bytes)) = pending.inserted.get(zkas_hash) {
+            return Ok(Some(vk_bytes.clone()))
+        }
+        if pending.removed.contains(zkas_hash) {
+            return Ok(None)
+        }
+        drop(pending);
+
+        self.1.get_serialized(zkas_hash)
+    }
+
+    /// Keep provided [`VerifyingKey`] and its serialized form, for the zkas
+    /// bincode of provided hash, to get cached once the overlay is applied.
+    pub fn insert_vk(&self, zkas_hash: &blake3::Hash, vk: &VerifyingKey, vk_bytes: &[u8]) {
+        let mut pending = self.2.lock().unwrap();
+        pending.removed.remove(zkas_hash);
+        pending.inserted.insert(*zkas_hash, (vk.clone(), vk_bytes.to_vec()));
+    }
+
+    /// Mark the cached [`VerifyingKey`] of provided zkas bincode hash to get
+    /// invalidated once the overlay is applied.
+    pub fn remove_vk(&self, zkas_hash: &blake3::Hash) {
+        let mut pending = self.2.lock().unwrap();
+        pending.inserted.remove(zkas_hash);
+        pending.removed.insert(*zkas_hash);
+    }
+
+    /// Commit the overlay pending verifying keys changes to the cache.
+    /// Must only be called after the overlay is applied to canonical state.
+    pub fn commit_vks(&self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.2.lock().unwrap());
+        for zkas_hash in &pending.removed {
+            self.1.remove(zkas_hash)?;
+        }
+        for (zkas_hash, (vk, vk_bytes)) in &pending.inserted {
+            self.1.insert(zkas_hash, vk, vk_bytes)?;
+        }
+
+        Ok(())
     }
 
     /// Fetches the bincode for a given ContractId from the overlay's wasm tree.
@@ -236,9 +405,12 @@ impl ContractStoreOverlay {
     /// Inserts or replaces the bincode for a given ContractId into the overlay's
     /// wasm tree.
     pub fn insert(&self, contract_id: ContractId, bincode: &[u8]) -> Result<()> {
-        if let Err(e) =
-            self.0.lock().unwrap().insert(SLED_BINCODE_TREE, &serialize(&contract_id), bincode)
-        {
+        if let Err(e) = state_overlay_insert(
+            &mut self.0.lock().unwrap(),
+            SLED_BINCODE_TREE,
+            &serialize(&contract_id),
+            bincode,
+        ) {
             error!(target: "blockchain::contractstoreoverlay", "Failed to insert bincode to Wasm tree: {}", e);
             return Err(e.into())
         }
@@ -280,7 +452,12 @@ impl ContractStoreOverlay {
 
         // Now we add it so it's marked as initialized and create its tree.
         state_pointers.push(ptr);
-        lock.insert(SLED_CONTRACTS_TREE, &contract_id_bytes, &serialize(&state_pointers))?;
+        state_overlay_insert(
+            &mut lock,
+            SLED_CONTRACTS_TREE,
+            &contract_id_bytes,
+            &serialize(&state_pointers),
+        )?;
         lock.open_tree(&ptr, false)?;
 
         Ok(ptr)
@@ -316,6 +493,11 @@ impl ContractStoreOverlay {
         Ok(ptr)
     }
 
+    /// Retrieve the contracts [`StateSmt`] root from the overlay's state SMT tree.
+    pub fn get_state_root(&self) -> Result<pallas::Base> {
+        overlay_state_root(&self.0.lock().unwrap())
+    }
+
     /// Abstraction function for fetching a `ZkBinary` and its respective `VerifyingKey`
     /// from a contract's zkas sled tree.
     pub fn get_zkas(
@@ -331,20 +513,619 @@ impl ContractStoreOverlay {
             return Err(Error::ZkasBincodeNotFound)
         };
 
+        // Check if the overlay wrote this circuit, so we don't read its key again
+        let (zkbin_bytes, _): (Vec<u8>, Vec<u8>) = deserialize(&zkas_bytes).unwrap();
+        let zkas_hash = blake3::hash(&zkbin_bytes);
+        if let Some((vk, _)) = self.2.lock().unwrap().inserted.get(&zkas_hash) {
+            return Ok((ZkBinary::decode(&zkbin_bytes).unwrap(), vk.clone()))
+        }
+
+        // Overlay records are only cached once the overlay is applied
+        let (zkbin, zkas_hash, vk, vk_bytes) = self.1.decode_zkas(&zkas_bytes)?;
+        if let Some(vk_bytes) = vk_bytes {
+            self.insert_vk(&zkas_hash, &vk, &vk_bytes);
+        }
+
+        Ok((zkbin, vk))
+    }
+}
+
+/// Node-wide cache of the zkas circuits built [`VerifyingKey`]s, keyed by
+/// the blake3 hash of their zkas bincode. The most recently read keys are
+/// kept in memory, up to [`MAX_CACHED_VERIFYING_KEYS`], and all of them are
+/// persisted in their serialized form in their own sled tree, outside of
+/// any overlay, so they survive restarts and are shared by all forks and
+/// temporary verification databases. The cache only gets populated from
+/// canonical state, either by reading it or by applying an overlay to it,
+/// and the replaced circuits keys get invalidated on redeploy. Since keys
+/// are addressed by their circuit bincode, a redeployed circuit never hits
+/// a stale key.
+#[derive(Clone)]
+pub struct VerifyingKeyCache {
+    /// The `sled` tree storing the serialized verifying keys.
+    /// The layout looks like this:
+    /// ```plaintext
+    ///  tree: "_verifying_keys"
+    ///   key: blake3(zkas bincode)
+    /// value: Serialized VerifyingKey
+    /// ```
+    pub tree: sled::Tree,
+    /// Most recently read verifying keys
+    keys: Arc<Mutex<MemoryVerifyingKeys>>,
+}
+
+/// Bounded in memory map of [`VerifyingKey`]s, evicting the least
+/// recently used key when full.
+#[derive(Default)]
+struct MemoryVerifyingKeys {
+    /// Verifying keys, keyed by their zkas bincode hash
+    keys: HashMap<blake3::Hash, VerifyingKey>,
+    /// Keys usage order, from least to most recently used
+    order: VecDeque<blake3::Hash>,
+}
+
+impl MemoryVerifyingKeys {
+    fn get(&mut self, zkas_hash: &blake3::Hash) -> Option<VerifyingKey> {
+        let vk = self.keys.get(zkas_hash)?.clone();
+        self.order.retain(|h| h != zkas_hash);
+        self.order.push_back(*zkas_hash);
+        Some(vk)
+    }
+
+    fn insert(&mut self, zkas_hash: &blake3::Hash, vk: &VerifyingKey) {
+        self.remove(zkas_hash);
+        if self.keys.len() >= MAX_CACHED_VERIFYING_KEYS {
+            if let Some(evicted) = self.order.pop_front() {
+                self.keys.remove(&evicted);
+            }
+        }
+        self.keys.insert(*zkas_hash, vk.clone());
+        self.order.push_back(*zkas_hash);
+    }
+
+    fn remove(&mut self, zkas_hash: &blake3::Hash) {
+        if self.keys.remove(zkas_hash).is_some() {
+            self.order.retain(|h| h != zkas_hash);
+        }
+    }
+}
+
+impl VerifyingKeyCache {
+    /// Opens a new or existing `VerifyingKeyCache` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_VERIFYING_KEYS_TREE)?;
+        Ok(Self { tree, keys: Arc::new(Mutex::new(MemoryVerifyingKeys::default())) })
+    }
+
+    /// Retrieve the [`VerifyingKey`] of provided zkas bincode, either from
+    /// memory or by reading its serialized form. Returns `None` if it is
+    /// not cached.
+    pub fn get(&self, zkbin: &ZkBinary, zkas_hash: &blake3::Hash) -> Result<Option<VerifyingKey>> {
+        if let Some(vk) = self.keys.lock().unwrap().get(zkas_hash) {
+            return Ok(Some(vk))
+        }
+
+        let Some(vk_bytes) = self.get_serialized(zkas_hash)? else { return Ok(None) };
+        let vk = read_verifying_key(zkbin, vk_bytes)?;
+        self.keys.lock().unwrap().insert(zkas_hash, &vk);
+
+        Ok(Some(vk))
+    }
+
+    /// Retrieve the number of verifying keys kept in memory.
+    pub fn memory_len(&self) -> usize {
+        self.keys.lock().unwrap().keys.len()
+    }
+
+    /// Retrieve the serialized [`VerifyingKey`] of provided zkas bincode hash,
+    /// if it exists.
+    pub fn get_serialized(&self, zkas_hash: &blake3::Hash) -> Result<Option<Vec<u8>>> {
+        Ok(self.tree.get(zkas_hash.as_bytes())?.map(|vk_bytes| vk_bytes.to_vec()))
+    }
+
+    /// Insert provided [`VerifyingKey`] and its serialized form, for the zkas
+    /// bincode of provided hash.
+    pub fn insert(
+        &self,
+        zkas_hash: &blake3::Hash,
+        vk: &VerifyingKey,
+        vk_bytes: &[u8],
+    ) -> Result<()> {
+        self.tree.insert(zkas_hash.as_bytes(), vk_bytes)?;
+        self.keys.lock().unwrap().insert(zkas_hash, vk);
+        Ok(())
+    }
+
+    /// Invalidate the cached [`VerifyingKey`] of provided zkas bincode hash.
+    pub fn remove(&self, zkas_hash: &blake3::Hash) -> Result<()> {
+        self.tree.remove(zkas_hash.as_bytes())?;
+        self.keys.lock().unwrap().remove(zkas_hash);
+        Ok(())
+    }
+
+    /// Decode a contract's zkas sled tree record into its `ZkBinary`, its
+    /// bincode hash and respective `VerifyingKey`, using the cached key if
+    /// it exists. Otherwise, the record key is read and returned along with
+    /// its serialized form, so the caller can cache it.
+    #[allow(clippy::type_complexity)]
+    fn decode_zkas(
+        &self,
+        zkas_bytes: &[u8],
+    ) -> Result<(ZkBinary, blake3::Hash, VerifyingKey, Option<Vec<u8>>)> {
         // If anything in this function panics, that means corrupted data managed
         // to get into this sled tree. This should not be possible.
-        let (zkbin, vkbin): (Vec<u8>, Vec<u8>) = deserialize(&zkas_bytes).unwrap();
+        let (zkbin_bytes, vkbin): (Vec<u8>, Vec<u8>) = deserialize(zkas_bytes).unwrap();
 
         // The first vec is the compiled zkas binary
-        let zkbin = ZkBinary::decode(&zkbin).unwrap();
+        let zkbin = ZkBinary::decode(&zkbin_bytes).unwrap();
 
-        // Construct the circuit to be able to read the VerifyingKey
-        let circuit = ZkCircuit::new(empty_witnesses(&zkbin).unwrap(), &zkbin);
+        // Check if we already have its VerifyingKey
+        let zkas_hash = blake3::hash(&zkbin_bytes);
+        if let Some(vk) = self.get(&zkbin, &zkas_hash)? {
+            return Ok((zkbin, zkas_hash, vk, None))
+        }
 
         // The second one is the serialized VerifyingKey for it
-        let mut vk_buf = Cursor::new(vkbin);
-        let vk = VerifyingKey::read::<Cursor<Vec<u8>>, ZkCircuit>(&mut vk_buf, circuit).unwrap();
+        let vk = read_verifying_key(&zkbin, vkbin.clone())?;
 
-        Ok((zkbin, vk))
+        Ok((zkbin, zkas_hash, vk, Some(vkbin)))
+    }
+}
+
+/// Auxiliary function to read a serialized [`VerifyingKey`] of provided `ZkBinary`.
+fn read_verifying_key(zkbin: &ZkBinary, vk_bytes: Vec<u8>) -> Result<VerifyingKey> {
+    // Construct the circuit to be able to read the VerifyingKey
+    let circuit = ZkCircuit::new(empty_witnesses(zkbin)?, zkbin);
+    let mut vk_buf = Cursor::new(vk_bytes);
+    Ok(VerifyingKey::read::<Cursor<Vec<u8>>, ZkCircuit>(&mut vk_buf, circuit)?)
+}
+
+/// Sparse Merkle tree committing to all the contracts state records, meaning
+/// every `(tree, key, value)` record of the wasm, contracts pointers and
+/// contracts states trees. Records are grouped in buckets by their
+/// `(tree, key)` hash, and each non-empty bucket is a leaf of the tree,
+/// hashing its sorted records hashes. Contracts own SMTs nodes are not
+/// tracked, since they are already committed through their roots records.
+pub type StateSmt<'a, S> =
+    SparseMerkleTree<'a, STATE_SMT_DEPTH, { STATE_SMT_DEPTH + 1 }, pallas::Base, StateHasher, S>;
+
+/// Hasher of the contracts [`StateSmt`] nodes. The tree is never proven in
+/// ZK, so we use BLAKE2b instead of Poseidon.
+#[derive(Clone, Debug)]
+pub struct StateHasher;
+
+impl FieldHasher<pallas::Base, 2> for StateHasher {
+    fn hash(&self, inputs: [pallas::Base; 2]) -> pallas::Base {
+        hash_to_base(STATE_SMT_NODE_PERSONALIZATION, &[&inputs[0].to_repr(), &inputs[1].to_repr()])
+    }
+
+    fn hasher() -> Self {
+        Self
+    }
+}
+
+/// Auxiliary function to retrieve the contracts [`StateSmt`] empty nodes.
+fn state_smt_empty_nodes() -> &'static [pallas::Base; STATE_SMT_DEPTH + 1] {
+    static EMPTY_NODES: OnceLock<[pallas::Base; STATE_SMT_DEPTH + 1]> = OnceLock::new();
+    EMPTY_NODES.get_or_init(|| {
+        gen_empty_nodes::<{ STATE_SMT_DEPTH + 1 }, _, _>(&StateHasher, pallas::Base::ZERO)
+    })
+}
+
+/// Auxiliary function to parse a serialized contracts [`StateSmt`] node.
+fn parse_state_smt_node(bytes: &[u8]) -> Result<pallas::Base> {
+    let Ok(repr) = bytes.try_into() else {
+        return Err(Error::ParseFailed("Invalid state SMT node length"))
+    };
+    match Option::from(pallas::Base::from_repr(repr)) {
+        Some(node) => Ok(node),
+        None => Err(Error::ParseFailed("Noncanonical state SMT node")),
+    }
+}
+
+/// Auxiliary function to parse a contracts [`StateSmt`] bucket index key.
+fn parse_state_bucket_index(bytes: &[u8]) -> Result<u32> {
+    match bytes.try_into() {
+        Ok(bytes) => Ok(u32::from_be_bytes(bytes)),
+        Err(_) => Err(Error::ParseFailed("Invalid state bucket key length")),
+    }
+}
+
+/// Auxiliary function to compute the contracts [`StateSmt`] leaf of
+/// provided serialized bucket.
+fn state_bucket_leaf(bucket: &[u8]) -> pallas::Base {
+    hash_to_base(STATE_SMT_LEAF_PERSONALIZATION, &[bucket])
+}
+
+/// Contracts [`StateSmt`] storage over a sled overlay
+struct StateSmtOverlayStorage<'a>(&'a mut SledDbOverlay);
+
+impl StorageAdapter for StateSmtOverlayStorage<'_> {
+    type Value = pallas::Base;
+
+    fn put(&mut self, key: BigUint, value: pallas::Base) -> ContractResult {
+        if let Err(e) = self.0.insert(SLED_STATE_SMT_TREE, &key.to_bytes_le(), &value.to_repr()) {
+            error!(target: "blockchain::contractstoreoverlay", "Failed to insert state SMT node: {}", e);
+            return Err(ContractError::SmtPutFailed)
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &BigUint) -> Option<pallas::Base> {
+        let bytes = self.0.get(SLED_STATE_SMT_TREE, &key.to_bytes_le()).ok()??;
+        parse_state_smt_node(&bytes).ok()
+    }
+
+    fn del(&mut self, key: &BigUint) -> ContractResult {
+        if let Err(e) = self.0.remove(SLED_STATE_SMT_TREE, &key.to_bytes_le()) {
+            error!(target: "blockchain::contractstoreoverlay", "Failed to remove state SMT node: {}", e);
+            return Err(ContractError::SmtDelFailed)
+        }
+        Ok(())
+    }
+}
+
+/// Contracts [`StateSmt`] storage over its sled tree
+struct StateSmtTreeStorage<'a>(&'a sled::Tree);
+
+impl StorageAdapter for StateSmtTreeStorage<'_> {
+    type Value = pallas::Base;
+
+    fn put(&mut self, key: BigUint, value: pallas::Base) -> ContractResult {
+        if let Err(e) = self.0.insert(key.to_bytes_le(), &value.to_repr()[..]) {
+            error!(target: "blockchain::contractstore", "Failed to insert state SMT node: {}", e);
+            return Err(ContractError::SmtPutFailed)
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &BigUint) -> Option<pallas::Base> {
+        let bytes = self.0.get(key.to_bytes_le()).ok()??;
+        parse_state_smt_node(&bytes).ok()
+    }
+
+    fn del(&mut self, key: &BigUint) -> ContractResult {
+        if let Err(e) = self.0.remove(key.to_bytes_le()) {
+            error!(target: "blockchain::contractstore", "Failed to remove state SMT node: {}", e);
+            return Err(ContractError::SmtDelFailed)
+        }
+        Ok(())
+    }
+}
+
+/// Compute the contracts [`StateSmt`] root from scratch, using provided
+/// state buckets tree records, without storing the tree nodes.
+pub fn state_root_from_buckets(buckets: &[(Vec<u8>, Vec<u8>)]) -> Result<pallas::Base> {
+    let mut leaves = Vec::with_capacity(buckets.len());
+    for (key, value) in buckets {
+        leaves.push((parse_state_bucket_index(key)?, state_bucket_leaf(value)));
+    }
+    leaves.sort_unstable_by_key(|(index, _)| *index);
+
+    Ok(state_subtree_root(&leaves, 0))
+}
+
+/// Auxiliary function to compute the root of a contracts [`StateSmt`]
+/// subtree at provided level, containing provided sorted leaves.
+fn state_subtree_root(leaves: &[(u32, pallas::Base)], level: usize) -> pallas::Base {
+    if leaves.is_empty() {
+        return state_smt_empty_nodes()[level]
+    }
+    if level == STATE_SMT_DEPTH {
+        return leaves[0].1
+    }
+
+    // Left subtree leaves have the bit of this level unset
+    let bit = STATE_SMT_DEPTH - 1 - level;
+    let split = leaves.partition_point(|(index, _)| (index >> bit) & 1 == 0);
+    let left = state_subtree_root(&leaves[..split], level + 1);
+    let right = state_subtree_root(&leaves[split..], level + 1);
+    StateHasher.hash([left, right])
+}
+
+/// Auxiliary function to retrieve the contracts [`StateSmt`] root from
+/// provided sled overlay.
+fn overlay_state_root(overlay: &SledDbOverlay) -> Result<pallas::Base> {
+    match overlay.get(SLED_STATE_SMT_TREE, STATE_SMT_ROOT_KEY)? {
+        Some(bytes) => parse_state_smt_node(&bytes),
+        None => Ok(state_smt_empty_nodes()[0]),
+    }
+}
+
+/// Auxiliary function to update the contracts [`StateSmt`] of provided
+/// sled overlay, for a record getting written with provided value, or
+/// removed. Only the record bucket and its path get rewritten.
+fn update_state_smt(
+    overlay: &mut SledDbOverlay,
+    tree: &[u8],
+    key: &[u8],
+    value: Option<&[u8]>,
+) -> Result<()> {
+    let record = *blake3::hash(&serialize(&(tree.to_vec(), key.to_vec()))).as_bytes();
+    let index = u32::from_be_bytes(record[..4].try_into().unwrap());
+    let bucket_key = index.to_be_bytes();
+
+    // Each bucket holds its records hashes sorted, so it doesn't depend
+    // on the order they were written.
+    let mut bucket: Vec<([u8; 32], [u8; 32])> =
+        match overlay.get(SLED_STATE_BUCKETS_TREE, &bucket_key)? {
+            Some(bytes) => deserialize(&bytes)?,
+            None => vec![],
+        };
+    let position = bucket.binary_search_by(|(r, _)| r.cmp(&record));
+    match (position, value) {
+        (Ok(i), Some(value)) => bucket[i].1 = state_record_hash(tree, key, value),
+        (Err(i), Some(value)) => bucket.insert(i, (record, state_record_hash(tree, key, value))),
+        (Ok(i), None) => {
+            bucket.remove(i);
+        }
+        (Err(_), None) => return Ok(()),
+    }
+
+    let position = pallas::Base::from(index as u64);
+    if bucket.is_empty() {
+        overlay.remove(SLED_STATE_BUCKETS_TREE, &bucket_key)?;
+        let storage = StateSmtOverlayStorage(overlay);
+        let mut smt = StateSmt::new(storage, StateHasher, state_smt_empty_nodes());
+        smt.remove_leaves(vec![(position, pallas::Base::ZERO)])?;
+        return Ok(())
+    }
+
+    let bucket = serialize(&bucket);
+    overlay.insert(SLED_STATE_BUCKETS_TREE, &bucket_key, &bucket)?;
+    let storage = StateSmtOverlayStorage(overlay);
+    let mut smt = StateSmt::new(storage, StateHasher, state_smt_empty_nodes());
+    smt.insert_batch(vec![(position, state_bucket_leaf(&bucket))])?;
+
+    Ok(())
+}
+
+/// Auxiliary function to compute the hash of a `(tree, key, value)` record.
+fn state_record_hash(tree: &[u8], key: &[u8], value: &[u8]) -> [u8; 32] {
+    *blake3::hash(&serialize(&(tree.to_vec(), key.to_vec(), value.to_vec()))).as_bytes()
+}
+
+/// Insert a record into a contracts related tree of provided sled overlay,
+/// updating the contracts [`StateSmt`] accordingly. All writes to the
+/// wasm, contracts pointers and contracts states trees, except contracts
+/// own SMTs nodes, must go through this function, or [`state_overlay_remove`],
+/// so the state root stays consistent with the actual state.
+pub fn state_overlay_insert(
+    overlay: &mut SledDbOverlay,
+    tree: &[u8],
+    key: &[u8],
+    value: &[u8],
+) -> Result<Option<sled::IVec>> {
+    update_state_smt(overlay, tree, key, Some(value))?;
+    Ok(overlay.insert(tree, key, value)?)
+}
+
+/// Remove a record from a contracts related tree of provided sled overlay,
+/// updating the contracts [`StateSmt`] accordingly.
+pub fn state_overlay_remove(
+    overlay: &mut SledDbOverlay,
+    tree: &[u8],
+    key: &[u8],
+) -> Result<Option<sled::IVec>> {
+    if !overlay.contains_key(tree, key)? {
+        return Ok(None)
+    }
+    update_state_smt(overlay, tree, key, None)?;
+    Ok(overlay.remove(tree, key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blockchain::{Blockchain, BlockchainOverlay, BlockchainOverlayPtr},
+        zkas::{Analyzer, Compiler, Lexer, Parser},
+    };
+
+    /// Write or remove provided records in the contracts pointers tree of
+    /// provided overlay, returning the resulting contracts state root.
+    fn write_state_records(
+        overlay: &BlockchainOverlayPtr,
+        records: &[(u32, Option<u32>)],
+    ) -> Result<pallas::Base> {
+        let lock = overlay.lock().unwrap();
+        for (key, value) in records {
+            let mut db = lock.overlay.lock().unwrap();
+            match value {
+                Some(value) => state_overlay_insert(
+                    &mut db,
+                    SLED_CONTRACTS_TREE,
+                    &serialize(key),
+                    &serialize(value),
+                )?,
+                None => state_overlay_remove(&mut db, SLED_CONTRACTS_TREE, &serialize(key))?,
+            };
+        }
+        lock.contracts.get_state_root()
+    }
+
+    /// Compile the arithmetic zkas circuit and build its `VerifyingKey`,
+    /// returning its bincode along with the key and its serialized form.
+    fn arithmetic_circuit() -> Result<(Vec<u8>, ZkBinary, VerifyingKey, Vec<u8>)> {
+        let filename = "arithmetic.zk";
+        let source = include_str!("../../proof/arithmetic.zk").replace('\t', "    ");
+        let tokens = Lexer::new(filename, source.chars()).lex()?;
+        let (namespace, k, constants, witnesses, statements) =
+            Parser::new(filename, source.chars(), tokens).parse()?;
+        let mut analyzer =
+            Analyzer::new(filename, source.chars(), constants, witnesses, statements);
+        analyzer.analyze_types()?;
+        let bincode = Compiler::new(
+            filename,
+            source.chars(),
+            namespace,
+            k,
+            analyzer.constants,
+            analyzer.witnesses,
+            analyzer.statements,
+            analyzer.literals,
+            false,
+        )
+        .compile()?;
+
+        let zkbin = ZkBinary::decode(&bincode)?;
+        let circuit = ZkCircuit::new(empty_witnesses(&zkbin)?, &zkbin);
+        let vk = VerifyingKey::build(zkbin.k, &circuit);
+        let mut vk_bytes = vec![];
+        vk.write(&mut vk_bytes)?;
+
+        Ok((bincode, zkbin, vk, vk_bytes))
+    }
+
+    /// Serialize provided `VerifyingKey`, so keys can be compared.
+    fn vk_bytes(vk: &VerifyingKey) -> Vec<u8> {
+        let mut bytes = vec![];
+        vk.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn verifying_keys_cache() -> Result<()> {
+        let (bincode, zkbin, vk, vk_buf) = arithmetic_circuit()?;
+        let zkas_hash = blake3::hash(&bincode);
+
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        let cache = &blockchain.contracts.vks;
+
+        // Keys written in a discarded overlay never reach the cache
+        let overlay = BlockchainOverlay::new(&blockchain)?;
+        overlay.lock().unwrap().contracts.insert_vk(&zkas_hash, &vk, &vk_buf);
+        assert_eq!(
+            overlay.lock().unwrap().contracts.get_serialized_vk(&zkas_hash)?,
+            Some(vk_buf.clone())
+        );
+        assert_eq!(cache.get_serialized(&zkas_hash)?, None);
+        drop(overlay);
+        let overlay = BlockchainOverlay::new(&blockchain)?;
+        assert_eq!(overlay.lock().unwrap().contracts.get_serialized_vk(&zkas_hash)?, None);
+
+        // Full clones carry the pending keys
+        overlay.lock().unwrap().contracts.insert_vk(&zkas_hash, &vk, &vk_buf);
+        let clone = overlay.lock().unwrap().full_clone()?;
+        assert_eq!(
+            clone.lock().unwrap().contracts.get_serialized_vk(&zkas_hash)?,
+            Some(vk_buf.clone())
+        );
+
+        // Applying the overlay populates the cache, so next reads hit it
+        overlay.lock().unwrap().apply()?;
+        assert_eq!(cache.get_serialized(&zkas_hash)?, Some(vk_buf.clone()));
+        assert_eq!(cache.memory_len(), 1);
+        let cached = cache.get(&zkbin, &zkas_hash)?.unwrap();
+        assert_eq!(vk_bytes(&cached), vk_buf);
+
+        // A redeploy invalidation only happens once its overlay is applied
+        let overlay = BlockchainOverlay::new(&blockchain)?;
+        overlay.lock().unwrap().contracts.remove_vk(&zkas_hash);
+        assert_eq!(overlay.lock().unwrap().contracts.get_serialized_vk(&zkas_hash)?, None);
+        assert_eq!(cache.get_serialized(&zkas_hash)?, Some(vk_buf.clone()));
+        overlay.lock().unwrap().apply()?;
+        assert_eq!(cache.get_serialized(&zkas_hash)?, None);
+        assert_eq!(cache.memory_len(), 0);
+        assert!(cache.get(&zkbin, &zkas_hash)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verifying_keys_cache_persistence() -> Result<()> {
+        let (bincode, zkbin, vk, vk_buf) = arithmetic_circuit()?;
+        let zkas_hash = blake3::hash(&bincode);
+
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        let overlay = BlockchainOverlay::new(&blockchain)?;
+        overlay.lock().unwrap().contracts.insert_vk(&zkas_hash, &vk, &vk_buf);
+        overlay.lock().unwrap().apply()?;
+        drop(overlay);
+        drop(blockchain);
+
+        // A restarted node reads the key from its persisted form
+        let blockchain = Blockchain::new(&sled_db)?;
+        let cache = &blockchain.contracts.vks;
+        assert_eq!(cache.memory_len(), 0);
+        let cached = cache.get(&zkbin, &zkas_hash)?.unwrap();
+        assert_eq!(vk_bytes(&cached), vk_buf);
+        assert_eq!(cache.memory_len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verifying_keys_cache_bound() -> Result<()> {
+        let (_, _, vk, vk_buf) = arithmetic_circuit()?;
+
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let cache = VerifyingKeyCache::new(&sled_db)?;
+        let hashes: Vec<blake3::Hash> =
+            (0..MAX_CACHED_VERIFYING_KEYS + 5).map(|i| blake3::hash(&i.to_le_bytes())).collect();
+
+        for (i, zkas_hash) in hashes.iter().enumerate() {
+            cache.insert(zkas_hash, &vk, &vk_buf)?;
+            // Keep the first key recently used, so it doesn't get evicted
+            if i > 0 {
+                assert!(cache.keys.lock().unwrap().get(&hashes[0]).is_some());
+            }
+        }
+
+        // Memory is bounded, evicting the least recently used keys,
+        // while all of them remain persisted.
+        assert_eq!(cache.memory_len(), MAX_CACHED_VERIFYING_KEYS);
+        let memory = cache.keys.lock().unwrap();
+        assert!(memory.keys.contains_key(&hashes[0]));
+        assert!(!memory.keys.contains_key(&hashes[1]));
+        assert!(memory.keys.contains_key(&hashes[MAX_CACHED_VERIFYING_KEYS + 4]));
+        drop(memory);
+        for zkas_hash in &hashes {
+            assert!(cache.get_serialized(zkas_hash)?.is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn state_smt() -> Result<()> {
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        let empty_root = blockchain.contracts.get_state_root()?;
+        assert_eq!(blockchain.contracts.compute_state_root()?, empty_root);
+
+        // Records written in different orders produce the same root
+        let records: Vec<(u32, Option<u32>)> = (0..64).map(|i| (i, Some(i * 2))).collect();
+        let reversed: Vec<(u32, Option<u32>)> = records.iter().rev().cloned().collect();
+        let overlay_a = BlockchainOverlay::new(&blockchain)?;
+        let overlay_b = BlockchainOverlay::new(&blockchain)?;
+        let root = write_state_records(&overlay_a, &records)?;
+        assert_ne!(root, empty_root);
+        assert_eq!(write_state_records(&overlay_b, &reversed)?, root);
+
+        // Updating a record changes the root, and restoring it reverts it
+        assert_ne!(write_state_records(&overlay_a, &[(7, Some(0))])?, root);
+        assert_eq!(write_state_records(&overlay_a, &[(7, Some(14))])?, root);
+
+        // Removing all the records leads back to the empty root
+        let removals: Vec<(u32, Option<u32>)> = (0..64).map(|i| (i, None)).collect();
+        assert_eq!(write_state_records(&overlay_a, &removals)?, empty_root);
+        assert_eq!(write_state_records(&overlay_a, &[(64, None)])?, empty_root);
+
+        // Applied root matches the one recomputed from the buckets
+        overlay_b.lock().unwrap().apply()?;
+        assert_eq!(blockchain.contracts.get_state_root()?, root);
+        assert_eq!(blockchain.contracts.compute_state_root()?, root);
+
+        // Lost tree nodes are rebuilt from the buckets
+        blockchain.contracts.state_smt.clear()?;
+        assert_eq!(blockchain.contracts.get_state_root()?, empty_root);
+        assert_eq!(blockchain.contracts.compute_state_root()?, root);
+        blockchain.contracts.rebuild_state_smt()?;
+        assert_eq!(blockchain.contracts.get_state_root()?, root);
+
+        Ok(())
     }
 }
@@ -16,22 +16,30 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{fmt, str::FromStr};
+use std::{
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
 
 use darkfi_sdk::{
     blockchain::block_version,
     crypto::{MerkleNode, MerkleTree},
     hex::decode_hex_arr,
+    pasta::{group::ff::Field, pallas},
     AsHex,
 };
 #[cfg(feature = "async-serial")]
-use darkfi_serial::async_trait;
-use darkfi_serial::{deserialize, serialize, Encodable, SerialDecodable, SerialEncodable};
+use darkfi_serial::{async_trait, AsyncDecodable, AsyncEncodable, AsyncRead, AsyncWrite};
+use darkfi_serial::{
+    deserialize, serialize, Decodable, Encodable, SerialDecodable, SerialEncodable,
+};
+use num_bigint::BigUint;
 use sled_overlay::sled;
 
 use crate::{util::time::Timestamp, Error, Result};
 
-use super::{parse_record, parse_u32_key_record, SledDbOverlayPtr};
+use super::{merge_mining::MoneroPowData, parse_record, parse_u32_key_record, SledDbOverlayPtr};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, SerialEncodable, SerialDecodable)]
 // We have to introduce a type rather than using an alias so we can restrict API access
@@ -66,8 +74,30 @@ impl fmt::Display for HeaderHash {
     }
 }
 
-/// This struct represents a tuple of the form (version, previous, height, timestamp, nonce, merkle_tree).
+/// Block Proof of Work data, defining how the header PoW is verified
 #[derive(Debug, Clone, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub enum PowData {
+    /// Native RandomX PoW over the header hash, keyed by the previous block hash
+    DarkFi,
+    /// Monero merge mining auxiliary PoW, committing to the header template hash
+    Monero(MoneroPowData),
+}
+
+/// First block version committing to the contracts state root and the
+/// PoW data in its header. Older headers don't encode them, so their
+/// hashes stay the same.
+pub const BLOCK_VERSION_2: u8 = 2;
+
+/// Size of the PoW hashing blob of [`BLOCK_VERSION_2`] headers
+pub const POW_BLOB_SIZE: usize = 76;
+
+/// Offset of the nonce in the PoW hashing blob, same as in the Monero one,
+/// so stock RandomX miners roll it.
+pub const POW_BLOB_NONCE_OFFSET: usize = 39;
+
+/// This struct represents a tuple of the form (version, previous, height, timestamp, nonce, merkle_tree).
+/// Since [`BLOCK_VERSION_2`], it also contains the state root and the PoW data.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     /// Block version
     pub version: u8,
@@ -81,13 +111,27 @@ pub struct Header {
     pub nonce: u64,
     /// Merkle tree root of the transactions hashes contained in this block
     pub root: MerkleNode,
+    /// Contracts state SMT root after applying this block transactions.
+    /// Headers prior to [`BLOCK_VERSION_2`] don't commit to it, so it's zeroed.
+    pub state_root: pallas::Base,
+    /// Block Proof of Work data
+    pub pow_data: PowData,
 }
 
 impl Header {
     pub fn new(previous: HeaderHash, height: u32, timestamp: Timestamp, nonce: u64) -> Self {
         let version = block_version(height);
         let root = MerkleTree::new(1).root(0).unwrap();
-        Self { version, previous, height, timestamp, nonce, root }
+        Self {
+            version,
+            previous,
+            height,
+            timestamp,
+            nonce,
+            root,
+            state_root: pallas::Base::ZERO,
+            pow_data: PowData::DarkFi,
+        }
     }
 
     /// Compute the header's hash
@@ -101,6 +145,129 @@ impl Header {
 
         HeaderHash(hasher.finalize().into())
     }
+
+    /// Compute the header's PoW hashing blob, following the Monero hashing
+    /// blob layout, so stock RandomX miners can mine [`BLOCK_VERSION_2`]
+    /// headers. The blob contains the header version and height, the hash
+    /// of the header with a zero nonce, and the nonce in little-endian at
+    /// [`POW_BLOB_NONCE_OFFSET`]. Miners only roll its lowest 4 bytes, so
+    /// pools can use the rest as an extra nonce for each worker.
+    pub fn pow_blob(&self) -> [u8; POW_BLOB_SIZE] {
+        let mut header = self.clone();
+        header.nonce = 0;
+
+        let mut blob = [0u8; POW_BLOB_SIZE];
+        blob[0] = self.version;
+        blob[1..5].copy_from_slice(&self.height.to_le_bytes());
+        blob[7..POW_BLOB_NONCE_OFFSET].copy_from_slice(header.hash().inner());
+        blob[POW_BLOB_NONCE_OFFSET..POW_BLOB_NONCE_OFFSET + 8]
+            .copy_from_slice(&self.nonce.to_le_bytes());
+        blob
+    }
+
+    /// Compute the header's native PoW RandomX input, which is its PoW
+    /// hashing blob since [`BLOCK_VERSION_2`], and its hash before it.
+    pub fn pow_input(&self) -> Vec<u8> {
+        if self.version >= BLOCK_VERSION_2 {
+            return self.pow_blob().to_vec()
+        }
+        self.hash().inner().to_vec()
+    }
+
+    /// Interpret provided RandomX output of the header's native PoW as the
+    /// number compared against the mining target. Since [`BLOCK_VERSION_2`],
+    /// it is interpreted as little-endian, like Monero does, so stock RandomX
+    /// miners can check their shares, and as big-endian before it.
+    pub fn pow_output(&self, out_hash: &[u8]) -> BigUint {
+        if self.version >= BLOCK_VERSION_2 {
+            return BigUint::from_bytes_le(out_hash)
+        }
+        BigUint::from_bytes_be(out_hash)
+    }
+
+    /// Compute the header's template hash, which excludes its nonce and
+    /// PoW data, so merge mined chains can commit to it before mining.
+    pub fn template_hash(&self) -> HeaderHash {
+        let mut header = self.clone();
+        header.nonce = 0;
+        header.pow_data = PowData::DarkFi;
+        header.hash()
+    }
+}
+
+impl Encodable for Header {
+    fn encode<S: Write>(&self, s: &mut S) -> std::io::Result<usize> {
+        let mut len = 0;
+        len += self.version.encode(s)?;
+        len += self.previous.encode(s)?;
+        len += self.height.encode(s)?;
+        len += self.timestamp.encode(s)?;
+        len += self.nonce.encode(s)?;
+        len += self.root.encode(s)?;
+        if self.version >= BLOCK_VERSION_2 {
+            len += self.state_root.encode(s)?;
+            len += self.pow_data.encode(s)?;
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "async-serial")]
+#[async_trait]
+impl AsyncEncodable for Header {
+    async fn encode_async<S: AsyncWrite + Unpin + Send>(
+        &self,
+        s: &mut S,
+    ) -> std::io::Result<usize> {
+        let mut len = 0;
+        len += self.version.encode_async(s).await?;
+        len += self.previous.encode_async(s).await?;
+        len += self.height.encode_async(s).await?;
+        len += self.timestamp.encode_async(s).await?;
+        len += self.nonce.encode_async(s).await?;
+        len += self.root.encode_async(s).await?;
+        if self.version >= BLOCK_VERSION_2 {
+            len += self.state_root.encode_async(s).await?;
+            len += self.pow_data.encode_async(s).await?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Header {
+    fn decode<D: Read>(d: &mut D) -> std::io::Result<Self> {
+        let version = u8::decode(d)?;
+        let previous = HeaderHash::decode(d)?;
+        let height = u32::decode(d)?;
+        let timestamp = Timestamp::decode(d)?;
+        let nonce = u64::decode(d)?;
+        let root = MerkleNode::decode(d)?;
+        let (state_root, pow_data) = if version >= BLOCK_VERSION_2 {
+            (pallas::Base::decode(d)?, PowData::decode(d)?)
+        } else {
+            (pallas::Base::ZERO, PowData::DarkFi)
+        };
+        Ok(Self { version, previous, height, timestamp, nonce, root, state_root, pow_data })
+    }
+}
+
+#[cfg(feature = "async-serial")]
+#[async_trait]
+impl AsyncDecodable for Header {
+    async fn decode_async<D: AsyncRead + Unpin + Send>(d: &mut D) -> std::io::Result<Self> {
+        let version = u8::decode_async(d).await?;
+        let previous = HeaderHash::decode_async(d).await?;
+        let height = u32::decode_async(d).await?;
+        let timestamp = Timestamp::decode_async(d).await?;
+        let nonce = u64::decode_async(d).await?;
+        let root = MerkleNode::decode_async(d).await?;
+        let (state_root, pow_data) = if version >= BLOCK_VERSION_2 {
+            (pallas::Base::decode_async(d).await?, PowData::decode_async(d).await?)
+        } else {
+            (pallas::Base::ZERO, PowData::DarkFi)
+        };
+        Ok(Self { version, previous, height, timestamp, nonce, root, state_root, pow_data })
+    }
 }
 
 impl Default for Header {
@@ -118,7 +285,7 @@ impl Default for Header {
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = format!(
-            "{} {{\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n}}",
+            "{} {{\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n\t{}: {}\n}}",
             "Header",
             "Hash",
             self.hash(),
@@ -134,6 +301,13 @@ impl fmt::Display for Header {
             self.nonce,
             "Root",
             self.root,
+            "State root",
+            format!("{:?}", self.state_root),
+            "PoW",
+            match self.pow_data {
+                PowData::DarkFi => "DarkFi",
+                PowData::Monero(_) => "Monero merge mining",
+            },
         );
 
         write!(f, "{}", s)
@@ -142,6 +316,7 @@ impl fmt::Display for Header {
 
 pub const SLED_HEADER_TREE: &[u8] = b"_headers";
 pub const SLED_SYNC_HEADER_TREE: &[u8] = b"_sync_headers";
+pub const SLED_MONERO_SEED_TREE: &[u8] = b"_monero_seeds";
 
 /// The `HeaderStore` is a structure representing all `sled` trees related
 /// to storing the blockchain's blocks's header information.
@@ -154,6 +329,10 @@ pub struct HeaderStore {
     /// where the key is the height number, and the value is the serialized
     /// header.
     pub sync: sled::Tree,
+    /// The `sled` tree storing the RandomX keys merge mined headers used,
+    /// where the key is the Monero seed height, and the value is the
+    /// RandomX key.
+    pub monero_seeds: sled::Tree,
 }
 
 impl HeaderStore {
@@ -161,7 +340,8 @@ impl HeaderStore {
     pub fn new(db: &sled::Db) -> Result<Self> {
         let main = db.open_tree(SLED_HEADER_TREE)?;
         let sync = db.open_tree(SLED_SYNC_HEADER_TREE)?;
-        Ok(Self { main, sync })
+        let monero_seeds = db.open_tree(SLED_MONERO_SEED_TREE)?;
+        Ok(Self { main, sync, monero_seeds })
     }
 
     /// Insert a slice of [`Header`] into the store's main tree.
@@ -350,6 +530,7 @@ pub struct HeaderStoreOverlay(SledDbOverlayPtr);
 impl HeaderStoreOverlay {
     pub fn new(overlay: &SledDbOverlayPtr) -> Result<Self> {
         overlay.lock().unwrap().open_tree(SLED_HEADER_TREE, true)?;
+        overlay.lock().unwrap().open_tree(SLED_MONERO_SEED_TREE, true)?;
         Ok(Self(overlay.clone()))
     }
 
@@ -393,4 +574,28 @@ impl HeaderStoreOverlay {
 
         Ok(ret)
     }
+
+    /// Insert the RandomX key merge mined headers use for provided Monero
+    /// seed height into the overlay.
+    pub fn insert_monero_seed(&self, seed_height: u64, randomx_key: &[u8; 32]) -> Result<()> {
+        self.0.lock().unwrap().insert(
+            SLED_MONERO_SEED_TREE,
+            &seed_height.to_be_bytes(),
+            randomx_key,
+        )?;
+        Ok(())
+    }
+
+    /// Fetch the RandomX key merge mined headers use for provided Monero
+    /// seed height from the overlay, if any merge mined header used it.
+    pub fn get_monero_seed(&self, seed_height: u64) -> Result<Option<[u8; 32]>> {
+        let lock = self.0.lock().unwrap();
+        let Some(found) = lock.get(SLED_MONERO_SEED_TREE, &seed_height.to_be_bytes())? else {
+            return Ok(None)
+        };
+        match <[u8; 32]>::try_from(&found[..]) {
+            Ok(randomx_key) => Ok(Some(randomx_key)),
+            Err(_) => Err(Error::PoWInvalidMergeMiningData),
+        }
+    }
 }
@@ -0,0 +1,428 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A DarkFi block can be merge mined with Monero, by committing its header
+//! template hash in the merge mining tag of a Monero block coinbase
+//! transaction. The Monero block PoW then counts as the DarkFi block PoW,
+//! which is proven using the Monero block header, its coinbase transaction,
+//! and the Merkle branch linking the coinbase to the Monero block
+//! transactions tree root.
+//!
+//! When more chains are merge mined, the tag commits to the root of a
+//! Merkle tree of their hashes, so the DarkFi header template hash branch
+//! to that root is also provided, following the p2pool merge mining
+//! specification. The tag data encodes the number of merge mined chains
+//! and a nonce, which along with the DarkFi chain id define the slot of
+//! the header template hash in the tree, so a Monero block can only
+//! commit to a single DarkFi block.
+
+use darkfi_serial::{SerialDecodable, SerialEncodable};
+use monero::{
+    blockdata::transaction::{ExtraField, SubField, TxIn},
+    consensus::{deserialize, serialize},
+    Block, Hash, Transaction, VarInt,
+};
+
+use super::HeaderHash;
+use crate::{Error, Result};
+
+/// Number of Monero blocks each RandomX key(seed hash) is used for
+pub const SEEDHASH_EPOCH_BLOCKS: u64 = 2048;
+
+/// Number of Monero blocks after a seed block its hash becomes the RandomX key
+pub const SEEDHASH_EPOCH_LAG: u64 = 64;
+
+/// Monero merge mining auxiliary Proof of Work data.
+/// Monero structures are kept in their consensus encoding.
+#[derive(Debug, Clone, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct MoneroPowData {
+    /// Consensus encoded Monero block header
+    pub header: Vec<u8>,
+    /// RandomX key(seed hash) the Monero block was mined with, which is
+    /// the hash of the Monero block at its seed height
+    pub randomx_key: [u8; 32],
+    /// Number of transactions in the Monero block, including the coinbase
+    pub transaction_count: u64,
+    /// Consensus encoded Monero coinbase transaction
+    pub coinbase_tx: Vec<u8>,
+    /// Merkle branch of the coinbase transaction hash to the Monero
+    /// block transactions tree root
+    pub coinbase_branch: Vec<[u8; 32]>,
+    /// Merkle branch of the header template hash to the merge mining tag root
+    pub aux_branch: Vec<[u8; 32]>,
+}
+
+impl MoneroPowData {
+    /// Generate the auxiliary PoW data of provided consensus encoded Monero
+    /// block, mined using given RandomX key, along with the header template
+    /// hash merge mining Merkle branch.
+    pub fn new(block: &[u8], randomx_key: [u8; 32], aux_branch: Vec<[u8; 32]>) -> Result<Self> {
+        let Ok(block) = deserialize::<Block>(block) else {
+            return Err(Error::ParseFailed("Invalid Monero block"))
+        };
+
+        let mut hashes = Vec::with_capacity(block.tx_hashes.len() + 1);
+        hashes.push(block.miner_tx.hash());
+        hashes.extend_from_slice(&block.tx_hashes);
+
+        Ok(Self {
+            header: serialize(&block.header),
+            randomx_key,
+            transaction_count: hashes.len() as u64,
+            coinbase_tx: serialize(&block.miner_tx),
+            coinbase_branch: coinbase_merkle_branch(&hashes).iter().map(|h| h.0).collect(),
+            aux_branch,
+        })
+    }
+
+    /// Auxiliary function to decode the Monero coinbase transaction.
+    fn coinbase(&self) -> Result<Transaction> {
+        match deserialize::<Transaction>(&self.coinbase_tx) {
+            Ok(tx) => Ok(tx),
+            Err(_) => Err(Error::PoWInvalidMergeMiningData),
+        }
+    }
+
+    /// Retrieve the Monero block height, from its coinbase transaction
+    /// generation input.
+    pub fn height(&self) -> Result<u64> {
+        match self.coinbase()?.prefix.inputs.as_slice() {
+            [TxIn::Gen { height }] => Ok(height.0),
+            _ => Err(Error::PoWInvalidMergeMiningData),
+        }
+    }
+
+    /// Compute the Monero seed height of the Monero block, whose block
+    /// hash must be the RandomX key.
+    pub fn seed_height(&self) -> Result<u64> {
+        Ok(seed_height(self.height()?))
+    }
+
+    /// Compute the Monero block hashing blob, which is the RandomX input
+    /// of the Monero PoW. The coinbase transaction hash is hashed with its
+    /// Merkle branch, which must match the Monero block transactions count,
+    /// to produce the transactions tree root.
+    pub fn hashing_blob(&self) -> Result<Vec<u8>> {
+        // Coinbase is always the leftmost leaf, so the branch
+        // length is the Monero transactions tree depth.
+        if self.transaction_count == 0 ||
+            self.coinbase_branch.len() as u32 != self.transaction_count.ilog2()
+        {
+            return Err(Error::PoWInvalidMergeMiningData)
+        }
+
+        let mut root = self.coinbase()?.hash();
+        for sibling in &self.coinbase_branch {
+            root = keccak_pair(&root, &Hash(*sibling));
+        }
+
+        let mut blob = self.header.clone();
+        blob.extend_from_slice(&root.0);
+        blob.extend_from_slice(&serialize(&VarInt(self.transaction_count)));
+
+        Ok(blob)
+    }
+
+    /// Verify the Monero coinbase transaction merge mining tag commits
+    /// to provided header template hash, at the slot of provided chain id.
+    pub fn verify_commitment(
+        &self,
+        template_hash: &HeaderHash,
+        chain_id: &HeaderHash,
+    ) -> Result<bool> {
+        let coinbase = self.coinbase()?;
+        let Ok(extra) = ExtraField::try_parse(&coinbase.prefix.extra) else {
+            return Err(Error::PoWInvalidMergeMiningData)
+        };
+        let Some((data, tag_root)) = extra.0.iter().find_map(|field| match field {
+            SubField::MergeMining(data, root) => Some((data.0, *root)),
+            _ => None,
+        }) else {
+            return Ok(false)
+        };
+
+        // Decode the tag data and find our chain slot
+        let Some((n_aux_chains, nonce)) = decode_tag_data(data) else { return Ok(false) };
+        let slot = aux_slot(chain_id, nonce, n_aux_chains);
+
+        let leaf = Hash(*template_hash.inner());
+        Ok(aux_root(leaf, slot, n_aux_chains, &self.aux_branch) == Some(tag_root))
+    }
+}
+
+/// Compute the Monero seed height of provided Monero block height, following
+/// Monero `rx_seedheight`. The RandomX key changes every
+/// [`SEEDHASH_EPOCH_BLOCKS`] blocks, lagging [`SEEDHASH_EPOCH_LAG`] blocks
+/// behind its seed block.
+pub fn seed_height(height: u64) -> u64 {
+    if height <= SEEDHASH_EPOCH_BLOCKS + SEEDHASH_EPOCH_LAG {
+        return 0
+    }
+    (height - SEEDHASH_EPOCH_LAG - 1) & !(SEEDHASH_EPOCH_BLOCKS - 1)
+}
+
+/// Decode the merge mining tag data into the number of merge mined chains
+/// and the nonce. The lowest 3 bits hold the number of bits used to encode
+/// the chains count minus one, followed by that count and then the nonce.
+pub fn decode_tag_data(data: u64) -> Option<(u32, u32)> {
+    let n_bits = 1 + (data & 7) as u32;
+    let n_aux_chains = 1 + ((data >> 3) & ((1 << n_bits) - 1)) as u32;
+    let nonce = u32::try_from(data >> (3 + n_bits)).ok()?;
+    Some((n_aux_chains, nonce))
+}
+
+/// Encode provided number of merge mined chains and nonce into the
+/// merge mining tag data, using the minimum number of bits. At most
+/// 256 chains can be encoded.
+pub fn encode_tag_data(n_aux_chains: u32, nonce: u32) -> u64 {
+    let n_bits = (n_aux_chains.max(2) - 1).ilog2() + 1;
+    (n_bits as u64 - 1) | ((n_aux_chains as u64 - 1) << 3) | ((nonce as u64) << (3 + n_bits))
+}
+
+/// Compute the merge mining Merkle tree slot of provided chain id, for
+/// given tag nonce and number of merge mined chains. The slot is the
+/// Keccak hash of the chain id and the little-endian nonce, interpreted
+/// as a little-endian `u64`, modulo the chains count.
+pub fn aux_slot(chain_id: &HeaderHash, nonce: u32, n_aux_chains: u32) -> u32 {
+    if n_aux_chains <= 1 {
+        return 0
+    }
+
+    let hash = Hash::new([&chain_id.inner()[..], &nonce.to_le_bytes()].concat());
+    let value = u64::from_le_bytes(hash.0[..8].try_into().unwrap());
+    (value % n_aux_chains as u64) as u32
+}
+
+/// Compute the merge mining Merkle tree root of provided leaf, at given
+/// index of a tree with `count` leaves, using its Merkle branch. The tree
+/// follows the Monero transactions tree structure, where the trailing
+/// leaves get paired first. Returns `None` if the branch doesn't match
+/// the leaf depth.
+fn aux_root(leaf: Hash, index: u32, count: u32, branch: &[[u8; 32]]) -> Option<Hash> {
+    if index >= count {
+        return None
+    }
+    if count == 1 {
+        return branch.is_empty().then_some(leaf)
+    }
+
+    // Largest power of two smaller than the leaves count
+    let cnt = 1 << (count - 1).ilog2();
+    let copied = 2 * cnt - count;
+
+    // Leaves after the copied ones get paired at the first level
+    let mut branch = branch.iter();
+    let (mut root, mut pos) = if index < copied {
+        (leaf, index)
+    } else {
+        let j = index - copied;
+        let sibling = Hash(*branch.next()?);
+        let root =
+            if j & 1 == 0 { keccak_pair(&leaf, &sibling) } else { keccak_pair(&sibling, &leaf) };
+        (root, copied + j / 2)
+    };
+
+    // Rest of the tree
+    for _ in 0..cnt.ilog2() {
+        let sibling = Hash(*branch.next()?);
+        root =
+            if pos & 1 == 0 { keccak_pair(&root, &sibling) } else { keccak_pair(&sibling, &root) };
+        pos >>= 1;
+    }
+
+    if branch.next().is_some() {
+        return None
+    }
+
+    Some(root)
+}
+
+/// Auxiliary function to compute the Keccak hash of two concatenated hashes.
+fn keccak_pair(left: &Hash, right: &Hash) -> Hash {
+    Hash::new([left.0, right.0].concat())
+}
+
+/// Compute the Merkle branch of the first(coinbase) hash in the Monero
+/// transactions tree of provided hashes. The Monero tree first pairs the
+/// trailing hashes, so the rest of the tree is a perfect binary tree.
+/// The first hash is always the leftmost leaf, so the root is computed by
+/// hashing it with each branch sibling in order.
+pub fn coinbase_merkle_branch(hashes: &[Hash]) -> Vec<Hash> {
+    let mut branch = vec![];
+    if hashes.len() < 2 {
+        return branch
+    }
+
+    // Largest power of two smaller than the hashes count
+    let mut cnt = 1 << (hashes.len() - 1).ilog2();
+
+    // First level, where the trailing hashes get paired
+    let copied = 2 * cnt - hashes.len();
+    if copied == 0 {
+        branch.push(hashes[1]);
+    }
+    let mut level: Vec<Hash> = hashes[..copied].to_vec();
+    for pair in hashes[copied..].chunks(2) {
+        level.push(keccak_pair(&pair[0], &pair[1]));
+    }
+
+    // Rest of the tree
+    while cnt > 1 {
+        branch.push(level[1]);
+        level = level.chunks(2).map(|pair| keccak_pair(&pair[0], &pair[1])).collect();
+        cnt >>= 1;
+    }
+
+    branch
+}
+
+#[cfg(test)]
+mod tests {
+    use monero::Hash;
+
+    use super::{
+        aux_root, aux_slot, coinbase_merkle_branch, decode_tag_data, encode_tag_data, keccak_pair,
+        seed_height,
+    };
+    use crate::blockchain::HeaderHash;
+
+    /// Monero `tree_hash` reference implementation
+    fn tree_hash(hashes: &[Hash]) -> Hash {
+        match hashes.len() {
+            1 => hashes[0],
+            2 => keccak_pair(&hashes[0], &hashes[1]),
+            n => {
+                let mut cnt = 2;
+                while cnt < n {
+                    cnt <<= 1;
+                }
+                cnt >>= 1;
+
+                let mut ints = hashes[..2 * cnt - n].to_vec();
+                let mut i = 2 * cnt - n;
+                while ints.len() < cnt {
+                    ints.push(keccak_pair(&hashes[i], &hashes[i + 1]));
+                    i += 2;
+                }
+
+                while cnt > 2 {
+                    cnt >>= 1;
+                    ints = (0..cnt).map(|j| keccak_pair(&ints[2 * j], &ints[2 * j + 1])).collect();
+                }
+
+                keccak_pair(&ints[0], &ints[1])
+            }
+        }
+    }
+
+    /// Generate the Merkle branch of provided index in the tree of given hashes
+    fn merkle_branch(hashes: &[Hash], index: usize) -> Vec<[u8; 32]> {
+        let mut branch = vec![];
+        if hashes.len() < 2 {
+            return branch
+        }
+
+        let cnt = 1 << (hashes.len() - 1).ilog2();
+        let copied = 2 * cnt - hashes.len();
+        let mut pos = index;
+        if index >= copied {
+            let j = index - copied;
+            branch.push(hashes[copied + (j ^ 1)].0);
+            pos = copied + j / 2;
+        }
+
+        let mut level = hashes[..copied].to_vec();
+        level.extend(hashes[copied..].chunks(2).map(|pair| keccak_pair(&pair[0], &pair[1])));
+        while level.len() > 1 {
+            branch.push(level[pos ^ 1].0);
+            level = level.chunks(2).map(|pair| keccak_pair(&pair[0], &pair[1])).collect();
+            pos >>= 1;
+        }
+
+        branch
+    }
+
+    #[test]
+    fn test_aux_root() {
+        for n in 1..=17u32 {
+            let hashes: Vec<Hash> = (0..n).map(|i| Hash::new(i.to_le_bytes())).collect();
+            let root = tree_hash(&hashes);
+            for index in 0..n {
+                let branch = merkle_branch(&hashes, index as usize);
+                let leaf = hashes[index as usize];
+                assert_eq!(aux_root(leaf, index, n, &branch), Some(root));
+
+                // Any other slot must not produce the root
+                for other in (0..n).filter(|i| *i != index) {
+                    assert_ne!(aux_root(leaf, other, n, &branch), Some(root));
+                }
+
+                // Extended branches are rejected
+                let mut extended = branch.clone();
+                extended.push([0u8; 32]);
+                assert_eq!(aux_root(leaf, index, n, &extended), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tag_data() {
+        for n_aux_chains in 1..=256u32 {
+            for nonce in [0, 1, 0xdeadbeef, u32::MAX] {
+                let data = encode_tag_data(n_aux_chains, nonce);
+                assert_eq!(decode_tag_data(data), Some((n_aux_chains, nonce)));
+            }
+        }
+        // Single chain with zero nonce is encoded as zero
+        assert_eq!(encode_tag_data(1, 0), 0);
+        assert_eq!(decode_tag_data(0), Some((1, 0)));
+
+        // Slots are always within the chains count
+        let chain_id = HeaderHash::new([42u8; 32]);
+        for n_aux_chains in 1..=64 {
+            for nonce in 0..16 {
+                assert!(aux_slot(&chain_id, nonce, n_aux_chains) < n_aux_chains);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seed_height() {
+        // First epochs use the genesis block hash
+        assert_eq!(seed_height(0), 0);
+        assert_eq!(seed_height(2048 + 64), 0);
+
+        // Keys switch 64 blocks after each epoch seed block
+        assert_eq!(seed_height(2048 + 65), 2048);
+        assert_eq!(seed_height(4096 + 64), 2048);
+        assert_eq!(seed_height(4096 + 65), 4096);
+        assert_eq!(seed_height(3000000), 2998272);
+    }
+
+    #[test]
+    fn test_coinbase_merkle_branch() {
+        for n in 1..=33u32 {
+            let hashes: Vec<Hash> = (0..n).map(|i| Hash::new(i.to_le_bytes())).collect();
+            let branch = coinbase_merkle_branch(&hashes);
+            assert_eq!(branch.len() as u32, n.ilog2());
+
+            let root = branch.iter().fold(hashes[0], |root, sibling| keccak_pair(&root, sibling));
+            assert_eq!(root, tree_hash(&hashes));
+        }
+    }
+}
@@ -18,8 +18,8 @@
 
 use std::sync::{Arc, Mutex};
 
-use darkfi_sdk::tx::TransactionHash;
-use darkfi_serial::{deserialize, Decodable};
+use darkfi_sdk::{pasta::pallas, tx::TransactionHash};
+use darkfi_serial::{deserialize, serialize, Decodable};
 use log::debug;
 use sled_overlay::{sled, sled::Transactional};
 
@@ -28,29 +28,42 @@ use crate::{tx::Transaction, util::time::Timestamp, Error, Result};
 /// Block related definitions and storage implementations
 pub mod block_store;
 pub use block_store::{
-    Block, BlockDifficulty, BlockInfo, BlockStore, BlockStoreOverlay, SLED_BLOCK_DIFFICULTY_TREE,
-    SLED_BLOCK_ORDER_TREE, SLED_BLOCK_TREE,
+    Block, BlockDifficulty, BlockInfo, BlockStore, BlockStoreOverlay, TxInclusionProof,
+    SLED_BLOCK_DIFFICULTY_TREE, SLED_BLOCK_ORDER_TREE, SLED_BLOCK_PRUNED_TREE, SLED_BLOCK_TREE,
 };
 
 /// Header definition and storage implementation
 pub mod header_store;
 pub use header_store::{
-    Header, HeaderHash, HeaderStore, HeaderStoreOverlay, SLED_HEADER_TREE, SLED_SYNC_HEADER_TREE,
+    Header, HeaderHash, HeaderStore, HeaderStoreOverlay, PowData, BLOCK_VERSION_2,
+    POW_BLOB_NONCE_OFFSET, POW_BLOB_SIZE, SLED_HEADER_TREE, SLED_MONERO_SEED_TREE,
+    SLED_SYNC_HEADER_TREE,
 };
 
+/// Monero merge mining auxiliary Proof of Work definitions
+pub mod merge_mining;
+pub use merge_mining::MoneroPowData;
+
 /// Transactions related storage implementations
 pub mod tx_store;
 pub use tx_store::{
-    TxStore, TxStoreOverlay, SLED_PENDING_TX_ORDER_TREE, SLED_PENDING_TX_TREE,
-    SLED_TX_LOCATION_TREE, SLED_TX_TREE,
+    PendingTxFee, TxStore, TxStoreOverlay, SLED_EXPIRED_TX_TREE, SLED_PENDING_TX_FEE_TREE,
+    SLED_PENDING_TX_HEIGHT_TREE, SLED_PENDING_TX_NULLIFIERS_TREE,
+    SLED_PENDING_TX_NULLIFIER_INDEX_TREE, SLED_PENDING_TX_ORDER_TREE, SLED_PENDING_TX_SIZE_KEY,
+    SLED_PENDING_TX_SIZE_TREE, SLED_PENDING_TX_TREE, SLED_TX_LOCATION_TREE, SLED_TX_TREE,
 };
 
 /// Contracts and Wasm storage implementations
 pub mod contract_store;
 pub use contract_store::{
-    ContractStore, ContractStoreOverlay, SLED_BINCODE_TREE, SLED_CONTRACTS_TREE,
+    state_overlay_insert, state_overlay_remove, state_root_from_buckets, ContractStore,
+    ContractStoreOverlay, StateHasher, StateSmt, VerifyingKeyCache, SLED_BINCODE_TREE,
+    SLED_CONTRACTS_TREE, SLED_STATE_BUCKETS_TREE, SLED_STATE_SMT_TREE, SLED_VERIFYING_KEYS_TREE,
 };
 
+/// Maximum number of block heights pruned in a single atomic write
+pub const PRUNE_BATCH: u32 = 1000;
+
 /// Structure holding all sled trees that define the concept of Blockchain.
 #[derive(Clone)]
 pub struct Blockchain {
@@ -188,6 +201,129 @@ impl Blockchain {
         Ok(headers.iter().map(|h| h.clone().unwrap()).collect())
     }
 
+    /// Remove full blocks and their transactions of all blocks before
+    /// provided height, keeping their headers, order, difficulty and
+    /// transactions locations. Contracts state is not affected.
+    /// Genesis block is never pruned. Blocks are pruned in batches
+    /// of [`PRUNE_BATCH`] heights, each applied atomically.
+    /// On success, the function returns the number of pruned blocks.
+    pub fn prune(&self, height: u32) -> Result<usize> {
+        let mut start = self.blocks.get_pruned_height()?.unwrap_or(1);
+        let mut pruned = 0;
+        while start < height {
+            let end = (start + PRUNE_BATCH).min(height);
+            debug!(target: "blockchain", "prune(): {} -> {}", start, end);
+
+            // Grab the blocks to remove, along with their transactions
+            let heights: Vec<u32> = (start..end).collect();
+            let hashes: Vec<HeaderHash> =
+                self.blocks.get_order(&heights, false)?.into_iter().flatten().collect();
+            let mut txs_hashes = vec![];
+            for block in self.blocks.get(&hashes, false)?.into_iter().flatten() {
+                txs_hashes.extend(block.txs);
+            }
+
+            let trees = [
+                self.blocks.main.clone(),
+                self.transactions.main.clone(),
+                self.blocks.pruned.clone(),
+            ];
+            let batches = [
+                self.blocks.remove_batch(&hashes),
+                self.transactions.remove_batch(&txs_hashes),
+                self.blocks.insert_batch_pruned_height(end),
+            ];
+
+            // Perform an atomic transaction over the trees and apply the batches.
+            self.atomic_write(&trees, &batches)?;
+
+            pruned += hashes.len();
+            start = end;
+        }
+
+        Ok(pruned)
+    }
+
+    /// Retrieve the height before which full blocks have been pruned.
+    /// If blockchain was never pruned, returns `None`.
+    pub fn pruned_height(&self) -> Result<Option<u32>> {
+        self.blocks.get_pruned_height()
+    }
+
+    /// Check if the full block of provided height has been pruned.
+    /// Genesis block is never pruned.
+    pub fn is_height_pruned(&self, height: u32) -> Result<bool> {
+        match self.blocks.get_pruned_height()? {
+            Some(pruned_height) => Ok(height > 0 && height < pruned_height),
+            None => Ok(false),
+        }
+    }
+
+    /// Check if provided finalized transaction has been pruned, along with
+    /// its block. Unknown transactions are not considered pruned.
+    pub fn is_tx_pruned(&self, tx_hash: &TransactionHash) -> Result<bool> {
+        match self.transactions.get_location(&[*tx_hash], false)?[0] {
+            Some((height, _)) => self.is_height_pruned(height),
+            None => Ok(false),
+        }
+    }
+
+    /// Retrieve the names of all the sled trees forming the blockchain
+    /// state, sorted. These are the contracts wasm, states pointers, state
+    /// SMT and state buckets trees, all the contracts states trees and the blocks
+    /// difficulty tree, which together with the canonical headers is all a node
+    /// needs to continue validating from the last block.
+    pub fn state_trees(&self) -> Result<Vec<Vec<u8>>> {
+        let mut trees = vec![
+            SLED_BINCODE_TREE.to_vec(),
+            SLED_CONTRACTS_TREE.to_vec(),
+            SLED_STATE_SMT_TREE.to_vec(),
+            SLED_STATE_BUCKETS_TREE.to_vec(),
+            SLED_BLOCK_DIFFICULTY_TREE.to_vec(),
+        ];
+        for (_, states) in self.contracts.get_all_states()? {
+            trees.extend(states.iter().map(|state| state.as_bytes().to_vec()));
+        }
+        trees.sort();
+        trees.dedup();
+
+        Ok(trees)
+    }
+
+    /// Compute the blockchain state commitment, as the blake3 hash of all
+    /// the [`Blockchain::state_trees`] records. Each tree is hashed in order
+    /// as its serialized name and records count, followed by each of its
+    /// serialized `(key, value)` records.
+    pub fn state_checksum(&self) -> Result<blake3::Hash> {
+        let mut hasher = blake3::Hasher::new();
+        for name in self.state_trees()? {
+            let tree = self.sled_db.open_tree(&name)?;
+            hasher.update(&serialize(&name));
+            hasher.update(&serialize(&(tree.len() as u64)));
+            for record in tree.iter() {
+                let (key, value) = record?;
+                hasher.update(&serialize(&(key.to_vec(), value.to_vec())));
+            }
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Generate the [`TxInclusionProof`] of provided transaction hash
+    /// in its canonical block.
+    pub fn get_tx_proof(&self, tx_hash: &TransactionHash) -> Result<TxInclusionProof> {
+        let Some((height, index)) = self.transactions.get_location(&[*tx_hash], true)?[0] else {
+            return Err(Error::TransactionNotFound(tx_hash.as_string()))
+        };
+        if self.is_height_pruned(height)? {
+            return Err(Error::TransactionPruned(tx_hash.as_string()))
+        }
+        let hash = self.blocks.get_order(&[height], true)?[0].unwrap();
+        let block = self.blocks.get(&[hash], true)?[0].clone().unwrap();
+
+        TxInclusionProof::new(height, &block, index)
+    }
+
     /// Retrieve stored blocks count
     pub fn len(&self) -> usize {
         self.blocks.len()
@@ -245,21 +381,75 @@ impl Blockchain {
         Ok(!vec.is_empty())
     }
 
-    /// Insert a given slice of pending transactions into the blockchain database.
+    /// Insert a given slice of pending transactions into the blockchain database,
+    /// marking them as received at current canonical height.
     /// On success, the function returns the transaction hashes in the same order
     /// as the input transactions.
     pub fn add_pending_txs(&self, txs: &[Transaction]) -> Result<Vec<TransactionHash>> {
+        let (height, _) = self.last()?;
         let (txs_batch, txs_hashes) = self.transactions.insert_batch_pending(txs);
         let txs_order_batch = self.transactions.insert_batch_pending_order(&txs_hashes)?;
+        let txs_height_batch = self.transactions.insert_batch_pending_height(&txs_hashes, height);
 
         // Perform an atomic transaction over the trees and apply the batches.
-        let trees = [self.transactions.pending.clone(), self.transactions.pending_order.clone()];
-        let batches = [txs_batch, txs_order_batch];
+        let trees = [
+            self.transactions.pending.clone(),
+            self.transactions.pending_order.clone(),
+            self.transactions.pending_height.clone(),
+        ];
+        let batches = [txs_batch, txs_order_batch, txs_height_batch];
         self.atomic_write(&trees, &batches)?;
 
         Ok(txs_hashes)
     }
 
+    /// Insert a given slice of pending transactions into the blockchain database,
+    /// along with their fee information and revealed nullifiers, marking them
+    /// as received at current canonical height.
+    /// On success, the function returns the transaction hashes in the same order
+    /// as the input transactions.
+    pub fn add_pending_txs_with_metadata(
+        &self,
+        txs: &[Transaction],
+        fees: &[PendingTxFee],
+        nullifiers: &[Vec<pallas::Base>],
+    ) -> Result<Vec<TransactionHash>> {
+        let (height, _) = self.last()?;
+        let (txs_batch, txs_hashes) = self.transactions.insert_batch_pending(txs);
+        let txs_order_batch = self.transactions.insert_batch_pending_order(&txs_hashes)?;
+        let txs_fee_batch = self.transactions.insert_batch_pending_fee(&txs_hashes, fees)?;
+        let txs_nullifiers_batch =
+            self.transactions.insert_batch_pending_nullifiers(&txs_hashes, nullifiers)?;
+        let txs_nullifier_index_batch =
+            self.transactions.insert_batch_pending_nullifier_index(&txs_hashes, nullifiers)?;
+        let txs_height_batch = self.transactions.insert_batch_pending_height(&txs_hashes, height);
+
+        // Compute the pending txs total size change, replacing existing fee records
+        let added = fees.iter().map(|fee| fee.size).sum();
+        let removed = self.pending_txs_size(&txs_hashes)?;
+
+        // Perform an atomic transaction over the trees and apply the batches.
+        let trees = [
+            self.transactions.pending.clone(),
+            self.transactions.pending_order.clone(),
+            self.transactions.pending_fee.clone(),
+            self.transactions.pending_nullifiers.clone(),
+            self.transactions.pending_nullifier_index.clone(),
+            self.transactions.pending_height.clone(),
+        ];
+        let batches = [
+            txs_batch,
+            txs_order_batch,
+            txs_fee_batch,
+            txs_nullifiers_batch,
+            txs_nullifier_index_batch,
+            txs_height_batch,
+        ];
+        self.atomic_write_pending(&trees, &batches, added, removed)?;
+
+        Ok(txs_hashes)
+    }
+
     /// Retrieve all transactions from the pending tx store.
     /// Be careful as this will try to load everything in memory.
     pub fn get_pending_txs(&self) -> Result<Vec<Transaction>> {
@@ -277,6 +467,12 @@ impl Blockchain {
         Ok(ret)
     }
 
+    /// Retrieve all pending transactions hashes, in the order they were received.
+    /// Unlike [`Blockchain::get_pending_txs`], the actual transactions are not loaded.
+    pub fn get_pending_txs_hashes(&self) -> Result<Vec<TransactionHash>> {
+        Ok(self.transactions.get_all_pending_order()?.into_iter().map(|(_, hash)| hash).collect())
+    }
+
     /// Remove a given slice of pending transactions from the blockchain database.
     pub fn remove_pending_txs(&self, txs: &[Transaction]) -> Result<()> {
         let txs_hashes: Vec<TransactionHash> = txs.iter().map(|tx| tx.hash()).collect();
@@ -295,13 +491,75 @@ impl Blockchain {
             }
         }
 
-        let txs_batch = self.transactions.remove_batch_pending(txs);
+        let txs_batch = self.transactions.remove_batch_by_hash(txs);
         let txs_order_batch = self.transactions.remove_batch_pending_order(&removed_indexes);
+        let txs_fee_batch = self.transactions.remove_batch_by_hash(txs);
+        let txs_nullifiers_batch = self.transactions.remove_batch_by_hash(txs);
+        let txs_nullifier_index_batch =
+            self.transactions.remove_batch_pending_nullifier_index(txs)?;
+        let txs_height_batch = self.transactions.remove_batch_by_hash(txs);
+        let removed = self.pending_txs_size(txs)?;
 
         // Perform an atomic transaction over the trees and apply the batches.
-        let trees = [self.transactions.pending.clone(), self.transactions.pending_order.clone()];
-        let batches = [txs_batch, txs_order_batch];
-        self.atomic_write(&trees, &batches)?;
+        let trees = [
+            self.transactions.pending.clone(),
+            self.transactions.pending_order.clone(),
+            self.transactions.pending_fee.clone(),
+            self.transactions.pending_nullifiers.clone(),
+            self.transactions.pending_nullifier_index.clone(),
+            self.transactions.pending_height.clone(),
+        ];
+        let batches = [
+            txs_batch,
+            txs_order_batch,
+            txs_fee_batch,
+            txs_nullifiers_batch,
+            txs_nullifier_index_batch,
+            txs_height_batch,
+        ];
+        self.atomic_write_pending(&trees, &batches, 0, removed)?;
+
+        Ok(())
+    }
+
+    /// Auxiliary function to compute the total size of provided pending
+    /// transactions, using their fee information.
+    fn pending_txs_size(&self, txs: &[TransactionHash]) -> Result<u64> {
+        let fees = self.transactions.get_pending_fee(txs)?;
+        Ok(fees.into_iter().flatten().map(|fee| fee.size).sum())
+    }
+
+    /// Auxiliary function to write to multiple trees completely atomic, along
+    /// with updating the pending txs total size record by provided added and
+    /// removed bytes.
+    fn atomic_write_pending(
+        &self,
+        trees: &[sled::Tree],
+        batches: &[sled::Batch],
+        added: u64,
+        removed: u64,
+    ) -> Result<()> {
+        if trees.len() != batches.len() {
+            return Err(Error::InvalidInputLengths)
+        }
+
+        let mut trees = trees.to_vec();
+        trees.push(self.transactions.pending_size.clone());
+        trees.as_slice().transaction(|trees| {
+            let (size_tree, trees) = trees.split_last().unwrap();
+            for (index, tree) in trees.iter().enumerate() {
+                tree.apply_batch(&batches[index])?;
+            }
+
+            let size: u64 = match size_tree.get(SLED_PENDING_TX_SIZE_KEY)? {
+                Some(found) => deserialize(&found).unwrap_or(0),
+                None => 0,
+            };
+            let size = size.saturating_add(added).saturating_sub(removed);
+            size_tree.insert(SLED_PENDING_TX_SIZE_KEY, serialize(&size))?;
+
+            Ok::<(), sled::transaction::ConflictableTransactionError<sled::Error>>(())
+        })?;
 
         Ok(())
     }
@@ -364,10 +622,17 @@ impl BlockchainOverlay {
             SLED_BLOCK_DIFFICULTY_TREE,
             SLED_HEADER_TREE,
             SLED_SYNC_HEADER_TREE,
+            SLED_MONERO_SEED_TREE,
             SLED_TX_TREE,
             SLED_TX_LOCATION_TREE,
             SLED_PENDING_TX_TREE,
             SLED_PENDING_TX_ORDER_TREE,
+            SLED_PENDING_TX_FEE_TREE,
+            SLED_PENDING_TX_NULLIFIERS_TREE,
+            SLED_PENDING_TX_NULLIFIER_INDEX_TREE,
+            SLED_PENDING_TX_HEIGHT_TREE,
+            SLED_PENDING_TX_SIZE_TREE,
+            SLED_EXPIRED_TX_TREE,
             SLED_CONTRACTS_TREE,
             SLED_BINCODE_TREE,
         ];
@@ -378,7 +643,7 @@ impl BlockchainOverlay {
         let headers = HeaderStoreOverlay::new(&overlay)?;
         let blocks = BlockStoreOverlay::new(&overlay)?;
         let transactions = TxStoreOverlay::new(&overlay)?;
-        let contracts = ContractStoreOverlay::new(&overlay)?;
+        let contracts = ContractStoreOverlay::new(&overlay, &blockchain.contracts.vks)?;
 
         Ok(Arc::new(Mutex::new(Self { overlay, headers, blocks, transactions, contracts })))
     }
@@ -421,6 +686,11 @@ impl BlockchainOverlay {
         // Store header
         self.headers.insert(&[block.header.clone()])?;
 
+        // Pin merge mined header RandomX key to its Monero seed height
+        if let PowData::Monero(pow_data) = &block.header.pow_data {
+            self.headers.insert_monero_seed(pow_data.seed_height()?, &pow_data.randomx_key)?;
+        }
+
         // Store block
         let blk: Block = Block::from_block_info(block);
         let txs_hashes = blk.txs.clone();
@@ -507,6 +777,13 @@ impl BlockchainOverlay {
         Ok(())
     }
 
+    /// Apply the overlay changes to the underlying database, and commit
+    /// its contracts verifying keys changes to the node cache.
+    pub fn apply(&self) -> Result<()> {
+        self.overlay.lock().unwrap().apply()?;
+        self.contracts.commit_vks()
+    }
+
     /// Auxiliary function to create a full clone using SledDbOverlay::clone,
     /// generating new pointers for the underlying overlays.
     pub fn full_clone(&self) -> Result<BlockchainOverlayPtr> {
@@ -514,7 +791,7 @@ impl BlockchainOverlay {
         let headers = HeaderStoreOverlay::new(&overlay)?;
         let blocks = BlockStoreOverlay::new(&overlay)?;
         let transactions = TxStoreOverlay::new(&overlay)?;
-        let contracts = ContractStoreOverlay::new(&overlay)?;
+        let contracts = self.contracts.clone_with(&overlay)?;
 
         Ok(Arc::new(Mutex::new(Self { overlay, headers, blocks, transactions, contracts })))
     }
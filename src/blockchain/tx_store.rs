@@ -18,8 +18,10 @@
 
 use std::collections::HashMap;
 
-use darkfi_sdk::tx::TransactionHash;
-use darkfi_serial::{deserialize, serialize};
+use darkfi_sdk::{pasta::pallas, tx::TransactionHash};
+#[cfg(feature = "async-serial")]
+use darkfi_serial::async_trait;
+use darkfi_serial::{deserialize, serialize, SerialDecodable, SerialEncodable};
 use sled_overlay::sled;
 
 use crate::{tx::Transaction, Error, Result};
@@ -30,6 +32,41 @@ pub const SLED_TX_TREE: &[u8] = b"_transactions";
 pub const SLED_TX_LOCATION_TREE: &[u8] = b"_transaction_location";
 pub const SLED_PENDING_TX_TREE: &[u8] = b"_pending_transactions";
 pub const SLED_PENDING_TX_ORDER_TREE: &[u8] = b"_pending_transactions_order";
+pub const SLED_PENDING_TX_FEE_TREE: &[u8] = b"_pending_transactions_fee";
+pub const SLED_PENDING_TX_NULLIFIERS_TREE: &[u8] = b"_pending_transactions_nullifiers";
+pub const SLED_PENDING_TX_NULLIFIER_INDEX_TREE: &[u8] = b"_pending_transactions_nullifier_index";
+pub const SLED_PENDING_TX_HEIGHT_TREE: &[u8] = b"_pending_transactions_height";
+pub const SLED_EXPIRED_TX_TREE: &[u8] = b"_expired_transactions";
+pub const SLED_PENDING_TX_SIZE_TREE: &[u8] = b"_pending_transactions_size";
+
+/// Key of the pending txs total size record
+pub const SLED_PENDING_TX_SIZE_KEY: &[u8] = b"size";
+
+/// Fee information of a pending transaction, used to prioritize and
+/// evict pending transactions.
+#[derive(Clone, Debug, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct PendingTxFee {
+    /// Gas used by the transaction
+    pub gas_used: u64,
+    /// Fee paid by the transaction
+    pub gas_paid: u64,
+    /// Serialized transaction size, in bytes
+    pub size: u64,
+}
+
+impl PendingTxFee {
+    pub fn new(gas_used: u64, gas_paid: u64, size: u64) -> Self {
+        Self { gas_used, gas_paid, size }
+    }
+
+    /// Fee paid per 1000 gas units used.
+    pub fn fee_rate(&self) -> u64 {
+        if self.gas_used == 0 {
+            return self.gas_paid.saturating_mul(1000)
+        }
+        ((self.gas_paid as u128 * 1000) / self.gas_used as u128) as u64
+    }
+}
 
 /// The `TxStore` is a structure representing all `sled` trees related
 /// to storing the blockchain's transactions information.
@@ -52,6 +89,31 @@ pub struct TxStore {
     /// where the key is an incremental value, and the value is the serialized
     /// transaction.
     pub pending_order: sled::Tree,
+    /// The `sled` tree storing the fee information of all the node pending
+    /// transactions, where the key is the transaction hash, and the value is
+    /// the serialized [`PendingTxFee`].
+    pub pending_fee: sled::Tree,
+    /// The `sled` tree storing the nullifiers revealed by all the node pending
+    /// transactions, where the key is the transaction hash, and the value is
+    /// the serialized nullifiers vector.
+    pub pending_nullifiers: sled::Tree,
+    /// The `sled` tree indexing the nullifiers revealed by all the node
+    /// pending transactions, where the key is the nullifier, and the value
+    /// is the hash of the pending transaction revealing it.
+    pub pending_nullifier_index: sled::Tree,
+    /// The `sled` tree storing the canonical blockchain height at which each
+    /// of the node pending transactions was received, where the key is the
+    /// transaction hash, and the value is the serialized height.
+    pub pending_height: sled::Tree,
+    /// The `sled` tree storing the node pending transactions that expired,
+    /// so they don't get accepted again, where the key is the transaction
+    /// hash, and the value is the serialized canonical blockchain height
+    /// at which it expired.
+    pub expired: sled::Tree,
+    /// The `sled` tree storing the total size of all the node pending
+    /// transactions with fee information, in bytes, under the
+    /// [`SLED_PENDING_TX_SIZE_KEY`] record.
+    pub pending_size: sled::Tree,
 }
 
 impl TxStore {
@@ -61,7 +123,40 @@ impl TxStore {
         let location = db.open_tree(SLED_TX_LOCATION_TREE)?;
         let pending = db.open_tree(SLED_PENDING_TX_TREE)?;
         let pending_order = db.open_tree(SLED_PENDING_TX_ORDER_TREE)?;
-        Ok(Self { main, location, pending, pending_order })
+        let pending_fee = db.open_tree(SLED_PENDING_TX_FEE_TREE)?;
+        let pending_nullifiers = db.open_tree(SLED_PENDING_TX_NULLIFIERS_TREE)?;
+        let pending_nullifier_index = db.open_tree(SLED_PENDING_TX_NULLIFIER_INDEX_TREE)?;
+        let pending_height = db.open_tree(SLED_PENDING_TX_HEIGHT_TREE)?;
+        let expired = db.open_tree(SLED_EXPIRED_TX_TREE)?;
+        let pending_size = db.open_tree(SLED_PENDING_TX_SIZE_TREE)?;
+        let store = Self {
+            main,
+            location,
+            pending,
+            pending_order,
+            pending_fee,
+            pending_nullifiers,
+            pending_nullifier_index,
+            pending_height,
+            expired,
+            pending_size,
+        };
+
+        // Compute the pending txs total size of databases missing its record
+        if !store.pending_size.contains_key(SLED_PENDING_TX_SIZE_KEY)? {
+            let size: u64 = store.get_all_pending_fee()?.iter().map(|(_, fee)| fee.size).sum();
+            store.pending_size.insert(SLED_PENDING_TX_SIZE_KEY, serialize(&size))?;
+        }
+
+        // Index the pending txs nullifiers of databases missing their index
+        if store.pending_nullifier_index.is_empty() && !store.pending_nullifiers.is_empty() {
+            let (tx_hashes, nullifiers): (Vec<_>, Vec<_>) =
+                store.get_all_pending_nullifiers()?.into_iter().unzip();
+            let batch = store.insert_batch_pending_nullifier_index(&tx_hashes, &nullifiers)?;
+            store.pending_nullifier_index.apply_batch(batch)?;
+        }
+
+        Ok(store)
     }
 
     /// Insert a slice of [`Transaction`] into the store's main tree.
@@ -179,6 +274,96 @@ impl TxStore {
         Ok(batch)
     }
 
+    /// Generate the sled batch corresponding to an insert to the pending txs
+    /// fee tree, so caller can handle the write operation.
+    pub fn insert_batch_pending_fee(
+        &self,
+        tx_hashes: &[TransactionHash],
+        fees: &[PendingTxFee],
+    ) -> Result<sled::Batch> {
+        if tx_hashes.len() != fees.len() {
+            return Err(Error::InvalidInputLengths)
+        }
+
+        let mut batch = sled::Batch::default();
+
+        for (tx_hash, fee) in tx_hashes.iter().zip(fees.iter()) {
+            batch.insert(tx_hash.inner(), serialize(fee));
+        }
+
+        Ok(batch)
+    }
+
+    /// Generate the sled batch corresponding to an insert to the pending txs
+    /// nullifiers tree, so caller can handle the write operation.
+    pub fn insert_batch_pending_nullifiers(
+        &self,
+        tx_hashes: &[TransactionHash],
+        nullifiers: &[Vec<pallas::Base>],
+    ) -> Result<sled::Batch> {
+        if tx_hashes.len() != nullifiers.len() {
+            return Err(Error::InvalidInputLengths)
+        }
+
+        let mut batch = sled::Batch::default();
+
+        for (tx_hash, tx_nullifiers) in tx_hashes.iter().zip(nullifiers.iter()) {
+            batch.insert(tx_hash.inner(), serialize(tx_nullifiers));
+        }
+
+        Ok(batch)
+    }
+
+    /// Generate the sled batch corresponding to an insert to the pending txs
+    /// nullifier index tree, so caller can handle the write operation.
+    pub fn insert_batch_pending_nullifier_index(
+        &self,
+        tx_hashes: &[TransactionHash],
+        nullifiers: &[Vec<pallas::Base>],
+    ) -> Result<sled::Batch> {
+        if tx_hashes.len() != nullifiers.len() {
+            return Err(Error::InvalidInputLengths)
+        }
+
+        let mut batch = sled::Batch::default();
+
+        for (tx_hash, tx_nullifiers) in tx_hashes.iter().zip(nullifiers.iter()) {
+            for nullifier in tx_nullifiers {
+                batch.insert(serialize(nullifier), tx_hash.inner());
+            }
+        }
+
+        Ok(batch)
+    }
+
+    /// Generate the sled batch corresponding to an insert to the pending txs
+    /// height tree, so caller can handle the write operation.
+    pub fn insert_batch_pending_height(
+        &self,
+        tx_hashes: &[TransactionHash],
+        height: u32,
+    ) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+
+        for tx_hash in tx_hashes {
+            batch.insert(tx_hash.inner(), &serialize(&height));
+        }
+
+        batch
+    }
+
+    /// Generate the sled batch corresponding to an insert to the expired txs
+    /// tree, so caller can handle the write operation.
+    pub fn insert_batch_expired(&self, tx_hashes: &[TransactionHash], height: u32) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+
+        for tx_hash in tx_hashes {
+            batch.insert(tx_hash.inner(), &serialize(&height));
+        }
+
+        batch
+    }
+
     /// Check if the store's main tree contains a given transaction hash.
     pub fn contains(&self, tx_hash: &TransactionHash) -> Result<bool> {
         Ok(self.main.contains_key(tx_hash.inner())?)
@@ -189,6 +374,11 @@ impl TxStore {
         Ok(self.pending.contains_key(tx_hash.inner())?)
     }
 
+    /// Check if the store's expired txs tree contains a given transaction hash.
+    pub fn contains_expired(&self, tx_hash: &TransactionHash) -> Result<bool> {
+        Ok(self.expired.contains_key(tx_hash.inner())?)
+    }
+
     /// Fetch given tx hashes from the store's main tree.
     /// The resulting vector contains `Option`, which is `Some` if the tx
     /// was found in the txstore, and otherwise it is `None`, if it has not.
@@ -225,7 +415,7 @@ impl TxStore {
         &self,
         tx_hashes: &[TransactionHash],
         strict: bool,
-    ) -> Result<Vec<Option<(u64, u16)>>> {
+    ) -> Result<Vec<Option<(u32, u16)>>> {
         let mut ret = Vec::with_capacity(tx_hashes.len());
 
         for tx_hash in tx_hashes {
@@ -270,6 +460,54 @@ impl TxStore {
         Ok(ret)
     }
 
+    /// Fetch given tx hashes fee information from the store's pending txs fee tree.
+    /// The resulting vector contains `Option`, which is `Some` if the fee was
+    /// found in the pending txs fee store, and otherwise it is `None`, if it has not.
+    pub fn get_pending_fee(
+        &self,
+        tx_hashes: &[TransactionHash],
+    ) -> Result<Vec<Option<PendingTxFee>>> {
+        let mut ret = Vec::with_capacity(tx_hashes.len());
+
+        for tx_hash in tx_hashes {
+            match self.pending_fee.get(tx_hash.inner())? {
+                Some(found) => ret.push(Some(deserialize(&found)?)),
+                None => ret.push(None),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Fetch the pending transactions revealing given nullifiers from the
+    /// store's pending txs nullifier index tree.
+    /// The resulting vector contains `Option`, which is `Some` if a pending
+    /// transaction revealing the nullifier was found, and otherwise it is `None`.
+    pub fn get_pending_by_nullifiers(
+        &self,
+        nullifiers: &[pallas::Base],
+    ) -> Result<Vec<Option<TransactionHash>>> {
+        let mut ret = Vec::with_capacity(nullifiers.len());
+
+        for nullifier in nullifiers {
+            match self.pending_nullifier_index.get(serialize(nullifier))? {
+                Some(found) => ret.push(Some(deserialize(&found)?)),
+                None => ret.push(None),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Retrieve the total size of all the pending transactions with fee
+    /// information, in bytes, from the store's pending txs size tree.
+    pub fn get_pending_size(&self) -> Result<u64> {
+        match self.pending_size.get(SLED_PENDING_TX_SIZE_KEY)? {
+            Some(found) => Ok(deserialize(&found)?),
+            None => Ok(0),
+        }
+    }
+
     /// Retrieve all transactions from the store's main tree in the form of
     /// a tuple (`tx_hash`, `tx`).
     /// Be careful as this will try to load everything in memory.
@@ -311,6 +549,58 @@ impl TxStore {
         Ok(txs)
     }
 
+    /// Retrieve all transactions fee information from the store's pending txs
+    /// fee tree in the form of a tuple (`tx_hash`, `fee`).
+    /// Be careful as this will try to load everything in memory.
+    pub fn get_all_pending_fee(&self) -> Result<Vec<(TransactionHash, PendingTxFee)>> {
+        let mut fees = vec![];
+
+        for fee in self.pending_fee.iter() {
+            fees.push(parse_record(fee.unwrap())?);
+        }
+
+        Ok(fees)
+    }
+
+    /// Retrieve all transactions revealed nullifiers from the store's pending
+    /// txs nullifiers tree in the form of a tuple (`tx_hash`, `nullifiers`).
+    /// Be careful as this will try to load everything in memory.
+    pub fn get_all_pending_nullifiers(&self) -> Result<Vec<(TransactionHash, Vec<pallas::Base>)>> {
+        let mut nullifiers = vec![];
+
+        for record in self.pending_nullifiers.iter() {
+            nullifiers.push(parse_record(record.unwrap())?);
+        }
+
+        Ok(nullifiers)
+    }
+
+    /// Retrieve all transactions received heights from the store's pending
+    /// txs height tree in the form of a tuple (`tx_hash`, `height`).
+    /// Be careful as this will try to load everything in memory.
+    pub fn get_all_pending_height(&self) -> Result<Vec<(TransactionHash, u32)>> {
+        let mut heights = vec![];
+
+        for record in self.pending_height.iter() {
+            heights.push(parse_record(record.unwrap())?);
+        }
+
+        Ok(heights)
+    }
+
+    /// Retrieve all transactions expiry heights from the store's expired
+    /// txs tree in the form of a tuple (`tx_hash`, `height`).
+    /// Be careful as this will try to load everything in memory.
+    pub fn get_all_expired(&self) -> Result<Vec<(TransactionHash, u32)>> {
+        let mut heights = vec![];
+
+        for record in self.expired.iter() {
+            heights.push(parse_record(record.unwrap())?);
+        }
+
+        Ok(heights)
+    }
+
     /// Retrieve all transactions from the store's pending txs order tree in
     /// the form of a tuple (`u64`, `TransactionHash`).
     /// Be careful as this will try to load everything in memory.
@@ -369,6 +659,13 @@ impl TxStore {
         self.main.is_empty()
     }
 
+    /// Remove a slice of [`TransactionHash`] from the store's main tree.
+    pub fn remove(&self, txs_hashes: &[TransactionHash]) -> Result<()> {
+        let batch = self.remove_batch(txs_hashes);
+        self.main.apply_batch(batch)?;
+        Ok(())
+    }
+
     /// Remove a slice of [`TransactionHash`] from the store's pending txs tree.
     pub fn remove_pending(&self, txs_hashes: &[TransactionHash]) -> Result<()> {
         let batch = self.remove_batch_pending(txs_hashes);
@@ -383,9 +680,21 @@ impl TxStore {
         Ok(())
     }
 
+    /// Generate the sled batch corresponding to a remove from the store's main
+    /// tree, so caller can handle the write operation.
+    pub fn remove_batch(&self, txs_hashes: &[TransactionHash]) -> sled::Batch {
+        self.remove_batch_by_hash(txs_hashes)
+    }
+
     /// Generate the sled batch corresponding to a remove from the store's pending
     /// txs tree, so caller can handle the write operation.
     pub fn remove_batch_pending(&self, txs_hashes: &[TransactionHash]) -> sled::Batch {
+        self.remove_batch_by_hash(txs_hashes)
+    }
+
+    /// Generate the sled batch corresponding to a remove from any of the store's
+    /// trees keyed by the transaction hash, so caller can handle the write operation.
+    pub fn remove_batch_by_hash(&self, txs_hashes: &[TransactionHash]) -> sled::Batch {
         let mut batch = sled::Batch::default();
 
         for tx_hash in txs_hashes {
@@ -395,6 +704,30 @@ impl TxStore {
         batch
     }
 
+    /// Generate the sled batch corresponding to a remove from the store's pending
+    /// txs nullifier index tree, so caller can handle the write operation.
+    /// Only the nullifiers still indexed to the removed transactions are removed.
+    pub fn remove_batch_pending_nullifier_index(
+        &self,
+        txs_hashes: &[TransactionHash],
+    ) -> Result<sled::Batch> {
+        let mut batch = sled::Batch::default();
+
+        for tx_hash in txs_hashes {
+            let Some(found) = self.pending_nullifiers.get(tx_hash.inner())? else { continue };
+            let nullifiers: Vec<pallas::Base> = deserialize(&found)?;
+            for nullifier in nullifiers {
+                let key = serialize(&nullifier);
+                if self.pending_nullifier_index.get(&key)?.as_deref() == Some(&tx_hash.inner()[..])
+                {
+                    batch.remove(key);
+                }
+            }
+        }
+
+        Ok(batch)
+    }
+
     /// Generate the sled batch corresponding to a remove from the store's pending
     /// txs order tree, so caller can handle the write operation.
     pub fn remove_batch_pending_order(&self, indexes: &[u64]) -> sled::Batch {
@@ -503,3 +836,91 @@ impl TxStoreOverlay {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{BlockInfo, Blockchain};
+
+    fn transaction(signatures: usize) -> Transaction {
+        Transaction { signatures: vec![vec![]; signatures], ..Default::default() }
+    }
+
+    #[test]
+    fn test_pending_size() -> Result<()> {
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        blockchain.add_block(&BlockInfo::default())?;
+        assert_eq!(blockchain.transactions.get_pending_size()?, 0);
+
+        // Pending txs total size is updated along with their fee records
+        let txs = vec![transaction(1), transaction(2), transaction(3)];
+        let fees = vec![
+            PendingTxFee::new(1000, 100, 100),
+            PendingTxFee::new(1000, 100, 200),
+            PendingTxFee::new(1000, 100, 300),
+        ];
+        let hashes =
+            blockchain.add_pending_txs_with_metadata(&txs, &fees, &[vec![], vec![], vec![]])?;
+        assert_eq!(blockchain.transactions.get_pending_size()?, 600);
+
+        // Rewriting a fee record replaces its size
+        let fee = PendingTxFee::new(1000, 100, 150);
+        blockchain.add_pending_txs_with_metadata(&txs[..1], &[fee], &[vec![]])?;
+        assert_eq!(blockchain.transactions.get_pending_size()?, 650);
+
+        // Removing unknown transactions doesn't affect it
+        blockchain.remove_pending_txs_hashes(&[hashes[1], TransactionHash([0u8; 32])])?;
+        assert_eq!(blockchain.transactions.get_pending_size()?, 450);
+
+        // Databases missing the record get it computed when opened
+        blockchain.transactions.pending_size.clear()?;
+        assert_eq!(TxStore::new(&sled_db)?.get_pending_size()?, 450);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_nullifier_index() -> Result<()> {
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        blockchain.add_block(&BlockInfo::default())?;
+
+        // Pending txs nullifiers are indexed along with their records
+        let nullifiers: Vec<pallas::Base> = (0..4u64).map(pallas::Base::from).collect();
+        let txs = vec![transaction(1), transaction(2)];
+        let fees = vec![PendingTxFee::new(1000, 100, 100); 2];
+        let hashes = blockchain.add_pending_txs_with_metadata(
+            &txs,
+            &fees,
+            &[nullifiers[..2].to_vec(), nullifiers[2..3].to_vec()],
+        )?;
+        let store = &blockchain.transactions;
+        assert_eq!(
+            store.get_pending_by_nullifiers(&nullifiers)?,
+            vec![Some(hashes[0]), Some(hashes[0]), Some(hashes[1]), None]
+        );
+
+        // A transaction revealing an indexed nullifier takes it over, so
+        // removing the previous one doesn't drop it from the index.
+        let hash = blockchain.add_pending_txs_with_metadata(
+            &[transaction(3)],
+            &fees[..1],
+            &[nullifiers[2..].to_vec()],
+        )?[0];
+        blockchain.remove_pending_txs_hashes(&[hashes[0], hashes[1]])?;
+        assert_eq!(
+            store.get_pending_by_nullifiers(&nullifiers)?,
+            vec![None, None, Some(hash), Some(hash)]
+        );
+
+        // Databases missing the index get it built when opened
+        store.pending_nullifier_index.clear()?;
+        assert_eq!(
+            TxStore::new(&sled_db)?.get_pending_by_nullifiers(&nullifiers)?,
+            vec![None, None, Some(hash), Some(hash)]
+        );
+
+        Ok(())
+    }
+}
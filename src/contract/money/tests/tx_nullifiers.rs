@@ -0,0 +1,81 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Test that the validator reports the nullifiers revealed by a transaction,
+//! as inserted by the `Money` contract into its nullifiers sparse Merkle tree,
+//! which the mempool uses to find conflicting pending transactions.
+
+use darkfi::{blockchain::BlockchainOverlay, validator::verification::verify_transactions, Result};
+use darkfi_contract_test_harness::{init_logger, Holder, TestHarness};
+use darkfi_sdk::crypto::MerkleTree;
+
+#[test]
+fn tx_nullifiers() -> Result<()> {
+    smol::block_on(async {
+        init_logger();
+
+        // Holders this test will use
+        const HOLDERS: [Holder; 2] = [Holder::Alice, Holder::Bob];
+
+        // Initialize harness
+        let mut th = TestHarness::new(&HOLDERS, true).await?;
+
+        // Generate a new block mined by Alice
+        th.generate_block(&Holder::Alice, &HOLDERS).await?;
+        let current_block_height = 1;
+
+        // Alice transfers some tokens to Bob
+        let alice_coins = th.holders.get(&Holder::Alice).unwrap().unspent_money_coins.clone();
+        let (tx, (xfer_params, fee_params), _spent_coins) = th
+            .transfer(
+                alice_coins[0].note.value / 2,
+                &Holder::Alice,
+                &Holder::Bob,
+                &[alice_coins[0].clone()],
+                alice_coins[0].note.token_id,
+                current_block_height,
+                false,
+            )
+            .await?;
+
+        // Verify the transaction against Alice's state
+        let validator = &th.holders.get(&Holder::Alice).unwrap().validator;
+        let overlay = BlockchainOverlay::new(&validator.blockchain)?;
+        let (_, _, mut nullifiers) = verify_transactions(
+            &overlay,
+            current_block_height,
+            validator.consensus.module.read().await.target,
+            &[tx],
+            &mut MerkleTree::new(1),
+            true,
+        )
+        .await?;
+        overlay.lock().unwrap().overlay.lock().unwrap().purge_new_trees()?;
+
+        // It must reveal the transfer and fee inputs nullifiers
+        let mut expected: Vec<_> =
+            xfer_params.inputs.iter().map(|input| input.nullifier.inner()).collect();
+        expected.push(fee_params.unwrap().input.nullifier.inner());
+        nullifiers.sort();
+        expected.sort();
+        assert_eq!(nullifiers, expected);
+
+        // Thanks for reading
+        Ok(())
+    })
+}
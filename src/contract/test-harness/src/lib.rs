@@ -26,7 +26,12 @@ use darkfi::{
     runtime::vm_runtime::Runtime,
     tx::Transaction,
     util::{pcg::Pcg32, time::Timestamp},
-    validator::{Validator, ValidatorConfig, ValidatorPtr},
+    validator::{
+        consensus::{DEFAULT_MAX_FORKS, DEFAULT_MAX_FORK_DEPTH},
+        pow::DifficultyAlgorithm,
+        Validator, ValidatorConfig, ValidatorPtr, DEFAULT_MEMPOOL_MAX_SIZE,
+        DEFAULT_MIN_RELAY_FEE_RATE, DEFAULT_PENDING_TX_EXPIRY,
+    },
     zk::{empty_witnesses, halo2::Field, ProvingKey, ZkCircuit},
     zkas::ZkBinary,
     Result,
@@ -167,8 +172,17 @@ impl Wallet {
             finalization_threshold: 3,
             pow_target: 90,
             pow_fixed_difficulty: Some(BigUint::from(1_u8)),
+            pow_algorithm: DifficultyAlgorithm::Monero,
             genesis_block,
             verify_fees,
+            prune: None,
+            mempool_max_size: DEFAULT_MEMPOOL_MAX_SIZE,
+            min_relay_fee_rate: DEFAULT_MIN_RELAY_FEE_RATE,
+            pending_tx_expiry: DEFAULT_PENDING_TX_EXPIRY,
+            max_forks: DEFAULT_MAX_FORKS,
+            max_fork_depth: DEFAULT_MAX_FORK_DEPTH,
+            checkpoints: vec![],
+            block_v2_height: Some(1),
         };
         let validator = Validator::new(&sled_db, &validator_config).await?;
 
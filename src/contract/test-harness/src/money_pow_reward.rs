@@ -17,7 +17,7 @@
  */
 
 use darkfi::{
-    blockchain::{BlockInfo, Header},
+    blockchain::{BlockInfo, Header, BLOCK_VERSION_2},
     tx::{ContractCallLeaf, Transaction, TransactionBuilder},
     Result,
 };
@@ -115,12 +115,13 @@ impl TestHarness {
         let timestamp = previous.header.timestamp.checked_add(1.into())?;
 
         // Generate block header
-        let header = Header::new(
+        let mut header = Header::new(
             previous.hash(),
             previous.header.height + 1,
             timestamp,
             previous.header.nonce,
         );
+        header.version = wallet.validator.consensus.block_version(header.height);
 
         // Generate the block
         let mut block = BlockInfo::new_empty(header);
@@ -128,6 +129,11 @@ impl TestHarness {
         // Add producer transaction to the block
         block.append_txs(vec![tx]);
 
+        // Commit to the contracts state after applying the block
+        if block.header.version >= BLOCK_VERSION_2 {
+            block.header.state_root = wallet.validator.block_state_root(&block).await?;
+        }
+
         // Attach signature
         block.sign(&wallet.keypair.secret);
 
@@ -135,7 +141,7 @@ impl TestHarness {
         let mut found_owncoins = vec![];
         for holder in holders {
             let wallet = self.holders.get_mut(holder).unwrap();
-            wallet.validator.add_test_blocks(&[block.clone()]).await?;
+            wallet.validator.add_blocks(&[block.clone()]).await?;
             wallet.money_merkle_tree.append(MerkleNode::from(params.output.coin.inner()));
 
             // Attempt to decrypt the note to see if this is a coin for the holder
@@ -24,7 +24,10 @@ use std::{
 };
 
 use darkfi::{
-    blockchain::contract_store::SMART_CONTRACT_ZKAS_DB_NAME,
+    blockchain::contract_store::{
+        state_overlay_insert, SLED_STATE_BUCKETS_TREE, SLED_STATE_SMT_TREE,
+        SMART_CONTRACT_ZKAS_DB_NAME,
+    },
     zk::{empty_witnesses, ProvingKey, VerifyingKey, ZkCircuit},
     zkas::ZkBinary,
     Result,
@@ -45,7 +48,7 @@ use darkfi_sdk::crypto::{DAO_CONTRACT_ID, MONEY_CONTRACT_ID};
 use darkfi_serial::{deserialize, serialize};
 
 use log::debug;
-use sled_overlay::sled;
+use sled_overlay::{sled, SledDbOverlay};
 
 /// Update these if any circuits are changed.
 /// Delete the existing cachefiles, and enable debug logging, you will see the new hashes.
@@ -182,9 +185,13 @@ pub fn inject(sled_db: &sled::Db, vks: &Vks) -> Result<()> {
     let money_db_name = MONEY_CONTRACT_ID.hash_state_id(SMART_CONTRACT_ZKAS_DB_NAME);
     let dao_db_name = DAO_CONTRACT_ID.hash_state_id(SMART_CONTRACT_ZKAS_DB_NAME);
 
-    // Create the db trees
-    let money_tree = sled_db.open_tree(money_db_name)?;
-    let dao_tree = sled_db.open_tree(dao_db_name)?;
+    // Create the db trees in an overlay, so the contracts state root
+    // gets updated along with the injected records
+    let mut overlay = SledDbOverlay::new(sled_db, vec![]);
+    overlay.open_tree(SLED_STATE_SMT_TREE, true)?;
+    overlay.open_tree(SLED_STATE_BUCKETS_TREE, true)?;
+    overlay.open_tree(&money_db_name, false)?;
+    overlay.open_tree(&dao_db_name, false)?;
 
     for (bincode, namespace, vk) in vks.iter() {
         match namespace.as_str() {
@@ -196,7 +203,7 @@ pub fn inject(sled_db: &sled::Db, vks: &Vks) -> Result<()> {
             MONEY_CONTRACT_ZKAS_AUTH_TOKEN_MINT_NS_V1 => {
                 let key = serialize(&namespace.as_str());
                 let value = serialize(&(bincode.clone(), vk.clone()));
-                money_tree.insert(key, value)?;
+                state_overlay_insert(&mut overlay, &money_db_name, &key, &value)?;
             }
 
             // DAO contract circuits
@@ -210,12 +217,14 @@ pub fn inject(sled_db: &sled::Db, vks: &Vks) -> Result<()> {
             DAO_CONTRACT_ZKAS_DAO_AUTH_MONEY_TRANSFER_ENC_COIN_NS => {
                 let key = serialize(&namespace.as_str());
                 let value = serialize(&(bincode.clone(), vk.clone()));
-                dao_tree.insert(key, value)?;
+                state_overlay_insert(&mut overlay, &dao_db_name, &key, &value)?;
             }
 
             x => panic!("Found unhandled zkas namespace {}", x),
         }
     }
 
+    overlay.apply()?;
+
     Ok(())
 }
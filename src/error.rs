@@ -303,6 +303,15 @@ pub enum Error {
     #[error("Proposal already exists")]
     ProposalAlreadyExists,
 
+    #[error("Proposal forks off too deep behind the best fork")]
+    ProposalForkTooDeep,
+
+    #[error("Proposal fork ranks too low to be tracked")]
+    ProposalForkRankTooLow,
+
+    #[error("Proposal conflicts with a checkpoint")]
+    ProposalConflictsCheckpoint,
+
     #[error("Consensus task stopped")]
     ConsensusTaskStopped,
 
@@ -324,6 +333,9 @@ pub enum Error {
     #[error("Provided output hash is greater than current target")]
     PoWInvalidOutHash,
 
+    #[error("Provided merge mining data is invalid")]
+    PoWInvalidMergeMiningData,
+
     // ===============
     // Database errors
     // ===============
@@ -341,6 +353,9 @@ pub enum Error {
     #[error("Transaction {0} not found in database")]
     TransactionNotFound(String),
 
+    #[error("Transaction {0} has been pruned from database")]
+    TransactionPruned(String),
+
     #[error("Transaction already seen")]
     TransactionAlreadySeen,
 
@@ -562,6 +577,9 @@ pub enum TxVerifyFailed {
     #[error("Transaction {0} already exists")]
     AlreadySeenTx(String),
 
+    #[error("Transaction {0} has expired")]
+    ExpiredTx(String),
+
     #[error("Invalid transaction signature")]
     InvalidSignature,
 
@@ -583,6 +601,15 @@ pub enum TxVerifyFailed {
     #[error("Insufficient fee paid")]
     InsufficientFee,
 
+    #[error("Transaction fee rate {0} is below the minimum relay fee rate {1}")]
+    FeeRateTooLow(u64, u64),
+
+    #[error("Mempool is full and transaction fee rate is too low to evict others")]
+    MempoolFull,
+
+    #[error("Replacement transaction pays {0} fee, but at least {1} is required")]
+    InsufficientReplacementFee(u64, u64),
+
     #[error("Erroneous transactions found")]
     ErroneousTxs(Vec<crate::tx::Transaction>),
 }
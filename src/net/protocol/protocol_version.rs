@@ -154,6 +154,7 @@ impl ProtocolVersion {
         let node_id = settings.node_id.clone();
         let app_version = settings.app_version.clone();
         let external_addrs = settings.external_addrs.clone();
+        let features = settings.features.clone();
         drop(settings);
 
         let version = VersionMessage {
@@ -164,9 +165,9 @@ impl ProtocolVersion {
             resolve_recv_addr: self.channel.resolve_addr().clone(),
             ext_send_addr: external_addrs,
             /* NOTE: `features` is a list of enabled features in the
-            format Vec<(service, version)>. In the future, Protocols will
-            add their own data to this field when they are attached.*/
-            features: vec![],
+            format Vec<(service, version)>, configured by the application
+            for the optional protocols it attaches.*/
+            features,
         };
         self.channel.send(&version).await?;
 
@@ -104,6 +104,11 @@ pub struct Settings {
     /// Do not ban nodes that send messages without dispatchers if set
     /// to `Relaxed`. For most uses, should be set to `Strict`.
     pub ban_policy: BanPolicy,
+    /// Application features advertised to peers during the version
+    /// exchange, in the format (service, version). Applications add
+    /// their optional protocols here, so peers only use them with
+    /// nodes supporting them.
+    pub features: Vec<(String, u32)>,
 }
 
 impl Default for Settings {
@@ -137,6 +142,7 @@ impl Default for Settings {
             time_with_no_connections: 30,
             blacklist: vec![],
             ban_policy: BanPolicy::Strict,
+            features: vec![],
         }
     }
 }
@@ -311,6 +317,7 @@ impl From<SettingsOpt> for Settings {
                 .unwrap_or(def.time_with_no_connections),
             blacklist: opt.blacklist,
             ban_policy: opt.ban_policy,
+            features: def.features,
         }
     }
 }
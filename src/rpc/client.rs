@@ -24,7 +24,7 @@ use tinyjson::JsonValue;
 use url::Url;
 
 use super::{
-    common::{read_from_stream, write_to_stream, INIT_BUF_SIZE, READ_TIMEOUT},
+    common::{read_from_stream, write_to_stream, INIT_BUF_SIZE, MAX_BUF_SIZE, READ_TIMEOUT},
     jsonrpc::*,
 };
 use crate::{
@@ -122,9 +122,11 @@ impl RpcClient {
             .await?;
 
             if with_timeout {
-                let _ = io_timeout(READ_TIMEOUT, read_from_stream(&mut reader, &mut buf)).await?;
+                let _ =
+                    io_timeout(READ_TIMEOUT, read_from_stream(&mut reader, &mut buf, MAX_BUF_SIZE))
+                        .await?;
             } else {
-                let _ = read_from_stream(&mut reader, &mut buf).await?;
+                let _ = read_from_stream(&mut reader, &mut buf, MAX_BUF_SIZE).await?;
             }
 
             let val: JsonValue = String::from_utf8(buf)?.parse()?;
@@ -334,7 +336,7 @@ impl RpcChadClient {
                     Ok::<(), crate::Error>(())
                 },
                 async {
-                    let _ = read_from_stream(&mut reader, &mut buf).await?;
+                    let _ = read_from_stream(&mut reader, &mut buf, MAX_BUF_SIZE).await?;
                     let val: JsonValue = String::from_utf8(buf)?.parse()?;
                     let rep = JsonResult::try_from_value(&val)?;
                     rep_send.send(rep).await?;
@@ -17,103 +17,509 @@
  */
 
 //! Clock sync module
-use std::{net::UdpSocket, time::Duration};
+//!
+//! Estimates the offset between the local system clock and network time.
+//! A configurable set of time sources is queried concurrently, outliers
+//! are discarded and the remaining samples are combined with the clock
+//! offsets observed from connected P2P peers `VersionMessage` timestamps.
+//!
+//! Supported time sources:
+//! * Any P2P transport (e.g. `tcp://`, `tor://`) - an HTTP `HEAD` request
+//!   is performed over the transport and the response `Date` header is
+//!   used, so the source can be reached through Tor without leaking our
+//!   address.
+//! * `ntp://host[:port]` - raw NTP request over UDP. This always goes over
+//!   clearnet, so it is only used when explicitly configured.
+//!
+//! If no sources are configured, HTTP sources are reached through the
+//! P2P network allowed transports, see [`default_sources`].
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use log::debug;
+use futures::future::join_all;
+use log::{debug, info, warn};
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{resolve, UdpSocket},
+};
 use url::Url;
 
-use crate::{util::time::Timestamp, Error, Result};
+use crate::{
+    net::{transport::Dialer, P2pPtr},
+    system::{io_timeout, sleep},
+    util::time::{ClockOffset, Timestamp},
+    Error, Result,
+};
 
-/// Clock sync parameters
-const RETRIES: u8 = 10;
-/// TODO: Loop through set of ntps, get their average response concurrenyly.
-const NTP_ADDRESS: &str = "pool.ntp.org:123";
-const EPOCH: u32 = 2208988800; // 1900
+/// Seconds between the NTP epoch (1900) and the UNIX epoch (1970)
+const EPOCH: u64 = 2208988800;
+/// Default NTP port, used when a source doesn't specify one
+const NTP_PORT: u16 = 123;
+/// Maximum HTTP response head size we are willing to read
+const MAX_HTTP_HEAD_SIZE: usize = 8192;
+/// Maximum clock offset we apply, in milliseconds. Larger estimates are
+/// rejected, since they indicate broken or malicious time sources.
+pub const MAX_CLOCK_ADJUSTMENT: i64 = 70 * 60 * 1000;
+/// Default HTTP time sources hosts, reached through the P2P transports
+pub const DEFAULT_HTTP_HOSTS: [&str; 4] =
+    ["cloudflare.com", "google.com", "wikipedia.org", "debian.org"];
+/// NTP source used by the legacy [`ntp_request`] and [`check_clock`]
+const NTP_ADDRESS: &str = "ntp://pool.ntp.org:123";
 
-/// Raw NTP request execution
+/// Clock sync settings
+#[derive(Debug, Clone)]
+pub struct ClockSyncSettings {
+    /// Time sources to query. If empty, [`default_sources`] for the
+    /// P2P network allowed transports are used.
+    pub sources: Vec<Url>,
+    /// Use the median of connected P2P peers clock offsets as an extra sample
+    pub use_peers: bool,
+    /// Seconds between consecutive syncs
+    pub interval: u64,
+    /// Single source request timeout, in seconds
+    pub timeout: u64,
+    /// Maximum deviation from the samples median, in seconds,
+    /// before a sample is considered an outlier
+    pub max_deviation: u64,
+    /// Minimum number of valid samples required to update the offset
+    pub min_samples: usize,
+}
+
+impl Default for ClockSyncSettings {
+    fn default() -> Self {
+        Self {
+            sources: vec![],
+            use_peers: true,
+            interval: 600,
+            timeout: 5,
+            max_deviation: 10,
+            min_samples: 3,
+        }
+    }
+}
+
+// The following is used so we can have clock sync settings configurable
+// from TOML files.
+
+/// Defines the clock sync settings.
+#[derive(
+    Clone, Debug, Default, serde::Deserialize, structopt::StructOpt, structopt_toml::StructOptToml,
+)]
+#[structopt()]
+pub struct ClockSyncSettingsOpt {
+    /// Time sources to query (any P2P transport serving HTTP, or clearnet ntp://)
+    #[serde(default)]
+    #[structopt(long = "clock-source")]
+    pub sources: Vec<Url>,
+
+    /// Don't use the median of connected P2P peers clock offsets as an extra sample
+    #[serde(default)]
+    #[structopt(long = "clock-skip-peers")]
+    pub skip_peers: bool,
+
+    /// Seconds between consecutive clock syncs
+    #[structopt(long = "clock-interval")]
+    pub interval: Option<u64>,
+
+    /// Single time source request timeout, in seconds
+    #[structopt(long = "clock-timeout")]
+    pub timeout: Option<u64>,
+
+    /// Maximum deviation from the samples median, in seconds
+    #[structopt(long = "clock-max-deviation")]
+    pub max_deviation: Option<u64>,
+
+    /// Minimum number of valid samples required to update the clock offset
+    #[structopt(long = "clock-min-samples")]
+    pub min_samples: Option<usize>,
+}
+
+impl From<ClockSyncSettingsOpt> for ClockSyncSettings {
+    fn from(opt: ClockSyncSettingsOpt) -> Self {
+        let def = ClockSyncSettings::default();
+
+        Self {
+            sources: opt.sources,
+            use_peers: !opt.skip_peers,
+            interval: opt.interval.unwrap_or(def.interval),
+            timeout: opt.timeout.unwrap_or(def.timeout),
+            max_deviation: opt.max_deviation.unwrap_or(def.max_deviation),
+            min_samples: opt.min_samples.unwrap_or(def.min_samples),
+        }
+    }
+}
+
+/// Current UNIX time, in milliseconds
+fn now_millis() -> i64 {
+    UNIX_EPOCH.elapsed().unwrap().as_millis() as i64
+}
+
+/// Compute the offset, in milliseconds, of a remote time against the
+/// local clock, assuming the remote time was generated halfway through
+/// the request round trip.
+fn offset_millis(remote: i64, local_start: i64, rtt: Duration) -> i64 {
+    remote - (local_start + rtt.as_millis() as i64 / 2)
+}
+
+/// Default time sources for provided P2P network allowed transports.
+/// HTTP sources are reached through Tor if it's allowed, otherwise
+/// through plain TCP if the node already uses clearnet. Returns no
+/// sources for any other transport, so only peers get used.
+pub fn default_sources(transports: &[String]) -> Vec<Url> {
+    let scheme = if transports.iter().any(|t| t == "tor" || t == "tor+tls") {
+        "tor"
+    } else if transports.iter().any(|t| t == "tcp" || t == "tcp+tls") {
+        "tcp"
+    } else {
+        return vec![]
+    };
+
+    DEFAULT_HTTP_HOSTS
+        .iter()
+        .map(|host| Url::parse(&format!("{scheme}://{host}:80")).unwrap())
+        .collect()
+}
+
+/// Raw NTP request execution, returning the current NTP time.
+#[deprecated(note = "use `ntp_source_request` or `estimate_offset` instead")]
 pub async fn ntp_request() -> Result<Timestamp> {
+    let offset = ntp_source_request(&Url::parse(NTP_ADDRESS)?, Duration::from_secs(5)).await?;
+    Ok(Timestamp::from_u64(((now_millis() + offset) / 1000) as u64))
+}
+
+/// This is a very simple check to verify that the system time is correct,
+/// against a single clearnet NTP source.
+#[deprecated(note = "use `estimate_offset` or `clock_sync_task` instead")]
+pub async fn check_clock(_peers: &[Url]) -> Result<()> {
+    let offset = ntp_source_request(&Url::parse(NTP_ADDRESS)?, Duration::from_secs(5)).await?;
+    if offset.abs() >= 1000 {
+        return Err(Error::InvalidClock)
+    }
+
+    Ok(())
+}
+
+/// Raw NTP request execution, returning the local clock offset in milliseconds.
+pub async fn ntp_source_request(source: &Url, timeout: Duration) -> Result<i64> {
+    let Some(host) = source.host_str() else {
+        return Err(Error::UrlParse(format!("Invalid NTP source: {source}")))
+    };
+    let port = source.port().unwrap_or(NTP_PORT);
+    let Some(addr) = resolve((host, port)).await?.into_iter().next() else {
+        return Err(Error::UrlParse(format!("Unresolved NTP source: {source}")))
+    };
+
     // Create socket
-    let sock = UdpSocket::bind("0.0.0.0:0")?;
-    sock.set_read_timeout(Some(Duration::from_secs(5)))?;
-    sock.set_write_timeout(Some(Duration::from_secs(5)))?;
+    let sock = UdpSocket::bind("0.0.0.0:0").await?;
 
     // Execute request
     let mut packet = [0u8; 48];
     packet[0] = (3 << 6) | (4 << 3) | 3;
-    sock.send_to(&packet, NTP_ADDRESS)?;
-
-    // Parse response
-    sock.recv(&mut packet[..])?;
-    let (bytes, _) = packet[40..44].split_at(core::mem::size_of::<u32>());
-    let num = u32::from_be_bytes(bytes.try_into().unwrap());
-    let timestamp = Timestamp::from_u64((num - EPOCH) as u64);
-
-    Ok(timestamp)
-}
-
-/// This is a very simple check to verify that the system time is correct.
-///
-/// Retry loop is used in case discrepancies are found.
-/// If all retries fail, system clock is considered invalid.
-/// TODO: 1. Add proxy functionality in order not to leak connections
-pub async fn check_clock(peers: &[Url]) -> Result<()> {
-    debug!(target: "rpc::clock_sync", "System clock check started...");
-    let mut r = 0;
-    while r < RETRIES {
-        if let Err(e) = clock_check(peers).await {
-            debug!(target: "rpc::clock_sync", "Error during clock check: {:#?}", e);
-            r += 1;
-            continue
-        };
-        break
+    let local_start = now_millis();
+    let start = Instant::now();
+    sock.send_to(&packet, addr).await?;
+    io_timeout(timeout, sock.recv(&mut packet[..])).await?;
+    let rtt = start.elapsed();
+
+    // Parse response transmit timestamp
+    let secs = u32::from_be_bytes(packet[40..44].try_into().unwrap()) as u64;
+    let frac = u32::from_be_bytes(packet[44..48].try_into().unwrap()) as u64;
+    let Some(secs) = secs.checked_sub(EPOCH) else {
+        return Err(Error::ClockOutOfSync(format!("Invalid NTP response from {source}")))
+    };
+    let remote = (secs * 1000 + ((frac * 1000) >> 32)) as i64;
+
+    Ok(offset_millis(remote, local_start, rtt))
+}
+
+/// HTTP `HEAD` request execution over a P2P transport, returning the local
+/// clock offset in milliseconds, based on the response `Date` header.
+pub async fn http_request(source: &Url, timeout: Duration) -> Result<i64> {
+    let Some(host) = source.host_str() else {
+        return Err(Error::UrlParse(format!("Invalid HTTP source: {source}")))
+    };
+    let request = format!("HEAD / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+
+    let local_start = now_millis();
+    let start = Instant::now();
+    let dialer = Dialer::new(source.clone(), None).await?;
+    let head = io_timeout(timeout, async {
+        let mut stream = dialer.dial(Some(timeout)).await?;
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read until the end of the response head
+        let mut head = vec![];
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_HTTP_HEAD_SIZE {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(head)
+    })
+    .await?;
+    let rtt = start.elapsed();
+
+    let head = String::from_utf8_lossy(&head);
+    let Some(date) = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("date").then(|| value.trim())
+    }) else {
+        return Err(Error::ClockOutOfSync(format!("Missing Date header from {source}")))
+    };
+
+    let Some(remote) = parse_http_date(date) else {
+        return Err(Error::ClockOutOfSync(format!("Invalid Date header from {source}")))
+    };
+
+    // HTTP dates have a second precision, so we assume the remote
+    // time is in the middle of that second.
+    Ok(offset_millis(remote as i64 * 1000 + 500, local_start, rtt))
+}
+
+/// Parse an IMF-fixdate HTTP date (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`)
+/// into UNIX seconds.
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] =
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None
     }
 
-    debug!(target: "rpc::clock_sync", "System clock check finished. Retries: {}", r);
-    if r == RETRIES {
-        return Err(Error::InvalidClock)
+    let day: u64 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == parts[2])? as u64 + 1;
+    let year: u64 = parts[3].parse().ok()?;
+    let time: Vec<u64> = parts[4].split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    if time.len() != 3 || year < 1970 || day == 0 || day > 31 {
+        return None
     }
 
-    Ok(())
+    // Days since UNIX epoch, using the civil from days algorithm
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+
+    Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+/// Query a single time source, returning the local clock offset in milliseconds.
+pub async fn source_request(source: &Url, timeout: Duration) -> Result<i64> {
+    match source.scheme() {
+        "ntp" => ntp_source_request(source, timeout).await,
+        _ => http_request(source, timeout).await,
+    }
+}
+
+/// Grab the clock offsets, in milliseconds, of connected P2P peers.
+/// The offset is computed using the peer `VersionMessage` timestamp,
+/// which is generated when the channel gets established.
+pub async fn peers_offsets(p2p: &P2pPtr) -> Vec<i64> {
+    let mut offsets = vec![];
+    for channel in p2p.hosts().channels() {
+        let Some(version) = channel.version.lock().await.clone() else { continue };
+        let offset = version.timestamp as i64 - channel.info.start_time as i64;
+        offsets.push(offset * 1000);
+    }
+    offsets
+}
+
+/// Compute the median of provided samples. Samples must not be empty.
+fn median(samples: &mut [i64]) -> i64 {
+    samples.sort_unstable();
+    let mid = samples.len() / 2;
+    if samples.len() % 2 == 0 {
+        (samples[mid - 1] + samples[mid]) / 2
+    } else {
+        samples[mid]
+    }
 }
 
-async fn clock_check(_peers: &[Url]) -> Result<()> {
-    // Start elapsed time counter to cover for all requests and processing time
-    let requests_start = Timestamp::current_time();
-    // Poll one of the peers for their current UTC timestamp
-    //let peer_time = peer_request(peers).await?;
-    let peer_time = Some(Timestamp::current_time());
+/// Combine the configured time sources offsets with the connected peers
+/// ones. Peers are cheap to spin up, so all their offsets are collapsed
+/// into their median, counting as a single sample, and can never outvote
+/// the configured sources. Samples deviating more than `max_deviation`
+/// milliseconds from the samples median are discarded and the rest get
+/// averaged. Returns `None` if fewer than `min_samples` samples remain,
+/// or if the resulting offset exceeds [`MAX_CLOCK_ADJUSTMENT`].
+pub fn combine_offsets(
+    mut samples: Vec<i64>,
+    mut peers: Vec<i64>,
+    max_deviation: i64,
+    min_samples: usize,
+) -> Option<i64> {
+    if !peers.is_empty() {
+        samples.push(median(&mut peers));
+    }
 
-    // Start elapsed time counter to cover for NTP request and processing time
-    let ntp_request_start = Timestamp::current_time();
-    // Poll ntp.org for current timestamp
-    let ntp_time = ntp_request().await?;
+    if samples.is_empty() {
+        return None
+    }
 
-    // Stop elapsed time counters
-    let ntp_elapsed_time = ntp_request_start.elapsed()?;
-    let requests_elapsed_time = requests_start.elapsed()?;
+    let median = median(&mut samples);
+    let retained: Vec<i64> =
+        samples.into_iter().filter(|s| (s - median).abs() <= max_deviation).collect();
 
-    // Current system time
-    let system_time = Timestamp::current_time();
+    if retained.is_empty() || retained.len() < min_samples {
+        return None
+    }
 
-    // Add elapsed time to response times
-    let ntp_time = ntp_time.checked_add(ntp_elapsed_time)?;
-    let peer_time = match peer_time {
-        None => None,
-        Some(p) => Some(p.checked_add(requests_elapsed_time)?),
-    };
+    let offset = retained.iter().sum::<i64>() / retained.len() as i64;
+    if offset.abs() > MAX_CLOCK_ADJUSTMENT {
+        warn!(target: "rpc::clock_sync", "Estimated clock offset {}ms exceeds the maximum adjustment", offset);
+        return None
+    }
 
-    debug!(target: "rpc::clock_sync", "peer_time: {:#?}", peer_time);
-    debug!(target: "rpc::clock_sync", "ntp_time: {:#?}", ntp_time);
-    debug!(target: "rpc::clock_sync", "system_time: {:#?}", system_time);
+    Some(offset)
+}
+
+/// Query all configured time sources concurrently, combine them with
+/// the connected peers offsets and return the estimated local clock
+/// offset, in seconds.
+pub async fn estimate_offset(settings: &ClockSyncSettings, p2p: Option<&P2pPtr>) -> Result<i64> {
+    debug!(target: "rpc::clock_sync", "Estimating clock offset...");
+    let timeout = Duration::from_secs(settings.timeout);
+
+    let requests = settings.sources.iter().map(|source| async move {
+        match source_request(source, timeout).await {
+            Ok(offset) => {
+                debug!(target: "rpc::clock_sync", "Source {} offset: {}ms", source, offset);
+                Some(offset)
+            }
+            Err(e) => {
+                debug!(target: "rpc::clock_sync", "Source {} request failed: {}", source, e);
+                None
+            }
+        }
+    });
+    let samples: Vec<i64> = join_all(requests).await.into_iter().flatten().collect();
 
-    // We verify that system time is equal to peer (if exists) and ntp times
-    let check = match peer_time {
-        Some(p) => (system_time == p) && (system_time == ntp_time),
-        None => system_time == ntp_time,
+    let mut peers = vec![];
+    if settings.use_peers {
+        if let Some(p2p) = p2p {
+            peers = peers_offsets(p2p).await;
+            debug!(target: "rpc::clock_sync", "Peers offsets: {:?}", peers);
+        }
+    }
+
+    let Some(offset) =
+        combine_offsets(samples, peers, settings.max_deviation as i64 * 1000, settings.min_samples)
+    else {
+        return Err(Error::InvalidClock)
     };
 
-    match check {
-        true => Ok(()),
-        false => Err(Error::InvalidClock),
+    // Round to the closest second
+    Ok((offset as f64 / 1000.0).round() as i64)
+}
+
+/// Periodically estimate the local clock offset and store it in the
+/// provided [`ClockOffset`]. If an estimation fails, the previous
+/// offset is retained. If no time sources are configured, the
+/// [`default_sources`] of the P2P network allowed transports are used.
+pub async fn clock_sync_task(
+    mut settings: ClockSyncSettings,
+    p2p: Option<P2pPtr>,
+    clock_offset: ClockOffset,
+) -> Result<()> {
+    info!(target: "rpc::clock_sync", "Starting clock sync task");
+    if settings.sources.is_empty() {
+        if let Some(p2p) = &p2p {
+            settings.sources = default_sources(&p2p.settings().read().await.allowed_transports);
+        }
+    }
+    debug!(target: "rpc::clock_sync", "Time sources: {:?}", settings.sources);
+    loop {
+        match estimate_offset(&settings, p2p.as_ref()).await {
+            Ok(offset) => {
+                if offset != 0 {
+                    warn!(target: "rpc::clock_sync", "System clock offset from network time: {}s", offset);
+                }
+                clock_offset.set(offset);
+            }
+            Err(e) => {
+                warn!(
+                    target: "rpc::clock_sync",
+                    "Clock offset estimation failed, keeping previous offset {}s: {}",
+                    clock_offset.get(), e,
+                );
+            }
+        }
+
+        sleep(settings.interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_date_parsing() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"), Some(1835481599));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn transports_default_sources() {
+        let schemes = |transports: &[&str]| -> Vec<String> {
+            let transports: Vec<String> = transports.iter().map(|t| t.to_string()).collect();
+            default_sources(&transports).iter().map(|s| s.scheme().to_string()).collect()
+        };
+
+        // Tor is preferred, so we never leak our address
+        assert_eq!(schemes(&["tcp+tls", "tor"]), vec!["tor"; DEFAULT_HTTP_HOSTS.len()]);
+        assert_eq!(schemes(&["tcp+tls"]), vec!["tcp"; DEFAULT_HTTP_HOSTS.len()]);
+
+        // Other transports only use peers, and NTP is never a default
+        assert!(schemes(&["nym"]).is_empty());
+        assert!(ClockSyncSettings::default().sources.is_empty());
+    }
+
+    #[test]
+    fn offsets_combination() {
+        // Outliers get discarded
+        let samples = vec![1000, 1200, 800, 60000, -45000];
+        assert_eq!(combine_offsets(samples, vec![], 5000, 3), Some(1000));
+
+        // Not enough samples remaining
+        let samples = vec![1000, 60000, -45000];
+        assert_eq!(combine_offsets(samples, vec![], 5000, 3), None);
+
+        assert_eq!(combine_offsets(vec![], vec![], 5000, 0), None);
+    }
+
+    #[test]
+    fn peers_offsets_weight() {
+        // Peers count as a single sample, so they can't outvote sources
+        let peers = vec![3_600_000; 50];
+        assert_eq!(combine_offsets(vec![1000, 1200, 800], peers.clone(), 5000, 3), Some(1000));
+
+        // Nor satisfy the minimum samples requirement on their own
+        assert_eq!(combine_offsets(vec![], peers, 5000, 2), None);
+
+        // Their median gets averaged along with the sources
+        let peers = vec![1600, 1400, -1_000_000, 2_000_000, 1500];
+        assert_eq!(combine_offsets(vec![1000, 1200, 800], peers, 5000, 4), Some(1125));
+    }
+
+    #[test]
+    fn offsets_adjustment_cap() {
+        let samples = vec![MAX_CLOCK_ADJUSTMENT; 3];
+        assert_eq!(combine_offsets(samples, vec![], 5000, 3), Some(MAX_CLOCK_ADJUSTMENT));
+
+        let samples = vec![MAX_CLOCK_ADJUSTMENT + 1000; 3];
+        assert_eq!(combine_offsets(samples, vec![], 5000, 3), None);
+
+        let samples = vec![-MAX_CLOCK_ADJUSTMENT - 1000; 3];
+        assert_eq!(combine_offsets(samples, vec![], 5000, 3), None);
     }
 }
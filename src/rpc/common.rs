@@ -28,32 +28,44 @@ pub(super) const MAX_BUF_SIZE: usize = 1024 * 8192; // 8M
 pub(super) const READ_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// Internal read function that reads from the active stream into a buffer.
-/// Reading stops upon reaching CRLF or LF, or when `MAX_BUF_SIZE` is reached.
+/// Reading stops upon reaching CRLF or LF. If `max_size` bytes are read
+/// before that, an `InvalidData` error is returned.
 pub(super) async fn read_from_stream(
     reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
     buf: &mut Vec<u8>,
+    max_size: usize,
 ) -> io::Result<usize> {
     let mut total_read = 0;
 
     // Intermediate buffer we use to read byte-by-byte.
     let mut tmpbuf = [0_u8];
 
-    while total_read < MAX_BUF_SIZE {
-        buf.resize(total_read + INIT_BUF_SIZE, 0);
-
+    loop {
         match reader.read(&mut tmpbuf).await {
             Ok(0) if total_read == 0 => return Err(io::ErrorKind::ConnectionAborted.into()),
             Ok(0) => break, // Finished reading
             Ok(_) => {
                 // When we reach '\n', pop a possible '\r' from the buffer and bail.
                 if tmpbuf[0] == b'\n' {
-                    if buf[total_read - 1] == b'\r' {
-                        buf.pop();
+                    if total_read > 0 && buf[total_read - 1] == b'\r' {
                         total_read -= 1;
                     }
                     break
                 }
 
+                if total_read >= max_size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Read data exceeds maximum size",
+                    ))
+                }
+
+                // Grow the destination buffer in chunks, so we don't
+                // reallocate on every byte.
+                if total_read == buf.len() {
+                    buf.resize(total_read + INIT_BUF_SIZE, 0);
+                }
+
                 // Copy the read byte to the destination buffer.
                 buf[total_read] = tmpbuf[0];
                 total_read += 1;
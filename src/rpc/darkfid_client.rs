@@ -0,0 +1,497 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Typed client for the `darkfid` JSON-RPC API.
+//!
+//! Wraps an [`RpcClient`] and takes care of encoding and decoding the
+//! base64 serialized objects `darkfid` exchanges over JSON-RPC, so
+//! applications can work directly with [`BlockInfo`], [`Transaction`]
+//! and [`HeaderHash`] values.
+
+use std::{collections::HashMap, str::FromStr};
+
+use darkfi_sdk::{crypto::ContractId, tx::TransactionHash};
+use darkfi_serial::{deserialize_async, serialize_async, AsyncDecodable, AsyncEncodable};
+use log::debug;
+use tinyjson::JsonValue;
+use url::Url;
+
+use super::{
+    client::RpcClient,
+    jsonrpc::{JsonRequest, JsonResult},
+};
+use crate::{
+    blockchain::{BlockInfo, HeaderHash},
+    system::{ExecutorPtr, Publisher, PublisherPtr, Subscription},
+    tx::Transaction,
+    util::{encoding::base64, time::Timestamp},
+    Error, Result,
+};
+
+/// JSON-RPC client for a `darkfid` daemon, exposing its API
+/// methods using the native blockchain types.
+pub struct DarkfidClient {
+    /// The `darkfid` JSON-RPC endpoint
+    endpoint: Url,
+    /// Executor used to spawn the client connections
+    ex: ExecutorPtr,
+    /// JSON-RPC client used for request-reply methods
+    client: RpcClient,
+}
+
+impl DarkfidClient {
+    /// Instantiate a new `DarkfidClient` connected to the given `darkfid`
+    /// JSON-RPC endpoint.
+    pub async fn new(endpoint: Url, ex: ExecutorPtr) -> Result<Self> {
+        let client = RpcClient::new(endpoint.clone(), ex.clone()).await?;
+        Ok(Self { endpoint, ex, client })
+    }
+
+    /// Retrieve the configured `darkfid` JSON-RPC endpoint.
+    pub fn endpoint(&self) -> &Url {
+        &self.endpoint
+    }
+
+    /// Stop the underlying JSON-RPC client, closing the connection.
+    pub async fn stop(&self) {
+        self.client.stop().await;
+    }
+
+    /// Execute a raw request towards the `darkfid` JSON-RPC endpoint.
+    /// Can be used for methods the typed API doesn't cover.
+    pub async fn request(&self, method: &str, params: JsonValue) -> Result<JsonValue> {
+        debug!(target: "rpc::darkfid_client", "Executing request {} with params: {:?}", method, params);
+        let req = JsonRequest::new(method, params);
+        self.client.request(req).await
+    }
+
+    // =====================
+    // Miscellaneous methods
+    // =====================
+
+    /// Ping the `darkfid` daemon for liveness.
+    pub async fn ping(&self) -> Result<()> {
+        self.request("ping", JsonValue::Array(vec![])).await?;
+        Ok(())
+    }
+
+    /// Retrieve the `darkfid` daemon system clock.
+    pub async fn clock(&self) -> Result<Timestamp> {
+        let rep = self.request("clock", JsonValue::Array(vec![])).await?;
+        Ok(Timestamp::from(parse_string(&rep)?.parse::<u64>()?))
+    }
+
+    // ==================
+    // Blockchain methods
+    // ==================
+
+    /// Retrieve the finalized block in the given height.
+    pub async fn get_block(&self, height: u32) -> Result<BlockInfo> {
+        let params = JsonValue::Array(vec![JsonValue::String(height.to_string())]);
+        let rep = self.request("blockchain.get_block", params).await?;
+        decode_base64(&rep).await
+    }
+
+    /// Retrieve the finalized transaction with the given hash.
+    pub async fn get_tx(&self, tx_hash: &TransactionHash) -> Result<Transaction> {
+        let params = JsonValue::Array(vec![JsonValue::String(tx_hash.to_string())]);
+        let rep = self.request("blockchain.get_tx", params).await?;
+        decode_base64(&rep).await
+    }
+
+    /// Retrieve the height of the last finalized block.
+    pub async fn last_known_block(&self) -> Result<u32> {
+        let rep = self.request("blockchain.last_known_block", JsonValue::Array(vec![])).await?;
+        Ok(parse_number(&rep)? as u32)
+    }
+
+    /// Retrieve the next block height of the current best fork.
+    pub async fn best_fork_next_block_height(&self) -> Result<u32> {
+        let rep = self
+            .request("blockchain.best_fork_next_block_height", JsonValue::Array(vec![]))
+            .await?;
+        Ok(parse_number(&rep)? as u32)
+    }
+
+    /// Retrieve the configured block target time, in seconds.
+    pub async fn block_target(&self) -> Result<u32> {
+        let rep = self.request("blockchain.block_target", JsonValue::Array(vec![])).await?;
+        Ok(parse_number(&rep)? as u32)
+    }
+
+    /// Retrieve all the zkas bincodes of the given contract, along
+    /// with their namespace.
+    pub async fn lookup_zkas(&self, contract_id: &ContractId) -> Result<Vec<(String, Vec<u8>)>> {
+        let params = JsonValue::Array(vec![JsonValue::String(format!("{contract_id}"))]);
+        let rep = self.request("blockchain.lookup_zkas", params).await?;
+
+        let Some(pairs) = rep.get::<Vec<JsonValue>>() else {
+            return Err(Error::UnexpectedJsonRpc("Reply is not an array".to_string()))
+        };
+
+        let mut ret = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            let Some(pair) = pair.get::<Vec<JsonValue>>() else {
+                return Err(Error::UnexpectedJsonRpc("Reply element is not an array".to_string()))
+            };
+            if pair.len() != 2 {
+                return Err(Error::UnexpectedJsonRpc("Reply element is not a pair".to_string()))
+            }
+
+            let zkas_ns = parse_string(&pair[0])?.clone();
+            let Some(zkas_bincode) = base64::decode(parse_string(&pair[1])?) else {
+                return Err(Error::UnexpectedJsonRpc("Failed decoding base64 data".to_string()))
+            };
+            ret.push((zkas_ns, zkas_bincode));
+        }
+
+        Ok(ret)
+    }
+
+    /// Retrieve the `chain_id` used for merge mining.
+    pub async fn merge_mining_chain_id(&self) -> Result<HeaderHash> {
+        let rep = self.request("merge_mining_get_chain_id", JsonValue::Array(vec![])).await?;
+        let Some(chain_id) = rep.get::<HashMap<String, JsonValue>>() else {
+            return Err(Error::UnexpectedJsonRpc("Reply is not an object".to_string()))
+        };
+        let Some(chain_id) = chain_id.get("chain_id") else {
+            return Err(Error::UnexpectedJsonRpc("Reply is missing \"chain_id\"".to_string()))
+        };
+        HeaderHash::from_str(parse_string(chain_id)?)
+    }
+
+    // ===================
+    // Transaction methods
+    // ===================
+
+    /// Simulate the state transition of the given transaction
+    /// against the node's current best fork.
+    pub async fn simulate_tx(&self, tx: &Transaction) -> Result<bool> {
+        let params = JsonValue::Array(vec![encode_base64(tx).await]);
+        let rep = self.request("tx.simulate", params).await?;
+        parse_bool(&rep)
+    }
+
+    /// Broadcast the given transaction to the network.
+    /// Returns the transaction hash upon success.
+    pub async fn broadcast_tx(&self, tx: &Transaction) -> Result<TransactionHash> {
+        let params = JsonValue::Array(vec![encode_base64(tx).await]);
+        let rep = self.request("tx.broadcast", params).await?;
+        Ok(TransactionHash::from_str(parse_string(&rep)?)?)
+    }
+
+    /// Retrieve the hashes of all the node's pending transactions.
+    pub async fn pending_txs(&self) -> Result<Vec<TransactionHash>> {
+        let rep = self.request("tx.pending", JsonValue::Array(vec![])).await?;
+        parse_tx_hashes(&rep)
+    }
+
+    /// Remove all the node's pending transactions.
+    /// Returns the hashes of the removed transactions.
+    pub async fn clean_pending_txs(&self) -> Result<Vec<TransactionHash>> {
+        let rep = self.request("tx.clean_pending", JsonValue::Array(vec![])).await?;
+        parse_tx_hashes(&rep)
+    }
+
+    /// Compute the total gas of the given transaction, against the node's
+    /// current best fork.
+    pub async fn calculate_gas(&self, tx: &Transaction, include_fee: bool) -> Result<u64> {
+        let params =
+            JsonValue::Array(vec![encode_base64(tx).await, JsonValue::Boolean(include_fee)]);
+        let rep = self.request("tx.calculate_gas", params).await?;
+        Ok(parse_number(&rep)? as u64)
+    }
+
+    // ====================
+    // Subscription methods
+    // ====================
+
+    /// Subscribe to new finalized blocks, notifying each one of them
+    /// to the given publisher. This opens a dedicated connection to
+    /// `darkfid` and loops until an error occurs.
+    pub async fn subscribe_blocks(&self, publisher: PublisherPtr<BlockInfo>) -> Result<()> {
+        self.subscribe("blockchain.subscribe_blocks", publisher).await
+    }
+
+    /// Subscribe to new incoming transactions, notifying each one of them
+    /// to the given publisher. This opens a dedicated connection to
+    /// `darkfid` and loops until an error occurs.
+    pub async fn subscribe_txs(&self, publisher: PublisherPtr<Transaction>) -> Result<()> {
+        self.subscribe("blockchain.subscribe_txs", publisher).await
+    }
+
+    /// Subscribe to new incoming proposals, notifying each proposal hash
+    /// along with its block to the given publisher. This opens a dedicated
+    /// connection to `darkfid` and loops until an error occurs.
+    pub async fn subscribe_proposals(
+        &self,
+        publisher: PublisherPtr<(HeaderHash, BlockInfo)>,
+    ) -> Result<()> {
+        self.subscribe("blockchain.subscribe_proposals", publisher).await
+    }
+
+    /// Auxiliary function to subscribe to a `darkfid` notifications method,
+    /// where each notification parameter is a base64-encoded serialized `T`.
+    async fn subscribe<T: AsyncDecodable + Clone + Send>(
+        &self,
+        method: &str,
+        publisher: PublisherPtr<T>,
+    ) -> Result<()> {
+        // Subscriptions keep the connection busy, so we use a new client
+        let client = RpcClient::new(self.endpoint.clone(), self.ex.clone()).await?;
+        let notifications = Publisher::new();
+        let subscription = notifications.clone().subscribe().await;
+
+        let req = JsonRequest::new(method, JsonValue::Array(vec![]));
+        let result = smol::future::or(
+            client.subscribe(req, notifications),
+            notifications_loop(method, &subscription, &publisher),
+        )
+        .await;
+
+        subscription.unsubscribe().await;
+        client.stop().await;
+
+        result
+    }
+}
+
+/// Auxiliary function to decode received notifications of given method
+/// and forward them to the provided publisher.
+async fn notifications_loop<T: AsyncDecodable + Clone + Send>(
+    method: &str,
+    subscription: &Subscription<JsonResult>,
+    publisher: &PublisherPtr<T>,
+) -> Result<()> {
+    loop {
+        let notification = match subscription.receive().await {
+            JsonResult::Notification(n) => n,
+            JsonResult::Error(e) => {
+                return Err(Error::JsonRpcError((e.error.code, e.error.message)))
+            }
+            x => {
+                return Err(Error::UnexpectedJsonRpc(format!(
+                    "Got unexpected data from JSON-RPC: {x:?}"
+                )))
+            }
+        };
+
+        if notification.method != method {
+            return Err(Error::UnexpectedJsonRpc(format!(
+                "Got foreign notification from darkfid: {}",
+                notification.method
+            )))
+        }
+
+        let Some(params) = notification.params.get::<Vec<JsonValue>>() else {
+            return Err(Error::UnexpectedJsonRpc(
+                "Received notification params are not an array".to_string(),
+            ))
+        };
+
+        for param in params {
+            publisher.notify(decode_base64(param).await?).await;
+        }
+    }
+}
+
+/// Auxiliary function to serialize an object into a base64-encoded `JsonValue` string.
+async fn encode_base64<T: AsyncEncodable + Sync>(object: &T) -> JsonValue {
+    JsonValue::String(base64::encode(&serialize_async(object).await))
+}
+
+/// Auxiliary function to deserialize an object from a base64-encoded `JsonValue` string.
+async fn decode_base64<T: AsyncDecodable>(value: &JsonValue) -> Result<T> {
+    let Some(bytes) = base64::decode(parse_string(value)?) else {
+        return Err(Error::UnexpectedJsonRpc("Failed decoding base64 data".to_string()))
+    };
+    Ok(deserialize_async(&bytes).await?)
+}
+
+/// Auxiliary function to parse a `JsonValue` string.
+fn parse_string(value: &JsonValue) -> Result<&String> {
+    let Some(s) = value.get::<String>() else {
+        return Err(Error::UnexpectedJsonRpc(format!("Expected string, got: {value:?}")))
+    };
+    Ok(s)
+}
+
+/// Auxiliary function to parse a `JsonValue` number.
+fn parse_number(value: &JsonValue) -> Result<f64> {
+    let Some(n) = value.get::<f64>() else {
+        return Err(Error::UnexpectedJsonRpc(format!("Expected number, got: {value:?}")))
+    };
+    Ok(*n)
+}
+
+/// Auxiliary function to parse a `JsonValue` boolean.
+fn parse_bool(value: &JsonValue) -> Result<bool> {
+    let Some(b) = value.get::<bool>() else {
+        return Err(Error::UnexpectedJsonRpc(format!("Expected boolean, got: {value:?}")))
+    };
+    Ok(*b)
+}
+
+/// Auxiliary function to parse a `JsonValue` array of transaction hashes strings.
+fn parse_tx_hashes(value: &JsonValue) -> Result<Vec<TransactionHash>> {
+    let Some(hashes) = value.get::<Vec<JsonValue>>() else {
+        return Err(Error::UnexpectedJsonRpc(format!("Expected array, got: {value:?}")))
+    };
+
+    let mut ret = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        ret.push(TransactionHash::from_str(parse_string(hash)?)?);
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use async_trait::async_trait;
+    use smol::{
+        lock::{Mutex, MutexGuard},
+        net::TcpListener,
+        Executor,
+    };
+
+    use super::*;
+    use crate::{
+        rpc::{
+            jsonrpc::{ErrorCode, JsonError, JsonResponse, JsonSubscriber},
+            server::{listen_and_serve, RequestHandler},
+        },
+        system::{msleep, StoppableTask, StoppableTaskPtr},
+    };
+
+    /// Mock `darkfid` JSON-RPC server, replying with canned data.
+    struct DarkfidMock {
+        block: BlockInfo,
+        blocks_subscriber: JsonSubscriber,
+        rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
+    }
+
+    #[async_trait]
+    impl RequestHandler for DarkfidMock {
+        async fn handle_request(&self, req: JsonRequest) -> JsonResult {
+            let result = match req.method.as_str() {
+                "ping" => return self.pong(req.id, req.params).await,
+                "blockchain.get_block" => encode_base64(&self.block).await,
+                "blockchain.last_known_block" => JsonValue::Number(self.block.header.height as f64),
+                "blockchain.block_target" => JsonValue::String("90".to_string()),
+                "blockchain.subscribe_blocks" => return self.blocks_subscriber.clone().into(),
+                "merge_mining_get_chain_id" => JsonValue::Object(HashMap::from([(
+                    "chain_id".to_string(),
+                    JsonValue::String(self.block.hash().to_string()),
+                )])),
+                "tx.pending" => JsonValue::Array(
+                    self.block
+                        .txs
+                        .iter()
+                        .map(|tx| JsonValue::String(tx.hash().to_string()))
+                        .collect(),
+                ),
+                _ => return JsonError::new(ErrorCode::MethodNotFound, None, req.id).into(),
+            };
+
+            JsonResponse::new(result, req.id).into()
+        }
+
+        async fn connections_mut(&self) -> MutexGuard<'life0, HashSet<StoppableTaskPtr>> {
+            self.rpc_connections.lock().await
+        }
+    }
+
+    #[test]
+    fn darkfid_client() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            // Find an available port
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let sockaddr = listener.local_addr()?;
+            let endpoint = Url::parse(&format!("tcp://127.0.0.1:{}", sockaddr.port()))?;
+            drop(listener);
+
+            let block = BlockInfo::default();
+            let rpc_server = Arc::new(DarkfidMock {
+                block: block.clone(),
+                blocks_subscriber: JsonSubscriber::new("blockchain.subscribe_blocks"),
+                rpc_connections: Mutex::new(HashSet::new()),
+            });
+
+            let server_task = StoppableTask::new();
+            server_task.clone().start(
+                listen_and_serve(endpoint.clone(), rpc_server.clone(), None, executor.clone()),
+                |_| async move {},
+                Error::RpcServerStopped,
+                executor.clone(),
+            );
+
+            // Let the server spawn
+            msleep(500).await;
+
+            let client = DarkfidClient::new(endpoint.clone(), executor.clone()).await?;
+            assert_eq!(client.endpoint(), &endpoint);
+
+            // Replies must be decoded into their native types
+            client.ping().await?;
+            assert_eq!(client.get_block(0).await?.hash(), block.hash());
+            assert_eq!(client.last_known_block().await?, block.header.height);
+            assert_eq!(client.merge_mining_chain_id().await?, block.hash());
+            let pending: Vec<TransactionHash> = block.txs.iter().map(|tx| tx.hash()).collect();
+            assert_eq!(client.pending_txs().await?, pending);
+
+            // Replies of unexpected type must be rejected
+            assert!(matches!(client.block_target().await, Err(Error::UnexpectedJsonRpc(_))));
+
+            // Server errors must be propagated
+            assert!(matches!(
+                client.clock().await,
+                Err(Error::JsonRpcError((code, _))) if code == ErrorCode::MethodNotFound.code()
+            ));
+
+            // Notifications must be decoded and forwarded to the publisher
+            let publisher = Publisher::new();
+            let subscription = publisher.clone().subscribe().await;
+            let ex = executor.clone();
+            let subscribe_task = executor.spawn(async move {
+                let client = DarkfidClient::new(endpoint, ex).await?;
+                client.subscribe_blocks(publisher).await
+            });
+            msleep(500).await;
+            rpc_server
+                .blocks_subscriber
+                .notify(JsonValue::Array(vec![encode_base64(&block).await]))
+                .await;
+            assert_eq!(subscription.receive().await.hash(), block.hash());
+
+            // Foreign data must terminate the subscription
+            rpc_server.blocks_subscriber.notify(JsonValue::Array(vec![JsonValue::Null])).await;
+            assert!(matches!(subscribe_task.await, Err(Error::UnexpectedJsonRpc(_))));
+            subscription.unsubscribe().await;
+
+            client.stop().await;
+            server_task.stop().await;
+
+            Ok(())
+        }))
+    }
+}
@@ -47,6 +47,12 @@ pub enum ErrorCode {
     IdMismatch,
     /// Invalid/Unexpected reply
     InvalidReply,
+    /// Server connections limit reached
+    TooManyConnections,
+    /// Connection in-flight requests or IP rate limit reached
+    TooManyRequests,
+    /// Request exceeds the maximum allowed size
+    RequestTooLarge,
     /// Reserved for implementation-defined server-errors.
     ServerError(i32),
 }
@@ -61,6 +67,9 @@ impl ErrorCode {
             Self::InternalError => -32603,
             Self::IdMismatch => -32360,
             Self::InvalidReply => -32361,
+            Self::TooManyConnections => -32362,
+            Self::TooManyRequests => -32363,
+            Self::RequestTooLarge => -32364,
             Self::ServerError(c) => c,
         }
     }
@@ -74,6 +83,9 @@ impl ErrorCode {
             Self::InternalError => "internal error".to_string(),
             Self::IdMismatch => "id mismatch".to_string(),
             Self::InvalidReply => "invalid reply".to_string(),
+            Self::TooManyConnections => "too many connections".to_string(),
+            Self::TooManyRequests => "too many requests".to_string(),
+            Self::RequestTooLarge => "request too large".to_string(),
             Self::ServerError(_) => "server error".to_string(),
         }
     }
@@ -25,9 +25,16 @@ pub mod jsonrpc;
 /// Client-side JSON-RPC implementation
 pub mod client;
 
+/// Typed client for the `darkfid` JSON-RPC API
+#[cfg(feature = "blockchain")]
+pub mod darkfid_client;
+
 /// Server-side JSON-RPC implementation
 pub mod server;
 
+/// JSON-RPC server settings and limits
+pub mod settings;
+
 /// Clock sync utility module
 pub mod clock_sync;
 
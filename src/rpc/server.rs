@@ -16,10 +16,17 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashSet, io::ErrorKind, sync::Arc};
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use async_trait::async_trait;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use smol::{
     io::{BufReader, ReadHalf, WriteHalf},
     lock::{Mutex, MutexGuard},
@@ -30,6 +37,7 @@ use url::Url;
 use super::{
     common::{read_from_stream, write_to_stream, INIT_BUF_SIZE},
     jsonrpc::*,
+    settings::{RateLimiter, RpcSettings},
 };
 use crate::{
     net::transport::{Listener, PtListener, PtStream},
@@ -202,25 +210,43 @@ async fn handle_request(
     Ok(())
 }
 
+/// Auxiliary function to write a JSON-RPC error to the given stream writer.
+async fn write_error(
+    writer: &Arc<Mutex<WriteHalf<Box<dyn PtStream>>>>,
+    addr: &Url,
+    error: JsonError,
+) -> Result<()> {
+    debug!(target: "rpc::server", "{} <-- {}", addr, error.stringify()?);
+    let mut writer_lock = writer.lock().await;
+    write_to_stream(&mut writer_lock, &error.into()).await?;
+    drop(writer_lock);
+    Ok(())
+}
+
 /// Accept function that should run inside a loop for accepting incoming
 /// JSON-RPC requests and passing them to the [`RequestHandler`].
+/// Incoming requests are bounded by the provided [`RpcSettings`], and the
+/// per-IP request rate is tracked by the shared [`RateLimiter`].
 #[allow(clippy::type_complexity)]
 pub async fn accept(
     reader: Arc<Mutex<BufReader<ReadHalf<Box<dyn PtStream>>>>>,
     writer: Arc<Mutex<WriteHalf<Box<dyn PtStream>>>>,
     addr: Url,
     rh: Arc<impl RequestHandler + 'static>,
-    conn_limit: Option<usize>,
+    settings: RpcSettings,
+    rate_limiter: Arc<RateLimiter>,
     ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
     // If there's a connection limit set, we will refuse connections
     // after this point.
-    if let Some(conn_limit) = conn_limit {
-        if rh.clone().active_connections().await >= conn_limit {
+    if let Some(conn_limit) = settings.max_connections {
+        if rh.clone().active_connections().await > conn_limit {
             debug!(
                 target: "rpc::server::accept()",
                 "Connection limit reached, refusing new conn"
             );
+            write_error(&writer, &addr, JsonError::new(ErrorCode::TooManyConnections, None, 0))
+                .await?;
             return Err(Error::RpcConnectionsExhausted)
         }
     }
@@ -228,13 +254,28 @@ pub async fn accept(
     // We'll hold our background tasks here
     let tasks = Arc::new(Mutex::new(HashSet::new()));
 
+    // Number of requests currently being handled in the background
+    let inflight = Arc::new(AtomicUsize::new(0));
+
     loop {
         let mut buf = Vec::with_capacity(INIT_BUF_SIZE);
 
         let mut reader_lock = reader.lock().await;
-        let _ = read_from_stream(&mut reader_lock, &mut buf).await?;
+        let read = read_from_stream(&mut reader_lock, &mut buf, settings.max_request_size).await;
         drop(reader_lock);
 
+        if let Err(e) = read {
+            if e.kind() == ErrorKind::InvalidData {
+                warn!(
+                    target: "rpc::server::accept()",
+                    "[RPC SERVER] Request from {} exceeds maximum size, closing conn", addr,
+                );
+                write_error(&writer, &addr, JsonError::new(ErrorCode::RequestTooLarge, None, 0))
+                    .await?;
+            }
+            return Err(e.into())
+        }
+
         let line = match String::from_utf8(buf) {
             Ok(v) => v,
             Err(e) => {
@@ -272,14 +313,36 @@ pub async fn accept(
 
         debug!(target: "rpc::server", "{} --> {}", addr, val.stringify()?);
 
+        // Check the request is within the IP rate limit
+        if !rate_limiter.check(&addr).await {
+            debug!(target: "rpc::server::accept()", "Rate limit reached for {}", addr);
+            let message = "request rate limit exceeded".to_string();
+            let error = JsonError::new(ErrorCode::TooManyRequests, Some(message), req.id);
+            write_error(&writer, &addr, error).await?;
+            continue
+        }
+
+        // Check the connection in-flight requests limit
+        if let Some(inflight_limit) = settings.max_inflight_requests {
+            if inflight.load(Ordering::SeqCst) >= inflight_limit {
+                debug!(target: "rpc::server::accept()", "In-flight requests limit reached for {}", addr);
+                let message = "in-flight requests limit exceeded".to_string();
+                let error = JsonError::new(ErrorCode::TooManyRequests, Some(message), req.id);
+                write_error(&writer, &addr, error).await?;
+                continue
+            }
+        }
+
         // Create a new task to handle request in the background
         let task = StoppableTask::new();
 
         // Clone what needs to go in the background
         let task_ = task.clone();
         let tasks_ = tasks.clone();
+        let inflight_ = inflight.clone();
 
         // Detach the task
+        inflight.fetch_add(1, Ordering::SeqCst);
         task.clone().start(
             handle_request(
                 writer.clone(),
@@ -295,6 +358,7 @@ pub async fn accept(
                     "Removing background task {} from map", task_.task_id,
                 );
                 tasks_.lock().await.remove(&task_);
+                inflight_.fetch_sub(1, Ordering::SeqCst);
             },
             Error::DetachedTaskStopped,
             ex.clone(),
@@ -310,9 +374,11 @@ pub async fn accept(
 async fn run_accept_loop(
     listener: Box<dyn PtListener>,
     rh: Arc<impl RequestHandler + 'static>,
-    conn_limit: Option<usize>,
+    settings: RpcSettings,
     ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
+    let rate_limiter = Arc::new(RateLimiter::new(settings.ip_rate_limit));
+
     loop {
         match listener.next().await {
             Ok((stream, url)) => {
@@ -323,11 +389,23 @@ async fn run_accept_loop(
                 let reader = Arc::new(Mutex::new(BufReader::new(reader)));
                 let writer = Arc::new(Mutex::new(writer));
 
+                // Mark the connection before starting its task, so it is
+                // accounted for when checking the connections limit.
                 let task = StoppableTask::new();
+                rh.clone().mark_connection(task.clone()).await;
+
                 let task_ = task.clone();
                 let ex_ = ex.clone();
                 task.clone().start(
-                    accept(reader, writer, url.clone(), rh.clone(), conn_limit, ex_),
+                    accept(
+                        reader,
+                        writer,
+                        url.clone(),
+                        rh.clone(),
+                        settings.clone(),
+                        rate_limiter.clone(),
+                        ex_,
+                    ),
                     |_| async move {
                         info!(target: "rpc::server", "[RPC] Closed conn from {}", url);
                         rh_.clone().unmark_connection(task_.clone()).await;
@@ -335,8 +413,6 @@ async fn run_accept_loop(
                     Error::ChannelStopped,
                     ex.clone(),
                 );
-
-                rh.clone().mark_connection(task.clone()).await;
             }
 
             // As per accept(2) recommendation:
@@ -376,21 +452,27 @@ async fn run_accept_loop(
 
 /// Start a JSON-RPC server bound to the given accept URL and use the
 /// given [`RequestHandler`] to handle incoming requests.
+/// If no [`RpcSettings`] are provided, the defaults are used.
 pub async fn listen_and_serve(
     accept_url: Url,
     rh: Arc<impl RequestHandler + 'static>,
-    conn_limit: Option<usize>,
+    settings: Option<RpcSettings>,
     ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
     let listener = Listener::new(accept_url, None).await?.listen().await?;
-    run_accept_loop(listener, rh, conn_limit, ex.clone()).await
+    run_accept_loop(listener, rh, settings.unwrap_or_default(), ex.clone()).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{rpc::client::RpcClient, system::msleep};
-    use smol::{net::TcpListener, Executor};
+    use smol::{
+        io::{AsyncBufReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        Executor,
+    };
+    use std::collections::HashMap;
 
     struct RpcServer {
         rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
@@ -401,6 +483,10 @@ mod tests {
         async fn handle_request(&self, req: JsonRequest) -> JsonResult {
             match req.method.as_str() {
                 "ping" => return self.pong(req.id, req.params).await,
+                "sleep" => {
+                    msleep(1000).await;
+                    return self.pong(req.id, req.params).await
+                }
                 _ => panic!(),
             }
         }
@@ -482,4 +568,161 @@ mod tests {
             Ok(())
         }))
     }
+
+    /// Auxiliary function to start a JSON-RPC server using the given
+    /// settings on an available port.
+    async fn start_server(
+        settings: RpcSettings,
+        executor: Arc<Executor<'static>>,
+    ) -> Result<(Arc<RpcServer>, std::net::SocketAddr, StoppableTaskPtr)> {
+        // Find an available port
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let sockaddr = listener.local_addr()?;
+        let endpoint = Url::parse(&format!("tcp://127.0.0.1:{}", sockaddr.port()))?;
+        drop(listener);
+
+        let rpc_server = Arc::new(RpcServer { rpc_connections: Mutex::new(HashSet::new()) });
+        let server_task = StoppableTask::new();
+        server_task.clone().start(
+            listen_and_serve(endpoint, rpc_server.clone(), Some(settings), executor.clone()),
+            |_| async move {},
+            Error::RpcServerStopped,
+            executor,
+        );
+
+        // Let the server spawn
+        msleep(500).await;
+
+        Ok((rpc_server, sockaddr, server_task))
+    }
+
+    /// Auxiliary function to write a raw request line to the given stream.
+    async fn send(stream: &mut TcpStream, method: &str, params: &str, id: u16) -> Result<()> {
+        let req = format!(
+            "{{\"jsonrpc\": \"2.0\", \"method\": \"{method}\", \"params\": [{params}], \"id\": {id}}}\n"
+        );
+        stream.write_all(req.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Auxiliary function to read a reply line from the given stream,
+    /// returning its ID along with its error code, if any.
+    /// Returns `None` if the server closed the connection.
+    async fn recv(reader: &mut BufReader<TcpStream>) -> Result<Option<(u16, Option<i32>)>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None)
+        }
+
+        let val: JsonValue = line.trim().parse()?;
+        let val: &HashMap<String, JsonValue> = val.get().unwrap();
+        let id = *val["id"].get::<f64>().unwrap() as u16;
+        let code = val.get("error").map(|error| {
+            let error: &HashMap<String, JsonValue> = error.get().unwrap();
+            *error["code"].get::<f64>().unwrap() as i32
+        });
+
+        Ok(Some((id, code)))
+    }
+
+    #[test]
+    fn max_request_size() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = RpcSettings { max_request_size: 128, ..Default::default() };
+            let (rpc_server, sockaddr, server_task) =
+                start_server(settings, executor.clone()).await?;
+
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+
+            // Requests within the limit are served
+            send(&mut stream, "ping", "", 1).await?;
+            assert_eq!(recv(&mut reader).await?, Some((1, None)));
+
+            // Requests exceeding it get an error and the connection closed
+            let params = format!("\"{}\"", "x".repeat(128));
+            send(&mut stream, "ping", &params, 2).await?;
+            let code = ErrorCode::RequestTooLarge.code();
+            assert_eq!(recv(&mut reader).await?, Some((0, Some(code))));
+            // The unread remainder of the request might make the closing
+            // connection get reset, so we only check no more data arrives.
+            assert!(!matches!(recv(&mut reader).await, Ok(Some(_))));
+            msleep(500).await;
+            assert_eq!(rpc_server.active_connections().await, 0);
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+
+    #[test]
+    fn max_connections() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = RpcSettings { max_connections: Some(1), ..Default::default() };
+            let (rpc_server, sockaddr, server_task) =
+                start_server(settings, executor.clone()).await?;
+
+            let mut stream0 = TcpStream::connect(sockaddr).await?;
+            let mut reader0 = BufReader::new(stream0.clone());
+            send(&mut stream0, "ping", "", 1).await?;
+            assert_eq!(recv(&mut reader0).await?, Some((1, None)));
+            assert_eq!(rpc_server.active_connections().await, 1);
+
+            // Connections over the limit get an error and get closed
+            let stream1 = TcpStream::connect(sockaddr).await?;
+            let mut reader1 = BufReader::new(stream1);
+            let code = ErrorCode::TooManyConnections.code();
+            assert_eq!(recv(&mut reader1).await?, Some((0, Some(code))));
+            assert_eq!(recv(&mut reader1).await?, None);
+            msleep(500).await;
+            assert_eq!(rpc_server.active_connections().await, 1);
+
+            // Closing a connection frees its slot
+            drop(reader0);
+            drop(stream0);
+            msleep(500).await;
+            assert_eq!(rpc_server.active_connections().await, 0);
+            let mut stream2 = TcpStream::connect(sockaddr).await?;
+            let mut reader2 = BufReader::new(stream2.clone());
+            send(&mut stream2, "ping", "", 2).await?;
+            assert_eq!(recv(&mut reader2).await?, Some((2, None)));
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+
+    #[test]
+    fn max_inflight_requests() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = RpcSettings { max_inflight_requests: Some(1), ..Default::default() };
+            let (_rpc_server, sockaddr, server_task) =
+                start_server(settings, executor.clone()).await?;
+
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+
+            // A request while another one is still being handled gets
+            // rejected, without affecting the pending one.
+            send(&mut stream, "sleep", "", 1).await?;
+            send(&mut stream, "ping", "", 2).await?;
+            let code = ErrorCode::TooManyRequests.code();
+            assert_eq!(recv(&mut reader).await?, Some((2, Some(code))));
+            assert_eq!(recv(&mut reader).await?, Some((1, None)));
+
+            // Once it got handled, new requests are served again
+            msleep(500).await;
+            send(&mut stream, "ping", "", 3).await?;
+            assert_eq!(recv(&mut reader).await?, Some((3, None)));
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
 }
@@ -0,0 +1,174 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use smol::lock::Mutex;
+use url::Url;
+
+use super::common::MAX_BUF_SIZE;
+
+/// Time window the per-IP request rate limit is applied on.
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// JSON-RPC server settings, used to bound the resources
+/// connected clients can consume.
+#[derive(Debug, Clone)]
+pub struct RpcSettings {
+    /// Maximum number of concurrent connections, unlimited if `None`
+    pub max_connections: Option<usize>,
+    /// Maximum number of in-flight requests per connection, unlimited if `None`
+    pub max_inflight_requests: Option<usize>,
+    /// Maximum size of a single request, in bytes
+    pub max_request_size: usize,
+    /// Maximum number of requests per IP address within a
+    /// [`RATE_LIMIT_WINDOW`], unlimited if `None`. See [`RateLimiter`].
+    pub ip_rate_limit: Option<usize>,
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_inflight_requests: None,
+            max_request_size: MAX_BUF_SIZE,
+            ip_rate_limit: None,
+        }
+    }
+}
+
+// The following is used so we can have JSON-RPC settings configurable
+// from TOML files.
+
+/// Defines the JSON-RPC server settings.
+#[derive(
+    Clone, Debug, Default, serde::Deserialize, structopt::StructOpt, structopt_toml::StructOptToml,
+)]
+#[structopt()]
+pub struct RpcSettingsOpt {
+    /// Maximum number of concurrent JSON-RPC connections
+    #[structopt(long = "rpc-max-connections")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of in-flight JSON-RPC requests per connection
+    #[structopt(long = "rpc-max-inflight-requests")]
+    pub max_inflight_requests: Option<usize>,
+
+    /// Maximum JSON-RPC request size, in bytes
+    #[structopt(long = "rpc-max-request-size")]
+    pub max_request_size: Option<usize>,
+
+    /// Maximum number of JSON-RPC requests per IP address per minute
+    #[structopt(long = "rpc-ip-rate-limit")]
+    pub ip_rate_limit: Option<usize>,
+}
+
+impl From<RpcSettingsOpt> for RpcSettings {
+    fn from(opt: RpcSettingsOpt) -> Self {
+        let def = RpcSettings::default();
+
+        Self {
+            max_connections: opt.max_connections,
+            max_inflight_requests: opt.max_inflight_requests,
+            max_request_size: opt.max_request_size.unwrap_or(def.max_request_size),
+            ip_rate_limit: opt.ip_rate_limit,
+        }
+    }
+}
+
+/// Per-IP address request rate limiter, shared between all the
+/// connections of a JSON-RPC server.
+///
+/// Requests are keyed on the peer address host as reported by the
+/// transport. Connections forwarded by a local proxy, like a Tor onion
+/// service, all originate from `127.0.0.1`, so they are limited as a
+/// single client. Such deployments should either size the limit for
+/// all their clients or leave it unset.
+pub struct RateLimiter {
+    /// Maximum number of requests per IP address within a window,
+    /// unlimited if `None`
+    limit: Option<usize>,
+    /// Window start and requests count for each seen IP address
+    requests: Mutex<HashMap<String, (Instant, usize)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit, requests: Mutex::new(HashMap::new()) }
+    }
+
+    /// Register a request coming from given address, and check if it is
+    /// still within the rate limit. Addresses without a host, like Unix
+    /// sockets, are never limited.
+    pub async fn check(&self, addr: &Url) -> bool {
+        let Some(limit) = self.limit else { return true };
+        let Some(host) = addr.host_str().filter(|h| !h.is_empty()) else { return true };
+
+        let mut requests = self.requests.lock().await;
+
+        // Forget addresses whose window has expired
+        requests.retain(|_, (start, _)| start.elapsed() < RATE_LIMIT_WINDOW);
+
+        let (_, count) = requests.entry(host.to_string()).or_insert((Instant::now(), 0));
+        if *count >= limit {
+            return false
+        }
+        *count += 1;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter() {
+        smol::block_on(async {
+            let limiter = RateLimiter::new(Some(2));
+            let addr0 = Url::parse("tcp://127.0.0.1:1234").unwrap();
+            let addr1 = Url::parse("tcp://127.0.0.2:1234").unwrap();
+            let unix = Url::parse("unix:///tmp/rpc.sock").unwrap();
+
+            assert!(limiter.check(&addr0).await);
+            assert!(limiter.check(&addr0).await);
+            assert!(!limiter.check(&addr0).await);
+
+            // Different port, same host
+            assert!(!limiter.check(&Url::parse("tcp://127.0.0.1:4321").unwrap()).await);
+
+            // Other hosts have their own limit
+            assert!(limiter.check(&addr1).await);
+
+            // Unix sockets are never limited
+            for _ in 0..3 {
+                assert!(limiter.check(&unix).await);
+            }
+
+            // No limit configured
+            let limiter = RateLimiter::new(None);
+            for _ in 0..3 {
+                assert!(limiter.check(&addr0).await);
+            }
+        });
+    }
+}
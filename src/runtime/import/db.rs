@@ -25,7 +25,9 @@ use wasmer::{FunctionEnvMut, WasmPtr};
 
 use super::acl::acl_allow;
 use crate::{
-    blockchain::contract_store::SMART_CONTRACT_ZKAS_DB_NAME,
+    blockchain::contract_store::{
+        state_overlay_insert, state_overlay_remove, SMART_CONTRACT_ZKAS_DB_NAME,
+    },
     runtime::vm_runtime::{ContractSection, Env},
     zk::{empty_witnesses, VerifyingKey, ZkCircuit},
     zkas::ZkBinary,
@@ -437,15 +439,13 @@ pub(crate) fn db_set(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, ptr_len: u3
     }
 
     // Insert key-value pair into the database corresponding to this contract
-    if env
-        .blockchain
-        .lock()
-        .unwrap()
-        .overlay
-        .lock()
-        .unwrap()
-        .insert(&db_handle.tree, &key, &value)
-        .is_err()
+    if state_overlay_insert(
+        &mut env.blockchain.lock().unwrap().overlay.lock().unwrap(),
+        &db_handle.tree,
+        &key,
+        &value,
+    )
+    .is_err()
     {
         error!(
             target: "runtime::db::db_set",
@@ -557,7 +557,12 @@ pub(crate) fn db_del(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, ptr_len: u3
     }
 
     // Remove key-value pair from the database corresponding to this contract
-    if env.blockchain.lock().unwrap().overlay.lock().unwrap().remove(&db_handle.tree, &key).is_err()
+    if state_overlay_remove(
+        &mut env.blockchain.lock().unwrap().overlay.lock().unwrap(),
+        &db_handle.tree,
+        &key,
+    )
+    .is_err()
     {
         error!(
             target: "runtime::db::db_del",
@@ -903,6 +908,7 @@ pub(crate) fn zkas_db_set(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, ptr_le
     // Check if there is existing bincode and compare it. Return DB_SUCCESS if
     // they're the same. The assumption should be that VerifyingKey was generated
     // already so we can skip things after this guard.
+    let mut replaced_zkbin = None;
     match env
         .blockchain
         .lock()
@@ -926,6 +932,8 @@ pub(crate) fn zkas_db_set(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, ptr_le
                     );
                     return wasm::entrypoint::SUCCESS
                 }
+
+                replaced_zkbin = Some(existing_zkbin);
             }
         }
         Err(e) => {
@@ -937,48 +945,80 @@ pub(crate) fn zkas_db_set(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, ptr_le
         }
     };
 
-    // We didn't find any existing bincode, so let's create a new VerifyingKey and write it all.
-    info!(
-        target: "runtime::db::zkas_db_set",
-        "[WASM] [{}] zkas_db_set(): Creating VerifyingKey for {} zkas circuit",
-        cid, zkbin.namespace,
-    );
+    // Invalidate the replaced circuit cached VerifyingKey, once the
+    // overlay gets applied to canonical state.
+    let blockchain = env.blockchain.lock().unwrap();
+    if let Some(replaced_zkbin) = replaced_zkbin {
+        blockchain.contracts.remove_vk(&blake3::hash(&replaced_zkbin));
+    }
+
+    // We didn't find any existing bincode, so let's grab its cached VerifyingKey,
+    // or create a new one, and write it all.
+    let zkas_hash = blake3::hash(&zkbin_bytes);
+    let vk_buf = match blockchain.contracts.get_serialized_vk(&zkas_hash) {
+        Ok(Some(vk_buf)) => {
+            debug!(
+                target: "runtime::db::zkas_db_set",
+                "[WASM] [{}] zkas_db_set(): Using cached VerifyingKey for {} zkas circuit",
+                cid, zkbin.namespace,
+            );
+            vk_buf
+        }
+        Ok(None) => {
+            info!(
+                target: "runtime::db::zkas_db_set",
+                "[WASM] [{}] zkas_db_set(): Creating VerifyingKey for {} zkas circuit",
+                cid, zkbin.namespace,
+            );
+
+            let witnesses = match empty_witnesses(&zkbin) {
+                Ok(w) => w,
+                Err(e) => {
+                    error!(
+                        target: "runtime::db::zkas_db_set",
+                        "[WASM] [{}] zkas_db_set(): Failed to create empty witnesses: {}", cid, e,
+                    );
+                    return darkfi_sdk::error::DB_SET_FAILED
+                }
+            };
+
+            // Construct the circuit and build the VerifyingKey
+            let circuit = ZkCircuit::new(witnesses, &zkbin);
+            let vk = VerifyingKey::build(zkbin.k, &circuit);
+            let mut vk_buf = vec![];
+            if let Err(e) = vk.write(&mut vk_buf) {
+                error!(
+                    target: "runtime::db::zkas_db_set",
+                    "[WASM] [{}] zkas_db_set(): Failed to serialize VerifyingKey: {}", cid, e,
+                );
+                return darkfi_sdk::error::DB_SET_FAILED
+            }
+
+            // Cache it once the overlay gets applied, so we don't have to build it again
+            blockchain.contracts.insert_vk(&zkas_hash, &vk, &vk_buf);
 
-    let witnesses = match empty_witnesses(&zkbin) {
-        Ok(w) => w,
+            vk_buf
+        }
         Err(e) => {
             error!(
                 target: "runtime::db::zkas_db_set",
-                "[WASM] [{}] zkas_db_set(): Failed to create empty witnesses: {}", cid, e,
+                "[WASM] [{}] zkas_db_set(): Failed to retrieve cached VerifyingKey: {}", cid, e,
             );
             return darkfi_sdk::error::DB_SET_FAILED
         }
     };
-
-    // Construct the circuit and build the VerifyingKey
-    let circuit = ZkCircuit::new(witnesses, &zkbin);
-    let vk = VerifyingKey::build(zkbin.k, &circuit);
-    let mut vk_buf = vec![];
-    if let Err(e) = vk.write(&mut vk_buf) {
-        error!(
-            target: "runtime::db::zkas_db_set",
-            "[WASM] [{}] zkas_db_set(): Failed to serialize VerifyingKey: {}", cid, e,
-        );
-        return darkfi_sdk::error::DB_SET_FAILED
-    }
+    drop(blockchain);
 
     // Insert the key-value pair into the database.
     let key = serialize(&zkbin.namespace);
     let value = serialize(&(zkbin_bytes, vk_buf));
-    if env
-        .blockchain
-        .lock()
-        .unwrap()
-        .overlay
-        .lock()
-        .unwrap()
-        .insert(&db_handle.tree, &key, &value)
-        .is_err()
+    if state_overlay_insert(
+        &mut env.blockchain.lock().unwrap().overlay.lock().unwrap(),
+        &db_handle.tree,
+        &key,
+        &value,
+    )
+    .is_err()
     {
         error!(
             target: "runtime::db::zkas_db_set",
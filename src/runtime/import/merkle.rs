@@ -28,7 +28,10 @@ use log::{debug, error};
 use wasmer::{FunctionEnvMut, WasmPtr};
 
 use super::acl::acl_allow;
-use crate::runtime::vm_runtime::{ContractSection, Env};
+use crate::{
+    blockchain::contract_store::state_overlay_insert,
+    runtime::vm_runtime::{ContractSection, Env},
+};
 
 /// Adds data to merkle tree. The tree, database connection, and new data to add is
 /// read from `ptr` at offset specified by `len`.
@@ -247,7 +250,7 @@ pub(crate) fn merkle_add(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u3
     }
 
     // Apply changes to overlay
-    if overlay.insert(&db_info.tree, &tree_key, &tree_data).is_err() {
+    if state_overlay_insert(&mut overlay, &db_info.tree, &tree_key, &tree_data).is_err() {
         error!(
             target: "runtime::merkle::merkle_add",
             "[WASM] [{}] merkle_add(): Couldn't insert to db_info tree", cid,
@@ -277,7 +280,7 @@ pub(crate) fn merkle_add(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u3
     env.call_idx.encode(&mut value_data).expect("Unable to serialize call_idx");
     assert_eq!(value_data.len(), 32 + 1);
 
-    if overlay.insert(&db_roots.tree, &latest_root_data, &value_data).is_err() {
+    if state_overlay_insert(&mut overlay, &db_roots.tree, &latest_root_data, &value_data).is_err() {
         error!(
             target: "runtime::merkle::merkle_add",
             "[WASM] [{}] merkle_add(): Couldn't insert to db_roots tree", cid,
@@ -291,7 +294,7 @@ pub(crate) fn merkle_add(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u3
         "[WASM] [{}] merkle_add(): Replacing latest Merkle root pointer", cid,
     );
 
-    if overlay.insert(&db_info.tree, &root_key, &latest_root_data).is_err() {
+    if state_overlay_insert(&mut overlay, &db_info.tree, &root_key, &latest_root_data).is_err() {
         error!(
             target: "runtime::merkle::merkle_add",
             "[WASM] [{}] merkle_add(): Couldn't insert latest root to db_info tree", cid,
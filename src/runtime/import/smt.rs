@@ -33,9 +33,13 @@ use num_bigint::BigUint;
 use wasmer::{FunctionEnvMut, WasmPtr};
 
 use super::acl::acl_allow;
-use crate::runtime::vm_runtime::{ContractSection, Env};
+use crate::{
+    blockchain::contract_store::state_overlay_insert,
+    runtime::vm_runtime::{ContractSection, Env},
+};
 
-/// An SMT adapter for sled overlay storage. Compatible with the WasmDb SMT adapter
+/// An SMT adapter for sled overlay storage. Compatible with the WasmDb SMT adapter.
+/// Nodes are not tracked by the contracts state SMT, since their roots records are.
 pub struct SledStorage<'a> {
     overlay: &'a mut sled_overlay::SledDbOverlay,
     tree_key: &'a [u8],
@@ -342,7 +346,13 @@ pub(crate) fn sparse_merkle_insert_batch(
         target: "runtime::smt::sparse_merkle_insert_batch",
         "[WASM] [{}] sparse_merkle_insert_batch(): Appending SMT root to db: {:?}", cid, latest_root,
     );
-    if overlay.insert(&db_roots.tree, &latest_root_data, &serialize(&root_value_data_set)).is_err()
+    if state_overlay_insert(
+        &mut overlay,
+        &db_roots.tree,
+        &latest_root_data,
+        &serialize(&root_value_data_set),
+    )
+    .is_err()
     {
         error!(
             target: "runtime::smt::sparse_merkle_insert_batch",
@@ -356,7 +366,7 @@ pub(crate) fn sparse_merkle_insert_batch(
         target: "runtime::smt::sparse_merkle_insert_batch",
         "[WASM] [{}] sparse_merkle_insert_batch(): Replacing latest SMT root pointer", cid,
     );
-    if overlay.insert(&db_info.tree, &root_key, &latest_root_data).is_err() {
+    if state_overlay_insert(&mut overlay, &db_info.tree, &root_key, &latest_root_data).is_err() {
         error!(
             target: "runtime::smt::sparse_merkle_insert_batch",
             "[WASM] [{}] sparse_merkle_insert_batch(): Couldn't insert latest root to db_info tree", cid,
@@ -364,6 +374,10 @@ pub(crate) fn sparse_merkle_insert_batch(
         return darkfi_sdk::error::INTERNAL_ERROR
     }
 
+    // Keep track of the inserted nullifiers, so the transaction
+    // verifier can find pending transactions spending the same coins.
+    env.nullifiers.borrow_mut().extend_from_slice(&nullifiers);
+
     // Subtract used gas.
     // Here we count:
     // * The number of nullifiers we inserted into the DB
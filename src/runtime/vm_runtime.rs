@@ -21,7 +21,7 @@ use std::{
     sync::Arc,
 };
 
-use darkfi_sdk::{crypto::ContractId, tx::TransactionHash, wasm, AsHex};
+use darkfi_sdk::{crypto::ContractId, pasta::pallas, tx::TransactionHash, wasm, AsHex};
 use darkfi_serial::serialize;
 use log::{debug, error, info};
 use wasmer::{
@@ -103,6 +103,8 @@ pub struct Env {
     pub tx_hash: TransactionHash,
     /// The index for this call in the transaction
     pub call_idx: u8,
+    /// Nullifiers inserted into the contract sparse Merkle trees
+    pub nullifiers: RefCell<Vec<pallas::Base>>,
     /// Parent `Instance`
     pub instance: Option<Arc<Instance>>,
 }
@@ -205,6 +207,7 @@ impl Runtime {
                 block_target,
                 tx_hash,
                 call_idx,
+                nullifiers: RefCell::new(vec![]),
                 instance: None,
             },
         );
@@ -538,6 +541,12 @@ impl Runtime {
         Ok(())
     }
 
+    /// Retrieve the nullifiers inserted into the contract sparse Merkle
+    /// trees by the executed calls.
+    pub fn nullifiers(&self) -> Vec<pallas::Base> {
+        self.ctx.as_ref(&self.store).nullifiers.borrow().clone()
+    }
+
     /// Prints the wasm contract logs.
     fn print_logs(&self) {
         let logs = self.ctx.as_ref(&self.store).logs.borrow();
@@ -34,6 +34,7 @@ use darkfi_sdk::{
 use darkfi_serial::async_trait;
 
 use darkfi_serial::{Encodable, SerialDecodable, SerialEncodable};
+use futures::future::join_all;
 use log::{debug, error};
 
 use crate::{
@@ -66,55 +67,18 @@ pub struct Transaction {
 // ANCHOR_END: transaction
 
 impl Transaction {
-    /// Verify ZK proofs for the entire transaction.
+    /// Verify ZK proofs for the entire transaction, batching together
+    /// the proofs created for the same circuit.
     pub async fn verify_zkps(
         &self,
         verifying_keys: &HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
         zkp_table: Vec<Vec<(String, Vec<pallas::Base>)>>,
     ) -> Result<()> {
-        // TODO: Are we sure we should assert here?
-        assert_eq!(self.calls.len(), self.proofs.len());
-        assert_eq!(self.calls.len(), zkp_table.len());
+        let mut batch = ZkProofBatch::default();
+        batch.add(0, self, verifying_keys, zkp_table)?;
 
-        for (call, (proofs, pubvals)) in zip!(self.calls, self.proofs, zkp_table) {
-            assert_eq!(proofs.len(), pubvals.len());
-
-            let Some(contract_map) = verifying_keys.get(&call.data.contract_id.to_bytes()) else {
-                error!(
-                    target: "tx::verify_zkps",
-                    "[TX] Verifying keys not found for contract {}",
-                    call.data.contract_id,
-                );
-                return Err(TxVerifyFailed::InvalidZkProof.into())
-            };
-
-            for (proof, (zk_ns, public_vals)) in proofs.iter().zip(pubvals.iter()) {
-                if let Some(vk) = contract_map.get(zk_ns) {
-                    // We have a verifying key for this
-                    debug!(target: "tx::verify_zkps", "[TX] public inputs: {:#?}", public_vals);
-                    if let Err(e) = proof.verify(vk, public_vals) {
-                        error!(
-                            target: "tx::verify_zkps",
-                            "[TX] Failed verifying {}::{} ZK proof: {:#?}",
-                            call.data.contract_id, zk_ns, e
-                        );
-                        return Err(TxVerifyFailed::InvalidZkProof.into())
-                    }
-                    debug!(
-                        target: "tx::verify_zkps",
-                        "[TX] Successfully verified {}::{} ZK proof",
-                        call.data.contract_id, zk_ns,
-                    );
-                    continue
-                }
-
-                error!(
-                    target: "tx::verify_zkps",
-                    "[TX] {}::{} circuit VK nonexistent",
-                    call.data.contract_id, zk_ns,
-                );
-                return Err(TxVerifyFailed::InvalidZkProof.into())
-            }
+        if !batch.verify(verifying_keys).is_empty() {
+            return Err(TxVerifyFailed::InvalidZkProof.into())
         }
 
         Ok(())
@@ -195,6 +159,156 @@ impl Transaction {
     }
 }
 
+/// A set of ZK proofs from one or more transactions, grouped by the circuit
+/// they were created for, so each group can be verified at once using halo2's
+/// batch verifier. This way all the proofs of a block, or a batch of pending
+/// transactions, get verified together instead of one by one.
+#[derive(Default)]
+pub struct ZkProofBatch {
+    /// Proofs grouped by their contract ID and zkas namespace, along with
+    /// the index of the item they belong to and their public inputs
+    groups: HashMap<([u8; 32], String), Vec<(usize, Proof, Vec<pallas::Base>)>>,
+}
+
+impl ZkProofBatch {
+    /// Add the ZK proofs of provided transaction to the batch, tagged with the
+    /// given item index. Returns an error if the proofs don't match the public
+    /// inputs table, or a verifying key for them doesn't exist, in which case
+    /// none of the transaction proofs are added.
+    pub fn add(
+        &mut self,
+        index: usize,
+        tx: &Transaction,
+        verifying_keys: &HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
+        zkp_table: Vec<Vec<(String, Vec<pallas::Base>)>>,
+    ) -> Result<()> {
+        if tx.calls.len() != tx.proofs.len() || tx.calls.len() != zkp_table.len() {
+            error!(
+                target: "tx::ZkProofBatch::add",
+                "[TX] Transaction {} proofs don't match its calls", tx.hash(),
+            );
+            return Err(TxVerifyFailed::InvalidZkProof.into())
+        }
+
+        let mut items = vec![];
+        for (call, (proofs, pubvals)) in zip!(tx.calls, tx.proofs, zkp_table) {
+            if proofs.len() != pubvals.len() {
+                error!(
+                    target: "tx::ZkProofBatch::add",
+                    "[TX] Contract {} call proofs don't match its public inputs",
+                    call.data.contract_id,
+                );
+                return Err(TxVerifyFailed::InvalidZkProof.into())
+            }
+
+            let contract_id = call.data.contract_id.to_bytes();
+            let Some(contract_map) = verifying_keys.get(&contract_id) else {
+                error!(
+                    target: "tx::ZkProofBatch::add",
+                    "[TX] Verifying keys not found for contract {}",
+                    call.data.contract_id,
+                );
+                return Err(TxVerifyFailed::InvalidZkProof.into())
+            };
+
+            for (proof, (zk_ns, public_vals)) in proofs.iter().zip(pubvals.iter()) {
+                if !contract_map.contains_key(zk_ns) {
+                    error!(
+                        target: "tx::ZkProofBatch::add",
+                        "[TX] {}::{} circuit VK nonexistent",
+                        call.data.contract_id, zk_ns,
+                    );
+                    return Err(TxVerifyFailed::InvalidZkProof.into())
+                }
+
+                debug!(target: "tx::ZkProofBatch::add", "[TX] public inputs: {:#?}", public_vals);
+                items.push(((contract_id, zk_ns.clone()), proof.clone(), public_vals.clone()));
+            }
+        }
+
+        for (key, proof, public_vals) in items {
+            self.groups.entry(key).or_default().push((index, proof, public_vals));
+        }
+
+        Ok(())
+    }
+
+    /// Verify all the proofs in the batch. Each circuit group is verified using
+    /// halo2's batch verifier, on the blocking threads pool, with at most as many
+    /// groups running in parallel as the available CPU cores. If a group fails,
+    /// its proofs are verified one by one to identify the invalid ones. Returns
+    /// the sorted indexes of the items that contain an invalid proof.
+    pub async fn verify(
+        self,
+        verifying_keys: &HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
+    ) -> Vec<usize> {
+        let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let mut groups: Vec<_> = self
+            .groups
+            .into_iter()
+            .map(|((contract_id, zk_ns), proofs)| {
+                let vk = verifying_keys.get(&contract_id).and_then(|map| map.get(&zk_ns)).cloned();
+                (vk, zk_ns, proofs)
+            })
+            .collect();
+
+        let mut invalid = vec![];
+        while !groups.is_empty() {
+            let tasks = groups.drain(..workers.min(groups.len())).map(|(vk, zk_ns, proofs)| {
+                smol::unblock(move || match vk {
+                    Some(vk) => verify_proofs_group(&vk, &zk_ns, &proofs),
+                    None => proofs.iter().map(|(index, _, _)| *index).collect(),
+                })
+            });
+            invalid.extend(join_all(tasks).await.into_iter().flatten());
+        }
+
+        invalid.sort_unstable();
+        invalid.dedup();
+        invalid
+    }
+}
+
+/// Auxiliary function to verify a group of proofs created for the same circuit.
+/// Returns the indexes of the items containing an invalid proof.
+fn verify_proofs_group(
+    vk: &VerifyingKey,
+    zk_ns: &str,
+    proofs: &[(usize, Proof, Vec<pallas::Base>)],
+) -> Vec<usize> {
+    // Batch verification only pays off for more than one proof
+    if proofs.len() > 1 {
+        let batch: Vec<(&Proof, &[pallas::Base])> =
+            proofs.iter().map(|(_, proof, public_vals)| (proof, &public_vals[..])).collect();
+        if Proof::verify_batch(vk, &batch) {
+            debug!(
+                target: "tx::verify_proofs_group",
+                "[TX] Successfully batch verified {} {} ZK proofs", proofs.len(), zk_ns,
+            );
+            return vec![]
+        }
+        debug!(
+            target: "tx::verify_proofs_group",
+            "[TX] Batch verification of {} ZK proofs failed, verifying them one by one", zk_ns,
+        );
+    }
+
+    let mut invalid = vec![];
+    for (index, proof, public_vals) in proofs {
+        if let Err(e) = proof.verify(vk, public_vals) {
+            error!(
+                target: "tx::verify_proofs_group",
+                "[TX] Failed verifying {} ZK proof: {:#?}", zk_ns, e,
+            );
+            invalid.push(*index);
+            continue
+        }
+        debug!(target: "tx::verify_proofs_group", "[TX] Successfully verified {} ZK proof", zk_ns);
+    }
+
+    invalid
+}
+
 // Avoid showing the proofs and sigs in the debug output since often they are very long.
 impl std::fmt::Debug for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
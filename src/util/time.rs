@@ -16,7 +16,14 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{fmt, time::UNIX_EPOCH};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::UNIX_EPOCH,
+};
 
 #[cfg(feature = "async-serial")]
 use darkfi_serial::async_trait;
@@ -89,6 +96,31 @@ impl std::fmt::Display for Timestamp {
     }
 }
 
+/// Shared estimated offset, in seconds, between the network time and
+/// the local system clock. Clones point to the same underlying value,
+/// so a clock sync task can update it while others read it.
+#[derive(Clone, Debug, Default)]
+pub struct ClockOffset(Arc<AtomicI64>);
+
+impl ClockOffset {
+    /// Returns the current estimated offset, in seconds.
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Set the estimated offset, in seconds.
+    pub fn set(&self, offset: i64) {
+        self.0.store(offset, Ordering::Relaxed)
+    }
+
+    /// Generate a `Timestamp` of the current time, adjusted by the
+    /// estimated offset.
+    pub fn adjusted_time(&self) -> Timestamp {
+        let now = Timestamp::current_time().inner();
+        Timestamp(now.saturating_add_signed(self.get()))
+    }
+}
+
 #[derive(Clone, Copy, Debug, SerialEncodable, SerialDecodable, PartialEq, PartialOrd, Eq)]
 pub struct NanoTimestamp(pub u128);
 
@@ -16,10 +16,13 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use darkfi_sdk::{crypto::MerkleTree, tx::TransactionHash};
-use darkfi_serial::{async_trait, SerialDecodable, SerialEncodable};
+use darkfi_serial::{async_trait, deserialize, SerialDecodable, SerialEncodable};
 use log::{debug, info, warn};
 use num_bigint::BigUint;
 use sled_overlay::database::SledDbOverlayStateDiff;
@@ -30,10 +33,13 @@ use crate::{
         block_store::{BlockDifficulty, BlockRanks},
         BlockInfo, Blockchain, BlockchainOverlay, BlockchainOverlayPtr, HeaderHash,
     },
+    system::{Publisher, PublisherPtr},
     tx::Transaction,
     validator::{
-        pow::PoWModule,
-        utils::{best_fork_index, block_rank, find_extended_fork_index},
+        pow::{DifficultyAlgorithm, PoWModule},
+        utils::{
+            best_fork_index, block_rank, block_version, find_extended_fork_index, order_by_fee_rate,
+        },
         verification::{verify_proposal, verify_transaction},
     },
     zk::VerifyingKey,
@@ -50,6 +56,61 @@ const GAS_LIMIT_MULTIPLIER_UNPROPOSED_TXS: u64 = 50;
 /// Gas limit for unproposed transactions
 pub const GAS_LIMIT_UNPROPOSED_TXS: u64 = GAS_TX_AVG * GAS_LIMIT_MULTIPLIER_UNPROPOSED_TXS;
 
+/// Default maximum number of forks tracked at the same time
+pub const DEFAULT_MAX_FORKS: usize = 64;
+
+/// Default maximum number of blocks a fork tip can be behind the best fork tip
+pub const DEFAULT_MAX_FORK_DEPTH: u32 = 32;
+
+/// Snapshot of the forks currently tracked by [`Consensus`], along with
+/// counters of the forks and proposals it refused to keep in memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForksMetrics {
+    /// Number of tracked forks
+    pub forks: usize,
+    /// Total number of proposals held by the tracked forks
+    pub proposals: usize,
+    /// Number of proposals held by the longest tracked fork
+    pub max_fork_length: usize,
+    /// Number of forks dropped since startup
+    pub dropped_forks: u64,
+    /// Number of proposals rejected since startup
+    pub rejected_proposals: u64,
+    /// Number of proposals discarded since startup, because their
+    /// fork got dropped or another fork got finalized
+    pub orphaned_proposals: u64,
+}
+
+/// Events describing changes of the node's best chain view, which
+/// consists of the canonical blockchain followed by the best fork
+/// proposals. Each event carries the block height and hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BestChainEvent {
+    /// Proposal was appended to the best chain view
+    Appended(u32, HeaderHash),
+    /// Previously appended proposal is no longer part of the best chain view
+    Reverted(u32, HeaderHash),
+    /// Previously appended proposal got finalized into the canonical blockchain
+    Finalized(u32, HeaderHash),
+}
+
+/// Finality status of a transaction, as seen by the node. Confirmations
+/// are the number of blocks, in the canonical blockchain followed by the
+/// best fork proposals, from the transaction block up to the best fork tip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TxFinalityStatus {
+    /// Transaction is not known to the node
+    Unknown,
+    /// Transaction is pending, waiting to be included in a block
+    Pending,
+    /// Transaction is included in the best fork proposal of given height and
+    /// hash, with given confirmations
+    Proposed(u32, HeaderHash, u32),
+    /// Transaction is included in the canonical block of given height and
+    /// hash, with given confirmations
+    Finalized(u32, HeaderHash, u32),
+}
+
 /// This struct represents the information required by the consensus algorithm
 pub struct Consensus {
     /// Canonical (finalized) blockchain
@@ -62,6 +123,24 @@ pub struct Consensus {
     pub module: RwLock<PoWModule>,
     /// Lock to restrict when proposals appends can happen
     pub append_lock: RwLock<()>,
+    /// Publisher of best chain view changes
+    pub events: PublisherPtr<BestChainEvent>,
+    /// Last notified best chain view proposals heights and hashes
+    best_chain: RwLock<Vec<(u32, HeaderHash)>>,
+    /// Maximum number of forks tracked at the same time
+    pub max_forks: usize,
+    /// Maximum number of blocks a fork tip can be behind the best fork tip
+    pub max_fork_depth: u32,
+    /// Trusted blocks heights and hashes the blockchain must follow
+    pub checkpoints: BTreeMap<u32, HeaderHash>,
+    /// Optional height `BLOCK_VERSION_2` blocks activate at
+    pub block_v2_height: Option<u32>,
+    /// Counter of forks dropped since startup
+    dropped_forks: AtomicU64,
+    /// Counter of proposals rejected since startup
+    rejected_proposals: AtomicU64,
+    /// Counter of proposals orphaned since startup
+    orphaned_proposals: AtomicU64,
 }
 
 impl Consensus {
@@ -71,12 +150,56 @@ impl Consensus {
         finalization_threshold: usize,
         pow_target: u32,
         pow_fixed_difficulty: Option<BigUint>,
+        pow_algorithm: DifficultyAlgorithm,
+        max_forks: usize,
+        max_fork_depth: u32,
+        checkpoints: &[(u32, HeaderHash)],
+        block_v2_height: Option<u32>,
     ) -> Result<Self> {
         let forks = RwLock::new(vec![]);
-        let module =
-            RwLock::new(PoWModule::new(blockchain.clone(), pow_target, pow_fixed_difficulty)?);
+        let module = RwLock::new(PoWModule::new(
+            blockchain.clone(),
+            pow_target,
+            pow_fixed_difficulty,
+            pow_algorithm,
+        )?);
         let append_lock = RwLock::new(());
-        Ok(Self { blockchain, finalization_threshold, forks, module, append_lock })
+        let events = Publisher::new();
+        let best_chain = RwLock::new(vec![]);
+        Ok(Self {
+            blockchain,
+            finalization_threshold,
+            forks,
+            module,
+            append_lock,
+            events,
+            best_chain,
+            max_forks,
+            max_fork_depth,
+            checkpoints: checkpoints.iter().cloned().collect(),
+            block_v2_height,
+            dropped_forks: AtomicU64::new(0),
+            rejected_proposals: AtomicU64::new(0),
+            orphaned_proposals: AtomicU64::new(0),
+        })
+    }
+
+    /// Retrieve the highest configured checkpoint, if any.
+    pub fn last_checkpoint(&self) -> Option<(u32, HeaderHash)> {
+        self.checkpoints.last_key_value().map(|(height, hash)| (*height, *hash))
+    }
+
+    /// Verify provided block height and hash don't conflict with our checkpoints.
+    pub fn verify_checkpoint(&self, height: u32, hash: &HeaderHash) -> bool {
+        match self.checkpoints.get(&height) {
+            Some(checkpoint) => checkpoint == hash,
+            None => true,
+        }
+    }
+
+    /// Retrieve the version blocks of provided height must have.
+    pub fn block_version(&self, height: u32) -> u8 {
+        block_version(height, self.block_v2_height)
     }
 
     /// Generate a new empty fork.
@@ -114,6 +237,35 @@ impl Consensus {
                 }
             }
         }
+
+        // Reject proposals conflicting with our checkpoints, or forking off
+        // below the last one after our canonical blockchain has passed it.
+        let height = proposal.block.header.height;
+        let below_checkpoint = match self.last_checkpoint() {
+            Some((checkpoint, _)) => {
+                height <= checkpoint && self.blockchain.last()?.0 >= checkpoint
+            }
+            None => false,
+        };
+        if below_checkpoint || !self.verify_checkpoint(height, &proposal.hash) {
+            drop(lock);
+            self.rejected_proposals.fetch_add(1, Ordering::SeqCst);
+            debug!(target: "validator::consensus::append_proposal", "Proposal {} conflicts with our checkpoints", proposal.hash);
+            return Err(Error::ProposalConflictsCheckpoint)
+        }
+
+        // Reject proposals forking off too deep behind our best fork tip,
+        // before spending any resources verifying them.
+        if !lock.is_empty() {
+            let best_fork_length = lock[best_fork_index(&lock)?].proposals.len() as u32;
+            let best_tip_height = self.blockchain.last()?.0 + best_fork_length;
+            if proposal.block.header.height.saturating_add(self.max_fork_depth) <= best_tip_height {
+                drop(lock);
+                self.rejected_proposals.fetch_add(1, Ordering::SeqCst);
+                debug!(target: "validator::consensus::append_proposal", "Proposal {} forks off too deep", proposal.hash);
+                return Err(Error::ProposalForkTooDeep)
+            }
+        }
         drop(lock);
 
         // Verify proposal and grab corresponding fork
@@ -122,32 +274,179 @@ impl Consensus {
         // Append proposal to the fork
         fork.append_proposal(proposal).await?;
 
-        // TODO: to keep memory usage low, we should only append forks that
-        // are higher ranking than our current best one
-
         // If a fork index was found, replace forks with the mutated one,
         // otherwise push the new fork.
         let mut lock = self.forks.write().await;
-        match index {
-            Some(i) => {
-                if i < lock.len() && lock[i].proposals == fork.proposals[..fork.proposals.len() - 1]
-                {
-                    lock[i] = fork;
-                } else {
-                    lock.push(fork);
-                }
+        let appended = match index {
+            Some(i)
+                if i < lock.len() &&
+                    lock[i].proposals == fork.proposals[..fork.proposals.len() - 1] =>
+            {
+                lock[i] = fork;
+                i
             }
-            None => {
+            _ => {
                 lock.push(fork);
+                lock.len() - 1
             }
-        }
+        };
+
+        // To keep memory usage bounded, drop the forks that can't overtake
+        // the best one. If the proposal fork got dropped, we reject it.
+        let dropped = self.drop_weak_forks(&mut lock)?;
         drop(lock);
+        if dropped.contains(&appended) {
+            self.rejected_proposals.fetch_add(1, Ordering::SeqCst);
+            debug!(target: "validator::consensus::append_proposal", "Proposal {} fork ranks too low", proposal.hash);
+            return Err(Error::ProposalForkRankTooLow)
+        }
 
         info!(target: "validator::consensus::append_proposal", "Appended proposal {}", proposal.hash);
 
+        // Notify subscribers about best chain view changes
+        self.notify_best_chain().await?;
+
         Ok(())
     }
 
+    /// Auxiliary function to drop the forks that can't overtake the best one.
+    /// Forks with their tip more than the configured maximum fork depth behind
+    /// the best fork tip are dropped, along with the lowest ranking forks, if
+    /// we track more than the configured maximum number of forks. Best fork is
+    /// never dropped. Returns the indexes the dropped forks had in the vector.
+    fn drop_weak_forks(&self, forks: &mut Vec<Fork>) -> Result<Vec<usize>> {
+        let best = best_fork_index(forks)?;
+        let best_fork_length = forks[best].proposals.len() as u32;
+
+        // Find the forks too deep behind the best fork tip
+        let mut dropped: Vec<usize> = (0..forks.len())
+            .filter(|i| {
+                *i != best &&
+                    forks[*i].proposals.len() as u32 + self.max_fork_depth <= best_fork_length
+            })
+            .collect();
+
+        // Find the lowest ranking forks exceeding the maximum number of forks
+        let remaining = forks.len() - dropped.len();
+        if remaining > self.max_forks {
+            let mut ranked: Vec<usize> =
+                (0..forks.len()).filter(|i| *i != best && !dropped.contains(i)).collect();
+            ranked.sort_by(|a, b| {
+                (&forks[*a].targets_rank, &forks[*a].hashes_rank)
+                    .cmp(&(&forks[*b].targets_rank, &forks[*b].hashes_rank))
+            });
+            let excess = (remaining - self.max_forks).min(ranked.len());
+            dropped.extend_from_slice(&ranked[..excess]);
+        }
+
+        if dropped.is_empty() {
+            return Ok(dropped)
+        }
+        dropped.sort_unstable();
+        info!(target: "validator::consensus::drop_weak_forks", "Dropping {} forks", dropped.len());
+
+        // Find the new trees only referenced by the dropped forks
+        let mut referenced_trees = HashSet::new();
+        let mut dropped_trees = HashSet::new();
+        for (index, fork) in forks.iter().enumerate() {
+            let fork_overlay = fork.overlay.lock().unwrap();
+            let overlay = fork_overlay.overlay.lock().unwrap();
+            if dropped.contains(&index) {
+                for tree in &overlay.state.new_tree_names {
+                    dropped_trees.insert(tree.clone());
+                }
+                continue
+            }
+            for tree in &overlay.state.initial_tree_names {
+                referenced_trees.insert(tree.clone());
+            }
+            for tree in &overlay.state.new_tree_names {
+                referenced_trees.insert(tree.clone());
+            }
+        }
+
+        // Drop unreferenced trees from the database
+        for tree in dropped_trees.difference(&referenced_trees) {
+            self.blockchain.sled_db.drop_tree(tree)?;
+        }
+
+        // Count the proposals only referenced by the dropped forks
+        let mut kept_proposals = HashSet::new();
+        let mut orphaned_proposals = HashSet::new();
+        for (index, fork) in forks.iter().enumerate() {
+            if dropped.contains(&index) {
+                orphaned_proposals.extend(fork.proposals.iter());
+                continue
+            }
+            kept_proposals.extend(fork.proposals.iter());
+        }
+        let orphaned = orphaned_proposals.difference(&kept_proposals).count();
+
+        // Drop the forks
+        let mut index = 0;
+        forks.retain(|_| {
+            let keep = !dropped.contains(&index);
+            index += 1;
+            keep
+        });
+        self.dropped_forks.fetch_add(dropped.len() as u64, Ordering::SeqCst);
+        self.orphaned_proposals.fetch_add(orphaned as u64, Ordering::SeqCst);
+
+        Ok(dropped)
+    }
+
+    /// Retrieve current forks tracking metrics.
+    pub async fn forks_metrics(&self) -> ForksMetrics {
+        let forks = self.forks.read().await;
+        ForksMetrics {
+            forks: forks.len(),
+            proposals: forks.iter().map(|fork| fork.proposals.len()).sum(),
+            max_fork_length: forks.iter().map(|fork| fork.proposals.len()).max().unwrap_or(0),
+            dropped_forks: self.dropped_forks.load(Ordering::SeqCst),
+            rejected_proposals: self.rejected_proposals.load(Ordering::SeqCst),
+            orphaned_proposals: self.orphaned_proposals.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Retrieve provided transaction finality status, by looking it up in the
+    /// canonical blockchain, the best fork proposals and the pending
+    /// transactions store, in that order.
+    pub async fn tx_finality_status(&self, tx_hash: &TransactionHash) -> Result<TxFinalityStatus> {
+        // Grab best fork, so its proposals don't change while we look up
+        let forks = self.forks.read().await;
+        let fork = &forks[best_fork_index(&forks)?];
+        let (last_height, _) = self.blockchain.last()?;
+        let tip_height = last_height + fork.proposals.len() as u32;
+
+        // Check canonical blockchain
+        if let Some((height, _)) = self.blockchain.transactions.get_location(&[*tx_hash], false)?[0]
+        {
+            let Some(hash) = self.blockchain.blocks.get_order(&[height], false)?[0] else {
+                return Err(Error::BlockHeightNotFound(height))
+            };
+            return Ok(TxFinalityStatus::Finalized(height, hash, tip_height - height + 1))
+        }
+
+        // Check best fork proposals
+        let location =
+            fork.overlay.lock().unwrap().transactions.get_location_raw(tx_hash.inner())?;
+        if let Some(location) = location {
+            let (height, _): (u32, u16) = deserialize(&location)?;
+            let index = height.checked_sub(last_height + 1).map(|i| i as usize);
+            if let Some(hash) = index.and_then(|i| fork.proposals.get(i)) {
+                return Ok(TxFinalityStatus::Proposed(height, *hash, tip_height - height + 1))
+            }
+        }
+        drop(forks);
+
+        // Check pending transactions
+        if self.blockchain.transactions.contains_pending(tx_hash)? {
+            return Ok(TxFinalityStatus::Pending)
+        }
+
+        Ok(TxFinalityStatus::Unknown)
+    }
+
     /// Given a proposal, find the fork chain it extends, and return its full clone.
     /// If the proposal extends the fork not on its tail, a new fork is created and
     /// we re-apply the proposals up to the extending one. If proposal extends canonical,
@@ -206,7 +505,7 @@ impl Consensus {
             let (next_target, next_difficulty) = fork.module.next_mine_target_and_difficulty()?;
 
             // Calculate block rank
-            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target);
+            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target)?;
 
             // Update PoW module
             fork.module.append(block.header.timestamp, &next_difficulty);
@@ -430,6 +729,7 @@ impl Consensus {
         let prefix_last_index = excess - 1;
         let prefix_last = prefix.last().unwrap();
         let mut keep = vec![true; forks.len()];
+        let mut orphaned_proposals = HashSet::new();
         let mut referenced_trees = HashSet::new();
         let mut referenced_txs = HashSet::new();
         let finalized_txs_hashes: Vec<TransactionHash> =
@@ -464,6 +764,9 @@ impl Consensus {
                 &fork.proposals[prefix_last_index] != prefix_last
             {
                 keep[index] = false;
+                // Fork diverged before the prefix end, so all its
+                // proposals outside of it got orphaned.
+                orphaned_proposals.extend(fork.proposals.iter().filter(|p| !prefix.contains(p)));
                 continue
             }
 
@@ -541,6 +844,7 @@ impl Consensus {
         // Drop invalid forks
         let mut iter = keep.iter();
         forks.retain(|_| *iter.next().unwrap());
+        self.orphaned_proposals.fetch_add(orphaned_proposals.len() as u64, Ordering::SeqCst);
 
         // Remove finalized proposals txs from the unporposed txs sled tree
         self.blockchain.remove_pending_txs_hashes(&finalized_txs_hashes)?;
@@ -551,6 +855,9 @@ impl Consensus {
         // Drop forks lock
         drop(forks);
 
+        // Notify subscribers about best chain view changes
+        self.notify_best_chain().await?;
+
         Ok(())
     }
 
@@ -561,6 +868,79 @@ impl Consensus {
         *forks = vec![Fork::new(self.blockchain.clone(), self.module.read().await.clone()).await?];
         drop(forks);
         debug!(target: "validator::consensus::purge_forks", "Forks purged!");
+
+        // Notify subscribers about best chain view changes
+        self.notify_best_chain().await?;
+
+        Ok(())
+    }
+
+    /// Auxiliary function to compare current best fork proposals against
+    /// the last notified best chain view and publish the corresponding
+    /// [`BestChainEvent`]s. Proposals of the previous view that are now
+    /// canonical are reported as finalized, lowest first, while the ones no
+    /// longer present in either the canonical blockchain or the best fork are
+    /// all reported as reverted, highest first, before the newly appended ones.
+    pub async fn notify_best_chain(&self) -> Result<()> {
+        // Grab current best fork proposals
+        let (last_height, _) = self.blockchain.last()?;
+        let forks = self.forks.read().await;
+        let proposals = match best_fork_index(&forks) {
+            Ok(index) => forks[index].proposals.clone(),
+            Err(Error::ForksNotFound) => vec![],
+            Err(e) => return Err(e),
+        };
+        drop(forks);
+        let new_view: Vec<(u32, HeaderHash)> = proposals
+            .into_iter()
+            .enumerate()
+            .map(|(index, hash)| (last_height + 1 + index as u32, hash))
+            .collect();
+
+        // Grab a lock over last notified view
+        let mut best_chain = self.best_chain.write().await;
+        let mut events = vec![];
+
+        // Check previous view proposals that are now part of the canonical blockchain
+        let mut old_view = vec![];
+        let mut reverted = vec![];
+        for (height, hash) in best_chain.drain(..) {
+            if height > last_height {
+                old_view.push((height, hash));
+                continue
+            }
+            match self.blockchain.blocks.get_order(&[height], false)?[0] {
+                Some(canonical) if canonical == hash => {
+                    events.push(BestChainEvent::Finalized(height, hash))
+                }
+                _ => reverted.push((height, hash)),
+            }
+        }
+
+        // Find the common prefix of the two views
+        let common =
+            old_view.iter().zip(new_view.iter()).take_while(|(old, new)| old == new).count();
+
+        // Revert the old view excess along with the non canonical ones, highest first
+        reverted.extend_from_slice(&old_view[common..]);
+        for (height, hash) in reverted.iter().rev() {
+            events.push(BestChainEvent::Reverted(*height, *hash));
+        }
+
+        // Append the new view excess
+        for (height, hash) in &new_view[common..] {
+            events.push(BestChainEvent::Appended(*height, *hash));
+        }
+
+        *best_chain = new_view;
+
+        // Publish the events while holding the view lock, so they are ordered
+        for event in events {
+            debug!(target: "validator::consensus::notify_best_chain", "Best chain event: {:?}", event);
+            self.events.notify(event).await;
+        }
+        drop(best_chain);
+
         Ok(())
     }
 }
@@ -614,7 +994,7 @@ pub struct Fork {
 
 impl Fork {
     pub async fn new(blockchain: Blockchain, module: PoWModule) -> Result<Self> {
-        let mempool = blockchain.get_pending_txs()?.iter().map(|tx| tx.hash()).collect();
+        let mempool = blockchain.get_pending_txs_hashes()?;
         let overlay = BlockchainOverlay::new(&blockchain)?;
         // Retrieve last block difficulty to access current ranks
         let last_difficulty = blockchain.last_block_difficulty()?;
@@ -638,7 +1018,7 @@ impl Fork {
         let (next_target, next_difficulty) = self.module.next_mine_target_and_difficulty()?;
 
         // Calculate block rank
-        let (target_distance_sq, hash_distance_sq) = block_rank(&proposal.block, &next_target);
+        let (target_distance_sq, hash_distance_sq) = block_rank(&proposal.block, &next_target)?;
 
         // Update fork ranks
         self.targets_rank += target_distance_sq.clone();
@@ -688,8 +1068,8 @@ impl Fork {
         Ok(proposal.block.header.height + 1)
     }
 
-    /// Auxiliary function to retrieve unproposed valid transactions,
-    /// along with their total gas used and total paid fees.
+    /// Auxiliary function to retrieve unproposed valid transactions, prioritized
+    /// by their paid fee rate, along with their total gas used and total paid fees.
     pub async fn unproposed_txs(
         &self,
         blockchain: &Blockchain,
@@ -718,17 +1098,22 @@ impl Fork {
         // Grab all current proposals transactions hashes
         let proposals_txs = overlay.lock().unwrap().get_blocks_txs_hashes(&self.proposals)?;
 
+        // Order the forks' mempool by paid fee rate, highest first.
+        // Transactions with the same fee rate retain their insertion order.
+        let fees = blockchain.transactions.get_pending_fee(&self.mempool)?;
+        let mempool = order_by_fee_rate(&self.mempool, &fees);
+
         // Iterate through all pending transactions in the forks' mempool
         let mut unproposed_txs = vec![];
-        for tx in &self.mempool {
+        for tx in mempool {
             // If the hash is contained in the proposals transactions vec, skip it
-            if proposals_txs.contains(tx) {
+            if proposals_txs.contains(&tx) {
                 continue
             }
 
             // Retrieve the actual unproposed transaction
             let unproposed_tx =
-                blockchain.transactions.get_pending(&[*tx], true)?[0].clone().unwrap();
+                blockchain.transactions.get_pending(&[tx], true)?[0].clone().unwrap();
 
             // Update the verifying keys map
             for call in &unproposed_tx.calls {
@@ -0,0 +1,158 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_sdk::tx::TransactionHash;
+use log::{debug, info};
+use num_bigint::BigUint;
+use sled_overlay::sled;
+
+use crate::{
+    blockchain::{
+        block_store::{BlockDifficulty, BlockInfo, BlockRanks},
+        Blockchain, Header, HeaderHash, TxInclusionProof,
+    },
+    validator::pow::{DifficultyAlgorithm, PoWModule},
+    Error, Result,
+};
+
+/// A header-only light client. It keeps track of the canonical headers
+/// sequence, verifying each header links to the previous one and its
+/// PoW is valid using a [`PoWModule`], so transactions inclusion proofs
+/// can be verified without downloading full blocks.
+///
+/// Headers are stored using the usual [`Blockchain`] stores, so the light
+/// client database holds the full genesis block, followed by the headers,
+/// their order and difficulties.
+pub struct LightClient {
+    /// Headers-only blockchain
+    pub blockchain: Blockchain,
+    /// PoW module state at our last header
+    pub module: PoWModule,
+}
+
+impl LightClient {
+    /// Open a new or existing light client on the given sled database,
+    /// for the network of provided genesis block.
+    pub fn new(
+        db: &sled::Db,
+        genesis_block: &BlockInfo,
+        pow_target: u32,
+        pow_fixed_difficulty: Option<BigUint>,
+        pow_algorithm: DifficultyAlgorithm,
+    ) -> Result<Self> {
+        let blockchain = Blockchain::new(db)?;
+
+        // Add genesis block if blockchain is empty
+        if blockchain.is_empty() {
+            info!(target: "validator::light_client::new", "Appending genesis block");
+            blockchain.add_block(genesis_block)?;
+            blockchain
+                .blocks
+                .insert_difficulty(&[BlockDifficulty::genesis(genesis_block.header.timestamp)])?;
+        }
+
+        // Check we are following the same network
+        let genesis_hash = genesis_block.hash();
+        if blockchain.genesis()?.1 != genesis_hash {
+            return Err(Error::BlockIsInvalid(genesis_hash.as_string()))
+        }
+
+        let module =
+            PoWModule::new(blockchain.clone(), pow_target, pow_fixed_difficulty, pow_algorithm)?;
+
+        Ok(Self { blockchain, module })
+    }
+
+    /// Retrieve our last header height and hash.
+    pub fn last(&self) -> Result<(u32, HeaderHash)> {
+        self.blockchain.last()
+    }
+
+    /// Verify and append provided headers sequence, which must extend
+    /// our last header. Each header must link to the previous one and
+    /// have a valid PoW for its expected mining target. If any header is
+    /// invalid, none of them get appended.
+    pub fn append_headers(&mut self, headers: &[Header]) -> Result<()> {
+        let (mut last_height, mut last_hash) = self.last()?;
+        let mut module = self.module.clone();
+        let mut heights = Vec::with_capacity(headers.len());
+        let mut difficulties = Vec::with_capacity(headers.len());
+        for header in headers {
+            let header_hash = header.hash();
+            debug!(target: "validator::light_client::append_headers", "Verifying header {} - {}", header.height, header_hash);
+
+            // Check header extends our last one
+            if header.height != last_height + 1 || header.previous != last_hash {
+                return Err(Error::BlockIsInvalid(header_hash.as_string()))
+            }
+
+            // Verify header timestamp and PoW
+            module.verify_current_header(header)?;
+
+            // Update PoW module and keep track of the header difficulty.
+            // Light client doesn't track forks, so we don't need blocks ranks.
+            let difficulty = module.next_difficulty()?;
+            module.append(header.timestamp, &difficulty);
+            let ranks = BlockRanks::new(0u64.into(), 0u64.into(), 0u64.into(), 0u64.into());
+            difficulties.push(BlockDifficulty::new(
+                header.height,
+                header.timestamp,
+                difficulty,
+                module.cummulative_difficulty.clone(),
+                ranks,
+            ));
+
+            heights.push(header.height);
+            last_height = header.height;
+            last_hash = header_hash;
+        }
+
+        // Store the headers, their difficulties and finally their order,
+        // since that is what defines our last header.
+        let (headers_batch, hashes) = self.blockchain.headers.insert_batch(headers);
+        self.blockchain.headers.main.apply_batch(headers_batch)?;
+        self.blockchain
+            .blocks
+            .difficulty
+            .apply_batch(self.blockchain.blocks.insert_batch_difficulty(&difficulties))?;
+        self.blockchain
+            .blocks
+            .order
+            .apply_batch(self.blockchain.blocks.insert_batch_order(&heights, &hashes))?;
+
+        self.module = module;
+
+        Ok(())
+    }
+
+    /// Verify provided [`TxInclusionProof`] includes given transaction hash
+    /// in one of our canonical headers. Returns an error if the proof height
+    /// has not been synced yet.
+    pub fn verify_tx_proof(
+        &self,
+        tx_hash: &TransactionHash,
+        proof: &TxInclusionProof,
+    ) -> Result<bool> {
+        let Some(hash) = self.blockchain.blocks.get_order(&[proof.height], false)?[0] else {
+            return Err(Error::BlockHeightNotFound(proof.height))
+        };
+        let header = self.blockchain.headers.get(&[hash], true)?[0].clone().unwrap();
+
+        Ok(proof.verify(tx_hash, &header))
+    }
+}
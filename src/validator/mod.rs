@@ -18,7 +18,8 @@
 
 use std::{collections::HashMap, sync::Arc};
 
-use darkfi_sdk::crypto::MerkleTree;
+use darkfi_sdk::{crypto::MerkleTree, pasta::pallas, tx::TransactionHash};
+use darkfi_serial::serialize;
 use log::{debug, error, info, warn};
 use num_bigint::BigUint;
 use sled_overlay::sled;
@@ -27,7 +28,7 @@ use smol::lock::RwLock;
 use crate::{
     blockchain::{
         block_store::{BlockDifficulty, BlockInfo, BlockRanks},
-        Blockchain, BlockchainOverlay, HeaderHash,
+        Blockchain, BlockchainOverlay, HeaderHash, PendingTxFee,
     },
     error::TxVerifyFailed,
     tx::Transaction,
@@ -41,21 +42,41 @@ use consensus::{Consensus, Fork, Proposal};
 
 /// DarkFi PoW module
 pub mod pow;
-use pow::PoWModule;
+use pow::{DifficultyAlgorithm, PoWModule};
 
 /// Verification functions
 pub mod verification;
 use verification::{
-    verify_block, verify_checkpoint_block, verify_genesis_block, verify_producer_transaction,
-    verify_transaction, verify_transactions,
+    apply_block_transactions, verify_block, verify_checkpoint_block, verify_genesis_block,
+    verify_producer_transaction, verify_transaction, verify_transactions,
 };
 
 /// Fee calculation helpers
 pub mod fees;
 
+/// Header-only light client
+pub mod light_client;
+
 /// Helper utilities
 pub mod utils;
-use utils::{best_fork_index, block_rank, deploy_native_contracts};
+use utils::{
+    best_fork_index, block_rank, deploy_native_contracts, select_evictions,
+    verify_min_relay_fee_rate, verify_replacement_fee,
+};
+
+/// Default maximum total size of the pending transactions, in bytes
+pub const DEFAULT_MEMPOOL_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default minimum fee rate, as fee paid per 1000 gas units,
+/// for a transaction to be accepted in the mempool
+pub const DEFAULT_MIN_RELAY_FEE_RATE: u64 = 1000;
+
+/// Default number of blocks after which a pending transaction expires
+pub const DEFAULT_PENDING_TX_EXPIRY: u32 = 1000;
+
+/// Minimum fee increase, in percent, a transaction must pay over the
+/// pending transactions spending the same coins, in order to replace them
+pub const REPLACEMENT_FEE_BUMP_PERCENT: u64 = 10;
 
 /// Configuration for initializing [`Validator`]
 #[derive(Clone)]
@@ -66,10 +87,33 @@ pub struct ValidatorConfig {
     pub pow_target: u32,
     /// Optional fixed difficulty, for testing purposes
     pub pow_fixed_difficulty: Option<BigUint>,
+    /// Currently configured PoW difficulty adjustment algorithm
+    pub pow_algorithm: DifficultyAlgorithm,
     /// Genesis block
     pub genesis_block: BlockInfo,
     /// Flag to enable tx fee verification
     pub verify_fees: bool,
+    /// Optional number of finalized blocks to keep full data for.
+    /// If set, older blocks and their transactions get pruned.
+    pub prune: Option<u32>,
+    /// Maximum total size of the pending transactions, in bytes.
+    /// When full, lowest fee rate transactions get evicted.
+    pub mempool_max_size: u64,
+    /// Minimum fee rate, as fee paid per 1000 gas units, for a
+    /// transaction to be accepted in the mempool
+    pub min_relay_fee_rate: u64,
+    /// Number of blocks after which a pending transaction expires
+    /// and gets removed from the mempool
+    pub pending_tx_expiry: u32,
+    /// Maximum number of forks tracked at the same time
+    pub max_forks: usize,
+    /// Maximum number of blocks a fork tip can be behind the best fork tip
+    pub max_fork_depth: u32,
+    /// Trusted blocks heights and hashes the blockchain must follow
+    pub checkpoints: Vec<(u32, HeaderHash)>,
+    /// Optional height `BLOCK_VERSION_2` blocks activate at.
+    /// If not set, blocks stay at their default version.
+    pub block_v2_height: Option<u32>,
 }
 
 /// Atomic pointer to validator.
@@ -85,6 +129,14 @@ pub struct Validator {
     pub synced: RwLock<bool>,
     /// Flag to enable tx fee verification
     pub verify_fees: bool,
+    /// Optional number of finalized blocks to keep full data for
+    pub prune: Option<u32>,
+    /// Maximum total size of the pending transactions, in bytes
+    pub mempool_max_size: u64,
+    /// Minimum fee rate for a transaction to be accepted in the mempool
+    pub min_relay_fee_rate: u64,
+    /// Number of blocks after which a pending transaction expires
+    pub pending_tx_expiry: u32,
 }
 
 impl Validator {
@@ -107,7 +159,7 @@ impl Validator {
         };
 
         // Write the changes to the actual chain db
-        overlay.lock().unwrap().overlay.lock().unwrap().apply()?;
+        overlay.lock().unwrap().apply()?;
 
         info!(target: "validator::new", "Initializing Consensus");
         let consensus = Consensus::new(
@@ -115,16 +167,35 @@ impl Validator {
             config.finalization_threshold,
             config.pow_target,
             config.pow_fixed_difficulty.clone(),
+            config.pow_algorithm,
+            config.max_forks,
+            config.max_fork_depth,
+            &config.checkpoints,
+            config.block_v2_height,
         )?;
 
+        // Verify our genesis block follows our checkpoints
+        let genesis = blockchain.genesis()?;
+        if !consensus.verify_checkpoint(genesis.0, &genesis.1) {
+            error!(target: "validator::new", "Genesis block {} doesn't match checkpoint", genesis.1);
+            return Err(Error::BlockIsInvalid(genesis.1.as_string()))
+        }
+
         // Create the actual state
         let state = Arc::new(Self {
             blockchain,
             consensus,
             synced: RwLock::new(false),
             verify_fees: config.verify_fees,
+            prune: config.prune,
+            mempool_max_size: config.mempool_max_size,
+            min_relay_fee_rate: config.min_relay_fee_rate,
+            pending_tx_expiry: config.pending_tx_expiry,
         });
 
+        // Remove pending transactions that expired while we were offline
+        state.expire_pending_txs().await?;
+
         info!(target: "validator::new", "Finished initializing validator");
         Ok(state)
     }
@@ -180,16 +251,24 @@ impl Validator {
             return Err(TxVerifyFailed::AlreadySeenTx(tx_hash.as_string()).into())
         }
 
+        // Check if this tx has expired while pending
+        if self.blockchain.transactions.contains_expired(&tx_hash)? {
+            info!(target: "validator::append_tx", "Transaction has expired");
+            return Err(TxVerifyFailed::ExpiredTx(tx_hash.as_string()).into())
+        }
+
         // Verify state transition
         info!(target: "validator::append_tx", "Starting state transition validation");
         let tx_vec = [tx.clone()];
-        let mut valid = false;
+        let mut valid_forks = vec![];
+        let mut gas = (0, 0);
+        let mut nullifiers = vec![];
 
         // Grab a lock over current consensus forks state
         let mut forks = self.consensus.forks.write().await;
 
         // Iterate over node forks to verify transaction validity in their overlays
-        for fork in forks.iter_mut() {
+        for (index, fork) in forks.iter().enumerate() {
             // Clone fork state
             let fork_clone = fork.full_clone()?;
 
@@ -212,15 +291,54 @@ impl Validator {
 
             // Handle response
             match verify_result {
-                Ok(_) => {}
+                Ok((gas_used, gas_paid, tx_nullifiers)) => {
+                    gas = (gas_used, gas_paid);
+                    nullifiers = tx_nullifiers;
+                }
                 Err(Error::TxVerifyFailed(TxVerifyFailed::ErroneousTxs(_))) => continue,
                 Err(e) => return Err(e),
             }
 
-            valid = true;
+            valid_forks.push(index);
+        }
+
+        // Return error if transaction is not valid for any fork
+        if valid_forks.is_empty() {
+            return Err(TxVerifyFailed::ErroneousTxs(tx_vec.to_vec()).into())
+        }
 
-            // Store transaction hash in forks' mempool
-            if write {
+        // Check transaction pays the minimum relay fee rate
+        let fee = PendingTxFee::new(gas.0, gas.1, serialize(tx).len() as u64);
+        if self.verify_fees {
+            if let Err(fee_rate) = verify_min_relay_fee_rate(&fee, self.min_relay_fee_rate) {
+                return Err(TxVerifyFailed::FeeRateTooLow(fee_rate, self.min_relay_fee_rate).into())
+            }
+        }
+
+        // Find the pending transactions spending the same coins,
+        // which this transaction replaces by paying a higher fee.
+        let replaced =
+            if self.verify_fees { self.mempool_replacements(&nullifiers, &fee)? } else { vec![] };
+
+        if !write {
+            return Ok(())
+        }
+
+        if !replaced.is_empty() {
+            info!(target: "validator::append_tx", "Replacing {} conflicting txs in pending txs store", replaced.len());
+        }
+
+        // Find the lower fee rate transactions to evict, if mempool is full
+        let evicted = self.mempool_evictions(&fee, &replaced)?;
+        if !evicted.is_empty() {
+            info!(target: "validator::append_tx", "Evicting {} lower fee rate txs from pending txs store", evicted.len());
+        }
+        let removed = [replaced, evicted].concat();
+
+        // Update forks' mempools
+        for (index, fork) in forks.iter_mut().enumerate() {
+            fork.mempool.retain(|tx| !removed.contains(tx));
+            if valid_forks.contains(&index) {
                 fork.mempool.push(tx_hash);
             }
         }
@@ -228,17 +346,153 @@ impl Validator {
         // Drop forks lock
         drop(forks);
 
-        // Return error if transaction is not valid for any fork
-        if !valid {
-            return Err(TxVerifyFailed::ErroneousTxs(tx_vec.to_vec()).into())
+        // Update pending txs store
+        self.blockchain.remove_pending_txs_hashes(&removed)?;
+        self.blockchain.add_pending_txs_with_metadata(&tx_vec, &[fee], &[nullifiers])?;
+        info!(target: "validator::append_tx", "Appended tx to pending txs store");
+
+        Ok(())
+    }
+
+    /// Auxiliary function to find the pending transactions revealing any of the
+    /// provided nullifiers, which a new transaction with provided fee information
+    /// replaces. The new transaction must pay at least [`REPLACEMENT_FEE_BUMP_PERCENT`]
+    /// more than the combined fee of the transactions it replaces, with a fee rate
+    /// not lower than any of theirs, otherwise an error is returned.
+    fn mempool_replacements(
+        &self,
+        nullifiers: &[pallas::Base],
+        fee: &PendingTxFee,
+    ) -> Result<Vec<TransactionHash>> {
+        let mut replaced = vec![];
+        if nullifiers.is_empty() {
+            return Ok(replaced)
+        }
+
+        for tx_hash in self.blockchain.transactions.get_pending_by_nullifiers(nullifiers)? {
+            let Some(tx_hash) = tx_hash else { continue };
+            if !replaced.contains(&tx_hash) {
+                replaced.push(tx_hash);
+            }
+        }
+
+        if replaced.is_empty() {
+            return Ok(replaced)
+        }
+
+        // Pending transactions without fee information are treated as paying nothing
+        let replaced_fees: Vec<PendingTxFee> = self
+            .blockchain
+            .transactions
+            .get_pending_fee(&replaced)?
+            .into_iter()
+            .flatten()
+            .collect();
+        if let Err(required) =
+            verify_replacement_fee(fee, &replaced_fees, REPLACEMENT_FEE_BUMP_PERCENT)
+        {
+            return Err(TxVerifyFailed::InsufficientReplacementFee(fee.gas_paid, required).into())
+        }
+
+        Ok(replaced)
+    }
+
+    /// Auxiliary function to find the pending transactions that must be evicted,
+    /// so a new transaction with provided fee information fits in the configured
+    /// mempool size, excluding the provided transactions it replaces. Lowest fee
+    /// rate transactions are evicted first, as long as their fee rate is lower than
+    /// the new transaction one. If the new transaction doesn't fit, an error is returned.
+    fn mempool_evictions(
+        &self,
+        fee: &PendingTxFee,
+        replaced: &[TransactionHash],
+    ) -> Result<Vec<TransactionHash>> {
+        // Check the pending txs total size, excluding the replaced ones
+        let replaced_size: u64 = self
+            .blockchain
+            .transactions
+            .get_pending_fee(replaced)?
+            .into_iter()
+            .flatten()
+            .map(|pending_fee| pending_fee.size)
+            .sum();
+        let size = self.blockchain.transactions.get_pending_size()?.saturating_sub(replaced_size);
+        if size + fee.size <= self.mempool_max_size {
+            return Ok(vec![])
+        }
+
+        // Mempool is full, so we grab all the pending txs fee information
+        let mut pending = self.blockchain.transactions.get_all_pending_fee()?;
+        pending.retain(|(tx_hash, _)| !replaced.contains(tx_hash));
+        match select_evictions(pending, size, fee, self.mempool_max_size) {
+            Some(evicted) => Ok(evicted),
+            None => Err(TxVerifyFailed::MempoolFull.into()),
+        }
+    }
+
+    /// The node removes expired transactions from the pending txs store and
+    /// the forks' mempools. A transaction expires once the canonical blockchain
+    /// has grown by the configured number of blocks since it was received.
+    /// Pending transactions without a received height record, from older
+    /// databases, start aging from current height.
+    /// Expired transactions are remembered for another expiry period, so
+    /// they get rejected if peers still holding them relay them again.
+    pub async fn expire_pending_txs(&self) -> Result<()> {
+        let (last_height, _) = self.blockchain.last()?;
+
+        // Forget transactions that expired a whole expiry period ago
+        let forgotten: Vec<TransactionHash> = self
+            .blockchain
+            .transactions
+            .get_all_expired()?
+            .into_iter()
+            .filter(|(_, height)| last_height.saturating_sub(*height) >= self.pending_tx_expiry)
+            .map(|(tx, _)| tx)
+            .collect();
+        if !forgotten.is_empty() {
+            debug!(target: "validator::expire_pending_txs", "Forgetting {} expired txs", forgotten.len());
+            let batch = self.blockchain.transactions.remove_batch_by_hash(&forgotten);
+            self.blockchain.transactions.expired.apply_batch(batch)?;
         }
 
-        // Add transaction to pending txs store
-        if write {
-            self.blockchain.add_pending_txs(&tx_vec)?;
-            info!(target: "validator::append_tx", "Appended tx to pending txs store");
+        // Find pending transactions missing a received height record
+        let heights: HashMap<TransactionHash, u32> =
+            self.blockchain.transactions.get_all_pending_height()?.into_iter().collect();
+        let pending = self.blockchain.get_pending_txs_hashes()?;
+        let missing: Vec<TransactionHash> =
+            pending.iter().filter(|tx| !heights.contains_key(tx)).cloned().collect();
+        if !missing.is_empty() {
+            debug!(target: "validator::expire_pending_txs", "Marking {} pending txs as received at height {}", missing.len(), last_height);
+            let batch =
+                self.blockchain.transactions.insert_batch_pending_height(&missing, last_height);
+            self.blockchain.transactions.pending_height.apply_batch(batch)?;
+        }
+
+        // Find the expired ones
+        let expired: Vec<TransactionHash> = pending
+            .into_iter()
+            .filter(|tx| match heights.get(tx) {
+                Some(height) => last_height.saturating_sub(*height) >= self.pending_tx_expiry,
+                None => false,
+            })
+            .collect();
+        if expired.is_empty() {
+            return Ok(())
         }
 
+        info!(target: "validator::expire_pending_txs", "Removing {} expired transactions...", expired.len());
+
+        // Remove them from forks' mempools
+        let mut forks = self.consensus.forks.write().await;
+        for fork in forks.iter_mut() {
+            fork.mempool.retain(|tx| !expired.contains(tx));
+        }
+        drop(forks);
+
+        self.blockchain.remove_pending_txs_hashes(&expired)?;
+        let batch = self.blockchain.transactions.insert_batch_expired(&expired, last_height);
+        self.blockchain.transactions.expired.apply_batch(batch)?;
+
         Ok(())
     }
 
@@ -393,6 +647,18 @@ impl Validator {
         self.consensus.reset_forks(&finalized_proposals, &finalized_fork, &finalized_txs).await?;
         info!(target: "validator::finalization", "Finalization completed!");
 
+        // Remove pending transactions that expired with the new canonical height
+        self.expire_pending_txs().await?;
+
+        // Prune blocks older than the configured depth
+        if let Some(depth) = self.prune {
+            let last = finalized_blocks.last().unwrap().header.height;
+            let pruned = self.blockchain.prune(last.saturating_sub(depth))?;
+            if pruned > 0 {
+                info!(target: "validator::finalization", "Pruned {} blocks", pruned);
+            }
+        }
+
         // Release append lock
         drop(append_lock);
 
@@ -448,7 +714,7 @@ impl Validator {
             let (next_target, next_difficulty) = module.next_mine_target_and_difficulty()?;
 
             // Calculate block rank
-            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target);
+            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target)?;
 
             // Update current ranks
             current_targets_rank += target_distance_sq.clone();
@@ -479,10 +745,11 @@ impl Validator {
         }
 
         debug!(target: "validator::add_checkpoint_blocks", "Applying overlay changes");
-        overlay.lock().unwrap().overlay.lock().unwrap().apply()?;
+        overlay.lock().unwrap().apply()?;
 
-        // Remove blocks transactions from pending txs store
+        // Remove blocks transactions and expired ones from pending txs store
         self.blockchain.remove_pending_txs(&removed_txs)?;
+        self.expire_pending_txs().await?;
 
         // Update PoW module
         *self.consensus.module.write().await = module.clone();
@@ -491,14 +758,29 @@ impl Validator {
         *self.consensus.forks.write().await =
             vec![Fork::new(self.blockchain.clone(), module).await?];
 
+        // Notify subscribers about best chain view changes
+        self.consensus.notify_best_chain().await?;
+
         Ok(())
     }
 
-    /// Validate a set of [`BlockInfo`] in sequence and apply them if all are valid.
-    /// Note: this function should only be used in tests when we don't want to
-    /// perform consensus logic.
-    pub async fn add_test_blocks(&self, blocks: &[BlockInfo]) -> Result<()> {
-        debug!(target: "validator::add_test_blocks", "Instantiating BlockchainOverlay");
+    /// Compute the contracts state root of provided [`BlockInfo`], by applying
+    /// its transactions over the canonical blockchain state, which must end
+    /// at the block previous to it.
+    pub async fn block_state_root(&self, block: &BlockInfo) -> Result<pallas::Base> {
+        let overlay = BlockchainOverlay::new(&self.blockchain)?;
+        let block_target = self.consensus.module.read().await.target;
+        let overlay = apply_block_transactions(&overlay, block, block_target).await?;
+        let state_root = overlay.lock().unwrap().contracts.get_state_root()?;
+        Ok(state_root)
+    }
+
+    /// Validate a set of [`BlockInfo`] in sequence, using full block verification,
+    /// and apply them directly to canonical if all are valid, skipping consensus
+    /// logic. Used when importing a blocks sequence from a bootstrap file, by the
+    /// regtest miner and in tests when we don't want to perform consensus logic.
+    pub async fn add_blocks(&self, blocks: &[BlockInfo]) -> Result<()> {
+        debug!(target: "validator::add_blocks", "Instantiating BlockchainOverlay");
         let overlay = BlockchainOverlay::new(&self.blockchain)?;
 
         // Retrieve last block
@@ -518,7 +800,16 @@ impl Validator {
         // Validate and insert each block
         for block in blocks {
             // Verify block
-            match verify_block(&overlay, &module, block, previous, self.verify_fees).await {
+            match verify_block(
+                &overlay,
+                &module,
+                block,
+                previous,
+                self.verify_fees,
+                self.consensus.block_v2_height,
+            )
+            .await
+            {
                 Ok(()) => { /* Do nothing */ }
                 // Skip already existing block
                 Err(Error::BlockAlreadyExists(_)) => {
@@ -526,7 +817,7 @@ impl Validator {
                     continue
                 }
                 Err(e) => {
-                    error!(target: "validator::add_test_blocks", "Erroneous block found in set: {}", e);
+                    error!(target: "validator::add_blocks", "Erroneous block found in set: {}", e);
                     overlay.lock().unwrap().overlay.lock().unwrap().purge_new_trees()?;
                     return Err(Error::BlockIsInvalid(block.hash().as_string()))
                 }
@@ -536,7 +827,7 @@ impl Validator {
             let (next_target, next_difficulty) = module.next_mine_target_and_difficulty()?;
 
             // Calculate block rank
-            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target);
+            let (target_distance_sq, hash_distance_sq) = block_rank(block, &next_target)?;
 
             // Update current ranks
             current_targets_rank += target_distance_sq.clone();
@@ -569,11 +860,12 @@ impl Validator {
             previous = block;
         }
 
-        debug!(target: "validator::add_test_blocks", "Applying overlay changes");
-        overlay.lock().unwrap().overlay.lock().unwrap().apply()?;
+        debug!(target: "validator::add_blocks", "Applying overlay changes");
+        overlay.lock().unwrap().apply()?;
 
-        // Purge pending erroneous txs since canonical state has been changed
+        // Purge pending expired and erroneous txs since canonical state has been changed
         self.blockchain.remove_pending_txs(&removed_txs)?;
+        self.expire_pending_txs().await?;
         self.purge_pending_txs().await?;
 
         // Update PoW module
@@ -620,7 +912,8 @@ impl Validator {
             return Err(e)
         }
 
-        let gas_values = verify_result.unwrap();
+        let (gas_used, gas_paid, _) = verify_result.unwrap();
+        let gas_values = (gas_used, gas_paid);
 
         if !write {
             debug!(target: "validator::add_transactions", "Skipping apply of state updates because write=false");
@@ -630,6 +923,7 @@ impl Validator {
 
         debug!(target: "validator::add_transactions", "Applying overlay changes");
         overlay.apply()?;
+        lock.contracts.commit_vks()?;
         Ok(gas_values)
     }
 
@@ -679,6 +973,7 @@ impl Validator {
 
         debug!(target: "validator::add_test_producer_transaction", "Applying overlay changes");
         overlay.apply()?;
+        lock.contracts.commit_vks()?;
         Ok(())
     }
 
@@ -689,6 +984,7 @@ impl Validator {
         &self,
         pow_target: u32,
         pow_fixed_difficulty: Option<BigUint>,
+        pow_algorithm: DifficultyAlgorithm,
     ) -> Result<()> {
         let blocks = self.blockchain.get_all()?;
 
@@ -697,9 +993,11 @@ impl Validator {
             return Ok(())
         }
 
-        // Create an in memory blockchain overlay
+        // Create an in memory blockchain overlay, sharing our verifying keys
+        // cache so contracts circuits keys don't have to be rebuilt
         let sled_db = sled::Config::new().temporary(true).open()?;
-        let blockchain = Blockchain::new(&sled_db)?;
+        let mut blockchain = Blockchain::new(&sled_db)?;
+        blockchain.contracts.vks = self.blockchain.contracts.vks.clone();
         let overlay = BlockchainOverlay::new(&blockchain)?;
 
         // Set previous
@@ -712,15 +1010,26 @@ impl Validator {
         verify_genesis_block(&overlay, previous, pow_target).await?;
 
         // Write the changes to the in memory db
-        overlay.lock().unwrap().overlay.lock().unwrap().apply()?;
+        overlay.lock().unwrap().apply()?;
 
         // Create a PoW module to validate each block
-        let mut module = PoWModule::new(blockchain, pow_target, pow_fixed_difficulty)?;
+        let mut module =
+            PoWModule::new(blockchain, pow_target, pow_fixed_difficulty, pow_algorithm)?;
 
         // Validate and insert each block
         for block in &blocks[1..] {
             // Verify block
-            if verify_block(&overlay, &module, block, previous, self.verify_fees).await.is_err() {
+            if verify_block(
+                &overlay,
+                &module,
+                block,
+                previous,
+                self.verify_fees,
+                self.consensus.block_v2_height,
+            )
+            .await
+            .is_err()
+            {
                 error!(target: "validator::validate_blockchain", "Erroneous block found in set");
                 overlay.lock().unwrap().overlay.lock().unwrap().purge_new_trees()?;
                 return Err(Error::BlockIsInvalid(block.hash().as_string()))
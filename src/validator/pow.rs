@@ -28,16 +28,19 @@ use std::{
 use darkfi_sdk::num_traits::{One, Zero};
 use log::debug;
 use num_bigint::BigUint;
-use randomx::{RandomXCache, RandomXDataset, RandomXFlags, RandomXVM};
+use randomx::{RandomXDataset, RandomXFlags, RandomXVM};
 use smol::channel::Receiver;
 
 use crate::{
     blockchain::{
         block_store::{BlockDifficulty, BlockInfo},
-        Blockchain, BlockchainOverlayPtr,
+        Blockchain, BlockchainOverlayPtr, Header, HeaderHash, PowData, BLOCK_VERSION_2,
     },
-    util::{ringbuffer::RingBuffer, time::Timestamp},
-    validator::utils::median,
+    util::{
+        ringbuffer::RingBuffer,
+        time::{ClockOffset, Timestamp},
+    },
+    validator::utils::{header_pow_hash, median},
     Error, Result,
 };
 
@@ -66,20 +69,191 @@ const RETAINED: usize = 600;
 const CUT_BEGIN: usize = 60;
 /// Already known cutoff end index for this config
 const CUT_END: usize = 660;
+/// Amount of latest blocks solve times to use for LWMA difficulty calculation.
+/// Must be < BUF_SIZE.
+const LWMA_WINDOW: usize = 60;
 /// How many most recent blocks to use to verify new blocks' timestamp
 const BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW: usize = 60;
 /// Time limit in the future of what blocks can be
 const BLOCK_FUTURE_TIME_LIMIT: Timestamp = Timestamp::from_u64(60 * 60 * 2);
 
+/// A difficulty adjustment algorithm, computing the next mining difficulty
+/// from the latest blocks timestamps and cummulative difficulties.
+pub trait DifficultyAdjustment {
+    /// Compute the next mining difficulty for provided target block time,
+    /// in seconds. Provided ring buffers are ordered from oldest to newest
+    /// block and always contain at least 2 items.
+    fn next_difficulty(
+        &self,
+        target: u32,
+        timestamps: &RingBuffer<Timestamp, BUF_SIZE>,
+        difficulties: &RingBuffer<BigUint, BUF_SIZE>,
+    ) -> Result<BigUint>;
+}
+
+/// Monero style difficulty adjustment. Uses the total work done over
+/// the latest blocks window, excluding its outlier timestamps.
+pub struct MoneroDifficulty;
+
+impl MoneroDifficulty {
+    /// Calculate cutoff indexes.
+    /// If buffers have been filled, we return the
+    /// already known indexes, for performance.
+    fn cutoff(&self, length: usize) -> Result<(usize, usize)> {
+        if length >= DIFFICULTY_WINDOW {
+            return Ok((CUT_BEGIN, CUT_END))
+        }
+
+        let (cut_begin, cut_end) = if length <= RETAINED {
+            (0, length)
+        } else {
+            let cut_begin = (length - RETAINED + 1) / 2;
+            (cut_begin, cut_begin + RETAINED)
+        };
+        // Sanity check
+        if
+        /* cut_begin < 0 || */
+        cut_begin + 2 > cut_end || cut_end > length {
+            return Err(Error::PoWCuttofCalculationError)
+        }
+
+        Ok((cut_begin, cut_end))
+    }
+}
+
+impl DifficultyAdjustment for MoneroDifficulty {
+    fn next_difficulty(
+        &self,
+        target: u32,
+        timestamps: &RingBuffer<Timestamp, BUF_SIZE>,
+        difficulties: &RingBuffer<BigUint, BUF_SIZE>,
+    ) -> Result<BigUint> {
+        // Retrieve first DIFFICULTY_WINDOW timestamps from the ring buffer
+        let mut timestamps: Vec<Timestamp> =
+            timestamps.iter().take(DIFFICULTY_WINDOW).cloned().collect();
+        let length = timestamps.len();
+
+        // Sort the timestamps vector
+        timestamps.sort_unstable();
+
+        // Grab cutoff indexes
+        let (cut_begin, cut_end) = self.cutoff(length)?;
+
+        // Calculate total time span
+        let cut_end = cut_end - 1;
+
+        let mut time_span = timestamps[cut_end].checked_sub(timestamps[cut_begin])?;
+        if time_span.inner() == 0 {
+            time_span = 1.into();
+        }
+
+        // Calculate total work done during this time span
+        let total_work = &difficulties[cut_end] - &difficulties[cut_begin];
+        if total_work <= BigUint::zero() {
+            return Err(Error::PoWTotalWorkIsZero)
+        }
+
+        // Compute next difficulty
+        let next_difficulty =
+            (total_work * target + time_span.inner() - BigUint::one()) / time_span.inner();
+
+        Ok(next_difficulty)
+    }
+}
+
+/// Linearly Weighted Moving Average difficulty adjustment. Averages the
+/// latest blocks difficulties and solve times, giving linearly more weight
+/// to the most recent ones, so it reacts faster to hashrate changes, which
+/// suits networks with few miners.
+pub struct LwmaDifficulty;
+
+impl DifficultyAdjustment for LwmaDifficulty {
+    fn next_difficulty(
+        &self,
+        target: u32,
+        timestamps: &RingBuffer<Timestamp, BUF_SIZE>,
+        difficulties: &RingBuffer<BigUint, BUF_SIZE>,
+    ) -> Result<BigUint> {
+        // Grab the latest LWMA_WINDOW blocks, along with their previous one
+        let length = timestamps.len();
+        let start = length.saturating_sub(LWMA_WINDOW + 1);
+        let n = (length - start - 1) as u64;
+        let target = target as u64;
+
+        // Accumulate the weighted solve times and the blocks difficulties.
+        // Solve times are capped to 6 target times, and timestamps are forced
+        // to be increasing, to limit manipulation.
+        let mut previous_timestamp = timestamps[start].inner();
+        let mut weighted_solve_times: u64 = 0;
+        let mut total_work = BigUint::zero();
+        for (weight, index) in (start + 1..length).enumerate() {
+            let timestamp = timestamps[index].inner().max(previous_timestamp + 1);
+            let solve_time = (timestamp - previous_timestamp).min(6 * target);
+            previous_timestamp = timestamp;
+            weighted_solve_times += (weight as u64 + 1) * solve_time;
+            total_work += &difficulties[index] - &difficulties[index - 1];
+        }
+        if total_work <= BigUint::zero() {
+            return Err(Error::PoWTotalWorkIsZero)
+        }
+
+        // Limit how fast difficulty can rise
+        let weights_sum = n * (n + 1) / 2;
+        weighted_solve_times = weighted_solve_times.max(weights_sum * target / 10).max(1);
+
+        // Compute next difficulty
+        let next_difficulty =
+            (total_work * target * weights_sum) / (BigUint::from(n) * weighted_solve_times);
+
+        Ok(next_difficulty.max(BigUint::one()))
+    }
+}
+
+/// Supported difficulty adjustment algorithms, selectable per network
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DifficultyAlgorithm {
+    /// [`MoneroDifficulty`] adjustment
+    #[default]
+    Monero,
+    /// [`LwmaDifficulty`] adjustment
+    Lwma,
+}
+
+impl DifficultyAlgorithm {
+    /// Grab the algorithm's difficulty adjustment implementation.
+    pub fn adjustment(&self) -> &'static dyn DifficultyAdjustment {
+        match self {
+            Self::Monero => &MoneroDifficulty,
+            Self::Lwma => &LwmaDifficulty,
+        }
+    }
+}
+
+impl std::str::FromStr for DifficultyAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "monero" => Ok(Self::Monero),
+            "lwma" => Ok(Self::Lwma),
+            _ => Err(Error::ParseFailed("Unknown difficulty algorithm")),
+        }
+    }
+}
+
 /// This struct represents the information required by the PoW algorithm
 #[derive(Clone)]
 pub struct PoWModule {
     /// Genesis block timestamp
     pub genesis: Timestamp,
+    /// Merge mining chain id, which is the genesis block hash
+    pub chain_id: HeaderHash,
     /// Target block time, in seconds
     pub target: u32,
     /// Optional fixed difficulty
     pub fixed_difficulty: Option<BigUint>,
+    /// Difficulty adjustment algorithm
+    pub algorithm: DifficultyAlgorithm,
     /// Latest block timestamps ringbuffer
     pub timestamps: RingBuffer<Timestamp, BUF_SIZE>,
     /// Latest block cummulative difficulties ringbuffer
@@ -89,6 +263,9 @@ pub struct PoWModule {
     /// access(optimization), since its always same as
     /// difficulties buffer last.
     pub cummulative_difficulty: BigUint,
+    /// Estimated offset of the local clock from network time,
+    /// shared with the node clock sync task
+    pub clock_offset: ClockOffset,
 }
 
 impl PoWModule {
@@ -96,9 +273,12 @@ impl PoWModule {
         blockchain: Blockchain,
         target: u32,
         fixed_difficulty: Option<BigUint>,
+        algorithm: DifficultyAlgorithm,
     ) -> Result<Self> {
-        // Retrieve genesis block timestamp
-        let genesis = blockchain.genesis_block()?.header.timestamp;
+        // Retrieve genesis block timestamp and hash
+        let genesis_block = blockchain.genesis_block()?;
+        let genesis = genesis_block.header.timestamp;
+        let chain_id = genesis_block.hash();
 
         // Retrieving last BUF_SIZE difficulties from blockchain to build the buffers
         let mut timestamps = RingBuffer::<Timestamp, BUF_SIZE>::new();
@@ -118,26 +298,25 @@ impl PoWModule {
 
         Ok(Self {
             genesis,
+            chain_id,
             target,
             fixed_difficulty,
+            algorithm,
             timestamps,
             difficulties,
             cummulative_difficulty,
+            clock_offset: ClockOffset::default(),
         })
     }
 
-    /// Compute the next mining difficulty, based on current ring buffers.
+    /// Compute the next mining difficulty, based on current ring buffers,
+    /// using the configured difficulty adjustment algorithm.
     /// If ring buffers contain 2 or less items, difficulty 1 is returned.
     /// If a fixed difficulty has been set, this function will always
     /// return that after first 2 difficulties.
     pub fn next_difficulty(&self) -> Result<BigUint> {
-        // Retrieve first DIFFICULTY_WINDOW timestamps from the ring buffer
-        let mut timestamps: Vec<Timestamp> =
-            self.timestamps.iter().take(DIFFICULTY_WINDOW).cloned().collect();
-
         // Check we have enough timestamps
-        let length = timestamps.len();
-        if length < 2 {
+        if self.timestamps.len() < 2 {
             return Ok(BigUint::one())
         }
 
@@ -146,55 +325,11 @@ impl PoWModule {
             return Ok(diff.clone())
         }
 
-        // Sort the timestamps vector
-        timestamps.sort_unstable();
-
-        // Grab cutoff indexes
-        let (cut_begin, cut_end) = self.cutoff(length)?;
-
-        // Calculate total time span
-        let cut_end = cut_end - 1;
-
-        let mut time_span = timestamps[cut_end].checked_sub(timestamps[cut_begin])?;
-        if time_span.inner() == 0 {
-            time_span = 1.into();
-        }
-
-        // Calculate total work done during this time span
-        let total_work = &self.difficulties[cut_end] - &self.difficulties[cut_begin];
-        if total_work <= BigUint::zero() {
-            return Err(Error::PoWTotalWorkIsZero)
-        }
-
-        // Compute next difficulty
-        let next_difficulty =
-            (total_work * self.target + time_span.inner() - BigUint::one()) / time_span.inner();
-
-        Ok(next_difficulty)
-    }
-
-    /// Calculate cutoff indexes.
-    /// If buffers have been filled, we return the
-    /// already known indexes, for performance.
-    fn cutoff(&self, length: usize) -> Result<(usize, usize)> {
-        if length >= DIFFICULTY_WINDOW {
-            return Ok((CUT_BEGIN, CUT_END))
-        }
-
-        let (cut_begin, cut_end) = if length <= RETAINED {
-            (0, length)
-        } else {
-            let cut_begin = (length - RETAINED + 1) / 2;
-            (cut_begin, cut_begin + RETAINED)
-        };
-        // Sanity check
-        if
-        /* cut_begin < 0 || */
-        cut_begin + 2 > cut_end || cut_end > length {
-            return Err(Error::PoWCuttofCalculationError)
-        }
-
-        Ok((cut_begin, cut_end))
+        self.algorithm.adjustment().next_difficulty(
+            self.target,
+            &self.timestamps,
+            &self.difficulties,
+        )
     }
 
     /// Compute the next mine target
@@ -215,9 +350,10 @@ impl PoWModule {
     }
 
     /// Verify provided block timestamp is not far in the future and
-    /// check its valid acorrding to current timestamps median
+    /// check its valid acorrding to current timestamps median.
+    /// Current time is adjusted by the estimated clock offset.
     pub fn verify_current_timestamp(&self, timestamp: Timestamp) -> Result<bool> {
-        if timestamp > Timestamp::current_time().checked_add(BLOCK_FUTURE_TIME_LIMIT)? {
+        if timestamp > self.clock_offset.adjusted_time().checked_add(BLOCK_FUTURE_TIME_LIMIT)? {
             return Ok(false)
         }
 
@@ -250,39 +386,53 @@ impl PoWModule {
 
     /// Verify provided block timestamp and hash
     pub fn verify_current_block(&self, block: &BlockInfo) -> Result<()> {
-        // First we verify the block's timestamp
-        if !self.verify_current_timestamp(block.header.timestamp)? {
+        self.verify_current_header(&block.header)
+    }
+
+    /// Verify provided header timestamp and hash
+    pub fn verify_current_header(&self, header: &Header) -> Result<()> {
+        // First we verify the header's timestamp
+        if !self.verify_current_timestamp(header.timestamp)? {
             return Err(Error::PoWInvalidTimestamp)
         }
 
-        // Then we verify the block's hash
-        self.verify_block_hash(block)
+        // Then we verify the header's hash
+        self.verify_header_hash(header)
     }
 
     /// Verify provided block corresponds to next mine target
     pub fn verify_block_hash(&self, block: &BlockInfo) -> Result<()> {
-        // Then we verify the proof of work:
-        let verifier_setup = Instant::now();
+        self.verify_header_hash(&block.header)
+    }
 
+    /// Verify provided header corresponds to next mine target.
+    /// Merge mined headers are verified using their Monero block PoW,
+    /// which must commit to their template hash at our chain slot.
+    /// Only [`BLOCK_VERSION_2`] headers can be merge mined.
+    pub fn verify_header_hash(&self, header: &Header) -> Result<()> {
         // Grab the next mine target
         let target = self.next_mine_target()?;
 
-        // Setup verifier
-        let flags = RandomXFlags::default();
-        let cache = RandomXCache::new(flags, block.header.previous.inner()).unwrap();
-        let vm = RandomXVM::new(flags, &cache).unwrap();
-        debug!(target: "validator::pow::verify_block", "[VERIFIER] Setup time: {:?}", verifier_setup.elapsed());
+        // Merge mined headers template excludes the nonce, so it must be
+        // zero, otherwise a single Monero PoW would be valid for any nonce.
+        if let PowData::Monero(pow_data) = &header.pow_data {
+            if header.version < BLOCK_VERSION_2 ||
+                header.nonce != 0 ||
+                !pow_data.verify_commitment(&header.template_hash(), &self.chain_id)?
+            {
+                return Err(Error::PoWInvalidMergeMiningData)
+            }
+        }
 
-        // Compute the output hash
+        // Then we verify the proof of work:
         let verification_time = Instant::now();
-        let out_hash = vm.hash(block.hash().inner());
-        let out_hash = BigUint::from_bytes_be(&out_hash);
+        let out_hash = header_pow_hash(header)?;
+        debug!(target: "validator::pow::verify_block", "[VERIFIER] Verification time: {:?}", verification_time.elapsed());
 
         // Verify hash is less than the expected mine target
         if out_hash > target {
             return Err(Error::PoWInvalidOutHash)
         }
-        debug!(target: "validator::pow::verify_block", "[VERIFIER] Verification time: {:?}", verification_time.elapsed());
 
         Ok(())
     }
@@ -323,6 +473,7 @@ impl std::fmt::Display for PoWModule {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "PoWModule:")?;
         write!(f, "\ttarget: {}", self.target)?;
+        write!(f, "\talgorithm: {:?}", self.algorithm)?;
         write!(f, "\ttimestamps: {:?}", self.timestamps)?;
         write!(f, "\tdifficulties: {:?}", self.difficulties)?;
         write!(f, "\tcummulative_difficulty: {}", self.cummulative_difficulty)
@@ -378,8 +529,8 @@ pub fn mine_block(
                     break
                 }
 
-                let out_hash = vm.hash(block.hash().inner());
-                let out_hash = BigUint::from_bytes_be(&out_hash);
+                let out_hash = vm.hash(&block.header.pow_input());
+                let out_hash = block.header.pow_output(&out_hash);
                 if out_hash <= target {
                     found_block.store(true, Ordering::SeqCst);
                     found_nonce.store(miner_nonce, Ordering::SeqCst);
@@ -421,16 +572,29 @@ mod tests {
         process::Command,
     };
 
-    use darkfi_sdk::num_traits::Num;
+    use darkfi_sdk::num_traits::{Num, One};
+    use monero::{
+        blockdata::transaction::{ExtraField, RawExtraField, SubField},
+        consensus::serialize,
+        Hash, VarInt,
+    };
     use num_bigint::BigUint;
     use sled_overlay::sled;
 
     use crate::{
-        blockchain::{BlockInfo, Blockchain},
-        Result,
+        blockchain::{
+            merge_mining::{aux_slot, encode_tag_data},
+            BlockInfo, Blockchain, BlockchainOverlay, Header, MoneroPowData, PowData,
+            BLOCK_VERSION_2, POW_BLOB_NONCE_OFFSET,
+        },
+        validator::{
+            utils::{block_rank, header_pow_hash},
+            verification::verify_monero_seed,
+        },
+        Error, Result,
     };
 
-    use super::PoWModule;
+    use super::{DifficultyAlgorithm, PoWModule};
 
     const DEFAULT_TEST_THREADS: usize = 2;
     const DEFAULT_TEST_DIFFICULTY_TARGET: u32 = 120;
@@ -441,7 +605,12 @@ mod tests {
         let blockchain = Blockchain::new(&sled_db)?;
         let genesis_block = BlockInfo::default();
         blockchain.add_block(&genesis_block)?;
-        let mut module = PoWModule::new(blockchain, DEFAULT_TEST_DIFFICULTY_TARGET, None)?;
+        let mut module = PoWModule::new(
+            blockchain,
+            DEFAULT_TEST_DIFFICULTY_TARGET,
+            None,
+            DifficultyAlgorithm::Monero,
+        )?;
 
         let output = Command::new("./script/research/pow/gen_wide_data.py").output().unwrap();
         let reader = Cursor::new(output.stdout);
@@ -469,6 +638,48 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lwma_difficulty() -> Result<()> {
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        let mut genesis_block = BlockInfo::default();
+        genesis_block.header.timestamp = 0.into();
+        blockchain.add_block(&genesis_block)?;
+        let mut module = PoWModule::new(
+            blockchain,
+            DEFAULT_TEST_DIFFICULTY_TARGET,
+            None,
+            DifficultyAlgorithm::Lwma,
+        )?;
+
+        // Blocks found on target keep difficulty stable
+        let target = DEFAULT_TEST_DIFFICULTY_TARGET as u64;
+        let difficulty = BigUint::from(1000_u64);
+        let mut timestamp = 0;
+        for _ in 0..100 {
+            module.append(timestamp.into(), &difficulty);
+            timestamp += target;
+        }
+        assert_eq!(module.next_difficulty()?, difficulty);
+
+        // Blocks found faster than target increase difficulty
+        for _ in 0..10 {
+            module.append(timestamp.into(), &difficulty);
+            timestamp += target / 2;
+        }
+        let increased = module.next_difficulty()?;
+        assert!(increased > difficulty);
+
+        // Blocks found slower than target decrease difficulty
+        for _ in 0..20 {
+            module.append(timestamp.into(), &increased);
+            timestamp += target * 4;
+        }
+        assert!(module.next_difficulty()? < increased);
+
+        Ok(())
+    }
+
     #[test]
     fn test_miner_correctness() -> Result<()> {
         // Default setup
@@ -477,7 +688,12 @@ mod tests {
         let mut genesis_block = BlockInfo::default();
         genesis_block.header.timestamp = 0.into();
         blockchain.add_block(&genesis_block)?;
-        let module = PoWModule::new(blockchain, DEFAULT_TEST_DIFFICULTY_TARGET, None)?;
+        let module = PoWModule::new(
+            blockchain,
+            DEFAULT_TEST_DIFFICULTY_TARGET,
+            None,
+            DifficultyAlgorithm::Monero,
+        )?;
         let (_, recvr) = smol::channel::bounded(1);
 
         // Mine next block
@@ -488,6 +704,162 @@ mod tests {
         // Verify it
         module.verify_current_block(&next_block)?;
 
+        // Mine next block as a version 2 one, hashing its PoW hashing blob
+        let mut next_block = BlockInfo::default();
+        next_block.header.version = BLOCK_VERSION_2;
+        next_block.header.previous = genesis_block.hash();
+        module.mine_block(&mut next_block, DEFAULT_TEST_THREADS, &recvr)?;
+        module.verify_current_block(&next_block)?;
+
+        // Changing the nonce only changes its bytes in the hashing blob
+        let blob = next_block.header.pow_blob();
+        next_block.header.nonce += 1;
+        let next_blob = next_block.header.pow_blob();
+        let nonce_bytes = POW_BLOB_NONCE_OFFSET..POW_BLOB_NONCE_OFFSET + 8;
+        assert_eq!(blob[..POW_BLOB_NONCE_OFFSET], next_blob[..POW_BLOB_NONCE_OFFSET]);
+        assert_eq!(next_blob[nonce_bytes.clone()], next_block.header.nonce.to_le_bytes());
+        assert_eq!(blob[nonce_bytes.end..], next_blob[nonce_bytes.end..]);
+
+        Ok(())
+    }
+
+    /// Build a consensus encoded Monero block, with no transactions other
+    /// than its coinbase, which contains provided merge mining tag.
+    fn monero_block(tag: SubField) -> Vec<u8> {
+        let extra = RawExtraField::from(ExtraField(vec![tag])).0;
+        let mut blob = vec![];
+
+        // Header: major and minor versions, timestamp, previous block and nonce
+        blob.extend(serialize(&VarInt(16)));
+        blob.extend(serialize(&VarInt(16)));
+        blob.extend(serialize(&VarInt(1700000000)));
+        blob.extend([1u8; 32]);
+        blob.extend(1234u32.to_le_bytes());
+
+        // Coinbase: version, unlock time, a single generation input,
+        // no outputs, the extra field and the null RingCT signature.
+        blob.extend(serialize(&VarInt(2)));
+        blob.extend(serialize(&VarInt(3000060)));
+        blob.extend(serialize(&VarInt(1)));
+        blob.push(0xff);
+        blob.extend(serialize(&VarInt(3000000)));
+        blob.extend(serialize(&VarInt(0)));
+        blob.extend(serialize(&VarInt(extra.len() as u64)));
+        blob.extend(extra);
+        blob.push(0);
+
+        // No other transactions
+        blob.extend(serialize(&VarInt(0)));
+
+        blob
+    }
+
+    #[test]
+    fn test_merge_mined_header() -> Result<()> {
+        // Setup using fixed difficulty 1, so any PoW is valid
+        let sled_db = sled::Config::new().temporary(true).open()?;
+        let blockchain = Blockchain::new(&sled_db)?;
+        let mut genesis_block = BlockInfo::default();
+        genesis_block.header.timestamp = 0.into();
+        blockchain.add_block(&genesis_block)?;
+        let overlay = BlockchainOverlay::new(&blockchain)?;
+        let module = PoWModule::new(
+            blockchain,
+            DEFAULT_TEST_DIFFICULTY_TARGET,
+            Some(BigUint::one()),
+            DifficultyAlgorithm::Monero,
+        )?;
+        assert_eq!(module.chain_id, genesis_block.hash());
+        let randomx_key = [2u8; 32];
+
+        // Merge mine the next header as the only chain
+        let mut header = Header::new(genesis_block.hash(), 1, 1.into(), 0);
+        header.version = BLOCK_VERSION_2;
+        let template_hash = header.template_hash();
+        let tag =
+            SubField::MergeMining(VarInt(encode_tag_data(1, 0)), Hash(*template_hash.inner()));
+        let pow_data = MoneroPowData::new(&monero_block(tag), randomx_key, vec![])?;
+        assert_eq!(pow_data.height()?, 3000000);
+        assert_eq!(pow_data.seed_height()?, 2998272);
+        header.pow_data = PowData::Monero(pow_data.clone());
+        module.verify_header_hash(&header)?;
+
+        // Headers prior to version 2 can't be merge mined
+        let mut invalid = header.clone();
+        invalid.version = 1;
+        assert!(matches!(
+            module.verify_header_hash(&invalid),
+            Err(Error::PoWInvalidMergeMiningData)
+        ));
+
+        // Once its block is added, its RandomX key is pinned to its Monero
+        // seed height, so headers using another key for it are rejected.
+        verify_monero_seed(&overlay, &header)?;
+        overlay
+            .lock()
+            .unwrap()
+            .add_block(&BlockInfo { header: header.clone(), ..Default::default() })?;
+        verify_monero_seed(&overlay, &header)?;
+        let mut invalid = header.clone();
+        invalid.pow_data = PowData::Monero(MoneroPowData { randomx_key: [4u8; 32], ..pow_data });
+        assert!(matches!(
+            verify_monero_seed(&overlay, &invalid),
+            Err(Error::PoWInvalidMergeMiningData)
+        ));
+
+        // Its rank uses the Monero PoW output
+        let block = BlockInfo { header: header.clone(), ..Default::default() };
+        let out_hash = header_pow_hash(&header)?;
+        let hash_distance = BigUint::from_bytes_be(&[0xFF; 32]) - out_hash;
+        let (_, hash_distance_sq) = block_rank(&block, &module.next_mine_target()?)?;
+        assert_eq!(hash_distance_sq, &hash_distance * &hash_distance);
+
+        // Merge mined headers must not use a nonce
+        let mut invalid = header.clone();
+        invalid.nonce = 1;
+        assert!(matches!(
+            module.verify_header_hash(&invalid),
+            Err(Error::PoWInvalidMergeMiningData)
+        ));
+
+        // A different header is not committed
+        let mut invalid = header.clone();
+        invalid.timestamp = 2.into();
+        assert!(matches!(
+            module.verify_header_hash(&invalid),
+            Err(Error::PoWInvalidMergeMiningData)
+        ));
+
+        // Merge mine it along with another chain, so the tag commits
+        // to the Merkle root of both chains headers.
+        let nonce = 7;
+        let other = Hash([3u8; 32]);
+        let leaf = Hash(*template_hash.inner());
+        let slot = aux_slot(&module.chain_id, nonce, 2);
+        let root = if slot == 0 {
+            Hash::new([leaf.0, other.0].concat())
+        } else {
+            Hash::new([other.0, leaf.0].concat())
+        };
+        let tag = SubField::MergeMining(VarInt(encode_tag_data(2, nonce)), root);
+        let pow_data = MoneroPowData::new(&monero_block(tag), randomx_key, vec![other.0])?;
+        header.pow_data = PowData::Monero(pow_data);
+        module.verify_header_hash(&header)?;
+
+        // Our header committed at the other slot is rejected
+        let root = if slot == 0 {
+            Hash::new([other.0, leaf.0].concat())
+        } else {
+            Hash::new([leaf.0, other.0].concat())
+        };
+        let tag = SubField::MergeMining(VarInt(encode_tag_data(2, nonce)), root);
+        let pow_data = MoneroPowData::new(&monero_block(tag), randomx_key, vec![other.0])?;
+        header.pow_data = PowData::Monero(pow_data);
+        assert!(matches!(
+            module.verify_header_hash(&header),
+            Err(Error::PoWInvalidMergeMiningData)
+        ));
+
         Ok(())
     }
 }
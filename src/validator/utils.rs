@@ -25,7 +25,7 @@ use num_bigint::BigUint;
 use randomx::{RandomXCache, RandomXFlags, RandomXVM};
 
 use crate::{
-    blockchain::{BlockInfo, BlockchainOverlayPtr},
+    blockchain::{BlockInfo, BlockchainOverlayPtr, Header, PendingTxFee, PowData, BLOCK_VERSION_2},
     runtime::vm_runtime::Runtime,
     validator::consensus::{Fork, Proposal},
     Error, Result,
@@ -108,15 +108,51 @@ pub async fn deploy_native_contracts(
     Ok(())
 }
 
+/// Auxiliary function to calculate provided block height block version,
+/// given the optional height [`BLOCK_VERSION_2`] blocks activate at.
+/// Genesis block always has the default version.
+pub fn block_version(height: u32, block_v2_height: Option<u32>) -> u8 {
+    match block_v2_height {
+        Some(activation) if height > 0 && height >= activation => BLOCK_VERSION_2,
+        _ => darkfi_sdk::blockchain::block_version(height),
+    }
+}
+
+/// Compute a header's PoW output hash number, based on its PoW data.
+/// Native headers PoW input is hashed using RandomX keyed by their previous
+/// block hash, and the output is interpreted based on their version. Merge mined
+/// headers use their Monero block PoW, which is the RandomX hash of the
+/// Monero hashing blob, keyed by the Monero seed hash and interpreted as a
+/// little-endian number.
+/// Note: this function doesn't verify merge mined headers commitments.
+pub fn header_pow_hash(header: &Header) -> Result<BigUint> {
+    let flags = RandomXFlags::default();
+    let out_hash = match &header.pow_data {
+        PowData::DarkFi => {
+            let cache = RandomXCache::new(flags, header.previous.inner()).unwrap();
+            let vm = RandomXVM::new(flags, &cache).unwrap();
+            header.pow_output(&vm.hash(&header.pow_input()))
+        }
+        PowData::Monero(pow_data) => {
+            let blob = pow_data.hashing_blob()?;
+            let cache = RandomXCache::new(flags, &pow_data.randomx_key).unwrap();
+            let vm = RandomXVM::new(flags, &cache).unwrap();
+            BigUint::from_bytes_le(&vm.hash(&blob))
+        }
+    };
+
+    Ok(out_hash)
+}
+
 /// Compute a block's rank, assuming that its valid, based on provided mining target.
 ///
 /// Block's rank is the tuple of its squared mining target distance from max 32 bytes int,
-/// along with its squared RandomX hash number distance from max 32 bytes int.
+/// along with its squared PoW output hash number distance from max 32 bytes int.
 /// Genesis block has rank (0, 0).
-pub fn block_rank(block: &BlockInfo, target: &BigUint) -> (BigUint, BigUint) {
+pub fn block_rank(block: &BlockInfo, target: &BigUint) -> Result<(BigUint, BigUint)> {
     // Genesis block has rank 0
     if block.header.height == 0 {
-        return (0u64.into(), 0u64.into())
+        return Ok((0u64.into(), 0u64.into()))
     }
 
     // Grab the max 32 bytes int
@@ -126,18 +162,97 @@ pub fn block_rank(block: &BlockInfo, target: &BigUint) -> (BigUint, BigUint) {
     let target_distance = &max - target;
     let target_distance_sq = &target_distance * &target_distance;
 
-    // Setup RandomX verifier
-    let flags = RandomXFlags::default();
-    let cache = RandomXCache::new(flags, block.header.previous.inner()).unwrap();
-    let vm = RandomXVM::new(flags, &cache).unwrap();
-
     // Compute the output hash distance
-    let out_hash = vm.hash(block.hash().inner());
-    let out_hash = BigUint::from_bytes_be(&out_hash);
+    let out_hash = header_pow_hash(&block.header)?;
     let hash_distance = max - out_hash;
     let hash_distance_sq = &hash_distance * &hash_distance;
 
-    (target_distance_sq, hash_distance_sq)
+    Ok((target_distance_sq, hash_distance_sq))
+}
+
+/// Auxiliary function to verify that a transaction with provided fee information
+/// can replace the pending transactions with provided fees. Its paid fee must be
+/// at least `bump_percent` higher than their combined paid fee, and its fee rate
+/// must not be lower than any of theirs. Returns the required paid fee on failure.
+pub fn verify_replacement_fee(
+    fee: &PendingTxFee,
+    replaced: &[PendingTxFee],
+    bump_percent: u64,
+) -> std::result::Result<(), u64> {
+    let mut replaced_paid: u64 = 0;
+    let mut replaced_fee_rate = 0;
+    for pending_fee in replaced {
+        replaced_paid = replaced_paid.saturating_add(pending_fee.gas_paid);
+        replaced_fee_rate = replaced_fee_rate.max(pending_fee.fee_rate());
+    }
+
+    let bump = (replaced_paid.saturating_mul(bump_percent) / 100).max(1);
+    let required = replaced_paid.saturating_add(bump);
+    if fee.gas_paid < required || fee.fee_rate() < replaced_fee_rate {
+        return Err(required)
+    }
+
+    Ok(())
+}
+
+/// Auxiliary function to verify that a transaction with provided fee information
+/// pays at least the provided minimum relay fee rate. Returns its fee rate on failure.
+pub fn verify_min_relay_fee_rate(
+    fee: &PendingTxFee,
+    min_fee_rate: u64,
+) -> std::result::Result<(), u64> {
+    let fee_rate = fee.fee_rate();
+    if fee_rate < min_fee_rate {
+        return Err(fee_rate)
+    }
+
+    Ok(())
+}
+
+/// Auxiliary function to select which of the provided pending transactions must be
+/// evicted, so a new transaction with provided fee information fits in `max_size`,
+/// given their current total `size`. Lowest fee rate transactions are selected first,
+/// as long as their fee rate is lower than the new transaction one. Returns `None`
+/// if the new transaction doesn't fit.
+pub fn select_evictions(
+    mut pending: Vec<(TransactionHash, PendingTxFee)>,
+    mut size: u64,
+    fee: &PendingTxFee,
+    max_size: u64,
+) -> Option<Vec<TransactionHash>> {
+    let fee_rate = fee.fee_rate();
+    pending.sort_by_key(|(_, pending_fee)| pending_fee.fee_rate());
+
+    let mut evicted = vec![];
+    for (tx_hash, pending_fee) in pending {
+        if size + fee.size <= max_size || pending_fee.fee_rate() >= fee_rate {
+            break
+        }
+        size = size.saturating_sub(pending_fee.size);
+        evicted.push(tx_hash);
+    }
+
+    if size + fee.size > max_size {
+        return None
+    }
+
+    Some(evicted)
+}
+
+/// Auxiliary function to order provided transactions by their fee rate, highest
+/// first, using their fee information. Transactions without fee information are
+/// treated as paying nothing, while the ones with the same fee rate retain their order.
+pub fn order_by_fee_rate(
+    txs: &[TransactionHash],
+    fees: &[Option<PendingTxFee>],
+) -> Vec<TransactionHash> {
+    let mut ordered: Vec<(TransactionHash, u64)> = txs
+        .iter()
+        .zip(fees.iter())
+        .map(|(tx, fee)| (*tx, fee.as_ref().map(|fee| fee.fee_rate()).unwrap_or(0)))
+        .collect();
+    ordered.sort_by(|a, b| b.1.cmp(&a.1));
+    ordered.into_iter().map(|(tx, _)| tx).collect()
 }
 
 /// Auxiliary function to calculate the middle value between provided u64 numbers
@@ -245,3 +360,94 @@ pub fn best_fork_index(forks: &[Fork]) -> Result<usize> {
 
     Ok(best_index)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_replacement_fee() {
+        let replaced = [PendingTxFee::new(1000, 100, 500), PendingTxFee::new(2000, 100, 500)];
+
+        // Combined paid fee is 200, so at least 220 must be paid, with a
+        // fee rate not lower than the highest replaced one (100).
+        let fee = PendingTxFee::new(2200, 220, 500);
+        assert_eq!(verify_replacement_fee(&fee, &replaced, 10), Ok(()));
+
+        // Paying more than the bump is also accepted
+        let fee = PendingTxFee::new(1000, 500, 500);
+        assert_eq!(verify_replacement_fee(&fee, &replaced, 10), Ok(()));
+
+        // Paying less than the 10% bump is rejected
+        let fee = PendingTxFee::new(2000, 219, 500);
+        assert_eq!(verify_replacement_fee(&fee, &replaced, 10), Err(220));
+
+        // Paying enough, but at a lower fee rate, is rejected
+        let fee = PendingTxFee::new(4000, 300, 500);
+        assert_eq!(verify_replacement_fee(&fee, &replaced, 10), Err(220));
+
+        // Replacing a zero fee transaction requires a minimum bump of one
+        let free = [PendingTxFee::new(1000, 0, 500)];
+        assert_eq!(verify_replacement_fee(&PendingTxFee::new(1000, 0, 500), &free, 10), Err(1));
+        assert_eq!(verify_replacement_fee(&PendingTxFee::new(1000, 1, 500), &free, 10), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_min_relay_fee_rate() {
+        // Fee rate is the fee paid per 1000 gas units used
+        assert_eq!(verify_min_relay_fee_rate(&PendingTxFee::new(2000, 200, 500), 100), Ok(()));
+        assert_eq!(verify_min_relay_fee_rate(&PendingTxFee::new(2000, 199, 500), 100), Err(99));
+
+        // Zero gas transactions are rated by their paid fee
+        assert_eq!(verify_min_relay_fee_rate(&PendingTxFee::new(0, 1, 500), 1000), Ok(()));
+        assert_eq!(verify_min_relay_fee_rate(&PendingTxFee::new(0, 0, 500), 1), Err(0));
+
+        // Everything passes a zero minimum
+        assert_eq!(verify_min_relay_fee_rate(&PendingTxFee::new(1000, 0, 500), 0), Ok(()));
+    }
+
+    #[test]
+    fn test_select_evictions() {
+        let low = TransactionHash([1u8; 32]);
+        let mid = TransactionHash([2u8; 32]);
+        let high = TransactionHash([3u8; 32]);
+        let pending = vec![
+            (high, PendingTxFee::new(1000, 300, 400)),
+            (low, PendingTxFee::new(1000, 100, 400)),
+            (mid, PendingTxFee::new(1000, 200, 400)),
+        ];
+
+        // Nothing gets evicted while the new transaction fits
+        let fee = PendingTxFee::new(1000, 50, 400);
+        assert_eq!(select_evictions(pending.clone(), 1200, &fee, 1600), Some(vec![]));
+
+        // Lowest fee rate transactions get evicted first, until the new one fits
+        let fee = PendingTxFee::new(1000, 250, 400);
+        assert_eq!(select_evictions(pending.clone(), 1200, &fee, 1200), Some(vec![low]));
+        let fee = PendingTxFee::new(1000, 250, 800);
+        assert_eq!(select_evictions(pending.clone(), 1200, &fee, 1200), Some(vec![low, mid]));
+
+        // Transactions paying the same or higher fee rate are never evicted
+        let fee = PendingTxFee::new(1000, 200, 800);
+        assert_eq!(select_evictions(pending.clone(), 1200, &fee, 1200), None);
+
+        // A transaction larger than the mempool never fits
+        let fee = PendingTxFee::new(1000, 1000, 1300);
+        assert_eq!(select_evictions(pending, 1200, &fee, 1200), None);
+    }
+
+    #[test]
+    fn test_order_by_fee_rate() {
+        let txs: Vec<TransactionHash> = (0..4).map(|i| TransactionHash([i; 32])).collect();
+        let fees = vec![
+            Some(PendingTxFee::new(1000, 100, 500)),
+            None,
+            Some(PendingTxFee::new(500, 100, 500)),
+            Some(PendingTxFee::new(2000, 200, 500)),
+        ];
+
+        // Highest fee rate first, with ties retaining their order
+        let expected = vec![txs[2], txs[0], txs[3], txs[1]];
+        assert_eq!(order_by_fee_rate(&txs, &fees), expected);
+    }
+}
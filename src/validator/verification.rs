@@ -19,7 +19,6 @@
 use std::collections::HashMap;
 
 use darkfi_sdk::{
-    blockchain::block_version,
     crypto::{
         schnorr::SchnorrPublic, ContractId, MerkleTree, PublicKey, DEPLOYOOOR_CONTRACT_ID,
         MONEY_CONTRACT_ID,
@@ -35,16 +34,17 @@ use smol::io::Cursor;
 
 use crate::{
     blockchain::{
-        block_store::append_tx_to_merkle_tree, BlockInfo, Blockchain, BlockchainOverlayPtr,
-        HeaderHash,
+        block_store::append_tx_to_merkle_tree, BlockInfo, Blockchain, BlockchainOverlayPtr, Header,
+        HeaderHash, PowData, BLOCK_VERSION_2,
     },
     error::TxVerifyFailed,
     runtime::vm_runtime::Runtime,
-    tx::{Transaction, MAX_TX_CALLS, MIN_TX_CALLS},
+    tx::{Transaction, ZkProofBatch, MAX_TX_CALLS, MIN_TX_CALLS},
     validator::{
         consensus::{Consensus, Fork, Proposal, GAS_LIMIT_UNPROPOSED_TXS},
         fees::{circuit_gas_use, PALLAS_SCHNORR_SIGNATURE_FEE},
-        pow::PoWModule,
+        pow::{DifficultyAlgorithm, PoWModule},
+        utils::block_version,
     },
     zk::VerifyingKey,
     Error, Result,
@@ -70,7 +70,7 @@ pub async fn verify_genesis_block(
     }
 
     // Block version must be correct
-    if block.header.version != block_version(block.header.height) {
+    if block.header.version != block_version(block.header.height, None) {
         return Err(Error::BlockIsInvalid(block_hash))
     }
 
@@ -118,15 +118,21 @@ pub async fn verify_genesis_block(
 /// Validate provided block according to set rules.
 ///
 /// A block is considered valid when the following rules apply:
-///     1. Block version is correct for its height
+///     1. Block version is correct for its height, given the optional
+///        height `BLOCK_VERSION_2` blocks activate at
 ///     2. Parent hash is equal to the hash of the previous block
 ///     3. Block height increments previous block height by 1
 ///     4. Timestamp is valid based on PoWModule validation
 ///     5. Block hash is valid based on PoWModule validation
 /// Additional validity rules can be applied.
-pub fn validate_block(block: &BlockInfo, previous: &BlockInfo, module: &PoWModule) -> Result<()> {
+pub fn validate_block(
+    block: &BlockInfo,
+    previous: &BlockInfo,
+    module: &PoWModule,
+    block_v2_height: Option<u32>,
+) -> Result<()> {
     // Check block version (1)
-    if block.header.version != block_version(block.header.height) {
+    if block.header.version != block_version(block.header.height, block_v2_height) {
         return Err(Error::BlockIsInvalid(block.hash().as_string()))
     }
 
@@ -158,15 +164,18 @@ pub fn validate_blockchain(
     blockchain: &Blockchain,
     pow_target: u32,
     pow_fixed_difficulty: Option<BigUint>,
+    pow_algorithm: DifficultyAlgorithm,
+    block_v2_height: Option<u32>,
 ) -> Result<()> {
     // Generate a PoW module
-    let mut module = PoWModule::new(blockchain.clone(), pow_target, pow_fixed_difficulty)?;
+    let mut module =
+        PoWModule::new(blockchain.clone(), pow_target, pow_fixed_difficulty, pow_algorithm)?;
     // We use block order store here so we have all blocks in order
     let blocks = blockchain.blocks.get_all_order()?;
     for (index, block) in blocks[1..].iter().enumerate() {
         let full_blocks = blockchain.get_blocks_by_hash(&[blocks[index].1, block.1])?;
         let full_block = &full_blocks[1];
-        validate_block(full_block, &full_blocks[0], &module)?;
+        validate_block(full_block, &full_blocks[0], &module, block_v2_height)?;
         // Update PoW module
         module.append(full_block.header.timestamp, &module.next_difficulty()?);
     }
@@ -181,6 +190,7 @@ pub async fn verify_block(
     block: &BlockInfo,
     previous: &BlockInfo,
     verify_fees: bool,
+    block_v2_height: Option<u32>,
 ) -> Result<()> {
     let block_hash = block.hash();
     debug!(target: "validator::verification::verify_block", "Validating block {}", block_hash);
@@ -191,7 +201,10 @@ pub async fn verify_block(
     }
 
     // Validate block, using its previous
-    validate_block(block, previous, module)?;
+    validate_block(block, previous, module, block_v2_height)?;
+
+    // Verify merge mined block RandomX key
+    verify_monero_seed(overlay, &block.header)?;
 
     // Verify transactions vector contains at least one(producers) transaction
     if block.txs.is_empty() {
@@ -235,6 +248,9 @@ pub async fn verify_block(
         return Err(Error::BlockIsInvalid(block_hash.as_string()))
     }
 
+    // Verify contracts state root matches header one
+    verify_state_root(overlay, block)?;
+
     // Verify producer signature
     verify_producer_signature(block, &public_key)?;
 
@@ -300,6 +316,9 @@ pub async fn verify_checkpoint_block(
         return Err(Error::BlockIsInvalid(block_hash.as_string()))
     }
 
+    // Verify contracts state root matches header one
+    verify_state_root(overlay, block)?;
+
     // Verify producer signature
     verify_producer_signature(block, &public_key)?;
 
@@ -310,6 +329,73 @@ pub async fn verify_checkpoint_block(
     Ok(())
 }
 
+/// Verify the contracts state root of provided overlay, after applying the
+/// block transactions to it, matches the one committed in the block header.
+/// Headers prior to [`BLOCK_VERSION_2`] don't commit to it, so they are skipped.
+pub fn verify_state_root(overlay: &BlockchainOverlayPtr, block: &BlockInfo) -> Result<()> {
+    if block.header.version < BLOCK_VERSION_2 {
+        return Ok(())
+    }
+
+    let state_root = overlay.lock().unwrap().contracts.get_state_root()?;
+    if state_root != block.header.state_root {
+        error!(target: "validator::verification::verify_state_root", "Block state root is invalid: {:?}", state_root);
+        return Err(Error::BlockIsInvalid(block.hash().as_string()))
+    }
+
+    Ok(())
+}
+
+/// Verify provided merge mined header RandomX key is the one previous merge
+/// mined headers used for its Monero seed height. Nodes don't follow the
+/// Monero chain, so the first key used for each seed height gets pinned when
+/// its block is added, and later merge mined headers must use the same one.
+pub fn verify_monero_seed(overlay: &BlockchainOverlayPtr, header: &Header) -> Result<()> {
+    let PowData::Monero(pow_data) = &header.pow_data else { return Ok(()) };
+
+    let seed_height = pow_data.seed_height()?;
+    match overlay.lock().unwrap().headers.get_monero_seed(seed_height)? {
+        Some(randomx_key) if randomx_key != pow_data.randomx_key => {
+            error!(target: "validator::verification::verify_monero_seed", "Header RandomX key doesn't match Monero seed height {} one", seed_height);
+            Err(Error::PoWInvalidMergeMiningData)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Apply provided block transactions over a clone of provided overlay, without formal
+/// verification, and return the cloned overlay. Block producers use it to compute the
+/// contracts state root to commit in the block header, before mining it.
+pub async fn apply_block_transactions(
+    overlay: &BlockchainOverlayPtr,
+    block: &BlockInfo,
+    block_target: u32,
+) -> Result<BlockchainOverlayPtr> {
+    let overlay = overlay.lock().unwrap().full_clone()?;
+
+    // Verify transactions vector contains at least one(producers) transaction
+    if block.txs.is_empty() {
+        return Err(Error::BlockContainsNoTransactions(block.hash().as_string()))
+    }
+
+    // Apply transactions, exluding producer(last) one
+    let mut tree = MerkleTree::new(1);
+    let txs = &block.txs[..block.txs.len() - 1];
+    apply_transactions(&overlay, block.header.height, block_target, txs, &mut tree).await?;
+
+    // Apply producer transaction
+    apply_producer_transaction(
+        &overlay,
+        block.header.height,
+        block_target,
+        block.txs.last().unwrap(),
+        &mut tree,
+    )
+    .await?;
+
+    Ok(overlay)
+}
+
 /// Verify block proposer signature, using the producer transaction signature as signing key
 /// over blocks header hash.
 pub fn verify_producer_signature(block: &BlockInfo, public_key: &PublicKey) -> Result<()> {
@@ -548,6 +634,53 @@ pub async fn verify_transaction(
     verifying_keys: &mut HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
     verify_fee: bool,
 ) -> Result<(u64, u64)> {
+    let tx_hash = tx.hash();
+    let (gas_used, gas_paid, zkp_table, _) = verify_transaction_execution(
+        overlay,
+        verifying_block_height,
+        block_target,
+        tx,
+        verifying_keys,
+        verify_fee,
+    )
+    .await?;
+
+    debug!(target: "validator::verification::verify_transaction", "Verifying ZK proofs for transaction {}", tx_hash);
+    if let Err(e) = tx.verify_zkps(verifying_keys, zkp_table).await {
+        error!(
+            target: "validator::verification::verify_transaction",
+            "[VALIDATOR] ZK proof verification for tx {} failed: {}", tx_hash, e,
+        );
+        return Err(TxVerifyFailed::InvalidZkProof.into())
+    }
+    debug!(target: "validator::verification::verify_transaction", "ZK proof verification successful");
+
+    // Append hash to merkle tree
+    append_tx_to_merkle_tree(tree, tx);
+
+    debug!(target: "validator::verification::verify_transaction", "The total gas used for transaction {}: {}", tx_hash, gas_used);
+    debug!(target: "validator::verification::verify_transaction", "Transaction {} verified successfully", tx_hash);
+    Ok((gas_used, gas_paid))
+}
+
+/// Public inputs of a [`Transaction`] ZK proofs, for each of its calls,
+/// in the form of tuples (`zkas_ns`, `public_inputs`).
+type ZkpTable = Vec<Vec<(String, Vec<pallas::Base>)>>;
+
+/// Verify WASM execution and signatures for a given [`Transaction`], and apply
+/// it to the provided overlay. Its ZK proofs are not verified; instead the
+/// public inputs table required to verify them is returned, along with the
+/// total gas used and paid fee, so the caller can verify them later. The
+/// nullifiers its calls inserted into the contracts sparse Merkle trees are
+/// returned as well.
+async fn verify_transaction_execution(
+    overlay: &BlockchainOverlayPtr,
+    verifying_block_height: u32,
+    block_target: u32,
+    tx: &Transaction,
+    verifying_keys: &mut HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
+    verify_fee: bool,
+) -> Result<(u64, u64, ZkpTable, Vec<pallas::Base>)> {
     let tx_hash = tx.hash();
     debug!(target: "validator::verification::verify_transaction", "Validating transaction {}", tx_hash);
 
@@ -570,6 +703,8 @@ pub async fn verify_transaction(
     let mut zkp_table = vec![];
     // Table of public keys used for signature verification
     let mut sig_table = vec![];
+    // Nullifiers revealed by the transaction calls
+    let mut nullifiers = vec![];
 
     // Index of the Fee-paying call
     let mut fee_call_idx = 0;
@@ -678,6 +813,7 @@ pub async fn verify_transaction(
         debug!(target: "validator::verification::verify_transaction", "Executing \"apply\" call");
         runtime.apply(&state_update)?;
         debug!(target: "validator::verification::verify_transaction", "Successfully executed \"apply\" call");
+        nullifiers.extend(runtime.nullifiers());
 
         // If this call is supposed to deploy a new contract, we have to instantiate
         // a new `Runtime` and run its deploy function.
@@ -786,22 +922,7 @@ pub async fn verify_transaction(
     }
     debug!(target: "validator::verification::verify_transaction", "Signature verification successful");
 
-    debug!(target: "validator::verification::verify_transaction", "Verifying ZK proofs for transaction {}", tx_hash);
-    if let Err(e) = tx.verify_zkps(verifying_keys, zkp_table).await {
-        error!(
-            target: "validator::verification::verify_transaction",
-            "[VALIDATOR] ZK proof verification for tx {} failed: {}", tx_hash, e,
-        );
-        return Err(TxVerifyFailed::InvalidZkProof.into())
-    }
-    debug!(target: "validator::verification::verify_transaction", "ZK proof verification successful");
-
-    // Append hash to merkle tree
-    append_tx_to_merkle_tree(tree, tx);
-
-    debug!(target: "validator::verification::verify_transaction", "The total gas used for transaction {}: {}", tx_hash, gas_used);
-    debug!(target: "validator::verification::verify_transaction", "Transaction {} verified successfully", tx_hash);
-    Ok((gas_used, gas_paid))
+    Ok((gas_used, gas_paid, zkp_table, nullifiers))
 }
 
 /// Apply given [`Transaction`] to the provided overlay.
@@ -882,8 +1003,15 @@ async fn apply_transaction(
 ///
 /// In case any of the transactions fail, they will be returned to the caller as an error.
 /// If all transactions are valid, the function will return the total gas used and total
-/// paid fees from all the transactions. Additionally, their hash is appended to the provided
-/// Merkle tree.
+/// paid fees from all the transactions, along with the nullifiers they revealed.
+/// Additionally, their hash is appended to the provided Merkle tree.
+///
+/// The ZK proofs of all the transactions are verified together after their execution,
+/// using a [`ZkProofBatch`]. If any transaction contains an invalid proof, the overlay
+/// and the Merkle tree are restored to their initial state and the rest of the valid
+/// transactions, up to the one that exceeded the gas limit if any, are executed again,
+/// so the invalid ones leave no trace behind, same as the transactions failing their
+/// execution.
 pub async fn verify_transactions(
     overlay: &BlockchainOverlayPtr,
     verifying_block_height: u32,
@@ -891,19 +1019,12 @@ pub async fn verify_transactions(
     txs: &[Transaction],
     tree: &mut MerkleTree,
     verify_fees: bool,
-) -> Result<(u64, u64)> {
+) -> Result<(u64, u64, Vec<pallas::Base>)> {
     debug!(target: "validator::verification::verify_transactions", "Verifying {} transactions", txs.len());
     if txs.is_empty() {
-        return Ok((0, 0))
+        return Ok((0, 0, vec![]))
     }
 
-    // Tracker for failed txs
-    let mut erroneous_txs = vec![];
-
-    // Total gas accumulators
-    let mut total_gas_used = 0;
-    let mut total_gas_paid = 0;
-
     // Map of ZK proof verifying keys for the current transaction batch
     let mut vks: HashMap<[u8; 32], HashMap<String, VerifyingKey>> = HashMap::new();
 
@@ -914,28 +1035,107 @@ pub async fn verify_transactions(
         }
     }
 
-    // Iterate over transactions and attempt to verify them
-    for tx in txs {
-        overlay.lock().unwrap().checkpoint();
-        let (tx_gas_used, tx_gas_paid) = match verify_transaction(
+    // Keep the initial overlay state, in case we have to execute the transactions again
+    let initial_state = overlay.lock().unwrap().overlay.lock().unwrap().state.clone();
+    let initial_tree = tree.clone();
+
+    // Tracker for failed txs indexes
+    let mut erroneous = vec![];
+
+    // Index of the transaction exceeding the gas limit, where execution stops
+    let mut limit = txs.len();
+    loop {
+        let (total_gas_used, total_gas_paid, zk_batch, nullifiers) = execute_transactions(
             overlay,
             verifying_block_height,
             block_target,
-            tx,
+            txs,
             tree,
             &mut vks,
             verify_fees,
+            &mut erroneous,
+            &mut limit,
         )
-        .await
-        {
-            Ok(gas_values) => gas_values,
-            Err(e) => {
-                warn!(target: "validator::verification::verify_transactions", "Transaction verification failed: {}", e);
-                erroneous_txs.push(tx.clone());
-                overlay.lock().unwrap().revert_to_checkpoint()?;
-                continue
+        .await?;
+
+        // Verify all the executed transactions ZK proofs together
+        debug!(target: "validator::verification::verify_transactions", "Verifying transactions ZK proofs");
+        let invalid = zk_batch.verify(&vks).await;
+        if invalid.is_empty() {
+            if !erroneous.is_empty() {
+                erroneous.sort_unstable();
+                let erroneous_txs = erroneous.iter().map(|index| txs[*index].clone()).collect();
+                return Err(TxVerifyFailed::ErroneousTxs(erroneous_txs).into())
             }
-        };
+
+            return Ok((total_gas_used, total_gas_paid, nullifiers))
+        }
+
+        // Restore the initial state and execute the rest of the transactions again
+        for index in invalid {
+            warn!(target: "validator::verification::verify_transactions", "Transaction {} ZK proof verification failed", txs[index].hash());
+            erroneous.push(index);
+        }
+        overlay.lock().unwrap().overlay.lock().unwrap().state = initial_state.clone();
+        *tree = initial_tree.clone();
+    }
+}
+
+/// Auxiliary function to execute provided set of [`Transaction`] in sequence, up to
+/// the one at index `limit` and skipping the ones whose index is in `erroneous`, and
+/// apply them to the provided overlay. Transactions failing their execution are
+/// reverted and their index is appended to `erroneous`. If the gas limit gets exceeded,
+/// execution stops and `limit` is set to the index of the exceeding transaction. Returns the total gas used and paid fees of the applied transactions,
+/// along with the batch of their ZK proofs, for the caller to verify, and the
+/// nullifiers they revealed.
+#[allow(clippy::too_many_arguments)]
+async fn execute_transactions(
+    overlay: &BlockchainOverlayPtr,
+    verifying_block_height: u32,
+    block_target: u32,
+    txs: &[Transaction],
+    tree: &mut MerkleTree,
+    vks: &mut HashMap<[u8; 32], HashMap<String, VerifyingKey>>,
+    verify_fees: bool,
+    erroneous: &mut Vec<usize>,
+    limit: &mut usize,
+) -> Result<(u64, u64, ZkProofBatch, Vec<pallas::Base>)> {
+    // Total gas accumulators
+    let mut total_gas_used = 0;
+    let mut total_gas_paid = 0;
+
+    // Batch of all the transactions ZK proofs
+    let mut zk_batch = ZkProofBatch::default();
+
+    // Nullifiers revealed by the applied transactions
+    let mut nullifiers = vec![];
+
+    // Iterate over transactions and attempt to verify them
+    for (index, tx) in txs.iter().enumerate().take(*limit) {
+        if erroneous.contains(&index) {
+            continue
+        }
+
+        overlay.lock().unwrap().checkpoint();
+        let (tx_gas_used, tx_gas_paid, zkp_table, tx_nullifiers) =
+            match verify_transaction_execution(
+                overlay,
+                verifying_block_height,
+                block_target,
+                tx,
+                vks,
+                verify_fees,
+            )
+            .await
+            {
+                Ok(values) => values,
+                Err(e) => {
+                    warn!(target: "validator::verification::verify_transactions", "Transaction verification failed: {}", e);
+                    erroneous.push(index);
+                    overlay.lock().unwrap().revert_to_checkpoint()?;
+                    continue
+                }
+            };
 
         // Calculate current accumulated gas usage
         let accumulated_gas_usage = total_gas_used + tx_gas_used;
@@ -943,21 +1143,30 @@ pub async fn verify_transactions(
         // Check gas limit - if accumulated gas used exceeds it, break out of loop
         if accumulated_gas_usage > GAS_LIMIT_UNPROPOSED_TXS {
             warn!(target: "validator::verification::verify_transactions", "Transaction {} exceeds configured transaction gas limit: {} - {}", tx.hash(), accumulated_gas_usage, GAS_LIMIT_UNPROPOSED_TXS);
-            erroneous_txs.push(tx.clone());
+            erroneous.push(index);
             overlay.lock().unwrap().revert_to_checkpoint()?;
+            *limit = index;
             break
         }
 
+        // Add its ZK proofs to the batch
+        if let Err(e) = zk_batch.add(index, tx, vks, zkp_table) {
+            warn!(target: "validator::verification::verify_transactions", "Transaction verification failed: {}", e);
+            erroneous.push(index);
+            overlay.lock().unwrap().revert_to_checkpoint()?;
+            continue
+        }
+
+        // Append hash to merkle tree
+        append_tx_to_merkle_tree(tree, tx);
+
         // Update accumulated total gas
         total_gas_used += tx_gas_used;
         total_gas_paid += tx_gas_paid;
+        nullifiers.extend(tx_nullifiers);
     }
 
-    if !erroneous_txs.is_empty() {
-        return Err(TxVerifyFailed::ErroneousTxs(erroneous_txs).into())
-    }
-
-    Ok((total_gas_used, total_gas_paid))
+    Ok((total_gas_used, total_gas_paid, zk_batch, nullifiers))
 }
 
 /// Apply given set of [`Transaction`] in sequence, without formal verification.
@@ -1026,9 +1235,16 @@ pub async fn verify_proposal(
     let previous = fork.overlay.lock().unwrap().last_block()?;
 
     // Verify proposal block (3)
-    if verify_block(&fork.overlay, &fork.module, &proposal.block, &previous, verify_fees)
-        .await
-        .is_err()
+    if verify_block(
+        &fork.overlay,
+        &fork.module,
+        &proposal.block,
+        &previous,
+        verify_fees,
+        consensus.block_v2_height,
+    )
+    .await
+    .is_err()
     {
         error!(target: "validator::verification::verify_pow_proposal", "Erroneous proposal block found");
         fork.overlay.lock().unwrap().overlay.lock().unwrap().purge_new_trees()?;
@@ -25,7 +25,7 @@ use darkfi_serial::{SerialDecodable, SerialEncodable};
 use halo2_proofs::{
     helpers::SerdeFormat,
     plonk,
-    plonk::{Circuit, SingleVerifier},
+    plonk::{BatchVerifier, Circuit, SingleVerifier},
     poly::commitment::Params,
     transcript::{Blake2bRead, Blake2bWrite},
 };
@@ -211,6 +211,19 @@ impl Proof {
         plonk::verify_proof(&vk.params, &vk.vk, strategy, &[&[instances]], &mut transcript)
     }
 
+    /// Verify a batch of proofs created for the same circuit, using halo2's
+    /// batch verifier, which verifies the proofs in parallel and combines
+    /// their checks. Returns `false` if any of the proofs is invalid, without
+    /// identifying which one, so callers should fall back to [`Proof::verify`].
+    pub fn verify_batch(vk: &VerifyingKey, batch: &[(&Proof, &[pallas::Base])]) -> bool {
+        let mut verifier = BatchVerifier::new();
+        for (proof, instances) in batch {
+            verifier.add_proof(vec![vec![instances.to_vec()]], proof.0.clone());
+        }
+
+        verifier.finalize(&vk.params, &vk.vk)
+    }
+
     pub fn new(bytes: Vec<u8>) -> Self {
         Proof(bytes)
     }
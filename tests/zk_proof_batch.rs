@@ -0,0 +1,136 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2024 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+
+use darkfi::{
+    tx::{Transaction, ZkProofBatch},
+    zk::{
+        empty_witnesses,
+        proof::{ProvingKey, VerifyingKey},
+        vm::ZkCircuit,
+        vm_heap::Witness,
+        Proof,
+    },
+    zkas::{Analyzer, Compiler, Lexer, Parser, ZkBinary},
+    Result,
+};
+use darkfi_sdk::{
+    crypto::{pasta_prelude::*, ContractId, SecretKey},
+    dark_tree::DarkLeaf,
+    pasta::pallas,
+    tx::ContractCall,
+};
+use halo2_proofs::circuit::Value;
+use rand::rngs::OsRng;
+
+/// Compile the arithmetic zkas circuit source code
+fn arithmetic_zkbin() -> Result<ZkBinary> {
+    let filename = "arithmetic.zk";
+    let source = include_str!("../proof/arithmetic.zk").replace('\t', "    ");
+    let tokens = Lexer::new(filename, source.chars()).lex()?;
+    let (namespace, k, constants, witnesses, statements) =
+        Parser::new(filename, source.chars(), tokens).parse()?;
+    let mut analyzer = Analyzer::new(filename, source.chars(), constants, witnesses, statements);
+    analyzer.analyze_types()?;
+    let bincode = Compiler::new(
+        filename,
+        source.chars(),
+        namespace,
+        k,
+        analyzer.constants,
+        analyzer.witnesses,
+        analyzer.statements,
+        analyzer.literals,
+        false,
+    )
+    .compile()?;
+
+    ZkBinary::decode(&bincode)
+}
+
+#[test]
+fn zk_proof_batch_invalid_proof() -> Result<()> {
+    let zkbin = arithmetic_zkbin()?;
+    let circuit = ZkCircuit::new(empty_witnesses(&zkbin)?, &zkbin);
+    let pk = ProvingKey::build(zkbin.k, &circuit);
+    let vk = VerifyingKey::build(zkbin.k, &circuit);
+
+    let contract_id = ContractId::derive(SecretKey::random(&mut OsRng));
+    let vks =
+        HashMap::from([(contract_id.to_bytes(), HashMap::from([(zkbin.namespace.clone(), vk)]))]);
+
+    // Create a batch of single call transactions, where the third one
+    // contains a proof not matching its public inputs.
+    let bad_index = 2;
+    let mut batch = ZkProofBatch::default();
+    for index in 0..5 {
+        let a = pallas::Base::random(&mut OsRng);
+        let b = pallas::Base::random(&mut OsRng);
+        let prover_witnesses = vec![Witness::Base(Value::known(a)), Witness::Base(Value::known(b))];
+        let mut public_inputs = vec![a + b, a * b, a - b];
+        let circuit = ZkCircuit::new(prover_witnesses, &zkbin);
+        let proof = Proof::create(&pk, &[circuit], &public_inputs, &mut OsRng).unwrap();
+
+        if index == bad_index {
+            public_inputs[1] += pallas::Base::ONE;
+        }
+
+        let tx = Transaction {
+            calls: vec![DarkLeaf {
+                data: ContractCall { contract_id, data: vec![index as u8] },
+                parent_index: None,
+                children_indexes: vec![],
+            }],
+            proofs: vec![vec![proof]],
+            signatures: vec![vec![]],
+        };
+        let zkp_table = vec![vec![(zkbin.namespace.clone(), public_inputs)]];
+        batch.add(index, &tx, &vks, zkp_table)?;
+    }
+
+    // Only the transaction with the invalid proof must be identified
+    let invalid = smol::block_on(batch.verify(&vks));
+    assert_eq!(invalid, vec![bad_index]);
+
+    Ok(())
+}
+
+#[test]
+fn zk_proof_batch_missing_vk() -> Result<()> {
+    let zkbin = arithmetic_zkbin()?;
+    let contract_id = ContractId::derive(SecretKey::random(&mut OsRng));
+    let vks = HashMap::from([(contract_id.to_bytes(), HashMap::new())]);
+
+    // A proof for an unknown circuit can't be added to the batch
+    let tx = Transaction {
+        calls: vec![DarkLeaf {
+            data: ContractCall { contract_id, data: vec![] },
+            parent_index: None,
+            children_indexes: vec![],
+        }],
+        proofs: vec![vec![Proof::new(vec![])]],
+        signatures: vec![vec![]],
+    };
+    let zkp_table = vec![vec![(zkbin.namespace.clone(), vec![pallas::Base::ZERO; 3])]];
+    let mut batch = ZkProofBatch::default();
+    assert!(batch.add(0, &tx, &vks, zkp_table).is_err());
+    assert!(smol::block_on(batch.verify(&vks)).is_empty());
+
+    Ok(())
+}